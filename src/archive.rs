@@ -0,0 +1,70 @@
+//! Completed orders are moved out of the live ones after a while
+//!
+//! Live orders are read on almost every update, while delivered and
+//! canceled ones are only needed for history, statistics and exports.
+//! `ARCHIVE_AFTER_DAYS` (30 by default, 0 never) sets after how many days
+//! since completion an order is archived. Deleting an order archives it
+//! right away, only admins delete archived orders for good. A canceled
+//! order comes back from the archive when its owner publishes it again.
+
+use std::time::Duration;
+
+use crate::error::Error;
+use crate::Db;
+use crate::order::Status;
+
+/// How often we look for orders to archive
+const ARCHIVE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const DEFAULT_ARCHIVE_AFTER_DAYS: i64 = 30;
+
+/// None if orders are never archived
+pub fn archive_after() -> Option<chrono::Duration> {
+    let days = match std::env::var("ARCHIVE_AFTER_DAYS") {
+        Ok(s) => s.parse().unwrap_or_else(|e| {
+            log::warn!("ARCHIVE_AFTER_DAYS={s:?}: {e:?}");
+            DEFAULT_ARCHIVE_AFTER_DAYS
+        }),
+        Err(_) => DEFAULT_ARCHIVE_AFTER_DAYS,
+    };
+    (days > 0).then(|| chrono::Duration::days(days))
+}
+
+/// Archives orders completed longer than `after` ago, returns how many
+pub async fn archive_due(
+    mut db: Db,
+    after: chrono::Duration,
+) -> Result<usize, Error> {
+    let cutoff = db.now() - after;
+    let mut archived = 0;
+    for (pcid, _name) in db.public_chats().await? {
+        let mut orders = db.orders_by_status(pcid, Status::DeliveryConfirmed)
+            .await?;
+        orders.extend(db.orders_by_status(pcid, Status::Unpublished).await?);
+        for order in orders {
+            let due = order.completed_at().is_some_and(|t| t < cutoff);
+            if let (true, Some(oid)) = (due, order.id) {
+                db.archive_order(pcid, oid).await?;
+                archived += 1;
+            }
+        }
+    }
+    if archived > 0 {
+        log::info!("archived {archived} orders");
+    }
+    Ok(archived)
+}
+
+/// Archives completed orders when they're due, forever
+pub async fn run(db: Db) {
+    let after = match archive_after() {
+        Some(after) => after,
+        None => return,
+    };
+    loop {
+        if let Err(e) = archive_due(db.clone(), after).await {
+            log::error!("archive::run: {e:?}");
+        }
+        tokio::time::sleep(ARCHIVE_INTERVAL).await;
+    }
+}
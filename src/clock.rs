@@ -0,0 +1,48 @@
+#[cfg(test)]
+use std::sync::{Arc, Mutex};
+use crate::{DateTime, Offset};
+
+/// Where we get current time from
+///
+/// Everything that depends on current time should ask the clock instead
+/// of calling `Offset::now()`, so it can be tested with `Clock::fixed`
+#[derive(Clone, Debug, Default)]
+pub enum Clock {
+    /// Real time
+    #[default]
+    System,
+
+    /// Time that only changes when we say so
+    #[cfg(test)]
+    Fixed(Arc<Mutex<DateTime>>),
+}
+
+impl Clock {
+    #[cfg(test)]
+    pub fn fixed(now: DateTime) -> Clock {
+        Clock::Fixed(Arc::new(Mutex::new(now)))
+    }
+
+    pub fn now(&self) -> DateTime {
+        match self {
+            Clock::System => Offset::now(),
+            #[cfg(test)]
+            Clock::Fixed(now) => *now.lock().unwrap(),
+        }
+    }
+
+    /// Sets the time of a fixed clock, does nothing to the system clock
+    #[cfg(test)]
+    pub fn set(&self, t: DateTime) {
+        match self {
+            Clock::System => log::warn!("Trying to set the system clock"),
+            Clock::Fixed(now) => *now.lock().unwrap() = t,
+        }
+    }
+
+    /// Moves a fixed clock forward, does nothing to the system clock
+    #[cfg(test)]
+    pub fn advance(&self, dur: chrono::Duration) {
+        self.set(self.now() + dur)
+    }
+}
@@ -0,0 +1,296 @@
+//! What we collect about users and for how long we keep it
+//!
+//! The policy is read from the environment once:
+//! - `COLLECT_UPDATES` lists update kinds users are recorded from,
+//!   like `message,callback_query,chat_member`
+//! - `COLLECT_USER_FIELDS` lists optional fields of users we keep, from
+//!   `last_name`, `username` and `language_code`
+//! - `USER_RETENTION_DAYS` is how long we keep users that haven't been
+//!   seen and have no orders, 0 keeps them forever
+
+use std::sync::LazyLock;
+use std::time::Duration;
+
+use teloxide::types::{UpdateKind, User};
+
+use crate::error::Error;
+use crate::Db;
+use crate::DateTime;
+use crate::settings::UserSettings;
+
+/// How often we look for users to purge
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+const DEFAULT_RETENTION_DAYS: i64 = 90;
+
+/// Kinds of updates users can be recorded from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Source {
+    /// Messages in chats with the bot, and their edits
+    Message,
+    ChannelPost,
+    /// Inline queries and chosen inline results
+    InlineQuery,
+    CallbackQuery,
+    /// Shipping and pre-checkout queries
+    Payment,
+    PollAnswer,
+    /// Changes of members of chats, including the bot itself
+    ChatMember,
+    JoinRequest,
+}
+
+impl Source {
+    pub const ALL: &'static [Source] = &[
+        Source::Message,
+        Source::ChannelPost,
+        Source::InlineQuery,
+        Source::CallbackQuery,
+        Source::Payment,
+        Source::PollAnswer,
+        Source::ChatMember,
+        Source::JoinRequest,
+    ];
+
+    /// What the bot needs to know who's in its chats
+    const DEFAULT: &'static [Source] = &[
+        Source::Message,
+        Source::CallbackQuery,
+        Source::ChatMember,
+    ];
+
+    const fn id(self) -> &'static str {
+        match self {
+            Source::Message       => "message",
+            Source::ChannelPost   => "channel_post",
+            Source::InlineQuery   => "inline_query",
+            Source::CallbackQuery => "callback_query",
+            Source::Payment       => "payment",
+            Source::PollAnswer    => "poll_answer",
+            Source::ChatMember    => "chat_member",
+            Source::JoinRequest   => "join_request",
+        }
+    }
+
+    fn maybe_from_id(s: &str) -> Option<Source> {
+        Self::ALL.iter().copied().find(|source| source.id() == s)
+    }
+
+    /// None for updates without users
+    pub fn of(kind: &UpdateKind) -> Option<Source> {
+        match kind {
+            UpdateKind::Message(_)            => Some(Source::Message),
+            UpdateKind::EditedMessage(_)      => Some(Source::Message),
+            UpdateKind::ChannelPost(_)        => Some(Source::ChannelPost),
+            UpdateKind::EditedChannelPost(_)  => Some(Source::ChannelPost),
+            UpdateKind::InlineQuery(_)        => Some(Source::InlineQuery),
+            UpdateKind::ChosenInlineResult(_) => Some(Source::InlineQuery),
+            UpdateKind::CallbackQuery(_)      => Some(Source::CallbackQuery),
+            UpdateKind::ShippingQuery(_)      => Some(Source::Payment),
+            UpdateKind::PreCheckoutQuery(_)   => Some(Source::Payment),
+            UpdateKind::PollAnswer(_)         => Some(Source::PollAnswer),
+            UpdateKind::MyChatMember(_)       => Some(Source::ChatMember),
+            UpdateKind::ChatMember(_)         => Some(Source::ChatMember),
+            UpdateKind::ChatJoinRequest(_)    => Some(Source::JoinRequest),
+            UpdateKind::Poll(_)               => None,
+            UpdateKind::Error(_)              => None,
+        }
+    }
+}
+
+/// Optional fields of `User`, the id and the first name are always kept
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UserField {
+    LastName,
+    Username,
+    LanguageCode,
+}
+
+impl UserField {
+    pub const ALL: &'static [UserField] = &[
+        UserField::LastName,
+        UserField::Username,
+        UserField::LanguageCode,
+    ];
+
+    /// Names and usernames are shown in orders, the language isn't used
+    const DEFAULT: &'static [UserField] = &[
+        UserField::LastName,
+        UserField::Username,
+    ];
+
+    const fn id(self) -> &'static str {
+        match self {
+            UserField::LastName     => "last_name",
+            UserField::Username     => "username",
+            UserField::LanguageCode => "language_code",
+        }
+    }
+
+    fn maybe_from_id(s: &str) -> Option<UserField> {
+        Self::ALL.iter().copied().find(|field| field.id() == s)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CollectionPolicy {
+    pub sources: Vec<Source>,
+    pub user_fields: Vec<UserField>,
+    /// None keeps users forever
+    pub retention: Option<chrono::Duration>,
+}
+
+impl Default for CollectionPolicy {
+    fn default() -> Self {
+        CollectionPolicy {
+            sources: Source::DEFAULT.to_vec(),
+            user_fields: UserField::DEFAULT.to_vec(),
+            retention: Some(chrono::Duration::days(DEFAULT_RETENTION_DAYS)),
+        }
+    }
+}
+
+/// Parses a comma separated list, unknown items are logged and skipped
+fn parse_list<T>(var: &str, s: &str, from_id: fn(&str) -> Option<T>) -> Vec<T> {
+    s.split(',')
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .filter_map(|id| {
+            let item = from_id(id);
+            if item.is_none() {
+                log::warn!("{var}: unknown {id:?}");
+            }
+            item
+        })
+        .collect()
+}
+
+impl CollectionPolicy {
+    /// Reads the policy from the environment, defaults are used for
+    /// missing variables
+    pub fn from_env() -> Self {
+        let mut policy = CollectionPolicy::default();
+        if let Ok(s) = std::env::var("COLLECT_UPDATES") {
+            policy.sources = parse_list("COLLECT_UPDATES", &s, Source::maybe_from_id);
+        }
+        if let Ok(s) = std::env::var("COLLECT_USER_FIELDS") {
+            policy.user_fields =
+                parse_list("COLLECT_USER_FIELDS", &s, UserField::maybe_from_id);
+        }
+        if let Ok(s) = std::env::var("USER_RETENTION_DAYS") {
+            match s.parse::<i64>() {
+                Ok(0) => policy.retention = None,
+                Ok(days) => policy.retention = Some(chrono::Duration::days(days)),
+                Err(e) => log::warn!("USER_RETENTION_DAYS={s:?}: {e:?}"),
+            }
+        }
+        log::info!("collection policy: {policy:?}");
+        policy
+    }
+
+    pub fn records(&self, source: Source) -> bool {
+        self.sources.contains(&source)
+    }
+
+    /// `user` without the fields we don't keep
+    pub fn minimise(&self, user: &User) -> User {
+        let keeps = |field| self.user_fields.contains(&field);
+        User {
+            id: user.id,
+            is_bot: user.is_bot,
+            first_name: user.first_name.clone(),
+            last_name: user.last_name.clone()
+                .filter(|_| keeps(UserField::LastName)),
+            username: user.username.clone()
+                .filter(|_| keeps(UserField::Username)),
+            language_code: user.language_code.clone()
+                .filter(|_| keeps(UserField::LanguageCode)),
+        }
+    }
+}
+
+static POLICY: LazyLock<CollectionPolicy> = LazyLock::new(CollectionPolicy::from_env);
+
+pub fn policy() -> &'static CollectionPolicy {
+    &POLICY
+}
+
+/// Forgets profiles of users that haven't been seen for longer than the
+/// retention period and that no order, digest or setting needs, returns
+/// how many
+///
+/// They stay members of their chats, quiet members are still members
+pub async fn purge(mut db: Db, policy: &CollectionPolicy) -> Result<usize, Error> {
+    let retention = match policy.retention {
+        Some(retention) => retention,
+        None => return Ok(0),
+    };
+    let now: DateTime = db.now();
+    let mut purged = 0;
+    'users: for uid in db.users_not_seen_since(now - retention).await? {
+        if db.user_settings(uid).await? != UserSettings::default() {
+            continue
+        }
+        for (pcid, _name) in db.user_public_chats(uid).await? {
+            if !db.orders_involving(pcid, uid).await?.is_empty()
+                || db.digest_subscribers(pcid).await?.contains(&uid)
+            {
+                continue 'users
+            }
+        }
+        db.forget_profile(uid).await?;
+        purged += 1;
+    }
+    if purged > 0 {
+        log::info!("purged {purged} users not seen for {} days",
+                   retention.num_days());
+    }
+    Ok(purged)
+}
+
+/// Purges users by the policy, forever
+pub async fn run(db: Db) {
+    loop {
+        if let Err(e) = purge(db.clone(), policy()).await {
+            log::error!("collection::run: {e:?}");
+        }
+        tokio::time::sleep(PURGE_INTERVAL).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use teloxide::types::UserId;
+
+    #[test]
+    fn test_minimise_user() {
+        let user = User {
+            id: UserId(1),
+            is_bot: false,
+            first_name: "first".to_string(),
+            last_name: Some("last".to_string()),
+            username: Some("user".to_string()),
+            language_code: Some("hy".to_string()),
+        };
+        let policy = CollectionPolicy::default();
+        let kept = policy.minimise(&user);
+        assert_eq!(Some("last"), kept.last_name.as_deref());
+        assert_eq!(Some("user"), kept.username.as_deref());
+        assert_eq!(None, kept.language_code);
+
+        let policy = CollectionPolicy {
+            user_fields: parse_list("", "language_code, nickname,",
+                                    UserField::maybe_from_id),
+            ..CollectionPolicy::default()
+        };
+        let kept = policy.minimise(&user);
+        assert_eq!("first", kept.first_name);
+        assert_eq!(None, kept.last_name);
+        assert_eq!(None, kept.username);
+        assert_eq!(Some("hy"), kept.language_code.as_deref());
+
+        assert!(policy.records(Source::Message));
+        assert!(!policy.records(Source::PollAnswer));
+    }
+}
@@ -1,9 +1,11 @@
 use teloxide::prelude::*;
-use teloxide::types::{MessageKind, MessageNewChatMembers, ChatKind,
+use teloxide::types::{User, MessageKind, MessageNewChatMembers, ChatKind,
                       MessageLeftChatMember};
 use crate::error::Error;
+use crate::collection;
 use crate::Db;
 use crate::db::PubChatFromMsgError;
+use crate::moderation::ChatAdmins;
 
 pub async fn pub_chat_id_from_cq(
     db: &mut Db,
@@ -27,7 +29,7 @@ pub async fn pub_chat_id_from_cq(
     }
 
     let user = &q.from;
-    let pc = db.user_public_chats(user.id).await;
+    let pc = user_public_chats(db, user.id).await;
     if let Err(e) = pc {
         log::warn!("pub_chat_id_from_msg: {e:?}");
         return Err(PubChatFromMsgError::Other)
@@ -45,13 +47,83 @@ pub async fn pub_chat_id_from_cq(
     Ok(pc[0].0)
 }
 
+/// Public chats of `uid`, only the default one if they've chosen it
+/// in settings and are still there
+pub async fn user_public_chats(
+    db: &mut Db,
+    uid: UserId,
+) -> Result<Vec<(ChatId, String)>, Error> {
+    let chats = db.user_public_chats(uid).await?;
+    let default_chat = db.user_settings(uid).await?.default_chat;
+    match chats.iter().find(|(pcid, _name)| Some(*pcid) == default_chat) {
+        Some(chat) => Ok(vec![chat.clone()]),
+        None => Ok(chats),
+    }
+}
+
+/// Tells if `uid` is an admin (or the creator) of `pcid`
+///
+/// Asks Telegram unless we've asked recently, errors are logged
+/// and treated as "not an admin"
+pub async fn is_chat_admin(
+    bot: &AutoSend<Bot>,
+    db: &mut Db,
+    pcid: ChatId,
+    uid: UserId,
+) -> bool {
+    chat_admins(bot, db, pcid).await.contains(&uid)
+}
+
+/// Admins (and the creator) of `pcid` except bots
+///
+/// Asks Telegram unless we've asked recently, errors are logged
+/// and treated as "no admins"
+pub async fn chat_admins(
+    bot: &AutoSend<Bot>,
+    db: &mut Db,
+    pcid: ChatId,
+) -> Vec<UserId> {
+    let now = db.now();
+    match db.chat_admins(pcid).await {
+        Ok(Some(cached)) if cached.is_fresh(now) => {
+            return cached.admins
+        },
+        Ok(_) => {},
+        Err(e) => log::warn!("chat_admins {pcid}: {e:?}"),
+    }
+
+    let members = match bot.get_chat_administrators(pcid).await {
+        Ok(members) => members,
+        Err(e) => {
+            log::warn!("chat_admins {pcid}: {e:?}");
+            return Vec::new()
+        }
+    };
+    let admins = ChatAdmins {
+        fetched_at: now,
+        admins: members.iter()
+            .filter(|m| !m.user.is_bot)
+            .map(|m| m.user.id)
+            .collect(),
+    };
+    if let Err(e) = db.set_chat_admins(pcid, &admins).await {
+        log::warn!("chat_admins {pcid}: {e:?}");
+    }
+    admins.admins
+}
+
+/// Stores `user` with only the fields the collection policy keeps
+pub async fn remember_user(db: &mut Db, user: &User) -> Result<(), Error> {
+    db.update_user(collection::policy().minimise(user)).await
+}
+
 pub async fn collect_data_from_cq(
     db: &mut Db,
     cq: CallbackQuery,
 ) -> Result<(), Error> {
     log::debug!("-> collect_data_from_cq {cq:?}");
 
-    db.update_user(cq.from.clone()).await?;
+    remember_user(db, &cq.from).await?;
 
     if let Some(msg) = &cq.message {
         collect_data_from_msg(db, msg.clone()).await?;
@@ -69,7 +141,7 @@ pub async fn collect_data_from_msg(
     log::debug!("-> collect_data_from_msg, {msg:?}");
 
     if let Some(user) = msg.from()  {
-        db.update_user(user.clone()).await?;
+        remember_user(db, user).await?;
     }
 
     let cid = msg.chat.id;
@@ -102,7 +174,7 @@ async fn handle_new_chat_members(
     m: &MessageNewChatMembers
 ) -> Result<(), Error> {
     for u in m.new_chat_members.iter() {
-        db.update_user(u.clone()).await?;
+        remember_user(db, u).await?;
     }
 
     let uids = m.new_chat_members.iter().map(|m| m.id).collect();
@@ -117,7 +189,7 @@ async fn handle_left_chat_members(
     let user = &m.left_chat_member;
     let uid = user.id;
 
-    db.update_user(user.clone()).await?;
+    remember_user(db, user).await?;
     db.remove_chat_membership(cid, uid).await?;
 
     Ok(())
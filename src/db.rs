@@ -29,8 +29,8 @@ Try writting '/hello' into the public chat you're in to make sure \
 the bot knows you're there")
             },
             PubChatFromMsgError::MultipleChats => {
-                // TODO support for multiple chats per user
-                write!(f, "You are in multiple chats. This is not supported yet. Sorry!")
+                write!(f, "You are in multiple chats. Please choose the one \
+you want to use in Settings ⚙️ of the /menu")
             },
             PubChatFromMsgError::Other => {
                 write!(f, "Some error occured")
@@ -6,8 +6,17 @@ use teloxide::types::{User, UserId, Chat, ChatKind, MessageId};
 use std::sync::{Arc, RwLock};
 use std::collections::{BTreeSet, BTreeMap};
 use crate::error::Error;
-use crate::order::{self, Order, OrderId, Action, ActionKind, Status};
-use crate::order::ActionError;
+use crate::order::{Order, OrderId, Action, ActionKind, Status};
+use crate::order::{ActionError, ItemUpdate};
+use crate::clock::Clock;
+use crate::moderation::{ChatAdmins, Moderation, ModerationRecord};
+use crate::rate_limit::ChatLimits;
+use crate::digest::DigestSettings;
+use crate::settings::{UserSettings, HeldNotification};
+use crate::template::Template;
+use crate::ledger;
+use crate::DateTime;
+use crate::utils;
 
 
 #[derive(Clone, Debug)]
@@ -15,6 +24,22 @@ pub struct PublicChat {
     pub chat: Chat,
     pub members: Vec<UserId>,
     pub orders: Vec<Order>,
+    /// Completed orders that are moved out of the live ones
+    pub archive: Vec<Order>,
+    /// Users that can't post orders here
+    pub banned: BTreeSet<UserId>,
+    /// Everything admins did here, oldest first
+    pub moderation: Vec<ModerationRecord>,
+    /// Who owes whom, never changed, only appended to
+    pub ledger: Vec<ledger::Entry>,
+    pub limits: ChatLimits,
+    /// When members created their orders in the last day, deleted ones too
+    pub created: BTreeMap<UserId, Vec<DateTime>>,
+    /// Message that shows published orders
+    pub board: Option<MessageId>,
+    pub digest: DigestSettings,
+    /// Members that get the digest in private
+    pub digest_subscribers: BTreeSet<UserId>,
 }
 
 impl PublicChat {
@@ -23,6 +48,15 @@ impl PublicChat {
             chat,
             members: Vec::new(),
             orders: Vec::new(),
+            archive: Vec::new(),
+            banned: BTreeSet::new(),
+            moderation: Vec::new(),
+            ledger: Vec::new(),
+            limits: ChatLimits::default(),
+            created: BTreeMap::new(),
+            board: None,
+            digest: DigestSettings::default(),
+            digest_subscribers: BTreeSet::new(),
         }
     }
 
@@ -54,11 +88,26 @@ impl PublicChat {
 #[derive(Clone)]
 pub struct Db {
     db: Arc<RwLock<InnerDb>>,
+    clock: Clock,
 }
 
 impl Db {
-    pub async fn new() -> Result<Self, Error> {
-        Ok(Db { db: Arc::new(RwLock::new(InnerDb::default())) })
+    pub async fn new(clock: Clock) -> Result<Self, Error> {
+        Ok(Db { db: Arc::new(RwLock::new(InnerDb::default())), clock })
+    }
+
+    /// Current time according to our clock
+    pub fn now(&self) -> DateTime {
+        self.clock.now()
+    }
+
+    /// Checks that the lock isn't poisoned
+    pub async fn ping(&mut self) -> Result<(), Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let _db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
     }
 
     pub async fn user_public_chats(
@@ -76,6 +125,19 @@ impl Db {
         }).await.map_err(|e| format!("{e:?}").into()).flatten()
     }
 
+    /// All public chats the bot is in, with their names
+    pub async fn public_chats(
+        &mut self,
+    ) -> Result<Vec<(ChatId, String)>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.public_chats.iter()
+                .map(|pc| (pc.chat.id, pc.chat.title().unwrap_or_default().to_string()))
+                .collect())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
     /// Returns new order's `OrderId`
     pub async fn add_order(
         &mut self,
@@ -92,6 +154,22 @@ impl Db {
         Ok(oid)
     }
 
+    /// When `uid` created orders in `pcid` in the last day,
+    /// deleted orders included
+    pub async fn order_creations(
+        &mut self,
+        pcid: ChatId,
+        uid: UserId,
+    ) -> Result<Vec<DateTime>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.pub_chat(pcid)
+               .and_then(|pc| pc.created.get(&uid).cloned())
+               .unwrap_or_default())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
     pub async fn debug_stats(&mut self) -> Result<String, Error> {
         let db = self.db.clone();
         spawn_blocking(move || {
@@ -144,15 +222,146 @@ impl Db {
         }).await.map_err(|e| format!("{e:?}").into()).flatten()
     }
 
-    /// Performs the action and returns previous state and the Order
+    /// Orders in `pcid` that `uid` has created or is delivering,
+    /// archived ones included
+    pub async fn orders_involving(
+        &mut self,
+        pcid: ChatId,
+        uid: UserId,
+    ) -> Result<Vec<Order>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.pub_chat(pcid)
+               .map(|pc| pc.orders.iter()
+                    .chain(pc.archive.iter())
+                    .filter(|o| o.involves(uid))
+                    .cloned()
+                    .collect())
+               .unwrap_or_default())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Orders in `pcid` that mention `uid` anywhere, see `Order::mentions`,
+    /// archived ones included
+    pub async fn orders_mentioning(
+        &mut self,
+        pcid: ChatId,
+        uid: UserId,
+    ) -> Result<Vec<Order>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.pub_chat(pcid)
+               .map(|pc| pc.orders.iter()
+                    .chain(pc.archive.iter())
+                    .filter(|o| o.mentions(uid))
+                    .cloned()
+                    .collect())
+               .unwrap_or_default())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Get data of order that's in `pcid`
+    pub async fn get_order(
+        &mut self,
+        pcid: ChatId,
+        oid: OrderId,
+    ) -> Result<Option<Order>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.find_order(pcid, oid).cloned())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// All orders of `pcid`, live and archived
+    pub async fn chat_history(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<Vec<Order>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.pub_chat(pcid)
+               .map(|pc| pc.orders.iter().chain(pc.archive.iter()).cloned().collect())
+               .unwrap_or_default())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Completed orders of `pcid` that are moved out of the live ones
+    pub async fn archived_orders(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<Vec<Order>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.pub_chat(pcid).map(|pc| pc.archive.clone()).unwrap_or_default())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Moves the order to the archive, it can't be changed after that
+    pub async fn archive_order(
+        &mut self,
+        pcid: ChatId,
+        oid: OrderId,
+    ) -> Result<(), Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let mut db = db.write().map_err(|e| format!("lock: {e:?}"))?;
+            db.archive_order(pcid, oid).map_err(|e| format!("{e:?}").into())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Destroys an archived order, returns false if there's no such order
+    ///
+    /// It's moderation, `admin` is recorded in the log
+    pub async fn delete_archived_order(
+        &mut self,
+        pcid: ChatId,
+        admin: UserId,
+        oid: OrderId,
+    ) -> Result<bool, Error> {
+        let db = self.db.clone();
+        let now = self.now();
+        spawn_blocking(move || {
+            let mut db = db.write().map_err(|e| format!("lock: {e:?}"))?;
+            let pc = match db.pub_chat_mut(pcid) {
+                Some(pc) => pc,
+                None => return Ok(false),
+            };
+            let len = pc.archive.len();
+            pc.archive.retain(|o| o.id != Some(oid));
+            let deleted = pc.archive.len() != len;
+            if deleted {
+                db.order_msgs.remove(&oid);
+                db.add_moderation(pcid, ModerationRecord {
+                    admin,
+                    at: now,
+                    moderation: Moderation::Order {
+                        order_id: oid,
+                        action: ActionKind::Delete,
+                    },
+                });
+            }
+            Ok(deleted)
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Performs the action and returns the Order before and after it
     /// If the order is deleted then the returned order is None
+    ///
+    /// `is_admin` tells if `user` is an admin of `pcid`
     pub async fn perform_action(
         &mut self,
         user: User,
+        is_admin: bool,
         pcid: ChatId,
         action: Action,
-    ) -> Result<(order::Status, Option<Order>), ActionError> {
+    ) -> Result<(Order, Option<Order>), ActionError> {
         let db = self.db.clone();
+        let now = self.now();
         let res = spawn_blocking(move || {
             let db = db.write();
             if let Err(e) = db {
@@ -160,7 +369,7 @@ impl Db {
                 return Err(ActionError::Other);
             }
             let mut db = db.unwrap();
-            db.perform_action(user, pcid, &action)
+            db.perform_action(user, is_admin, pcid, &action, now)
         }).await;
 
         match res {
@@ -172,6 +381,33 @@ impl Db {
         }
     }
 
+    /// The courier ticks off an item of the order or tells its price,
+    /// returns the updated order
+    pub async fn update_item(
+        &mut self,
+        pcid: ChatId,
+        uid: UserId,
+        oid: OrderId,
+        idx: usize,
+        update: ItemUpdate,
+    ) -> Result<Order, ActionError> {
+        let db = self.db.clone();
+        let res = spawn_blocking(move || {
+            let mut db = db.write().map_err(|e| {
+                log::warn!("WLock: {e:?}");
+                ActionError::Other
+            })?;
+            let order = db.find_order_mut(pcid, oid)
+                .ok_or(ActionError::OrderNotFound(oid))?;
+            order.update_item(uid, idx, update)?;
+            Ok(order.clone())
+        }).await;
+        res.unwrap_or_else(|e| {
+            log::warn!("Error while updating an item: {e:?}");
+            Err(ActionError::Other)
+        })
+    }
+
     pub async fn add_members(
         &mut self,
         cid: ChatId,
@@ -185,6 +421,20 @@ impl Db {
         }).await.map_err(|e| format!("{e:?}").into()).flatten()
     }
 
+    /// Users we've seen in a public chat
+    pub async fn chat_members(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<Vec<UserId>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.pub_chat(pcid)
+               .map(|pc| pc.members.clone())
+               .unwrap_or_default())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
     pub async fn remove_chat_membership(
         &mut self,
         cid: ChatId,
@@ -203,13 +453,30 @@ impl Db {
         user: User,
     ) -> Result<(), Error> {
         let db = self.db.clone();
+        let now = self.now();
         spawn_blocking(move || {
             let mut db = db.write().map_err(|e| format!("lock: {e:?}"))?;
+            db.last_seen.insert(user.id, now);
             db.update_user(user);
             Ok(())
         }).await.map_err(|e| format!("{e:?}").into()).flatten()
     }
 
+    /// Users we've last seen before `t`
+    pub async fn users_not_seen_since(
+        &mut self,
+        t: DateTime,
+    ) -> Result<Vec<UserId>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.last_seen.iter()
+               .filter(|(_uid, seen)| **seen < t)
+               .map(|(uid, _seen)| *uid)
+               .collect())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
     pub async fn update_chat(
         &mut self,
         chat: Chat,
@@ -271,6 +538,417 @@ impl Db {
         }).await.map_err(|e| format!("{e:?}").into()).flatten()?;
         Ok(())
     }
+
+    /// Cached admins of the public chat, None if we've never asked
+    pub async fn chat_admins(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<Option<ChatAdmins>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.chat_admins.get(&pcid).cloned())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    pub async fn set_chat_admins(
+        &mut self,
+        pcid: ChatId,
+        admins: &ChatAdmins,
+    ) -> Result<(), Error> {
+        let db = self.db.clone();
+        let admins = admins.clone();
+        spawn_blocking(move || {
+            let mut db = db.write().map_err(|e| format!("lock: {e:?}"))?;
+            db.chat_admins.insert(pcid, admins);
+            Ok(())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Doesn't let `uid` post orders in `pcid` any more
+    pub async fn ban_user(
+        &mut self,
+        pcid: ChatId,
+        admin: UserId,
+        uid: UserId,
+    ) -> Result<(), Error> {
+        let db = self.db.clone();
+        let now = self.now();
+        spawn_blocking(move || {
+            let mut db = db.write().map_err(|e| format!("lock: {e:?}"))?;
+            db.set_banned(pcid, admin, uid, true, now)
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    pub async fn unban_user(
+        &mut self,
+        pcid: ChatId,
+        admin: UserId,
+        uid: UserId,
+    ) -> Result<(), Error> {
+        let db = self.db.clone();
+        let now = self.now();
+        spawn_blocking(move || {
+            let mut db = db.write().map_err(|e| format!("lock: {e:?}"))?;
+            db.set_banned(pcid, admin, uid, false, now)
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    pub async fn is_banned(
+        &mut self,
+        pcid: ChatId,
+        uid: UserId,
+    ) -> Result<bool, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.is_banned(pcid, uid))
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    pub async fn banned_users(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<Vec<UserId>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.pub_chat(pcid)
+               .map(|pc| pc.banned.iter().cloned().collect())
+               .unwrap_or_default())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Everything admins did in the chat, oldest first
+    pub async fn moderation_log(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<Vec<ModerationRecord>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.pub_chat(pcid)
+               .map(|pc| pc.moderation.clone())
+               .unwrap_or_default())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Appends the entry `make` returns for the current ledger of the chat,
+    /// so two clicks can't record the same repayment twice. Returns its
+    /// position and the entry, or why `make` refused to make one.
+    pub async fn add_checked_ledger_entry<F>(
+        &mut self,
+        pcid: ChatId,
+        make: F,
+    ) -> Result<Result<(usize, ledger::Entry), String>, Error>
+    where
+        F: Fn(&[ledger::Entry]) -> Result<ledger::Entry, String> + Send + 'static,
+    {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let mut db = db.write().map_err(|e| format!("lock: {e:?}"))?;
+            let pc = db.pub_chat_mut(pcid)
+                .ok_or_else(|| format!("no public chat {pcid}"))?;
+            let entry = match make(&pc.ledger) {
+                Ok(entry) => entry,
+                Err(e) => return Ok(Err(e)),
+            };
+            log::info!("ledger entry in {pcid}: {entry:?}");
+            pc.ledger.push(entry.clone());
+            Ok(Ok((pc.ledger.len() - 1, entry)))
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// All entries of the chat's ledger, oldest first
+    pub async fn ledger(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<Vec<ledger::Entry>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.pub_chat(pcid)
+               .map(|pc| pc.ledger.clone())
+               .unwrap_or_default())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Limits of the chat, the default ones if admins haven't changed them
+    pub async fn chat_limits(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<ChatLimits, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.chat_limits(pcid))
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    pub async fn set_chat_limits(
+        &mut self,
+        pcid: ChatId,
+        limits: &ChatLimits,
+    ) -> Result<(), Error> {
+        let db = self.db.clone();
+        let limits = *limits;
+        spawn_blocking(move || {
+            let mut db = db.write().map_err(|e| format!("lock: {e:?}"))?;
+            let pc = db.pub_chat_mut(pcid)
+                .ok_or_else(|| format!("no public chat {pcid}"))?;
+            pc.limits = limits;
+            Ok(())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Message in `pcid` that shows published orders, if we've sent one
+    pub async fn board_msg_id(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<Option<MessageId>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.pub_chat(pcid).and_then(|pc| pc.board))
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    pub async fn set_board_msg_id(
+        &mut self,
+        pcid: ChatId,
+        mid: MessageId,
+    ) -> Result<(), Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let mut db = db.write().map_err(|e| format!("lock: {e:?}"))?;
+            let pc = db.pub_chat_mut(pcid)
+                .ok_or_else(|| format!("no public chat {pcid}"))?;
+            pc.board = Some(mid);
+            Ok(())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Digest settings of the chat, the default ones if admins haven't
+    /// changed them
+    pub async fn digest_settings(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<DigestSettings, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.pub_chat(pcid).map(|pc| pc.digest).unwrap_or_default())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    pub async fn set_digest_settings(
+        &mut self,
+        pcid: ChatId,
+        settings: &DigestSettings,
+    ) -> Result<(), Error> {
+        let db = self.db.clone();
+        let settings = *settings;
+        spawn_blocking(move || {
+            let mut db = db.write().map_err(|e| format!("lock: {e:?}"))?;
+            let pc = db.pub_chat_mut(pcid)
+                .ok_or_else(|| format!("no public chat {pcid}"))?;
+            pc.digest = settings;
+            Ok(())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Members of `pcid` that get its digest in private
+    pub async fn digest_subscribers(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<Vec<UserId>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.pub_chat(pcid)
+               .map(|pc| pc.digest_subscribers.iter().copied().collect())
+               .unwrap_or_default())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    pub async fn set_digest_subscription(
+        &mut self,
+        pcid: ChatId,
+        uid: UserId,
+        subscribed: bool,
+    ) -> Result<(), Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let mut db = db.write().map_err(|e| format!("lock: {e:?}"))?;
+            let pc = db.pub_chat_mut(pcid)
+                .ok_or_else(|| format!("no public chat {pcid}"))?;
+            if subscribed {
+                pc.digest_subscribers.insert(uid);
+            } else {
+                pc.digest_subscribers.remove(&uid);
+            }
+            Ok(())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// What the user has chosen in settings, defaults if nothing yet
+    pub async fn user_settings(
+        &mut self,
+        uid: UserId,
+    ) -> Result<UserSettings, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.user_settings.get(&uid).copied().unwrap_or_default())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    pub async fn set_user_settings(
+        &mut self,
+        uid: UserId,
+        settings: &UserSettings,
+    ) -> Result<(), Error> {
+        let db = self.db.clone();
+        let settings = *settings;
+        spawn_blocking(move || {
+            let mut db = db.write().map_err(|e| format!("lock: {e:?}"))?;
+            db.user_settings.insert(uid, settings);
+            Ok(())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Keeps a notification for `uid` until `take_held_notifications`
+    pub async fn hold_notification(
+        &mut self,
+        uid: UserId,
+        held: &HeldNotification,
+    ) -> Result<(), Error> {
+        let db = self.db.clone();
+        let held = held.clone();
+        spawn_blocking(move || {
+            let mut db = db.write().map_err(|e| format!("lock: {e:?}"))?;
+            db.held.entry(uid).or_default().push(held);
+            Ok(())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Users that have held notifications
+    pub async fn users_with_held_notifications(
+        &mut self,
+    ) -> Result<Vec<UserId>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.held.keys().copied().collect())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Held notifications of `uid`, oldest first
+    pub async fn held_notifications(
+        &mut self,
+        uid: UserId,
+    ) -> Result<Vec<HeldNotification>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.held.get(&uid).cloned().unwrap_or_default())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Removes and returns held notifications of `uid`, oldest first
+    pub async fn take_held_notifications(
+        &mut self,
+        uid: UserId,
+    ) -> Result<Vec<HeldNotification>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let mut db = db.write().map_err(|e| format!("lock: {e:?}"))?;
+            Ok(db.held.remove(&uid).unwrap_or_default())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Templates of `uid` sorted by name
+    pub async fn user_templates(
+        &mut self,
+        uid: UserId,
+    ) -> Result<Vec<Template>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.templates.get(&uid)
+               .map(|templates| templates.values().cloned().collect())
+               .unwrap_or_default())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Saves a template of `uid`, replacing the one with the same name
+    pub async fn save_template(
+        &mut self,
+        uid: UserId,
+        template: &Template,
+    ) -> Result<(), Error> {
+        let db = self.db.clone();
+        let template = template.clone();
+        spawn_blocking(move || {
+            let mut db = db.write().map_err(|e| format!("lock: {e:?}"))?;
+            db.templates.entry(uid).or_default()
+                .insert(template.name.clone(), template);
+            Ok(())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Templates that make orders on schedule, with their owners
+    pub async fn recurring_templates(
+        &mut self,
+    ) -> Result<Vec<(UserId, Template)>, Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let db = db.read().map_err(|e| format!("Rlock: {e:?}"))?;
+            Ok(db.templates.iter()
+               .flat_map(|(uid, templates)| templates.values()
+                         .filter(|t| t.recurrence.is_some())
+                         .map(|t| (*uid, t.clone())))
+               .collect())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Returns false if `uid` has no template with this name
+    pub async fn delete_template(
+        &mut self,
+        uid: UserId,
+        name: &str,
+    ) -> Result<bool, Error> {
+        let db = self.db.clone();
+        let name = name.to_string();
+        spawn_blocking(move || {
+            let mut db = db.write().map_err(|e| format!("lock: {e:?}"))?;
+            Ok(db.templates.get_mut(&uid)
+               .is_some_and(|templates| templates.remove(&name).is_some()))
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Removes everything about `uid` except bans and the moderation log,
+    /// orders of other people are kept with `uid` replaced by anonymous
+    pub async fn forget_user(&mut self, uid: UserId) -> Result<(), Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let mut db = db.write().map_err(|e| format!("lock: {e:?}"))?;
+            db.forget_user(uid);
+            Ok(())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
+
+    /// Removes the user's name and when we've seen them, keeping the chats
+    /// they're in
+    pub async fn forget_profile(&mut self, uid: UserId) -> Result<(), Error> {
+        let db = self.db.clone();
+        spawn_blocking(move || {
+            let mut db = db.write().map_err(|e| format!("lock: {e:?}"))?;
+            db.users.remove(&uid);
+            db.last_seen.remove(&uid);
+            Ok(())
+        }).await.map_err(|e| format!("{e:?}").into()).flatten()
+    }
 }
 
 #[derive(Debug)]
@@ -281,6 +959,14 @@ struct InnerDb {
     users: BTreeMap<UserId, User>,
     /// Messages sent for order, so we can remove or edit them
     pub order_msgs: BTreeMap<OrderId, BTreeSet<(ChatId, i32)>>,
+    /// Cached admins of public chats
+    chat_admins: BTreeMap<ChatId, ChatAdmins>,
+    user_settings: BTreeMap<UserId, UserSettings>,
+    /// Notifications waiting for the end of quiet hours, oldest first
+    held: BTreeMap<UserId, Vec<HeldNotification>>,
+    last_seen: BTreeMap<UserId, DateTime>,
+    /// Templates of every user by their names
+    templates: BTreeMap<UserId, BTreeMap<String, Template>>,
 }
 
 impl Default for InnerDb {
@@ -290,6 +976,11 @@ impl Default for InnerDb {
             users:        BTreeMap::new(),
             max_id:       OrderId(0),
             order_msgs:   BTreeMap::new(),
+            chat_admins:  BTreeMap::new(),
+            user_settings: BTreeMap::new(),
+            held:         BTreeMap::new(),
+            last_seen:    BTreeMap::new(),
+            templates:    BTreeMap::new(),
         }
     }
 }
@@ -325,6 +1016,10 @@ impl InnerDb {
         order.id = Some(new_id);
         let s =
             format!("Added order {order:?} new id = {}", new_id.0);
+        let day_ago = order.created_at - chrono::Duration::days(1);
+        let created = pub_chat.created.entry(order.customer.id).or_default();
+        created.retain(|at| *at > day_ago);
+        created.push(order.created_at);
         pub_chat.orders.push(order.clone());
         log::info!("{}", s);
         Ok(new_id)
@@ -395,12 +1090,12 @@ impl InnerDb {
            .collect())
     }
 
-    fn find_order(
-        &mut self,
+    pub fn find_order(
+        &self,
         pub_chat_id: ChatId,
         order_id: OrderId
     ) -> Option<&Order> {
-        let pub_chat = self.pub_chat_mut(pub_chat_id);
+        let pub_chat = self.pub_chat(pub_chat_id);
         if pub_chat.is_none() {
             log::warn!("Could not find public chat id {pub_chat_id}");
             return None
@@ -423,54 +1118,152 @@ impl InnerDb {
         pub_chat.orders.iter_mut().find(|o| o.id == Some(order_id) )
     }
 
-    /// Deletes the order and returns its previous status
-    fn delete_order(
+    /// Moves the order to the archive of its chat
+    fn archive_order(
         &mut self,
         pub_chat_id: ChatId,
-        order_id: OrderId
-    ) -> Result<order::Status, ActionError> {
-        let pub_chat = self.pub_chat_mut(pub_chat_id);
-        if pub_chat.is_none() {
-            return Err(ActionError::OrderNotFound(order_id))
-        }
-        let pub_chat = pub_chat.unwrap();
+        order_id: OrderId,
+    ) -> Result<(), ActionError> {
+        let pub_chat = self.pub_chat_mut(pub_chat_id)
+            .ok_or(ActionError::OrderNotFound(order_id))?;
+        let pos = pub_chat.orders.iter()
+            .position(|o| o.id == Some(order_id))
+            .ok_or(ActionError::OrderNotFound(order_id))?;
+        let order = pub_chat.orders.remove(pos);
+        pub_chat.archive.push(order);
+        Ok(())
+    }
 
-        for (ii, order) in pub_chat.orders.iter().enumerate() {
-            if order.id == Some(order_id) {
-                let status = order.status();
-                pub_chat.orders.remove(ii);
-                return Ok(status);
-            }
-        }
-        Err(ActionError::OrderNotFound(order_id))
+    /// Moves the order back from the archive of its chat
+    fn unarchive_order(
+        &mut self,
+        pub_chat_id: ChatId,
+        order_id: OrderId,
+    ) -> Result<(), ActionError> {
+        let pub_chat = self.pub_chat_mut(pub_chat_id)
+            .ok_or(ActionError::OrderNotFound(order_id))?;
+        let pos = pub_chat.archive.iter()
+            .position(|o| o.id == Some(order_id))
+            .ok_or(ActionError::OrderNotFound(order_id))?;
+        let order = pub_chat.archive.remove(pos);
+        pub_chat.orders.push(order);
+        Ok(())
     }
 
     /// performs the action, returns modified order if successful
     pub fn perform_action(
         &mut self,
         user: User,
+        is_admin: bool,
         pub_chat_id: ChatId,
         action: &Action,
-    ) -> Result<(order::Status, Option<Order>), ActionError> {
+        now: DateTime,
+    ) -> Result<(Order, Option<Order>), ActionError> {
         let uid = user.id;
         log::info!("db.perform_action uid = {uid} pub_chat_id = {pub_chat_id}");
-        if action.kind == ActionKind::Delete {
-            let order = self.find_order(pub_chat_id, action.order_id)
-                .ok_or(ActionError::OrderNotFound(action.order_id))?;
-            if ! order.is_action_permitted(uid, action) {
-                return Err(ActionError::NotPermitted)
+        let archived = self.find_order(pub_chat_id, action.order_id).is_none();
+        let order = if archived && action.kind == ActionKind::Publish {
+            // Canceled orders come back from the archive to be published
+            self.pub_chat(pub_chat_id)
+                .and_then(|pc| pc.archive.iter()
+                          .find(|o| o.id == Some(action.order_id)))
+                .filter(|o| o.status() == Status::Unpublished)
+        } else {
+            self.find_order(pub_chat_id, action.order_id)
+        };
+        let order = order
+            .ok_or(ActionError::OrderNotFound(action.order_id))?
+            .clone();
+        order.check_action(uid, is_admin, action.kind)?;
+        if action.kind == ActionKind::Publish {
+            if self.is_banned(pub_chat_id, uid) {
+                return Err(ActionError::Banned);
+            }
+            let limits = self.chat_limits(pub_chat_id);
+            let orders = self.orders_submitted_by_user(pub_chat_id, uid)
+                .map_err(|_| ActionError::Other)?;
+            if !limits.can_publish_order(&orders) {
+                return Err(ActionError::TooManyOpenOrders(limits.open_orders));
             }
+        }
+        let moderation = order.is_moderation(uid, action.kind);
+        if archived {
+            self.unarchive_order(pub_chat_id, action.order_id)?;
+        }
 
-            let status = self.delete_order(pub_chat_id, action.order_id)?;
-            Ok((status, None))
+        let res = if action.kind == ActionKind::Delete {
+            // Orders are history, only admins destroy them in the archive
+            self.find_order_mut(pub_chat_id, action.order_id)
+                .ok_or(ActionError::OrderNotFound(action.order_id))?
+                .cancel_deleted(now);
+            self.archive_order(pub_chat_id, action.order_id)?;
+            (order, None)
         } else {
-            let order = self.find_order_mut(pub_chat_id, action.order_id)
+            let updated = self.find_order_mut(pub_chat_id, action.order_id)
                 .ok_or(ActionError::OrderNotFound(action.order_id))?;
-            let prev_status = order.perform_action(user, action)?;
-            Ok((prev_status, Some(order.clone())))
+            updated.perform_action(user, is_admin, action, now)?;
+            let updated = updated.clone();
+            // The debt is recorded with the confirmation, so it's never lost
+            if let Some(debt) = ledger::Entry::new_debt(&order, &updated, now) {
+                log::info!("ledger entry in {pub_chat_id}: {debt:?}");
+                self.pub_chat_mut(pub_chat_id)
+                    .ok_or(ActionError::OrderNotFound(action.order_id))?
+                    .ledger.push(debt);
+            }
+            (order, Some(updated))
+        };
+
+        if moderation {
+            self.add_moderation(pub_chat_id, ModerationRecord {
+                admin: uid,
+                at: now,
+                moderation: Moderation::Order {
+                    order_id: action.order_id,
+                    action: action.kind,
+                },
+            });
+        }
+        Ok(res)
+    }
+
+    fn chat_limits(&self, pcid: ChatId) -> ChatLimits {
+        self.pub_chat(pcid).map(|pc| pc.limits).unwrap_or_default()
+    }
+
+    fn is_banned(&self, pcid: ChatId, uid: UserId) -> bool {
+        self.pub_chat(pcid).map(|pc| pc.banned.contains(&uid)).unwrap_or(false)
+    }
+
+    fn add_moderation(&mut self, pcid: ChatId, record: ModerationRecord) {
+        log::info!("moderation in {pcid}: {record:?}");
+        match self.pub_chat_mut(pcid) {
+            Some(pc) => pc.moderation.push(record),
+            None => log::warn!("add_moderation: no public chat {pcid}"),
         }
     }
 
+    /// Bans or unbans `uid` in `pcid`
+    fn set_banned(
+        &mut self,
+        pcid: ChatId,
+        admin: UserId,
+        uid: UserId,
+        banned: bool,
+        now: DateTime,
+    ) -> Result<(), Error> {
+        let pc = self.pub_chat_mut(pcid)
+            .ok_or_else(|| format!("no public chat {pcid}"))?;
+        let moderation = if banned {
+            pc.banned.insert(uid);
+            Moderation::Ban(uid)
+        } else {
+            pc.banned.remove(&uid);
+            Moderation::Unban(uid)
+        };
+        self.add_moderation(pcid, ModerationRecord { admin, at: now, moderation });
+        Ok(())
+    }
+
     pub fn get_user(&self, uid: UserId) -> Option<&User> {
         self.users.get(&uid)
     }
@@ -503,6 +1296,28 @@ Weird that we couldn't find pub chat {cid}");
         chat.remove_user(uid);
     }
 
+    fn forget_user(&mut self, uid: UserId) {
+        let private_chat = utils::uid_to_cid(uid);
+        for pc in self.public_chats.iter_mut() {
+            let orders = pc.orders.iter_mut().chain(pc.archive.iter_mut());
+            for order in orders.filter(|o| o.mentions(uid)) {
+                order.forget_user(uid);
+                // Messages in their private chat are theirs
+                if let Some(msgs) = order.id.and_then(|oid| self.order_msgs.get_mut(&oid)) {
+                    msgs.retain(|(cid, _mid)| *cid != private_chat);
+                }
+            }
+            pc.remove_user(uid);
+            pc.digest_subscribers.remove(&uid);
+            pc.created.remove(&uid);
+        }
+        self.users.remove(&uid);
+        self.last_seen.remove(&uid);
+        self.user_settings.remove(&uid);
+        self.held.remove(&uid);
+        self.templates.remove(&uid);
+    }
+
     pub fn update_user(&mut self, user: User) {
         if let Some(u) = self.users.get_mut(&user.id) {
             *u = user.clone();
@@ -528,11 +1343,8 @@ Weird that we couldn't find pub chat {cid}");
 
             },
             ChatKind::Private(_) => {
-                // Nothing to do here
-                // TODO return error
-                let cid: ChatId = chat.id;
-                return Err(format!(
-                        "chat {cid} is private, cannot update it").into())
+                // Nothing to do here, we only keep public chats
+                log::debug!("update_chat: skipping private chat {}", chat.id);
             },
         }
         Ok(())
@@ -4,9 +4,19 @@ use teloxide::prelude::*;
 use teloxide::types::{User, Chat, MessageId};
 use redis;
 use crate::error::Error;
-use crate::order::{self, Order, OrderId, Action, ActionKind,
-                   Status, ActionError};
+use crate::order::{Order, OrderId, Action, ActionKind,
+                   Status, ActionError, ItemUpdate};
 use serde_json;
+use crate::clock::Clock;
+use crate::moderation::{ChatAdmins, Moderation, ModerationRecord};
+use crate::rate_limit::ChatLimits;
+use crate::digest::DigestSettings;
+use crate::settings::{UserSettings, HeldNotification};
+use crate::template::Template;
+use crate::ledger;
+use crate::DateTime;
+use crate::utils;
+use chrono::{TimeZone, Utc};
 
 fn to_err(e: redis::RedisError) -> Error {
     format!("Redis error: {e:?}").into()
@@ -22,28 +32,53 @@ fn to_err(e: redis::RedisError) -> Error {
 ///   pub_chat:id:name      String
 ///   pub_chat:id:members   Set<UserId>
 ///   user:id:public_chats  Set<ChatId>
+///   user:id:settings      SerializedData
+///   user:id:held          List<SerializedData>
+///   held_notifications    Set<UserId>
 ///   pub_chat:id:orders    Set<OrderId>
 ///   pub_chat:id:order:id  SerializedData
 ///   order_msgs:id         Set<(ChatId, MessageId)>
+///   pub_chat:id:admins    SerializedData
+///   pub_chat:id:banned    Set<UserId>
+///   pub_chat:id:moderation List<SerializedData>
+///   pub_chat:id:ledger    List<SerializedData>
+///   pub_chat:id:limits    SerializedData
+///   pub_chat:id:created:id SortedSet<OrderId> by creation time, a day long
+///   pub_chat:id:board     MessageId
+///   pub_chat:id:digest    SerializedData
+///   pub_chat:id:digest_subscribers Set<UserId>
 #[derive(Clone)]
 pub struct Db {
     c: redis::aio::ConnectionManager,
+    clock: Clock,
 }
 
 use crate::REDIS_URL;
 
 impl Db {
-    pub async fn new() -> Result<Self, Error> {
+    pub async fn new(clock: Clock) -> Result<Self, Error> {
         let client = redis::Client::open(REDIS_URL)
             .map_err(to_err)?;
         let connection = client.get_tokio_connection_manager()
             .await.map_err(to_err)?;
 
-        let db = Db { c: connection };
+        let db = Db { c: connection, clock };
 
         Ok(db)
     }
 
+    /// Current time according to our clock
+    pub fn now(&self) -> DateTime {
+        self.clock.now()
+    }
+
+    /// Checks that Redis is reachable
+    pub async fn ping(&mut self) -> Result<(), Error> {
+        let _pong: String = redis::cmd("PING")
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        Ok(())
+    }
+
     pub async fn user_public_chats(
         &mut self,
         uid: UserId,
@@ -53,6 +88,23 @@ impl Db {
         let pub_chats: Vec<i64> =
             redis::Cmd::smembers(user_pub_chats_key(uid))
             .query_async(&mut self.c).await?;
+        self.with_names(pub_chats).await
+    }
+
+    /// All public chats the bot is in, with their names
+    pub async fn public_chats(
+        &mut self,
+    ) -> Result<Vec<(ChatId, String)>, Error> {
+        log::debug!("public_chats");
+        let pub_chats: Vec<i64> = redis::Cmd::smembers(pub_chats_key())
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        self.with_names(pub_chats).await
+    }
+
+    async fn with_names(
+        &mut self,
+        pub_chats: Vec<i64>,
+    ) -> Result<Vec<(ChatId, String)>, Error> {
         // it's an error to query nothing
         if pub_chats.is_empty() {
             return Ok(Vec::new())
@@ -94,15 +146,34 @@ insteadd of {}", names.len(), pub_chats.len()).into());
         let oid = OrderId(oid);
         order.id = Some(oid);
 
+        let created_key = pub_chat_created_key(pcid, order.customer.id);
+        let day_ago = order.created_at - chrono::Duration::days(1);
         redis::pipe()
             .atomic()
             .set(pub_chat_order_key(pcid, oid), serde_json::to_vec(order)?)
             .sadd(pub_chat_orders_key(pcid), oid.0)
             .sadd(user_orders_key(order.customer.id), oid.0)
+            .zadd(&created_key, oid.0, order.created_at.timestamp())
+            .zrembyscore(&created_key, "-inf", day_ago.timestamp())
             .query_async(&mut self.c).await?;
         Ok(oid)
     }
 
+    /// When `uid` created orders in `pcid` in the last day,
+    /// deleted orders included
+    pub async fn order_creations(
+        &mut self,
+        pcid: ChatId,
+        uid: UserId,
+    ) -> Result<Vec<DateTime>, Error> {
+        let created: Vec<(u64, i64)> = redis::Cmd::zrange_withscores(
+                pub_chat_created_key(pcid, uid), 0, -1)
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        Ok(created.into_iter()
+           .map(|(_oid, at)| Utc.timestamp(at, 0))
+           .collect())
+    }
+
     /// Returns some debugging info
     pub async fn debug_stats(&mut self) -> Result<String, Error> {
         log::debug!("debug_stats");
@@ -156,10 +227,25 @@ insteadd of {}", names.len(), pub_chats.len()).into());
             .atomic()
             .set(user_key(user.id), serde_json::to_vec(&user)?)
             .sadd(users_key(), user.id.0)
+            .zadd(users_last_seen_key(), user.id.0, self.now().timestamp())
             .query_async(&mut self.c).await?;
         Ok(())
     }
 
+    /// Users we've last seen before `t`
+    ///
+    /// Users stored before we started to record when we see them aren't
+    /// returned until we see them again
+    pub async fn users_not_seen_since(
+        &mut self,
+        t: DateTime,
+    ) -> Result<Vec<UserId>, Error> {
+        let uids: Vec<u64> = redis::Cmd::zrangebyscore(
+                users_last_seen_key(), "-inf", format!("({}", t.timestamp()))
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        Ok(uids.into_iter().map(UserId).collect())
+    }
+
     /// Add new member to public chat
     pub async fn add_members(
         &mut self,
@@ -183,6 +269,16 @@ to private chat {cid} {uids:?}");
         pipe.query_async(&mut self.c).await.map_err(to_err)
     }
 
+    /// Users we've seen in a public chat
+    pub async fn chat_members(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<Vec<UserId>, Error> {
+        let uids: Vec<u64> = redis::Cmd::smembers(pub_chat_members_key(pcid))
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        Ok(uids.into_iter().map(UserId).collect())
+    }
+
     /// Remove user from chat
     pub async fn remove_chat_membership(
         &mut self,
@@ -208,6 +304,13 @@ to private chat {cid} {uids:?}");
         let order_keys: Vec<String> = oids.into_iter()
             .map(|oid| pub_chat_order_key(pcid, OrderId(oid)))
             .collect();
+        self.get_orders(order_keys).await
+    }
+
+    async fn get_orders(
+        &mut self,
+        order_keys: Vec<String>,
+    ) -> Result<Vec<Order>, Error> {
         // Redis doesn't allow to query for no keys,
         // so it's not just an optimization
         if order_keys.is_empty() {
@@ -230,6 +333,146 @@ to private chat {cid} {uids:?}");
         Ok(orders)
     }
 
+    /// All orders of `pcid`, live and archived
+    pub async fn chat_history(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<Vec<Order>, Error> {
+        let mut orders = self.pub_chat_orders(pcid).await?;
+        orders.extend(self.archived_orders(pcid).await?);
+        Ok(orders)
+    }
+
+    /// Completed orders of `pcid` that are moved out of the live ones
+    pub async fn archived_orders(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<Vec<Order>, Error> {
+        log::debug!("archived_orders {pcid}");
+
+        let oids: Vec<u64> =
+            redis::Cmd::smembers(pub_chat_archive_key(pcid))
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        let order_keys: Vec<String> = oids.into_iter()
+            .map(|oid| pub_chat_archived_order_key(pcid, OrderId(oid)))
+            .collect();
+        self.get_orders(order_keys).await
+    }
+
+    async fn get_archived_order(
+        &mut self,
+        pcid: ChatId,
+        oid: OrderId,
+    ) -> Result<Option<Order>, Error> {
+        let data: Option<Vec<u8>> =
+            redis::Cmd::get(pub_chat_archived_order_key(pcid, oid))
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        match data {
+            Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Moves the order to the archive, it can't be changed after that
+    ///
+    /// Its messages are kept, so they're still known if it's deleted
+    pub async fn archive_order(
+        &mut self,
+        pcid: ChatId,
+        oid: OrderId,
+    ) -> Result<(), Error> {
+        log::info!("archive_order {pcid} {oid}");
+        redis::pipe()
+            .atomic()
+            .rename(pub_chat_order_key(pcid, oid),
+                    pub_chat_archived_order_key(pcid, oid))
+            .srem(pub_chat_orders_key(pcid), oid.0)
+            .sadd(pub_chat_archive_key(pcid), oid.0)
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        Ok(())
+    }
+
+    /// Moves the order back from the archive
+    async fn unarchive_order(
+        &mut self,
+        pcid: ChatId,
+        oid: OrderId,
+    ) -> Result<(), Error> {
+        log::info!("unarchive_order {pcid} {oid}");
+        redis::pipe()
+            .atomic()
+            .rename(pub_chat_archived_order_key(pcid, oid),
+                    pub_chat_order_key(pcid, oid))
+            .srem(pub_chat_archive_key(pcid), oid.0)
+            .sadd(pub_chat_orders_key(pcid), oid.0)
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        Ok(())
+    }
+
+    /// Cancels the order unless it's done with and moves it to the archive
+    async fn archive_deleted_order(
+        &mut self,
+        pcid: ChatId,
+        mut order: Order,
+    ) -> Result<(), Error> {
+        let oid = order.id.ok_or("order has no id")?;
+        log::info!("archive_deleted_order {pcid} {oid}");
+        order.cancel_deleted(self.now());
+        redis::pipe()
+            .atomic()
+            .set(pub_chat_order_key(pcid, oid), serde_json::to_vec(&order)?)
+            .rename(pub_chat_order_key(pcid, oid),
+                    pub_chat_archived_order_key(pcid, oid))
+            .srem(pub_chat_orders_key(pcid), oid.0)
+            .sadd(pub_chat_archive_key(pcid), oid.0)
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        Ok(())
+    }
+
+    /// Destroys an archived order, returns false if there's no such order
+    ///
+    /// It's moderation, `admin` is recorded in the log
+    pub async fn delete_archived_order(
+        &mut self,
+        pcid: ChatId,
+        admin: UserId,
+        oid: OrderId,
+    ) -> Result<bool, Error> {
+        log::info!("delete_archived_order {pcid} {oid} by {admin}");
+        let order = match self.get_archived_order(pcid, oid).await? {
+            Some(order) => order,
+            None => return Ok(false),
+        };
+        redis::pipe()
+            .atomic()
+            .del(pub_chat_archived_order_key(pcid, oid))
+            .srem(pub_chat_archive_key(pcid), oid.0)
+            .srem(user_orders_key(order.customer.id), oid.0)
+            .del(order_msgs_key(oid))
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        self.add_moderation(pcid, &ModerationRecord {
+            admin,
+            at: self.now(),
+            moderation: Moderation::Order {
+                order_id: oid,
+                action: ActionKind::Delete,
+            },
+        }).await?;
+        Ok(true)
+    }
+
+    async fn update_archived_order(
+        &mut self,
+        pcid: ChatId,
+        order: &Order,
+    ) -> Result<(), Error> {
+        let oid = order.id.ok_or("order has no id")?;
+        redis::Cmd::set(pub_chat_archived_order_key(pcid, oid),
+                        serde_json::to_vec(order)?)
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        Ok(())
+    }
+
     /// Return orders in the chat, filtered by `status`
     pub async fn orders_by_status(
         &mut self,
@@ -308,69 +551,142 @@ to private chat {cid} {uids:?}");
         Ok(orders)
     }
 
-    /// Performs the action and returns previous state and the Order
+    /// Performs the action and returns the Order before and after it
     /// If the order is deleted then the returned order is None
+    ///
+    /// `is_admin` tells if `user` is an admin of `pcid`
     pub async fn perform_action(
         &mut self,
         user: User,
+        is_admin: bool,
         pcid: ChatId,
         action: Action,
-    ) -> Result<(order::Status, Option<Order>), ActionError> {
+    ) -> Result<(Order, Option<Order>), ActionError> {
         let uid = user.id;
         log::debug!("perform_action {uid} {pcid} {action:?}");
 
-        let order: Option<Order> =
+        let mut order: Option<Order> =
             self.get_order(pcid, action.order_id)
             .await.map_err(|_| ActionError::Other)?;
+        let archived = order.is_none();
+        if archived && action.kind == ActionKind::Publish {
+            // Canceled orders come back from the archive to be published
+            order = self.get_archived_order(pcid, action.order_id)
+                .await.map_err(|_| ActionError::Other)?
+                .filter(|o| o.status() == Status::Unpublished);
+        }
         if order.is_none() {
             return Err(ActionError::OrderNotFound(action.order_id));
         }
         let order = order.unwrap();
-        if ! order.is_action_permitted(uid, &action) {
-            return Err(ActionError::NotPermitted)
+        order.check_action(uid, is_admin, action.kind)?;
+        if action.kind == ActionKind::Publish {
+            if self.is_banned(pcid, uid).await.map_err(|_| ActionError::Other)? {
+                return Err(ActionError::Banned);
+            }
+            let limits = self.chat_limits(pcid)
+                .await.map_err(|_| ActionError::Other)?;
+            let orders = self.orders_submitted_by_user(pcid, uid)
+                .await.map_err(|_| ActionError::Other)?;
+            if !limits.can_publish_order(&orders) {
+                return Err(ActionError::TooManyOpenOrders(limits.open_orders));
+            }
+        }
+        let moderation = order.is_moderation(uid, action.kind);
+        if archived {
+            let res = self.unarchive_order(pcid, action.order_id).await;
+            if let Err(e) = res {
+                log::warn!("perform_action {uid} {pcid} : {e:?}");
+                return Err(ActionError::Other);
+            }
         }
 
-        if action.kind == ActionKind::Delete {
-            let res = self.delete_order_unchecked(
-                pcid, order.customer.id, action.order_id).await;
+        let res = if action.kind == ActionKind::Delete {
+            // Orders are history, only admins destroy them in the archive
+            let res = self.archive_deleted_order(pcid, order.clone()).await;
             if let Err(e) = res {
                 log::warn!("perform_action {uid} {pcid} : {e:?}");
                 return Err(ActionError::Other);
             }
-            Ok((order.status(), None))
+            (order, None)
         } else {
+            let prev = order.clone();
             let mut order = order;
-            let prev_status = order.perform_action(user, &action)?;
+            let now = self.now();
+            let prev_status = order.perform_action(user, is_admin, &action, now)?;
                 log::warn!("perform_action {uid} {pcid} : {prev_status} => {}", order.status());
-            let res = self.update_order(pcid, &order)
+            let debt = ledger::Entry::new_debt(&prev, &order, now);
+            let res = self.update_order_with_debt(pcid, &order, debt.as_ref())
                 .await;
             if let Err(e) = res {
                 log::warn!("perform_action {uid} {pcid} : {e:?}");
                 return Err(ActionError::Other);
             }
-            Ok((prev_status, Some(order)))
+            (prev, Some(order))
+        };
+
+        if moderation {
+            let record = ModerationRecord {
+                admin: uid,
+                at: self.now(),
+                moderation: Moderation::Order {
+                    order_id: action.order_id,
+                    action: action.kind,
+                },
+            };
+            if let Err(e) = self.add_moderation(pcid, &record).await {
+                log::warn!("perform_action {uid} {pcid} moderation: {e:?}");
+            }
         }
+        Ok(res)
     }
 
-    /// Deletes the order without checking permissions
-    async fn delete_order_unchecked(
+    /// The courier ticks off an item of the order or tells its price,
+    /// returns the updated order
+    pub async fn update_item(
         &mut self,
         pcid: ChatId,
         uid: UserId,
         oid: OrderId,
-    ) -> Result<(), Error> {
-        redis::pipe()
-            .atomic()
-            .del(pub_chat_order_key(pcid, oid))
-            .srem(pub_chat_orders_key(pcid), oid.0)
-            .srem(user_orders_key(uid), oid.0)
-            .del(order_msgs_key(oid))
-            .query_async(&mut self.c).await.map_err(to_err)?;
-        Ok(())
+        idx: usize,
+        update: ItemUpdate,
+    ) -> Result<Order, ActionError> {
+        log::debug!("update_item {uid} {pcid} {oid} {idx} {update:?}");
+        let mut order = self.get_order(pcid, oid).await
+            .map_err(|_| ActionError::Other)?
+            .ok_or(ActionError::OrderNotFound(oid))?;
+        order.update_item(uid, idx, update)?;
+        if let Err(e) = self.update_order(pcid, &order).await {
+            log::warn!("update_item {uid} {pcid} : {e:?}");
+            return Err(ActionError::Other);
+        }
+        Ok(order)
+    }
+
+    /// Orders in `pcid` that `uid` has created or is delivering,
+    /// archived ones included
+    pub async fn orders_involving(
+        &mut self,
+        pcid: ChatId,
+        uid: UserId,
+    ) -> Result<Vec<Order>, Error> {
+        let orders = self.chat_history(pcid).await?;
+        Ok(orders.into_iter().filter(|o| o.involves(uid)).collect())
+    }
+
+    /// Orders in `pcid` that mention `uid` anywhere, see `Order::mentions`,
+    /// archived ones included
+    pub async fn orders_mentioning(
+        &mut self,
+        pcid: ChatId,
+        uid: UserId,
+    ) -> Result<Vec<Order>, Error> {
+        let orders = self.chat_history(pcid).await?;
+        Ok(orders.into_iter().filter(|o| o.mentions(uid)).collect())
     }
 
     /// Get data of order that's in `pcid`
-    async fn get_order(
+    pub async fn get_order(
         &mut self,
         pcid: ChatId,
         oid: OrderId,
@@ -403,6 +719,28 @@ to private chat {cid} {uids:?}");
         Ok(())
     }
 
+    /// Updates the order and appends the debt it makes to the ledger of the
+    /// chat at once, so a confirmed delivery never loses its debt
+    async fn update_order_with_debt(
+        &mut self,
+        pcid: ChatId,
+        order: &Order,
+        debt: Option<&ledger::Entry>,
+    ) -> Result<(), Error> {
+        let debt = match debt {
+            Some(debt) => debt,
+            None => return self.update_order(pcid, order).await,
+        };
+        let oid = order.id.ok_or("order has no id")?;
+        log::info!("ledger entry in {pcid}: {debt:?}");
+        redis::pipe()
+            .atomic()
+            .set(pub_chat_order_key(pcid, oid), serde_json::to_vec(order)?)
+            .rpush(pub_chat_ledger_key(pcid), serde_json::to_vec(debt)?)
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        Ok(())
+    }
+
     /// Update chat data in the database
     pub async fn update_chat(
         &mut self,
@@ -457,20 +795,496 @@ to private chat {cid} {uids:?}");
 
         Ok(())
     }
+
+    /// Cached admins of the public chat, None if we've never asked
+    pub async fn chat_admins(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<Option<ChatAdmins>, Error> {
+        let data: Option<Vec<u8>> = redis::Cmd::get(pub_chat_admins_key(pcid))
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        match data {
+            Some(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            None => Ok(None),
+        }
+    }
+
+    pub async fn set_chat_admins(
+        &mut self,
+        pcid: ChatId,
+        admins: &ChatAdmins,
+    ) -> Result<(), Error> {
+        let data: Vec<u8> = serde_json::to_vec(admins)?;
+        redis::Cmd::set(pub_chat_admins_key(pcid), data)
+            .query_async(&mut self.c).await.map_err(to_err)
+    }
+
+    /// Doesn't let `uid` post orders in `pcid` any more
+    pub async fn ban_user(
+        &mut self,
+        pcid: ChatId,
+        admin: UserId,
+        uid: UserId,
+    ) -> Result<(), Error> {
+        log::debug!("ban_user {pcid} {uid} by {admin}");
+        redis::Cmd::sadd(pub_chat_banned_key(pcid), uid.0)
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        self.add_moderation(pcid, &ModerationRecord {
+            admin,
+            at: self.now(),
+            moderation: Moderation::Ban(uid),
+        }).await
+    }
+
+    pub async fn unban_user(
+        &mut self,
+        pcid: ChatId,
+        admin: UserId,
+        uid: UserId,
+    ) -> Result<(), Error> {
+        log::debug!("unban_user {pcid} {uid} by {admin}");
+        redis::Cmd::srem(pub_chat_banned_key(pcid), uid.0)
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        self.add_moderation(pcid, &ModerationRecord {
+            admin,
+            at: self.now(),
+            moderation: Moderation::Unban(uid),
+        }).await
+    }
+
+    pub async fn is_banned(
+        &mut self,
+        pcid: ChatId,
+        uid: UserId,
+    ) -> Result<bool, Error> {
+        redis::Cmd::sismember(pub_chat_banned_key(pcid), uid.0)
+            .query_async(&mut self.c).await.map_err(to_err)
+    }
+
+    pub async fn banned_users(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<Vec<UserId>, Error> {
+        let uids: Vec<u64> = redis::Cmd::smembers(pub_chat_banned_key(pcid))
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        Ok(uids.into_iter().map(UserId).collect())
+    }
+
+    async fn add_moderation(
+        &mut self,
+        pcid: ChatId,
+        record: &ModerationRecord,
+    ) -> Result<(), Error> {
+        log::info!("moderation in {pcid}: {record:?}");
+        let data: Vec<u8> = serde_json::to_vec(record)?;
+        redis::Cmd::rpush(pub_chat_moderation_key(pcid), data)
+            .query_async(&mut self.c).await.map_err(to_err)
+    }
+
+    /// Everything admins did in the chat, oldest first
+    pub async fn moderation_log(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<Vec<ModerationRecord>, Error> {
+        let data_items: Vec<Vec<u8>> =
+            redis::Cmd::lrange(pub_chat_moderation_key(pcid), 0, -1)
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        let mut records = Vec::with_capacity(data_items.len());
+        for data in data_items.into_iter() {
+            records.push(serde_json::from_slice(&data)?);
+        }
+        Ok(records)
+    }
+
+    /// Appends the entry `make` returns for the current ledger of the chat,
+    /// so two clicks can't record the same repayment twice. Returns its
+    /// position and the entry, or why `make` refused to make one.
+    ///
+    /// Entries are only appended, so the ledger hasn't changed since we've
+    /// read it if it's as long as it was. The script appends only then,
+    /// otherwise we read it again.
+    pub async fn add_checked_ledger_entry<F>(
+        &mut self,
+        pcid: ChatId,
+        make: F,
+    ) -> Result<Result<(usize, ledger::Entry), String>, Error>
+    where
+        F: Fn(&[ledger::Entry]) -> Result<ledger::Entry, String> + Send + 'static,
+    {
+        for _attempt in 0..LEDGER_ATTEMPTS {
+            let entries = self.ledger(pcid).await?;
+            let entry = match make(&entries) {
+                Ok(entry) => entry,
+                Err(e) => return Ok(Err(e)),
+            };
+            let len: usize = redis::cmd("EVAL")
+                .arg(APPEND_IF_LEN_SCRIPT)
+                .arg(1)
+                .arg(pub_chat_ledger_key(pcid))
+                .arg(entries.len())
+                .arg(serde_json::to_vec(&entry)?)
+                .query_async(&mut self.c).await.map_err(to_err)?;
+            if len > 0 {
+                log::info!("ledger entry in {pcid}: {entry:?}");
+                return Ok(Ok((len - 1, entry)))
+            }
+            log::debug!("ledger of {pcid} has changed, trying again");
+        }
+        Err(format!("ledger of {pcid} keeps changing").into())
+    }
+
+    /// All entries of the chat's ledger, oldest first
+    pub async fn ledger(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<Vec<ledger::Entry>, Error> {
+        let data_items: Vec<Vec<u8>> =
+            redis::Cmd::lrange(pub_chat_ledger_key(pcid), 0, -1)
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        let mut entries = Vec::with_capacity(data_items.len());
+        for data in data_items.into_iter() {
+            entries.push(serde_json::from_slice(&data)?);
+        }
+        Ok(entries)
+    }
+
+    /// Limits of the chat, the default ones if admins haven't changed them
+    pub async fn chat_limits(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<ChatLimits, Error> {
+        let data: Option<Vec<u8>> = redis::Cmd::get(pub_chat_limits_key(pcid))
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        match data {
+            Some(data) => Ok(serde_json::from_slice(&data)?),
+            None => Ok(ChatLimits::default()),
+        }
+    }
+
+    pub async fn set_chat_limits(
+        &mut self,
+        pcid: ChatId,
+        limits: &ChatLimits,
+    ) -> Result<(), Error> {
+        log::debug!("set_chat_limits {pcid} {limits:?}");
+        let data: Vec<u8> = serde_json::to_vec(limits)?;
+        redis::Cmd::set(pub_chat_limits_key(pcid), data)
+            .query_async(&mut self.c).await.map_err(to_err)
+    }
+
+    /// Message in `pcid` that shows published orders, if we've sent one
+    pub async fn board_msg_id(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<Option<MessageId>, Error> {
+        let mid: Option<i32> = redis::Cmd::get(pub_chat_board_key(pcid))
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        Ok(mid.map(|message_id| MessageId { message_id }))
+    }
+
+    pub async fn set_board_msg_id(
+        &mut self,
+        pcid: ChatId,
+        mid: MessageId,
+    ) -> Result<(), Error> {
+        redis::Cmd::set(pub_chat_board_key(pcid), mid.message_id)
+            .query_async(&mut self.c).await.map_err(to_err)
+    }
+
+    /// Digest settings of the chat, the default ones if admins haven't
+    /// changed them
+    pub async fn digest_settings(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<DigestSettings, Error> {
+        let data: Option<Vec<u8>> = redis::Cmd::get(pub_chat_digest_key(pcid))
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        match data {
+            Some(data) => Ok(serde_json::from_slice(&data)?),
+            None => Ok(DigestSettings::default()),
+        }
+    }
+
+    pub async fn set_digest_settings(
+        &mut self,
+        pcid: ChatId,
+        settings: &DigestSettings,
+    ) -> Result<(), Error> {
+        log::debug!("set_digest_settings {pcid} {settings:?}");
+        let data: Vec<u8> = serde_json::to_vec(settings)?;
+        redis::Cmd::set(pub_chat_digest_key(pcid), data)
+            .query_async(&mut self.c).await.map_err(to_err)
+    }
+
+    /// Members of `pcid` that get its digest in private
+    pub async fn digest_subscribers(
+        &mut self,
+        pcid: ChatId,
+    ) -> Result<Vec<UserId>, Error> {
+        let uids: Vec<u64> =
+            redis::Cmd::smembers(pub_chat_digest_subscribers_key(pcid))
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        Ok(uids.into_iter().map(UserId).collect())
+    }
+
+    pub async fn set_digest_subscription(
+        &mut self,
+        pcid: ChatId,
+        uid: UserId,
+        subscribed: bool,
+    ) -> Result<(), Error> {
+        log::info!("set_digest_subscription {pcid} {uid} {subscribed}");
+        let key = pub_chat_digest_subscribers_key(pcid);
+        let cmd = if subscribed {
+            redis::Cmd::sadd(key, uid.0)
+        } else {
+            redis::Cmd::srem(key, uid.0)
+        };
+        cmd.query_async(&mut self.c).await.map_err(to_err)
+    }
+
+    /// What the user has chosen in settings, defaults if nothing yet
+    pub async fn user_settings(
+        &mut self,
+        uid: UserId,
+    ) -> Result<UserSettings, Error> {
+        let data: Option<Vec<u8>> = redis::Cmd::get(user_settings_key(uid))
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        match data {
+            Some(data) => Ok(serde_json::from_slice(&data)?),
+            None => Ok(UserSettings::default()),
+        }
+    }
+
+    pub async fn set_user_settings(
+        &mut self,
+        uid: UserId,
+        settings: &UserSettings,
+    ) -> Result<(), Error> {
+        log::debug!("set_user_settings {uid} {settings:?}");
+        let data: Vec<u8> = serde_json::to_vec(settings)?;
+        redis::Cmd::set(user_settings_key(uid), data)
+            .query_async(&mut self.c).await.map_err(to_err)
+    }
+
+    /// Keeps a notification for `uid` until `take_held_notifications`
+    pub async fn hold_notification(
+        &mut self,
+        uid: UserId,
+        held: &HeldNotification,
+    ) -> Result<(), Error> {
+        log::debug!("hold_notification {uid} {held:?}");
+        let data: Vec<u8> = serde_json::to_vec(held)?;
+        redis::pipe()
+            .atomic()
+            .rpush(user_held_key(uid), data)
+            .sadd(held_notifications_key(), uid.0)
+            .query_async(&mut self.c).await.map_err(to_err)
+    }
+
+    /// Users that have held notifications
+    pub async fn users_with_held_notifications(
+        &mut self,
+    ) -> Result<Vec<UserId>, Error> {
+        let uids: Vec<u64> = redis::Cmd::smembers(held_notifications_key())
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        Ok(uids.into_iter().map(UserId).collect())
+    }
+
+    /// Held notifications of `uid`, oldest first
+    pub async fn held_notifications(
+        &mut self,
+        uid: UserId,
+    ) -> Result<Vec<HeldNotification>, Error> {
+        let data_items: Vec<Vec<u8>> =
+            redis::Cmd::lrange(user_held_key(uid), 0, -1)
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        let mut held = Vec::with_capacity(data_items.len());
+        for data in data_items.into_iter() {
+            held.push(serde_json::from_slice(&data)?);
+        }
+        Ok(held)
+    }
+
+    /// Removes and returns held notifications of `uid`, oldest first
+    pub async fn take_held_notifications(
+        &mut self,
+        uid: UserId,
+    ) -> Result<Vec<HeldNotification>, Error> {
+        let (data_items,): (Vec<Vec<u8>>,) = redis::pipe()
+            .atomic()
+            .lrange(user_held_key(uid), 0, -1)
+            .del(user_held_key(uid)).ignore()
+            .srem(held_notifications_key(), uid.0).ignore()
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        let mut held = Vec::with_capacity(data_items.len());
+        for data in data_items.into_iter() {
+            held.push(serde_json::from_slice(&data)?);
+        }
+        Ok(held)
+    }
+
+    /// Templates of `uid` sorted by name
+    pub async fn user_templates(
+        &mut self,
+        uid: UserId,
+    ) -> Result<Vec<Template>, Error> {
+        let data_items: Vec<Vec<u8>> =
+            redis::Cmd::hvals(user_templates_key(uid))
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        let mut templates = Vec::with_capacity(data_items.len());
+        for data in data_items.into_iter() {
+            templates.push(serde_json::from_slice::<Template>(&data)?);
+        }
+        templates.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(templates)
+    }
+
+    /// Saves a template of `uid`, replacing the one with the same name
+    pub async fn save_template(
+        &mut self,
+        uid: UserId,
+        template: &Template,
+    ) -> Result<(), Error> {
+        log::debug!("save_template {uid} {template:?}");
+        let data: Vec<u8> = serde_json::to_vec(template)?;
+        redis::pipe()
+            .atomic()
+            .hset(user_templates_key(uid), &template.name, data)
+            .sadd(template_users_key(), uid.0)
+            .query_async(&mut self.c).await.map_err(to_err)
+    }
+
+    /// Templates that make orders on schedule, with their owners
+    pub async fn recurring_templates(
+        &mut self,
+    ) -> Result<Vec<(UserId, Template)>, Error> {
+        let uids: Vec<u64> = redis::Cmd::smembers(template_users_key())
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        let mut recurring = Vec::new();
+        for uid in uids.into_iter().map(UserId) {
+            for template in self.user_templates(uid).await? {
+                if template.recurrence.is_some() {
+                    recurring.push((uid, template));
+                }
+            }
+        }
+        Ok(recurring)
+    }
+
+    /// Returns false if `uid` has no template with this name
+    pub async fn delete_template(
+        &mut self,
+        uid: UserId,
+        name: &str,
+    ) -> Result<bool, Error> {
+        log::debug!("delete_template {uid} {name:?}");
+        let deleted: u64 = redis::Cmd::hdel(user_templates_key(uid), name)
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        Ok(deleted > 0)
+    }
+
+    /// Removes everything about `uid` except bans and the moderation log,
+    /// orders of other people are kept with `uid` replaced by anonymous
+    pub async fn forget_user(&mut self, uid: UserId) -> Result<(), Error> {
+        log::info!("forget_user {uid}");
+        let pub_chats: Vec<i64> = redis::Cmd::smembers(pub_chats_key())
+            .query_async(&mut self.c).await.map_err(to_err)?;
+        for pcid in pub_chats.into_iter().map(ChatId) {
+            let archived = self.archived_orders(pcid).await?;
+            for mut order in self.orders_mentioning(pcid, uid).await? {
+                order.forget_user(uid);
+                if archived.iter().any(|o| o.id == order.id) {
+                    self.update_archived_order(pcid, &order).await?;
+                } else {
+                    self.update_order(pcid, &order).await?;
+                }
+
+                // Messages in their private chat are theirs
+                let oid = order.id.ok_or("order has no id")?;
+                let mut pipe = redis::pipe();
+                for (cid, mid) in self.order_msg_ids(oid).await? {
+                    if cid == utils::uid_to_cid(uid) {
+                        let pair = (cid, mid.message_id);
+                        pipe.srem(order_msgs_key(oid), serde_json::to_vec(&pair)?);
+                    }
+                }
+                pipe.query_async(&mut self.c).await.map_err(to_err)?;
+            }
+            redis::pipe()
+                .atomic()
+                .srem(pub_chat_members_key(pcid), uid.0)
+                .srem(pub_chat_digest_subscribers_key(pcid), uid.0)
+                .del(pub_chat_created_key(pcid, uid))
+                .query_async(&mut self.c).await.map_err(to_err)?;
+        }
+        redis::pipe()
+            .atomic()
+            .del(user_key(uid))
+            .del(user_orders_key(uid))
+            .del(user_pub_chats_key(uid))
+            .del(user_settings_key(uid))
+            .del(user_held_key(uid))
+            .del(user_templates_key(uid))
+            .srem(users_key(), uid.0)
+            .zrem(users_last_seen_key(), uid.0)
+            .srem(held_notifications_key(), uid.0)
+            .srem(template_users_key(), uid.0)
+            .query_async(&mut self.c).await.map_err(to_err)
+    }
+
+    /// Removes the user's name and when we've seen them, keeping the chats
+    /// they're in
+    pub async fn forget_profile(&mut self, uid: UserId) -> Result<(), Error> {
+        log::info!("forget_profile {uid}");
+        redis::pipe()
+            .atomic()
+            .del(user_key(uid))
+            .srem(users_key(), uid.0)
+            .zrem(users_last_seen_key(), uid.0)
+            .query_async(&mut self.c).await.map_err(to_err)
+    }
 }
 
 const PREFIX: &str = "dili";
 
+/// How many times we try to append to a ledger that others append to
+const LEDGER_ATTEMPTS: usize = 5;
+
+/// Appends ARGV[2] to the list KEYS[1] if it's ARGV[1] long, returns the
+/// new length or 0 if it isn't that long
+const APPEND_IF_LEN_SCRIPT: &str = r"
+if redis.call('llen', KEYS[1]) ~= tonumber(ARGV[1]) then
+    return 0
+end
+return redis.call('rpush', KEYS[1], ARGV[2])
+";
+
 // unfortunately I don't think it's possible to concat strings in const fn
 const fn users_key() -> &'static str {
     "dili_users"
 }
 
+// unfortunately I don't think it's possible to concat strings in const fn
+const fn users_last_seen_key() -> &'static str {
+    "dili_users_last_seen"
+}
+
 // unfortunately I don't think it's possible to concat strings in const fn
 const fn pub_chats_key() -> &'static str {
     "dili_pub_chats"
 }
 
+// unfortunately I don't think it's possible to concat strings in const fn
+const fn held_notifications_key() -> &'static str {
+    "dili_held_notifications"
+}
+
+// unfortunately I don't think it's possible to concat strings in const fn
+const fn template_users_key() -> &'static str {
+    "dili_template_users"
+}
+
 // unfortunately I don't think it's possible to concat strings in const fn
 const fn num_orders_key() -> &'static str {
     "dili_num_orders"
@@ -493,6 +1307,18 @@ fn user_pub_chats_key(uid: UserId) -> String {
     user_key(uid) + ":pub_chats"
 }
 
+fn user_settings_key(uid: UserId) -> String {
+    user_key(uid) + ":settings"
+}
+
+fn user_held_key(uid: UserId) -> String {
+    user_key(uid) + ":held"
+}
+
+fn user_templates_key(uid: UserId) -> String {
+    user_key(uid) + ":templates"
+}
+
 fn pub_chat_key(pc: ChatId) -> String {
     key(format!("pub_chat:{pc}").as_ref())
 }
@@ -514,6 +1340,52 @@ fn pub_chat_order_key(pc: ChatId, oid: OrderId) -> String {
     format!("{k}:order:{oid}")
 }
 
+fn pub_chat_archive_key(pc: ChatId) -> String {
+    pub_chat_key(pc) + ":archive"
+}
+
+fn pub_chat_archived_order_key(pc: ChatId, oid: OrderId) -> String {
+    let k = pub_chat_key(pc);
+    format!("{k}:archived_order:{oid}")
+}
+
+fn pub_chat_admins_key(pc: ChatId) -> String {
+    pub_chat_key(pc) + ":admins"
+}
+
+fn pub_chat_banned_key(pc: ChatId) -> String {
+    pub_chat_key(pc) + ":banned"
+}
+
+fn pub_chat_moderation_key(pc: ChatId) -> String {
+    pub_chat_key(pc) + ":moderation"
+}
+
+fn pub_chat_ledger_key(pc: ChatId) -> String {
+    pub_chat_key(pc) + ":ledger"
+}
+
+fn pub_chat_limits_key(pc: ChatId) -> String {
+    pub_chat_key(pc) + ":limits"
+}
+
+fn pub_chat_created_key(pc: ChatId, uid: UserId) -> String {
+    let k = pub_chat_key(pc);
+    format!("{k}:created:{uid}")
+}
+
+fn pub_chat_board_key(pc: ChatId) -> String {
+    pub_chat_key(pc) + ":board"
+}
+
+fn pub_chat_digest_key(pc: ChatId) -> String {
+    pub_chat_key(pc) + ":digest"
+}
+
+fn pub_chat_digest_subscribers_key(pc: ChatId) -> String {
+    pub_chat_key(pc) + ":digest_subscribers"
+}
+
 fn order_msgs_key(oid: OrderId) -> String {
     key(&format!("order_msgs:{oid}"))
 }
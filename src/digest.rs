@@ -0,0 +1,122 @@
+use chrono::NaiveTime;
+use serde::{Serialize, Deserialize};
+
+use crate::order::{Order, Status};
+use crate::DateTime;
+
+/// What the digest of a day covers
+pub const DIGEST_PERIOD_HOURS: i64 = 24;
+
+/// When a public chat gets its daily digest, admins change it with `/digest`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DigestSettings {
+    pub enabled: bool,
+
+    /// Time of day in UTC
+    pub at: NaiveTime,
+
+    /// When the last digest was sent, so we don't send two a day
+    #[serde(default)]
+    pub last_sent: Option<DateTime>,
+}
+
+impl Default for DigestSettings {
+    fn default() -> Self {
+        DigestSettings {
+            enabled: false,
+            at: NaiveTime::from_hms(9, 0, 0),
+            last_sent: None,
+        }
+    }
+}
+
+impl DigestSettings {
+    /// The last time the digest should have been sent before `now`
+    fn last_scheduled(&self, now: DateTime) -> DateTime {
+        let today = now.date().and_time(self.at)
+            .expect("time of day in UTC is never ambiguous");
+        if today <= now {
+            today
+        } else {
+            today - chrono::Duration::days(1)
+        }
+    }
+
+    /// Tells if it's time to send the digest
+    pub fn is_due(&self, now: DateTime) -> bool {
+        if !self.enabled {
+            return false
+        }
+        match self.last_sent {
+            Some(last_sent) => last_sent < self.last_scheduled(now),
+            None => true,
+        }
+    }
+}
+
+/// Orders of a public chat that a digest tells about
+#[derive(Clone, Debug, Default)]
+pub struct Digest {
+    /// Published during the period and still waiting for a courier
+    pub new: Vec<Order>,
+
+    /// Published before the period and still waiting for a courier
+    pub waiting: Vec<Order>,
+
+    /// Delivered during the period
+    pub delivered: Vec<Order>,
+}
+
+impl Digest {
+    /// Sorts `published` and `delivered` orders for a digest sent at `now`
+    pub fn new(
+        published: Vec<Order>,
+        delivered: Vec<Order>,
+        now: DateTime,
+    ) -> Digest {
+        let since = now - chrono::Duration::hours(DIGEST_PERIOD_HOURS);
+        let (mut new, mut waiting): (Vec<Order>, Vec<Order>) =
+            published.into_iter()
+            .filter(|o| o.status() == Status::Published)
+            .partition(|o| o.published_at.is_some_and(|t| t > since));
+        new.sort_by_key(|o| o.published_at);
+        waiting.sort_by_key(|o| o.published_at);
+
+        let mut delivered: Vec<Order> = delivered.into_iter()
+            .filter(|o| o.delivery_confirmed_at.is_some_and(|t| t > since))
+            .collect();
+        delivered.sort_by_key(|o| o.delivery_confirmed_at);
+
+        Digest { new, waiting, delivered }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.new.is_empty() && self.waiting.is_empty()
+            && self.delivered.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_digest_is_due_once_a_day() {
+        let at = |h, m| chrono::offset::Utc.ymd(2022, 7, 1).and_hms(h, m, 0);
+        let mut settings = DigestSettings {
+            enabled: true,
+            at: NaiveTime::from_hms(9, 0, 0),
+            last_sent: Some(at(8, 0)),
+        };
+        assert!(!settings.is_due(at(8, 59)));
+        assert!(settings.is_due(at(9, 0)));
+
+        settings.last_sent = Some(at(9, 0));
+        assert!(!settings.is_due(at(23, 0)));
+        assert!(settings.is_due(at(9, 0) + chrono::Duration::days(1)));
+
+        settings.enabled = false;
+        assert!(!settings.is_due(at(9, 0) + chrono::Duration::days(1)));
+    }
+}
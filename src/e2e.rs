@@ -0,0 +1,188 @@
+//! End-to-end tests that run `schema()` against a fake Bot API server
+//!
+//! Updates are injected with `Harness` methods and handled right away,
+//! everything the bot sends is recorded in `FakeApi`.
+
+mod fake_api;
+mod flows;
+
+use chrono::{TimeZone, offset::Utc};
+use serde_json::{json, Value};
+use teloxide::{
+    prelude::*,
+    dispatching::dialogue::{InMemStorage, Storage},
+    types::Me,
+};
+
+use crate::error::Error;
+use crate::ui::{State, MyStorage};
+use crate::Db;
+use crate::clock::Clock;
+use crate::rate_limit::RateLimiter;
+pub use fake_api::{FakeApi, SentMessage};
+
+/// A user that talks to the bot, their private chat has the same id
+#[derive(Clone, Copy, Debug)]
+pub struct TestUser {
+    pub id: u64,
+}
+
+impl TestUser {
+    pub fn json(&self) -> Value {
+        json!({
+            "id": self.id,
+            "is_bot": false,
+            "first_name": format!("User{}", self.id),
+            "username": format!("user{}", self.id),
+        })
+    }
+
+    pub fn private_chat(&self) -> ChatId {
+        ChatId(self.id as i64)
+    }
+}
+
+pub struct Harness {
+    pub api: FakeApi,
+    pub clock: Clock,
+    pub bot: AutoSend<Bot>,
+    me: Me,
+    storage: MyStorage,
+    pub db: Db,
+    limiter: RateLimiter,
+    next_update_id: i32,
+    next_message_id: i32,
+}
+
+impl Harness {
+    pub async fn new() -> Result<Harness, Error> {
+        let api = FakeApi::start().await?;
+        let url = url::Url::parse(&api.url)?;
+        let bot = Bot::new("0:test").set_api_url(url).auto_send();
+        let me = bot.get_me().await?;
+        let storage: MyStorage = InMemStorage::<State>::new().erase();
+        let clock = Clock::fixed(Utc.ymd(2022, 7, 1).and_hms(12, 0, 0));
+        let db = Db::new(clock.clone()).await?;
+
+        Ok(Harness {
+            api,
+            clock,
+            bot,
+            me,
+            storage,
+            db,
+            limiter: RateLimiter::new(),
+            next_update_id: 0,
+            // Bot's own message ids come from the server and start at 1,
+            // keep users' ones apart
+            next_message_id: 1_000_000,
+        })
+    }
+
+    /// Handles the update the same way the dispatcher would
+    pub async fn dispatch(&mut self, update: Value) -> Result<(), Error> {
+        // Update's deserializer doesn't work with `from_value`
+        let update: Update = serde_json::from_str(&update.to_string())?;
+        let deps = dptree::deps![
+            update,
+            self.bot.clone(),
+            self.me.clone(),
+            self.storage.clone(),
+            self.db.clone(),
+            self.limiter.clone()
+        ];
+        match crate::schema().dispatch(deps).await {
+            std::ops::ControlFlow::Break(res) => res,
+            std::ops::ControlFlow::Continue(_) => Ok(()),
+        }
+    }
+
+    /// `user` writes `text` in chat `cid`
+    pub async fn send_text(
+        &mut self,
+        user: TestUser,
+        cid: ChatId,
+        text: &str,
+    ) -> Result<(), Error> {
+        self.next_update_id += 1;
+        self.next_message_id += 1;
+        let update = json!({
+            "update_id": self.next_update_id,
+            "message": {
+                "message_id": self.next_message_id,
+                "date": 0,
+                "chat": fake_api::chat_json(cid.0),
+                "from": user.json(),
+                "text": text,
+            }
+        });
+        self.dispatch(update).await
+    }
+
+    /// `user` sends a photo with `caption` in chat `cid`
+    pub async fn send_photo(
+        &mut self,
+        user: TestUser,
+        cid: ChatId,
+        file_id: &str,
+        caption: &str,
+    ) -> Result<(), Error> {
+        self.next_update_id += 1;
+        self.next_message_id += 1;
+        let update = json!({
+            "update_id": self.next_update_id,
+            "message": {
+                "message_id": self.next_message_id,
+                "date": 0,
+                "chat": fake_api::chat_json(cid.0),
+                "from": user.json(),
+                "photo": [
+                    { "file_id": format!("{file_id}-small"),
+                      "file_unique_id": format!("{file_id}-small"),
+                      "width": 90, "height": 120 },
+                    { "file_id": file_id, "file_unique_id": file_id,
+                      "width": 900, "height": 1200 },
+                ],
+                "caption": caption,
+            }
+        });
+        self.dispatch(update).await
+    }
+
+    /// `user` clicks a button with `data` under the bot's message `msg`
+    pub async fn click(
+        &mut self,
+        user: TestUser,
+        msg: &SentMessage,
+        data: &str,
+    ) -> Result<(), Error> {
+        assert!(msg.buttons.iter().any(|b| b == data),
+                "no button {data:?} in message {msg:?}");
+        self.next_update_id += 1;
+        let update = json!({
+            "update_id": self.next_update_id,
+            "callback_query": {
+                "id": format!("cq{}", self.next_update_id),
+                "from": user.json(),
+                "message": msg.raw,
+                "chat_instance": "test",
+                "data": data,
+            }
+        });
+        self.dispatch(update).await
+    }
+
+    /// The last message the bot sent to `cid`
+    pub fn last_sent_to(&self, cid: ChatId) -> SentMessage {
+        self.api.sent().into_iter().rev()
+            .find(|m| m.chat_id == cid.0)
+            .unwrap_or_else(|| panic!("no messages were sent to {cid}"))
+    }
+
+    /// The last message in `cid` that has a button with `data`
+    pub fn last_with_button(&self, cid: ChatId, data: &str) -> SentMessage {
+        self.api.sent().into_iter().rev()
+            .find(|m| m.chat_id == cid.0 && m.buttons.iter().any(|b| b == data))
+            .unwrap_or_else(|| panic!("no message with {data:?} in {cid}"))
+    }
+}
@@ -0,0 +1,450 @@
+//! A fake Telegram Bot API server
+//!
+//! Answers the methods the bot uses with plausible results and records
+//! every message the bot sends, edits or deletes.
+
+use std::sync::{Arc, Mutex};
+
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+use crate::error::Error;
+
+pub const BOT_ID: u64 = 1_000_000;
+pub const BOT_USERNAME: &str = "dili_very_test_bot";
+
+/// A message the bot has sent or edited
+#[derive(Clone, Debug)]
+pub struct SentMessage {
+    pub chat_id: i64,
+    pub message_id: i32,
+    pub text: String,
+    /// Callback data of the inline keyboard buttons, row by row
+    pub buttons: Vec<String>,
+    /// The message as Telegram would return it
+    pub raw: Value,
+    /// Sent with `disable_notification`
+    pub silent: bool,
+}
+
+/// A file the bot has sent
+#[derive(Clone, Debug)]
+pub struct SentDocument {
+    pub chat_id: i64,
+    pub file_name: String,
+    pub content: String,
+}
+
+/// A photo the bot has sent by its file id
+#[derive(Clone, Debug)]
+pub struct SentPhoto {
+    pub chat_id: i64,
+    pub file_id: String,
+    pub caption: String,
+}
+
+impl SentMessage {
+    /// Text of the button with callback `data`
+    pub fn button_text(&self, data: &str) -> Option<String> {
+        self.raw["reply_markup"]["inline_keyboard"].as_array()?.iter()
+            .filter_map(|row| row.as_array())
+            .flatten()
+            .find(|btn| btn["callback_data"] == data)
+            .and_then(|btn| btn["text"].as_str())
+            .map(str::to_string)
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct Records {
+    pub sent: Vec<SentMessage>,
+    pub edited: Vec<SentMessage>,
+    pub documents: Vec<SentDocument>,
+    pub photos: Vec<SentPhoto>,
+    pub deleted: Vec<(i64, i32)>,
+    /// Names of all called methods, in order
+    pub calls: Vec<String>,
+    /// Texts of `answerCallbackQuery` calls that had one
+    pub answers: Vec<String>,
+    /// Users that `getChatAdministrators` returns for any chat
+    pub admins: Vec<u64>,
+    /// Messages somebody else has deleted
+    pub gone: Vec<(i64, i32)>,
+    /// How many next `sendMessage` calls hit the flood control
+    pub floods: u32,
+    /// Private chats of users who have blocked the bot
+    pub blocked: Vec<i64>,
+    next_message_id: i32,
+}
+
+#[derive(Clone)]
+pub struct FakeApi {
+    pub url: String,
+    records: Arc<Mutex<Records>>,
+}
+
+impl FakeApi {
+    /// Starts the server on a random local port
+    pub async fn start() -> Result<FakeApi, Error> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("http://{}/", listener.local_addr()?);
+        let records = Arc::new(Mutex::new(Records::default()));
+
+        let recs = records.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let recs = recs.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = serve_conn(recs, stream).await {
+                        log::debug!("fake api: connection error {e:?}");
+                    }
+                });
+            }
+        });
+
+        Ok(FakeApi { url, records })
+    }
+
+    pub fn sent(&self) -> Vec<SentMessage> {
+        self.records.lock().unwrap().sent.clone()
+    }
+
+    pub fn edited(&self) -> Vec<SentMessage> {
+        self.records.lock().unwrap().edited.clone()
+    }
+
+    pub fn documents(&self) -> Vec<SentDocument> {
+        self.records.lock().unwrap().documents.clone()
+    }
+
+    pub fn photos(&self) -> Vec<SentPhoto> {
+        self.records.lock().unwrap().photos.clone()
+    }
+
+    pub fn deleted(&self) -> Vec<(i64, i32)> {
+        self.records.lock().unwrap().deleted.clone()
+    }
+
+    pub fn calls(&self) -> Vec<String> {
+        self.records.lock().unwrap().calls.clone()
+    }
+
+    pub fn answers(&self) -> Vec<String> {
+        self.records.lock().unwrap().answers.clone()
+    }
+
+    /// Somebody else deletes a message of the bot
+    pub fn delete_by_user(&self, cid: i64, mid: i32) {
+        self.records.lock().unwrap().gone.push((cid, mid));
+    }
+
+    /// Makes the next `n` messages fail with `RetryAfter`
+    pub fn flood_next(&self, n: u32) {
+        self.records.lock().unwrap().floods = n;
+    }
+
+    /// Makes messages to `cid` fail as if the user has blocked the bot
+    pub fn set_blocked(&self, cid: i64, blocked: bool) {
+        let mut recs = self.records.lock().unwrap();
+        recs.blocked.retain(|c| *c != cid);
+        if blocked {
+            recs.blocked.push(cid);
+        }
+    }
+
+    pub fn set_admins(&self, admins: &[u64]) {
+        self.records.lock().unwrap().admins = admins.to_vec();
+    }
+
+    /// Forget everything recorded so far
+    pub fn clear(&self) {
+        let mut recs = self.records.lock().unwrap();
+        recs.sent.clear();
+        recs.edited.clear();
+        recs.documents.clear();
+        recs.photos.clear();
+        recs.deleted.clear();
+        recs.calls.clear();
+        recs.answers.clear();
+    }
+}
+
+/// Serves keep-alive connection until the client closes it
+async fn serve_conn(
+    records: Arc<Mutex<Records>>,
+    stream: TcpStream,
+) -> Result<(), Error> {
+    let mut stream = BufReader::new(stream);
+    loop {
+        let mut request_line = String::new();
+        if stream.read_line(&mut request_line).await? == 0 {
+            return Ok(())
+        }
+
+        let mut content_length = 0;
+        let mut boundary = None;
+        loop {
+            let mut header = String::new();
+            stream.read_line(&mut header).await?;
+            let header = header.trim_end();
+            if header.is_empty() { break }
+            if let Some((name, value)) = header.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    content_length = value.trim().parse()?;
+                }
+                if name.eq_ignore_ascii_case("content-type") {
+                    boundary = value.split_once("boundary=")
+                        .map(|(_, b)| b.trim().to_string());
+                }
+            }
+        }
+        let mut body = vec![0u8; content_length];
+        stream.read_exact(&mut body).await?;
+
+        // Path looks like /bot<token>/<Method>
+        let path = request_line.split(' ').nth(1).unwrap_or("");
+        let method = path.rsplit('/').next().unwrap_or("").to_lowercase();
+        let params: Value = if body.is_empty() {
+            json!({})
+        } else if let Some(boundary) = boundary {
+            multipart_params(&body, &boundary)
+        } else {
+            serde_json::from_slice(&body)?
+        };
+
+        let resp = {
+            let mut recs = records.lock().unwrap();
+            recs.calls.push(method.clone());
+            if method == "sendmessage" && recs.floods > 0 {
+                recs.floods -= 1;
+                json!({
+                    "ok": false,
+                    "error_code": 429,
+                    "description": "Too Many Requests: retry after 0",
+                    "parameters": { "retry_after": 0 },
+                })
+            } else if method == "sendmessage"
+                && recs.blocked.contains(&chat_id(&params))
+            {
+                json!({
+                    "ok": false,
+                    "error_code": 403,
+                    "description": "Forbidden: bot was blocked by the user",
+                })
+            } else if method == "editmessagetext" && is_gone(&recs, &params) {
+                json!({
+                    "ok": false,
+                    "error_code": 400,
+                    "description": "Bad Request: message to edit not found",
+                })
+            } else {
+                let result = handle_method(&mut recs, &method, &params);
+                json!({ "ok": true, "result": result })
+            }
+        }.to_string();
+        let resp = format!("HTTP/1.1 200 OK\r
+Content-Type: application/json\r
+Content-Length: {}\r
+\r
+{resp}", resp.len());
+        stream.get_mut().write_all(resp.as_bytes()).await?;
+    }
+}
+
+fn handle_method(recs: &mut Records, method: &str, params: &Value) -> Value {
+    match method {
+        "getme" => json!({
+            "id": BOT_ID,
+            "is_bot": true,
+            "first_name": "Dili",
+            "username": BOT_USERNAME,
+            "can_join_groups": true,
+            "can_read_all_group_messages": false,
+            "supports_inline_queries": false,
+        }),
+        "sendmessage" => {
+            recs.next_message_id += 1;
+            let msg = bot_message(recs.next_message_id, params);
+            recs.sent.push(msg.clone());
+            msg.raw
+        },
+        "senddocument" => {
+            recs.next_message_id += 1;
+            let cid = chat_id(params);
+            let document = &params["document"];
+            let file_name = document["file_name"].as_str().unwrap_or("");
+            recs.documents.push(SentDocument {
+                chat_id: cid,
+                file_name: file_name.to_string(),
+                content: document["content"].as_str().unwrap_or("").to_string(),
+            });
+            json!({
+                "message_id": recs.next_message_id,
+                "date": 0,
+                "chat": chat_json(cid),
+                "document": {
+                    "file_id": "document",
+                    "file_unique_id": "document",
+                    "file_name": file_name,
+                },
+            })
+        },
+        "sendphoto" => {
+            recs.next_message_id += 1;
+            let cid = chat_id(params);
+            let file_id = params["photo"].as_str().unwrap_or("");
+            recs.photos.push(SentPhoto {
+                chat_id: cid,
+                file_id: file_id.to_string(),
+                caption: params["caption"].as_str().unwrap_or("").to_string(),
+            });
+            json!({
+                "message_id": recs.next_message_id,
+                "date": 0,
+                "chat": chat_json(cid),
+                "photo": [{
+                    "file_id": file_id,
+                    "file_unique_id": file_id,
+                    "width": 800,
+                    "height": 600,
+                }],
+            })
+        },
+        "editmessagetext" => {
+            let mid = params["message_id"].as_i64().unwrap_or(0) as i32;
+            let msg = bot_message(mid, params);
+            recs.edited.push(msg.clone());
+            msg.raw
+        },
+        "deletemessage" => {
+            let cid = chat_id(params);
+            let mid = params["message_id"].as_i64().unwrap_or(0) as i32;
+            recs.deleted.push((cid, mid));
+            json!(true)
+        },
+        "getchatadministrators" => recs.admins.iter()
+            .map(|id| json!({
+                "user": {
+                    "id": id,
+                    "is_bot": false,
+                    "first_name": format!("User{id}"),
+                    "username": format!("user{id}"),
+                },
+                "status": "administrator",
+                "can_be_edited": false,
+                "is_anonymous": false,
+                "can_manage_chat": true,
+                "can_change_info": true,
+                "can_delete_messages": true,
+                "can_manage_video_chats": true,
+                "can_invite_users": true,
+                "can_restrict_members": true,
+                "can_pin_messages": true,
+                "can_promote_members": false,
+            }))
+            .collect(),
+        "answercallbackquery" => {
+            if let Some(text) = params["text"].as_str() {
+                recs.answers.push(text.to_string());
+            }
+            json!(true)
+        },
+        // setMyCommands and friends
+        _ => json!(true),
+    }
+}
+
+fn is_gone(recs: &Records, params: &Value) -> bool {
+    let mid = params["message_id"].as_i64().unwrap_or(0) as i32;
+    let msg = (chat_id(params), mid);
+    recs.gone.contains(&msg) || recs.deleted.contains(&msg)
+}
+
+fn chat_id(params: &Value) -> i64 {
+    match &params["chat_id"] {
+        Value::Number(n) => n.as_i64().unwrap_or(0),
+        Value::String(s) => s.parse().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+fn bot_message(message_id: i32, params: &Value) -> SentMessage {
+    let cid = chat_id(params);
+    let text = params["text"].as_str().unwrap_or("").to_string();
+    let mut raw = json!({
+        "message_id": message_id,
+        "date": 0,
+        "chat": chat_json(cid),
+        "from": {
+            "id": BOT_ID,
+            "is_bot": true,
+            "first_name": "Dili",
+            "username": BOT_USERNAME,
+        },
+        "text": text,
+    });
+    let markup = &params["reply_markup"];
+    if markup.is_object() {
+        raw["reply_markup"] = markup.clone();
+    }
+    let buttons = markup["inline_keyboard"].as_array()
+        .map(|rows| rows.iter()
+             .filter_map(|row| row.as_array())
+             .flatten()
+             .filter_map(|btn| btn["callback_data"].as_str())
+             .map(str::to_string)
+             .collect())
+        .unwrap_or_default();
+
+    let silent = params["disable_notification"].as_bool().unwrap_or(false);
+    SentMessage { chat_id: cid, message_id, text, buttons, raw, silent }
+}
+
+/// Fields of a `multipart/form-data` body, files become
+/// `{"file_name": .., "content": ..}`
+fn multipart_params(body: &[u8], boundary: &str) -> Value {
+    let body = String::from_utf8_lossy(body);
+    let mut params = json!({});
+    for part in body.split(&format!("--{boundary}")) {
+        let (headers, content) = match part.split_once("\r\n\r\n") {
+            Some(split) => split,
+            None => continue,
+        };
+        let content = content.strip_suffix("\r\n").unwrap_or(content);
+        let attr = |attr: &str| headers.split(';')
+            .filter_map(|a| a.trim().strip_prefix(&format!("{attr}=\"")))
+            .filter_map(|a| a.split('"').next())
+            .next()
+            .map(str::to_string);
+        let name = match attr("name") {
+            Some(name) => name,
+            None => continue,
+        };
+        params[name] = match attr("filename") {
+            Some(file_name) => json!({
+                "file_name": file_name,
+                "content": content,
+            }),
+            None => Value::String(content.to_string()),
+        };
+    }
+    // Files are sent in parts of their own and referred to by name
+    let attached: Vec<(String, String)> = params.as_object().unwrap().iter()
+        .filter_map(|(name, v)| v.as_str()?.strip_prefix("attach://")
+                    .map(|part| (name.clone(), part.to_string())))
+        .collect();
+    for (name, part) in attached {
+        params[name] = params[&part].take();
+    }
+    params
+}
+
+/// Users' private chats have positive ids, group chats negative
+pub fn chat_json(cid: i64) -> Value {
+    if cid > 0 {
+        json!({ "id": cid, "type": "private", "first_name": format!("User{cid}") })
+    } else {
+        json!({ "id": cid, "type": "supergroup", "title": format!("Group{cid}") })
+    }
+}
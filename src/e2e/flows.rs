@@ -0,0 +1,1322 @@
+use chrono::{Duration, TimeZone, offset::Utc};
+use teloxide::types::{ChatId, UserId};
+
+use super::{Harness, TestUser};
+use super::fake_api::SentMessage;
+use crate::order::Status;
+use crate::rate_limit::ChatLimits;
+
+const GROUP: ChatId = ChatId(-100);
+const OWNER: TestUser = TestUser { id: 1 };
+const COURIER: TestUser = TestUser { id: 2 };
+const ADMIN: TestUser = TestUser { id: 3 };
+
+/// Both users say hello in the group, so the bot knows they're there
+async fn setup() -> Harness {
+    let _ = env_logger::builder().is_test(true).try_init();
+    let mut h = Harness::new().await.unwrap();
+    h.send_text(OWNER, GROUP, "/hello").await.unwrap();
+    h.send_text(COURIER, GROUP, "/hello").await.unwrap();
+    h.send_text(ADMIN, GROUP, "/hello").await.unwrap();
+    h.api.set_admins(&[ADMIN.id]);
+
+    // The clock doesn't move by itself, so all clicks of a test happen
+    // in the same second
+    let limits = ChatLimits { callbacks_per_sec: 100, ..ChatLimits::default() };
+    h.db.set_chat_limits(GROUP, &limits).await.unwrap();
+    h.api.clear();
+    h
+}
+
+/// Creates an order through the dialogue and returns its id
+async fn create_order(h: &mut Harness) -> u64 {
+    let owner_cid = OWNER.private_chat();
+    h.send_text(OWNER, owner_cid, "/new_order").await.unwrap();
+    assert_eq!("What do you want?", h.last_sent_to(owner_cid).text);
+
+    h.send_text(OWNER, owner_cid, "Coffee beans").await.unwrap();
+    h.send_text(OWNER, owner_cid, "5000").await.unwrap();
+    h.send_text(OWNER, owner_cid, "500").await.unwrap();
+    h.send_text(OWNER, owner_cid, "From the shop near the bus stop")
+        .await.unwrap();
+
+    let created = h.api.sent().into_iter().rev()
+        .find(|m| m.text.starts_with("New Order is created!"))
+        .expect("order is not created");
+    assert!(created.text.contains("Coffee beans"));
+    assert!(created.text.contains("5000 AMD"));
+    assert!(created.text.contains("Reward: 500 AMD"));
+
+    let publish = created.buttons.iter()
+        .find(|b| b.starts_with("oa publish "))
+        .expect("no publish button");
+    publish.rsplit(' ').next().unwrap().parse().unwrap()
+}
+
+#[tokio::test]
+async fn test_order_happy_path() {
+    let mut h = setup().await;
+    let owner_cid = OWNER.private_chat();
+    let courier_cid = COURIER.private_chat();
+
+    let oid = create_order(&mut h).await;
+
+    // Publish
+    let publish = format!("oa publish {oid}");
+    let msg = h.last_with_button(owner_cid, &publish);
+    h.click(OWNER, &msg, &publish).await.unwrap();
+    assert!(h.api.deleted().contains(&(owner_cid.0, msg.message_id)));
+    let public = h.last_sent_to(GROUP);
+    assert!(public.text.starts_with("New order is published"));
+    assert!(public.text.contains("Published right now"));
+
+    // Take it in the group chat
+    let assign = format!("oa assign_to_me {oid}");
+    assert!(public.buttons.contains(&assign));
+    h.click(COURIER, &public, &assign).await.unwrap();
+    assert!(h.last_sent_to(GROUP).text.starts_with("Order is taken by"));
+    assert!(h.last_sent_to(owner_cid).text.starts_with("Congrats!"));
+    assert!(h.last_sent_to(courier_cid).text.starts_with("Order is assigned to"));
+
+    // Mark as delivered with the receipt
+    let delivered = format!("oa mark_as_delivered {oid}");
+    let msg = h.last_with_button(courier_cid, &delivered);
+    h.click(COURIER, &msg, &delivered).await.unwrap();
+    let question = h.last_sent_to(courier_cid);
+    assert!(question.text.starts_with("How much have you actually paid"));
+    assert_eq!(Some("As expected: 5000 AMD".to_string()),
+               question.button_text(&format!("dl same {oid}")));
+    h.send_text(COURIER, courier_cid, "a lot").await.unwrap();
+    assert!(h.last_sent_to(courier_cid).text
+            .starts_with("I don't understand the amount"));
+    h.send_photo(COURIER, courier_cid, "receipt-1", "5200").await.unwrap();
+    let msg = h.last_sent_to(owner_cid);
+    assert!(msg.text.contains("marked order as delivered. Paid 5200 AMD + \
+500 AMD reward = 5700 AMD to pay the courier. Please confirm it."));
+    assert!(msg.text.contains("Actually paid: 5200 AMD"));
+    let photo = h.api.photos().pop().unwrap();
+    assert_eq!((owner_cid.0, "receipt-1"), (photo.chat_id, photo.file_id.as_str()));
+    assert_eq!("Receipt for <b>Coffee beans</b>", photo.caption);
+
+    // Confirm
+    let confirm = format!("oa confirm_delivery {oid}");
+    h.click(OWNER, &msg, &confirm).await.unwrap();
+    assert_eq!("Order delivery is confirmed! You owe the courier 5700 AMD, \
+see /balance", h.last_sent_to(owner_cid).text);
+    assert!(h.last_sent_to(courier_cid).text
+            .starts_with("Order delivery is confirmed! Thank you! You're owed \
+5700 AMD for it"));
+
+    // Changed orders are sent again rather than edited, only the board is
+    assert!(h.api.edited().iter().all(|m| m.text.starts_with("📋 Open orders")));
+}
+
+/// Creates an order, publishes it and lets the courier take it
+async fn create_assigned_order(h: &mut Harness) -> u64 {
+    let oid = create_order(h).await;
+    let publish = format!("oa publish {oid}");
+    let msg = h.last_with_button(OWNER.private_chat(), &publish);
+    h.click(OWNER, &msg, &publish).await.unwrap();
+
+    let assign = format!("oa assign_to_me {oid}");
+    let public = h.last_with_button(GROUP, &assign);
+    h.click(COURIER, &public, &assign).await.unwrap();
+    oid
+}
+
+/// The courier marks the order as delivered, they've paid as expected
+async fn mark_as_delivered(h: &mut Harness, oid: u64) {
+    let courier_cid = COURIER.private_chat();
+    let delivered = format!("oa mark_as_delivered {oid}");
+    let msg = h.last_with_button(courier_cid, &delivered);
+    h.click(COURIER, &msg, &delivered).await.unwrap();
+    let same = format!("dl same {oid}");
+    let msg = h.last_with_button(courier_cid, &same);
+    h.click(COURIER, &msg, &same).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_owner_cancels_assigned_order_with_preset_reason() {
+    let mut h = setup().await;
+    let owner_cid = OWNER.private_chat();
+    let courier_cid = COURIER.private_chat();
+    let oid = create_assigned_order(&mut h).await;
+
+    let cancel = format!("oa cancel {oid}");
+    let msg = h.last_with_button(owner_cid, &cancel);
+    h.click(OWNER, &msg, &cancel).await.unwrap();
+    let ask = h.last_sent_to(owner_cid);
+    assert_eq!("Why are you canceling the order?", ask.text);
+
+    let reason = format!("oc plans_changed {oid}");
+    h.click(OWNER, &ask, &reason).await.unwrap();
+    assert!(h.api.deleted().contains(&(owner_cid.0, ask.message_id)));
+    assert!(h.last_sent_to(owner_cid).text.starts_with("The order is unpublished"));
+
+    let notice = h.last_sent_to(courier_cid);
+    assert!(notice.text.contains("has canceled the order you were going to deliver"));
+    assert!(notice.text.contains("Reason: My plans have changed"));
+    assert!(notice.text.contains("Canceled right now: My plans have changed"));
+    assert!(notice.buttons.is_empty());
+}
+
+#[tokio::test]
+async fn test_owner_cancels_with_own_reason() {
+    let mut h = setup().await;
+    let owner_cid = OWNER.private_chat();
+    let courier_cid = COURIER.private_chat();
+    let oid = create_assigned_order(&mut h).await;
+
+    let cancel = format!("oa cancel {oid}");
+    let msg = h.last_with_button(owner_cid, &cancel);
+    h.click(OWNER, &msg, &cancel).await.unwrap();
+    let ask = h.last_sent_to(owner_cid);
+    h.click(OWNER, &ask, &format!("oc own {oid}")).await.unwrap();
+    assert_eq!("Write why you're canceling the order",
+               h.last_sent_to(owner_cid).text);
+
+    h.send_text(OWNER, owner_cid, "Coffee <is> bad").await.unwrap();
+    let notice = h.last_sent_to(courier_cid);
+    assert!(notice.text.contains("Reason: Coffee &lt;is&gt; bad"));
+
+    // The dialogue is over, the order can be published again
+    let publish = format!("oa publish {oid}");
+    let msg = h.last_with_button(owner_cid, &publish);
+    h.click(OWNER, &msg, &publish).await.unwrap();
+    assert!(h.last_sent_to(GROUP).text.starts_with("New order is published"));
+}
+
+/// Gets an order to the point where the owner disputes the delivery
+async fn create_disputed_order(h: &mut Harness) -> u64 {
+    let owner_cid = OWNER.private_chat();
+    let courier_cid = COURIER.private_chat();
+    let oid = create_assigned_order(h).await;
+    mark_as_delivered(h, oid).await;
+
+    let dispute = format!("oa open_dispute {oid}");
+    let msg = h.last_with_button(owner_cid, &dispute);
+    h.click(OWNER, &msg, &dispute).await.unwrap();
+    assert!(h.last_sent_to(owner_cid).text.starts_with("What went wrong?"));
+    h.send_text(OWNER, owner_cid, "I got tea instead").await.unwrap();
+
+    let notice = h.last_sent_to(courier_cid);
+    assert!(notice.text.starts_with("There's a dispute about your order"));
+    assert!(notice.text.contains("Disputed right now: I got tea instead"));
+    oid
+}
+
+/// `user` opens the moderation view from the menu
+async fn open_moderation(h: &mut Harness, user: TestUser) {
+    let cid = user.private_chat();
+    h.send_text(user, cid, "/menu").await.unwrap();
+    let menu = h.last_with_button(cid, "moderation");
+    h.click(user, &menu, "moderation").await.unwrap();
+}
+
+/// Admin opens the moderation view and clicks `data` for the only dispute
+async fn resolve_dispute(h: &mut Harness, data: &str) {
+    let admin_cid = ADMIN.private_chat();
+    open_moderation(h, ADMIN).await;
+
+    let order = h.last_with_button(admin_cid, data);
+    assert!(order.text.starts_with("Dispute"));
+    assert!(order.text.contains("I got tea instead"));
+    h.click(ADMIN, &order, data).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_dispute_resolved_as_republished() {
+    let mut h = setup().await;
+    let oid = create_disputed_order(&mut h).await;
+
+    // Not an admin
+    let owner_cid = OWNER.private_chat();
+    let republish = format!("oa resolve_republish {oid}");
+    let mut msg = h.last_sent_to(owner_cid);
+    msg.buttons.push(republish.clone());
+    h.click(OWNER, &msg, &republish).await.unwrap();
+    assert!(h.api.sent().iter()
+            .any(|m| m.text == "You are not permitted to perform this action"));
+
+    resolve_dispute(&mut h, &republish).await;
+    for cid in [owner_cid, COURIER.private_chat()] {
+        assert!(h.last_sent_to(cid).text
+                .starts_with("The dispute is resolved, the order is published again"));
+    }
+    let public = h.last_sent_to(GROUP);
+    assert!(public.text.starts_with("The order is published again"));
+    assert!(public.buttons.contains(&format!("oa assign_to_me {oid}")));
+}
+
+#[tokio::test]
+async fn test_dispute_resolved_as_delivered() {
+    let mut h = setup().await;
+    let oid = create_disputed_order(&mut h).await;
+
+    resolve_dispute(&mut h, &format!("oa resolve_delivered {oid}")).await;
+    let msg = h.last_sent_to(COURIER.private_chat());
+    assert!(msg.text.starts_with("The dispute is resolved, the order is delivered"));
+    assert!(msg.text.contains("Delivered right now"));
+}
+
+#[tokio::test]
+async fn test_moderation_view_is_for_admins_only() {
+    let mut h = setup().await;
+    open_moderation(&mut h, COURIER).await;
+    assert!(h.api.sent().iter()
+            .any(|m| m.text == "Only admins of the chat can moderate it"));
+}
+
+#[tokio::test]
+async fn test_admin_unassigns_and_unpublishes() {
+    let mut h = setup().await;
+    let admin_cid = ADMIN.private_chat();
+    let oid = create_assigned_order(&mut h).await;
+
+    open_moderation(&mut h, ADMIN).await;
+    let unassign = format!("oa force_unassign {oid}");
+    let msg = h.last_with_button(admin_cid, &unassign);
+    h.click(ADMIN, &msg, &unassign).await.unwrap();
+    assert!(h.last_sent_to(COURIER.private_chat()).text
+            .starts_with("An admin of the chat has unassigned the courier"));
+    let public = h.last_sent_to(GROUP);
+    assert!(public.text.starts_with("The order is looking for a courier again"));
+
+    open_moderation(&mut h, ADMIN).await;
+    let unpublish = format!("oa unpublish {oid}");
+    let msg = h.last_with_button(admin_cid, &unpublish);
+    h.click(ADMIN, &msg, &unpublish).await.unwrap();
+    assert!(h.last_sent_to(OWNER.private_chat()).text
+            .starts_with("An admin of the chat has unpublished the order"));
+
+    // Both are in the log, and the admin list was only fetched once
+    open_moderation(&mut h, ADMIN).await;
+    let log = h.api.sent().into_iter().rev()
+        .find(|m| m.chat_id == admin_cid.0 && m.text.starts_with("Recently:"))
+        .expect("no moderation log");
+    assert!(log.text.contains(&format!("unpublish order {oid}")));
+    assert!(log.text.contains(&format!("force_unassign order {oid}")));
+    let fetched = h.api.calls().iter()
+        .filter(|c| *c == "getchatadministrators").count();
+    assert_eq!(1, fetched);
+}
+
+#[tokio::test]
+async fn test_banned_user_cannot_post_orders() {
+    let mut h = setup().await;
+    let owner_cid = OWNER.private_chat();
+    let admin_cid = ADMIN.private_chat();
+    let oid = create_order(&mut h).await;
+    let publish = format!("oa publish {oid}");
+    let msg = h.last_with_button(owner_cid, &publish);
+    h.click(OWNER, &msg, &publish).await.unwrap();
+
+    open_moderation(&mut h, ADMIN).await;
+    let ban = format!("mban {}", OWNER.id);
+    let msg = h.last_with_button(admin_cid, &ban);
+    h.click(ADMIN, &msg, &ban).await.unwrap();
+    assert!(h.last_sent_to(admin_cid).text.ends_with("can't post orders anymore"));
+
+    // The dialogue bails out with an error, like it does for other problems
+    let res = h.send_text(OWNER, owner_cid, "/new_order").await;
+    assert!(res.is_err());
+    assert_eq!("Admins of the chat don't allow you to post orders",
+               h.last_sent_to(owner_cid).text);
+
+    // Unban from the list of banned users
+    open_moderation(&mut h, ADMIN).await;
+    let unban = format!("munban {}", OWNER.id);
+    let msg = h.last_with_button(admin_cid, &unban);
+    h.click(ADMIN, &msg, &unban).await.unwrap();
+    create_order(&mut h).await;
+}
+
+/// `user` reports the order from its `public` message in the group
+async fn report(
+    h: &mut Harness,
+    user: TestUser,
+    public: &SentMessage,
+    oid: u64,
+    reason: &str,
+) {
+    let data = format!("oa report {oid}");
+    h.click(user, public, &data).await.unwrap();
+
+    let data = format!("or {reason} {} {oid}", GROUP.0);
+    let picker = h.last_with_button(user.private_chat(), &data);
+    assert_eq!("What's wrong with the order?", picker.text);
+    h.click(user, &picker, &data).await.unwrap();
+
+    // The group doesn't see who reports it
+    assert!(h.api.sent().iter().all(|m| m.chat_id != GROUP.0
+                                    || !m.text.contains("wrong with the order")));
+    assert!(h.api.sent().iter().any(|m| m.chat_id == user.private_chat().0
+                                    && m.text.starts_with("Thanks")));
+}
+
+/// `user` lists active orders from the menu
+async fn list_active_orders(h: &mut Harness, user: TestUser) {
+    let cid = user.private_chat();
+    h.send_text(user, cid, "/menu").await.unwrap();
+    let menu = h.last_with_button(cid, "list_active_orders");
+    h.click(user, &menu, "list_active_orders").await.unwrap();
+}
+
+#[tokio::test]
+async fn test_reported_order_is_hidden_until_reviewed() {
+    let mut h = setup().await;
+    let neighbour = TestUser { id: 4 };
+    h.send_text(neighbour, GROUP, "/hello").await.unwrap();
+    let owner_cid = OWNER.private_chat();
+    let admin_cid = ADMIN.private_chat();
+    let courier_cid = COURIER.private_chat();
+
+    let oid = create_order(&mut h).await;
+    let publish = format!("oa publish {oid}");
+    let msg = h.last_with_button(owner_cid, &publish);
+    h.click(OWNER, &msg, &publish).await.unwrap();
+    let public = h.last_sent_to(GROUP);
+
+    // Reporting doesn't interrupt an order the courier is writing
+    h.send_text(COURIER, courier_cid, "/new_order").await.unwrap();
+    report(&mut h, COURIER, &public, oid, "spam").await;
+    h.send_text(COURIER, courier_cid, "Tea").await.unwrap();
+    assert!(h.last_sent_to(courier_cid).text.starts_with("How much"));
+    for text in ["100", "50", "Black"] {
+        h.send_text(COURIER, courier_cid, text).await.unwrap();
+    }
+    assert!(h.api.sent().iter().any(|m| m.chat_id == courier_cid.0
+                                    && m.text.starts_with("New Order is created!")));
+    let notification = h.last_sent_to(admin_cid);
+    assert!(notification.text.contains("has reported the order as Spam"));
+    assert!(notification.buttons.contains(&format!("oa dismiss_reports {oid}")));
+
+    // Reporting twice doesn't count
+    h.api.clear();
+    h.click(COURIER, &public, &format!("oa report {oid}")).await.unwrap();
+    assert!(h.api.sent().iter()
+            .any(|m| m.text.starts_with("You've already reported this order")));
+
+    report(&mut h, ADMIN, &public, oid, "scam").await;
+    h.api.clear();
+    list_active_orders(&mut h, COURIER).await;
+    assert!(h.api.sent().iter().any(|m| m.chat_id == courier_cid.0
+                                    && m.text.contains("Coffee beans")));
+
+    // The bot can't write to those who haven't started it
+    h.api.set_blocked(neighbour.private_chat().0, true);
+    h.click(neighbour, &public, &format!("oa report {oid}")).await.unwrap();
+    assert!(h.last_sent_to(GROUP).text
+            .starts_with("To report an order, open a private chat with me"));
+    h.api.set_blocked(neighbour.private_chat().0, false);
+
+    report(&mut h, neighbour, &public, oid, "inappropriate").await;
+    let notification = h.last_sent_to(admin_cid);
+    assert!(notification.text.contains("The order is hidden"));
+    h.api.clear();
+    list_active_orders(&mut h, COURIER).await;
+    assert!(h.api.sent().iter().any(|m| m.chat_id == courier_cid.0
+                                    && m.text == "No active orders"));
+
+    // Admin decides it's fine
+    let dismiss = format!("oa dismiss_reports {oid}");
+    h.click(ADMIN, &notification, &dismiss).await.unwrap();
+    h.api.clear();
+    list_active_orders(&mut h, COURIER).await;
+    assert!(h.api.sent().iter().any(|m| m.chat_id == courier_cid.0
+                                    && m.text.contains("Coffee beans")));
+}
+
+#[tokio::test]
+async fn test_admin_limits_orders_and_clicks() {
+    let mut h = setup().await;
+    let owner_cid = OWNER.private_chat();
+
+    // Only admins change limits
+    h.send_text(OWNER, GROUP, "/limits open_orders 1").await.unwrap();
+    assert_eq!("Only admins of the chat can change its limits",
+               h.last_sent_to(GROUP).text);
+    h.send_text(ADMIN, GROUP, "/limits open_orders 1").await.unwrap();
+    h.send_text(ADMIN, GROUP, "/limits orders_per_day 2").await.unwrap();
+    assert!(h.last_sent_to(GROUP).text.contains("orders_per_day: 2"));
+    h.send_text(ADMIN, GROUP, "/limits callbacks_per_sec 0").await.unwrap();
+    let msg = h.last_sent_to(GROUP);
+    assert!(msg.text.starts_with("callbacks_per_sec must be at least 1"));
+    assert!(msg.text.contains("callbacks_per_sec: 100"));
+
+    let oid = create_order(&mut h).await;
+    let publish = format!("oa publish {oid}");
+    let msg = h.last_with_button(owner_cid, &publish);
+    h.click(OWNER, &msg, &publish).await.unwrap();
+
+    let oid = create_order(&mut h).await;
+    let publish = format!("oa publish {oid}");
+    let msg = h.last_with_button(owner_cid, &publish);
+    h.click(OWNER, &msg, &publish).await.unwrap();
+    assert!(h.last_sent_to(owner_cid).text
+            .starts_with("Sorry, you can have at most 1 published orders"));
+
+    // Deleted orders still count
+    let delete = format!("oa delete {oid}");
+    let created = h.last_with_button(owner_cid, &delete);
+    h.click(OWNER, &created, &delete).await.unwrap();
+
+    // The dialogue bails out with an error, like it does for other problems
+    let res = h.send_text(OWNER, owner_cid, "/new_order").await;
+    assert!(res.is_err());
+    assert!(h.last_sent_to(owner_cid).text
+            .starts_with("Sorry, you can create at most 2 orders a day"));
+
+    // Next day it's fine again
+    h.clock.advance(Duration::days(1));
+    let oid = create_order(&mut h).await;
+    let publish = format!("oa publish {oid}");
+    let msg = h.last_with_button(owner_cid, &publish);
+
+    // Clicking too fast
+    h.send_text(ADMIN, GROUP, "/limits callbacks_per_sec 1").await.unwrap();
+    h.api.clear();
+    h.click(OWNER, &msg, &publish).await.unwrap();
+    h.click(OWNER, &msg, &publish).await.unwrap();
+    assert_eq!(vec!["You're clicking too fast, please wait a second \
+and try again".to_string()], h.api.answers());
+    assert_eq!(1, h.api.sent().iter()
+               .filter(|m| m.text.starts_with("Sorry, you can have")).count());
+}
+
+#[tokio::test]
+async fn test_flood_control_doesnt_break_notifications() {
+    let mut h = setup().await;
+    let owner_cid = OWNER.private_chat();
+    let oid = create_order(&mut h).await;
+
+    // Telegram tells us to slow down while we're publishing
+    h.api.flood_next(2);
+    let publish = format!("oa publish {oid}");
+    let msg = h.last_with_button(owner_cid, &publish);
+    h.click(OWNER, &msg, &publish).await.unwrap();
+
+    assert!(h.last_sent_to(GROUP).text.starts_with("New order is published"));
+    let sent = h.api.calls().iter().filter(|c| *c == "sendmessage").count();
+    assert_eq!(h.api.sent().len() + 2, sent);
+}
+
+fn board(h: &Harness) -> SentMessage {
+    h.api.sent().into_iter().rev()
+        .find(|m| m.chat_id == GROUP.0 && m.text.starts_with("📋 Open orders"))
+        .expect("no board")
+}
+
+#[tokio::test]
+async fn test_board_lists_published_orders() {
+    let mut h = setup().await;
+    let oid = create_assigned_order(&mut h).await;
+
+    // Published, then edited once the order is taken
+    let first = board(&h);
+    assert!(first.text.contains("<b>Coffee beans</b>: 5000 AMD, reward 500 AMD, \
+published right now"));
+    assert_eq!(1, h.api.calls().iter().filter(|c| *c == "pinchatmessage").count());
+    let edited = h.api.edited().pop().unwrap();
+    assert_eq!(first.message_id, edited.message_id);
+    assert!(edited.text.ends_with("No open orders right now"));
+
+    // Somebody deletes the board, so the next change brings a new one
+    h.api.delete_by_user(GROUP.0, first.message_id);
+    let owner_cid = OWNER.private_chat();
+    for data in [format!("oa cancel {oid}"),
+                 format!("oc plans_changed {oid}"),
+                 format!("oa publish {oid}")] {
+        let msg = h.last_with_button(owner_cid, &data);
+        h.click(OWNER, &msg, &data).await.unwrap();
+    }
+
+    let second = board(&h);
+    assert_ne!(first.message_id, second.message_id);
+    assert!(second.text.contains("Coffee beans"));
+    assert_eq!(2, h.api.calls().iter().filter(|c| *c == "pinchatmessage").count());
+}
+
+#[tokio::test]
+async fn test_unrelated_user_cannot_cancel() {
+    let mut h = setup().await;
+    let owner_cid = OWNER.private_chat();
+
+    let oid = create_order(&mut h).await;
+    let publish = format!("oa publish {oid}");
+    let msg = h.last_with_button(owner_cid, &publish);
+    h.click(OWNER, &msg, &publish).await.unwrap();
+
+    // Courier forges a button that isn't shown to them
+    let mut public = h.last_sent_to(GROUP);
+    let cancel = format!("oa cancel {oid}");
+    let confirm = format!("oa confirm_delivery {oid}");
+    public.buttons.push(cancel.clone());
+    public.buttons.push(confirm.clone());
+    h.api.clear();
+    h.click(COURIER, &public, &cancel).await.unwrap();
+    assert!(h.api.sent().iter()
+            .any(|m| m.text == "You are not permitted to perform this action"));
+
+    // Nobody can confirm an order that isn't assigned
+    h.click(COURIER, &public, &confirm).await.unwrap();
+    assert!(h.api.sent().iter()
+            .any(|m| m.text == "This action is not available for the order anymore"));
+    assert!(!h.api.calls().iter().any(|c| c == "deletemessage"));
+}
+
+#[tokio::test]
+async fn test_order_age_is_shown() {
+    let mut h = setup().await;
+    let owner_cid = OWNER.private_chat();
+
+    let oid = create_order(&mut h).await;
+    let publish = format!("oa publish {oid}");
+    let msg = h.last_with_button(owner_cid, &publish);
+    h.click(OWNER, &msg, &publish).await.unwrap();
+
+    h.clock.advance(Duration::days(3));
+    h.send_text(OWNER, owner_cid, "/menu").await.unwrap();
+    let menu = h.last_with_button(owner_cid, "show_my_orders");
+    h.click(OWNER, &menu, "show_my_orders").await.unwrap();
+
+    let order = h.api.sent().into_iter()
+        .find(|m| m.text.starts_with("<b>Coffee beans</b>"))
+        .expect("order is not shown");
+    assert!(order.text.contains("Published 3 days ago"));
+}
+
+fn digests(h: &Harness, cid: ChatId) -> Vec<SentMessage> {
+    h.api.sent().into_iter()
+        .filter(|m| m.chat_id == cid.0 && m.text.starts_with("📰 Daily digest"))
+        .collect()
+}
+
+#[tokio::test]
+async fn test_daily_digest() {
+    let mut h = setup().await;
+    let owner_cid = OWNER.private_chat();
+    let courier_cid = COURIER.private_chat();
+
+    h.send_text(OWNER, GROUP, "/digest 09:00").await.unwrap();
+    assert_eq!("Only admins of the chat can change its digest",
+               h.last_sent_to(GROUP).text);
+    h.send_text(ADMIN, GROUP, "/digest 09:00").await.unwrap();
+    assert!(h.last_sent_to(GROUP).text.contains("Every day at 09:00 UTC"));
+    h.send_text(COURIER, courier_cid, "/digest subscribe").await.unwrap();
+    assert!(h.last_sent_to(courier_cid).text.contains("You get it in private"));
+
+    // One order is delivered, another one is waiting for a courier
+    let oid = create_assigned_order(&mut h).await;
+    mark_as_delivered(&mut h, oid).await;
+    let confirm = format!("oa confirm_delivery {oid}");
+    let msg = h.last_with_button(owner_cid, &confirm);
+    h.click(OWNER, &msg, &confirm).await.unwrap();
+    let oid = create_order(&mut h).await;
+    let publish = format!("oa publish {oid}");
+    let msg = h.last_with_button(owner_cid, &publish);
+    h.click(OWNER, &msg, &publish).await.unwrap();
+
+    // It's sent at 9:00 and only once
+    crate::ui::digest::send_due(h.bot.clone(), h.db.clone()).await.unwrap();
+    assert!(digests(&h, GROUP).is_empty());
+    h.clock.advance(Duration::hours(21));
+    crate::ui::digest::send_due(h.bot.clone(), h.db.clone()).await.unwrap();
+    crate::ui::digest::send_due(h.bot.clone(), h.db.clone()).await.unwrap();
+    let digest = digests(&h, GROUP);
+    assert_eq!(1, digest.len());
+    assert!(digest[0].text.contains("🆕 New orders</b> (1)"));
+    assert!(digest[0].text.contains("✅ Delivered</b> (1)"));
+    assert!(!digest[0].text.contains("Still waiting"));
+    assert_eq!(1, digests(&h, courier_cid).len());
+    assert!(digests(&h, owner_cid).is_empty());
+
+    // Next day the order is not new any more, and then it's turned off
+    h.clock.advance(Duration::days(1));
+    crate::ui::digest::send_due(h.bot.clone(), h.db.clone()).await.unwrap();
+    let digest = digests(&h, GROUP);
+    assert_eq!(2, digest.len());
+    assert!(digest[1].text.contains("⏳ Still waiting for a courier</b> (1)"));
+    assert!(!digest[1].text.contains("Delivered"));
+
+    h.send_text(ADMIN, GROUP, "/digest off").await.unwrap();
+    h.clock.advance(Duration::days(1));
+    crate::ui::digest::send_due(h.bot.clone(), h.db.clone()).await.unwrap();
+    assert_eq!(2, digests(&h, GROUP).len());
+}
+
+#[tokio::test]
+async fn test_settings() {
+    let mut h = setup().await;
+    let owner_cid = OWNER.private_chat();
+    let courier_cid = COURIER.private_chat();
+
+    // Courier is in two chats, so doesn't know where to create orders
+    const OTHER_GROUP: ChatId = ChatId(-200);
+    h.send_text(COURIER, OTHER_GROUP, "/hello").await.unwrap();
+    let res = h.send_text(COURIER, courier_cid, "/new_order").await;
+    assert!(res.is_err());
+    assert!(h.api.sent().iter().any(|m| m.chat_id == courier_cid.0
+            && m.text.starts_with("You're in 2 public chats")));
+
+    h.send_text(COURIER, courier_cid, "/menu").await.unwrap();
+    let menu = h.last_with_button(courier_cid, "settings");
+    h.click(COURIER, &menu, "settings").await.unwrap();
+    let settings = h.last_with_button(courier_cid, "st chat");
+    for data in ["st chat", "st new_orders", "st quiet", "st currency"] {
+        h.click(COURIER, &settings, data).await.unwrap();
+    }
+    let edited = h.api.edited().pop().unwrap();
+    assert_eq!(settings.message_id, edited.message_id);
+    assert_eq!(Some("🔔 New orders in private: on"),
+               edited.button_text("st new_orders").as_deref());
+    assert_eq!(Some("🌙 Quiet hours: 22:00-07:00"),
+               edited.button_text("st quiet").as_deref());
+    assert_eq!(Some("💱 Show prices also in: USD"),
+               edited.button_text("st currency").as_deref());
+
+    h.send_text(COURIER, courier_cid, "/new_order").await.unwrap();
+    assert_eq!("What do you want?", h.last_sent_to(courier_cid).text);
+    h.send_text(COURIER, courier_cid, "/menu").await.unwrap();
+
+    // New orders come privately, silently at night
+    for hour in [12, 23] {
+        h.clock.set(Utc.ymd(2022, 7, 1).and_hms(hour, 0, 0));
+        let oid = create_order(&mut h).await;
+        let publish = format!("oa publish {oid}");
+        let msg = h.last_with_button(owner_cid, &publish);
+        h.click(OWNER, &msg, &publish).await.unwrap();
+
+        let assign = format!("oa assign_to_me {oid}");
+        let sent = h.last_with_button(courier_cid, &assign);
+        assert!(sent.text.starts_with("New order is published"));
+        assert!(sent.text.contains("5000 AMD (≈ 12.50 USD)"));
+        assert_eq!(hour == 23, sent.silent);
+    }
+    assert!(!h.api.sent().iter().any(|m| m.chat_id == ADMIN.id as i64
+                                     && m.text.starts_with("New order")));
+}
+
+#[tokio::test]
+async fn test_quiet_hours_hold_notifications() {
+    let mut h = setup().await;
+    let owner_cid = OWNER.private_chat();
+    let courier_cid = COURIER.private_chat();
+
+    // 22:00-07:00 in UTC+4
+    h.send_text(COURIER, courier_cid, "/menu").await.unwrap();
+    let menu = h.last_with_button(courier_cid, "settings");
+    h.click(COURIER, &menu, "settings").await.unwrap();
+    let settings = h.last_with_button(courier_cid, "st quiet");
+    h.click(COURIER, &settings, "st quiet").await.unwrap();
+    let edited = h.api.edited().pop().unwrap();
+    assert_eq!(Some("🕒 Time zone: UTC+04:00 ➖"),
+               edited.button_text("st tz_minus").as_deref());
+
+    // It's 01:00 for the courier when the order is canceled
+    h.clock.set(Utc.ymd(2022, 7, 1).and_hms(21, 0, 0));
+    let oid = create_assigned_order(&mut h).await;
+    assert!(h.last_sent_to(courier_cid).silent);
+    for data in [format!("oa cancel {oid}"), format!("oc plans_changed {oid}")] {
+        let msg = h.last_with_button(owner_cid, &data);
+        h.click(OWNER, &msg, &data).await.unwrap();
+    }
+    let canceled = |h: &Harness| h.api.sent().into_iter()
+        .filter(|m| m.chat_id == courier_cid.0
+                && m.text.contains("has canceled the order"))
+        .count();
+    assert_eq!(0, canceled(&h));
+
+    crate::ui::quiet_hours::flush(h.bot.clone(), h.db.clone()).await.unwrap();
+    assert_eq!(0, canceled(&h));
+
+    // 07:00 for the courier, it waits while Telegram is flooded
+    h.clock.set(Utc.ymd(2022, 7, 2).and_hms(3, 0, 0));
+    h.api.flood_next(5);
+    crate::ui::quiet_hours::flush(h.bot.clone(), h.db.clone()).await.unwrap();
+    assert_eq!(0, canceled(&h));
+    crate::ui::quiet_hours::flush(h.bot.clone(), h.db.clone()).await.unwrap();
+    assert_eq!(1, canceled(&h));
+    assert!(!h.last_sent_to(courier_cid).silent);
+    crate::ui::quiet_hours::flush(h.bot.clone(), h.db.clone()).await.unwrap();
+    assert_eq!(1, canceled(&h));
+
+    // A courier that has blocked the bot won't get it, nor will anybody
+    // after days of failures
+    let held = |text: &str, held_at| crate::settings::HeldNotification {
+        pcid: GROUP,
+        order_id: None,
+        text: text.to_string(),
+        held_at,
+    };
+    let now = h.clock.now();
+    h.db.hold_notification(UserId(COURIER.id), &held("Blocked", now)).await.unwrap();
+    h.api.set_blocked(courier_cid.0, true);
+    crate::ui::quiet_hours::flush(h.bot.clone(), h.db.clone()).await.unwrap();
+    h.api.set_blocked(courier_cid.0, false);
+    h.db.hold_notification(UserId(OWNER.id), &held("Stale", now - Duration::days(3)))
+        .await.unwrap();
+    h.db.hold_notification(UserId(OWNER.id), &held("Fresh", now)).await.unwrap();
+    h.api.flood_next(5);
+    crate::ui::quiet_hours::flush(h.bot.clone(), h.db.clone()).await.unwrap();
+    crate::ui::quiet_hours::flush(h.bot.clone(), h.db.clone()).await.unwrap();
+    let texts: Vec<_> = h.api.sent().into_iter()
+        .filter(|m| ["Blocked", "Stale", "Fresh"].contains(&m.text.as_str()))
+        .map(|m| m.text)
+        .collect();
+    assert_eq!(vec!["Fresh"], texts);
+}
+
+#[tokio::test]
+async fn test_my_data_and_forget_me() {
+    let mut h = setup().await;
+    let owner_cid = OWNER.private_chat();
+    let courier_cid = COURIER.private_chat();
+
+    let oid = create_assigned_order(&mut h).await;
+    h.send_text(OWNER, owner_cid, "/forget_me").await.unwrap();
+    assert!(h.last_sent_to(owner_cid).text
+            .starts_with("You have 1 orders that aren't finished yet"));
+
+    mark_as_delivered(&mut h, oid).await;
+    let confirm = format!("oa confirm_delivery {oid}");
+    let msg = h.last_with_button(owner_cid, &confirm);
+    h.click(OWNER, &msg, &confirm).await.unwrap();
+
+    h.send_text(OWNER, GROUP, "/my_data").await.unwrap();
+    let doc = h.api.documents().pop().expect("no data is sent");
+    assert_eq!(owner_cid.0, doc.chat_id);
+    assert_eq!("my_data.json", doc.file_name);
+    let data: serde_json::Value = serde_json::from_str(&doc.content).unwrap();
+    assert_eq!(GROUP.0, data["chats"][0]["id"]);
+    assert_eq!("Coffee beans", data["chats"][0]["orders"][0]["name"]);
+    assert!(!data["chats"][0]["order_messages"].as_array().unwrap().is_empty());
+
+    h.send_text(OWNER, owner_cid, "/forget_me").await.unwrap();
+    let msg = h.last_with_button(owner_cid, "fm yes");
+    h.click(OWNER, &msg, "fm yes").await.unwrap();
+    assert!(h.api.edited().pop().unwrap().text.starts_with("Done"));
+
+    // The courier still has the order, but not who it was for
+    h.send_text(COURIER, courier_cid, "/my_data").await.unwrap();
+    let doc = h.api.documents().pop().unwrap();
+    assert_eq!(courier_cid.0, doc.chat_id);
+    let data: serde_json::Value = serde_json::from_str(&doc.content).unwrap();
+    let order = &data["chats"][0]["assignments"][0];
+    assert_eq!("Coffee beans", order["name"]);
+    assert_eq!("Deleted user", order["customer"]["first_name"]);
+
+    // Talking to the bot again makes it remember the profile, but nothing
+    // from before
+    h.send_text(OWNER, owner_cid, "/my_data").await.unwrap();
+    let doc = h.api.documents().pop().unwrap();
+    let data: serde_json::Value = serde_json::from_str(&doc.content).unwrap();
+    assert_eq!(OWNER.id, data["profile"]["id"]);
+    assert!(data["chats"].as_array().unwrap().is_empty());
+}
+
+#[tokio::test]
+async fn test_forget_me_covers_reports() {
+    let mut h = setup().await;
+    let admin_cid = ADMIN.private_chat();
+    let oid = create_order(&mut h).await;
+    let publish = format!("oa publish {oid}");
+    let msg = h.last_with_button(OWNER.private_chat(), &publish);
+    h.click(OWNER, &msg, &publish).await.unwrap();
+    let public = h.last_sent_to(GROUP);
+    report(&mut h, ADMIN, &public, oid, "spam").await;
+
+    h.send_text(ADMIN, admin_cid, "/my_data").await.unwrap();
+    let doc = h.api.documents().pop().unwrap();
+    let data: serde_json::Value = serde_json::from_str(&doc.content).unwrap();
+    let report = &data["chats"][0]["reports"][0];
+    assert_eq!(oid, report["order_id"]);
+    assert_eq!("Spam", report["report"]["reason"]);
+
+    h.send_text(ADMIN, admin_cid, "/forget_me").await.unwrap();
+    let msg = h.last_with_button(admin_cid, "fm yes");
+    h.click(ADMIN, &msg, "fm yes").await.unwrap();
+    let order = h.db.clone().get_order(GROUP, crate::order::OrderId(oid))
+        .await.unwrap().unwrap();
+    assert_eq!(crate::privacy::ANONYMOUS_USER_ID, order.reports[0].by);
+}
+
+#[tokio::test]
+async fn test_purge_users_not_seen() {
+    let mut h = setup().await;
+    create_order(&mut h).await;
+    h.send_text(COURIER, GROUP, "/digest subscribe").await.unwrap();
+
+    let policy = crate::collection::CollectionPolicy::default();
+    let purged = crate::collection::purge(h.db.clone(), &policy).await.unwrap();
+    assert_eq!(0, purged);
+
+    // Only the admin has no order and no digest to keep them for, as long
+    // as they keep the default settings
+    h.clock.advance(Duration::days(91));
+    let admin = UserId(ADMIN.id);
+    let settings = crate::settings::UserSettings {
+        hide_in_stats: true,
+        ..Default::default()
+    };
+    h.db.set_user_settings(admin, &settings).await.unwrap();
+    let purged = crate::collection::purge(h.db.clone(), &policy).await.unwrap();
+    assert_eq!(0, purged);
+
+    h.db.set_user_settings(admin, &Default::default()).await.unwrap();
+    let purged = crate::collection::purge(h.db.clone(), &policy).await.unwrap();
+    assert_eq!(1, purged);
+    assert!(h.db.get_user(admin).await.unwrap().is_none());
+    // They're still a member, so they can use the bot in the group
+    assert!(!h.db.user_public_chats(admin).await.unwrap().is_empty());
+    assert!(h.db.get_user(UserId(OWNER.id)).await.unwrap().is_some());
+    assert!(h.db.get_user(UserId(COURIER.id)).await.unwrap().is_some());
+}
+
+#[tokio::test]
+async fn test_completed_orders_are_archived() {
+    let mut h = setup().await;
+    let owner_cid = OWNER.private_chat();
+    let courier_cid = COURIER.private_chat();
+
+    let oid = create_assigned_order(&mut h).await;
+    let confirm = format!("oa confirm_delivery {oid}");
+    let msg = h.last_with_button(owner_cid, &confirm);
+    h.click(OWNER, &msg, &confirm).await.unwrap();
+    let canceled = create_assigned_order(&mut h).await;
+    for data in [format!("oa cancel {canceled}"),
+                 format!("oc plans_changed {canceled}")] {
+        let msg = h.last_with_button(owner_cid, &data);
+        h.click(OWNER, &msg, &data).await.unwrap();
+    }
+
+    let after = Duration::days(30);
+    assert_eq!(0, crate::archive::archive_due(h.db.clone(), after).await.unwrap());
+    h.clock.advance(Duration::days(31));
+    assert_eq!(2, crate::archive::archive_due(h.db.clone(), after).await.unwrap());
+    assert!(h.db.orders_by_status(GROUP, Status::DeliveryConfirmed).await
+            .unwrap().is_empty());
+    assert_eq!(2, h.db.archived_orders(GROUP).await.unwrap().len());
+
+    // Publishing the canceled one again brings it back
+    let publish = format!("oa publish {canceled}");
+    let msg = h.last_with_button(owner_cid, &publish);
+    h.click(OWNER, &msg, &publish).await.unwrap();
+    let order = h.db.get_order(GROUP, crate::order::OrderId(canceled))
+        .await.unwrap().unwrap();
+    assert_eq!(Status::Published, order.status());
+    assert_eq!(1, h.db.archived_orders(GROUP).await.unwrap().len());
+
+    // Deleting a delivered order archives it right away
+    let deleted = create_assigned_order(&mut h).await;
+    let confirm = format!("oa confirm_delivery {deleted}");
+    let msg = h.last_with_button(owner_cid, &confirm);
+    h.click(OWNER, &msg, &confirm).await.unwrap();
+    let delete = format!("oa delete {deleted}");
+    let msg = h.last_with_button(owner_cid, &delete);
+    h.click(OWNER, &msg, &delete).await.unwrap();
+    assert!(h.last_sent_to(owner_cid).text
+            .starts_with("Moved the order to the archive"));
+    assert_eq!(2, h.db.archived_orders(GROUP).await.unwrap().len());
+
+    // Archived orders are still history of the courier
+    h.send_text(COURIER, courier_cid, "/my_data").await.unwrap();
+    let doc = h.api.documents().pop().unwrap();
+    let data: serde_json::Value = serde_json::from_str(&doc.content).unwrap();
+    assert_eq!(2, data["chats"][0]["assignments"].as_array().unwrap().len());
+
+    h.send_text(OWNER, GROUP, &format!("/archive delete {oid}")).await.unwrap();
+    assert_eq!("Only admins of the chat can delete archived orders",
+               h.last_sent_to(GROUP).text);
+    h.send_text(ADMIN, GROUP, &format!("/archive delete {oid}")).await.unwrap();
+    assert_eq!(format!("Archived order {oid} is deleted"),
+               h.last_sent_to(GROUP).text);
+    assert_eq!(1, h.db.archived_orders(GROUP).await.unwrap().len());
+
+    // Deleting a draft archives it as a canceled order
+    let draft = create_order(&mut h).await;
+    let delete = format!("oa delete {draft}");
+    let msg = h.last_with_button(owner_cid, &delete);
+    h.click(OWNER, &msg, &delete).await.unwrap();
+    assert!(h.db.get_order(GROUP, crate::order::OrderId(draft)).await.unwrap()
+            .is_none());
+    h.send_text(ADMIN, GROUP, "/archive").await.unwrap();
+    assert!(h.last_sent_to(GROUP).text.starts_with(
+        "Archive of the chat: 2 orders, 1 of them delivered and 1 canceled"));
+}
+
+/// Publishes a new order, lets `courier` take it 30 minutes later and
+/// confirms its delivery 2 hours after that
+async fn deliver_order(h: &mut Harness, courier: TestUser) -> u64 {
+    let owner_cid = OWNER.private_chat();
+    let oid = create_order(h).await;
+    let publish = format!("oa publish {oid}");
+    let msg = h.last_with_button(owner_cid, &publish);
+    h.click(OWNER, &msg, &publish).await.unwrap();
+
+    h.clock.advance(Duration::minutes(30));
+    let assign = format!("oa assign_to_me {oid}");
+    let public = h.last_with_button(GROUP, &assign);
+    h.click(courier, &public, &assign).await.unwrap();
+
+    h.clock.advance(Duration::hours(2));
+    let confirm = format!("oa confirm_delivery {oid}");
+    let msg = h.last_with_button(owner_cid, &confirm);
+    h.click(OWNER, &msg, &confirm).await.unwrap();
+    oid
+}
+
+#[tokio::test]
+async fn test_stats() {
+    let mut h = setup().await;
+    deliver_order(&mut h, COURIER).await;
+    deliver_order(&mut h, COURIER).await;
+    deliver_order(&mut h, ADMIN).await;
+    create_order(&mut h).await;
+
+    h.send_text(OWNER, GROUP, "/stats").await.unwrap();
+    let stats = h.last_sent_to(GROUP).text;
+    assert!(stats.contains("Created: 4\nPublished: 3\nDelivered: 3\n"));
+    assert!(stats.contains("Median time to find a courier: 30 minutes"));
+    assert!(stats.contains("Median time to deliver: 2 hours"));
+    assert!(stats.contains("Items delivered worth: 15000 AMD"));
+    assert!(stats.contains("1. <a href=\"tg://user?id=2\">User2  @user2</a> — 2\n\
+2. <a href=\"tg://user?id=3\">User3  @user3</a> — 1"));
+
+    // The courier doesn't want to be on the leaderboard
+    let courier_cid = COURIER.private_chat();
+    h.send_text(COURIER, courier_cid, "/menu").await.unwrap();
+    let menu = h.last_with_button(courier_cid, "settings");
+    h.click(COURIER, &menu, "settings").await.unwrap();
+    let settings = h.last_with_button(courier_cid, "st stats");
+    h.click(COURIER, &settings, "st stats").await.unwrap();
+    assert_eq!(Some("🏆 Show me in chat stats: off"),
+               h.api.edited().pop().unwrap().button_text("st stats").as_deref());
+
+    h.clock.advance(Duration::days(2));
+    h.send_text(OWNER, GROUP, "/stats all").await.unwrap();
+    let stats = h.last_sent_to(GROUP).text;
+    assert!(stats.contains("Delivered: 3\n"));
+    assert!(stats.contains("1. <a href=\"tg://user?id=3\">User3  @user3</a> — 1"));
+    assert!(!stats.contains("User2"));
+
+    h.send_text(OWNER, GROUP, "/stats day").await.unwrap();
+    assert!(h.last_sent_to(GROUP).text.contains("Delivered: 0\n"));
+}
+
+#[tokio::test]
+async fn test_profile() {
+    let mut h = setup().await;
+    let owner_cid = OWNER.private_chat();
+    let courier_cid = COURIER.private_chat();
+    deliver_order(&mut h, COURIER).await;
+    create_assigned_order(&mut h).await;
+
+    h.send_text(OWNER, owner_cid, "/me").await.unwrap();
+    let me = h.last_sent_to(owner_cid);
+    assert!(me.text.contains("Group-100, member"));
+    assert!(me.text.contains(
+        "Orders created: 0 open, 1 being delivered, 1 completed"));
+    assert!(me.buttons.contains(&"show_my_orders".to_string()));
+    assert!(me.buttons.contains(&"settings".to_string()));
+
+    h.send_text(ADMIN, ADMIN.private_chat(), "/me").await.unwrap();
+    assert!(h.last_sent_to(ADMIN.private_chat()).text
+            .contains("Group-100, admin"));
+
+    // Orders link to their owner's public profile
+    let url = format!("https://t.me/{}?start=profile_{}",
+                      super::fake_api::BOT_USERNAME, OWNER.id);
+    assert!(h.api.sent().iter().any(|m| m.chat_id == GROUP.0
+        && m.text.starts_with("New order is published")
+        && m.text.contains(&format!("(<a href=\"{url}\">profile</a>)"))));
+
+    h.send_text(COURIER, courier_cid, &format!("/start profile_{}", OWNER.id))
+        .await.unwrap();
+    let profile = h.last_sent_to(courier_cid);
+    assert!(profile.text.contains("User1"));
+    assert!(profile.text.contains("1 being delivered, 1 completed"));
+    assert!(!profile.text.contains("Group-100"));
+    assert!(profile.buttons.is_empty());
+
+    // Someone who isn't in the group can't even tell the owner exists
+    let stranger = TestUser { id: 5 };
+    h.send_text(stranger, stranger.private_chat(),
+                &format!("/start profile_{}", OWNER.id)).await.unwrap();
+    let profile = h.last_sent_to(stranger.private_chat());
+    assert_eq!("I don't know this user anymore", profile.text);
+}
+
+/// Saves the order `oid` as a template from the list of the owner's orders
+async fn save_template(h: &mut Harness, oid: u64, name: &str) {
+    let owner_cid = OWNER.private_chat();
+    h.send_text(OWNER, owner_cid, "/me").await.unwrap();
+    let me = h.last_sent_to(owner_cid);
+    h.click(OWNER, &me, "show_my_orders").await.unwrap();
+
+    let save = format!("tp save {oid}");
+    let order = h.last_with_button(owner_cid, &save);
+    h.click(OWNER, &order, &save).await.unwrap();
+    assert!(h.last_sent_to(owner_cid).text
+            .starts_with("How do you want to call this template?"));
+    h.send_text(OWNER, owner_cid, name).await.unwrap();
+    assert!(h.api.sent().iter().any(|m| m.chat_id == owner_cid.0
+        && m.text.starts_with(&format!("Template \"{name}\" is saved"))));
+}
+
+#[tokio::test]
+async fn test_templates_and_duplicates() {
+    let mut h = setup().await;
+    let owner_cid = OWNER.private_chat();
+    let oid = create_order(&mut h).await;
+
+    save_template(&mut h, oid, "Coffee").await;
+
+    // Duplicate it with another price
+    let dup = format!("tp dup {oid}");
+    let order = h.last_with_button(owner_cid, &dup);
+    h.click(OWNER, &order, &dup).await.unwrap();
+    let draft = h.last_sent_to(owner_cid);
+    assert!(draft.text.starts_with("Here's your new order"));
+    assert!(draft.text.contains("Item cost: 5000 AMD\nReward: 500 AMD"));
+    h.click(OWNER, &draft, "nd price").await.unwrap();
+    assert!(h.last_sent_to(owner_cid).text.starts_with("How much is it"));
+    h.send_text(OWNER, owner_cid, "6000").await.unwrap();
+    let draft = h.last_sent_to(owner_cid);
+    assert!(draft.text.contains("Item cost: 6000 AMD\nReward: 500 AMD"));
+    h.click(OWNER, &draft, "nd create").await.unwrap();
+    let created = h.api.sent().into_iter().rev()
+        .find(|m| m.text.starts_with("New Order is created!"))
+        .unwrap();
+    assert!(created.text.contains("Coffee beans"));
+    assert!(created.text.contains("From the shop near the bus stop"));
+    assert!(created.text.contains("6000 AMD"));
+    assert!(!created.buttons.contains(&format!("oa publish {oid}")));
+
+    // New orders start from the template
+    h.send_text(OWNER, owner_cid, "/new_order").await.unwrap();
+    let start = h.last_sent_to(owner_cid);
+    assert!(start.text.starts_with("What do you want? Write it"));
+    let coffee = crate::template::key("Coffee");
+    h.click(OWNER, &start, &format!("tp use {coffee}")).await.unwrap();
+    let draft = h.last_sent_to(owner_cid);
+    assert!(draft.text.contains("Item cost: 5000 AMD"));
+    h.click(OWNER, &draft, "nd cancel").await.unwrap();
+    assert!(h.api.sent().iter().any(|m| m.chat_id == owner_cid.0
+        && m.text == "Okay, I won't create it"));
+
+    // And templates can be deleted, buttons of an old list still delete
+    // the template they show
+    save_template(&mut h, oid, "Beans").await;
+    h.send_text(OWNER, owner_cid, "/templates").await.unwrap();
+    let templates = h.last_sent_to(owner_cid);
+    let beans = crate::template::key("Beans");
+    assert_eq!(vec![format!("tp use {beans}"), format!("tp del {beans}"),
+                    format!("tp use {coffee}"), format!("tp del {coffee}")],
+               templates.buttons);
+    h.click(OWNER, &templates, &format!("tp del {beans}")).await.unwrap();
+    h.click(OWNER, &templates, &format!("tp del {coffee}")).await.unwrap();
+    assert!(h.last_sent_to(owner_cid).text
+            .starts_with("You have no templates yet"));
+}
+
+#[tokio::test]
+async fn test_recurring_orders() {
+    let mut h = setup().await;
+    let owner_cid = OWNER.private_chat();
+    let oid = create_order(&mut h).await;
+    save_template(&mut h, oid, "Coffee").await;
+    let orders = |h: &Harness| {
+        let mut db = h.db.clone();
+        async move {
+            db.orders_submitted_by_user(GROUP, UserId(OWNER.id)).await.unwrap()
+        }
+    };
+
+    // It's Friday 16:00 in Armenia
+    h.send_text(OWNER, owner_cid, "/repeat weekly fri 10:00 publish Coffee")
+        .await.unwrap();
+    assert_eq!("\"Coffee\" will make an order every week on Friday at 10:00, \
+published right away, next on Fri 8 Jul", h.last_sent_to(owner_cid).text);
+    crate::ui::recurring::create_due(h.bot.clone(), h.db.clone()).await.unwrap();
+    assert_eq!(1, orders(&h).await.len());
+
+    h.clock.set(Utc.ymd(2022, 7, 8).and_hms(6, 0, 0));
+    crate::ui::recurring::create_due(h.bot.clone(), h.db.clone()).await.unwrap();
+    let orders_now = orders(&h).await;
+    assert_eq!(2, orders_now.len());
+    let new = orders_now.iter().find(|o| o.id != Some(crate::order::OrderId(oid)))
+        .unwrap();
+    assert_eq!(Status::Published, new.status());
+    assert_eq!(Some("Coffee"), new.series.as_deref());
+    let public = h.last_sent_to(GROUP);
+    assert!(public.text.starts_with("New order is published"));
+    assert!(public.text.contains("🔁 Repeats from \"Coffee\""));
+    let notice = h.last_sent_to(owner_cid);
+    assert!(notice.text.starts_with("🔁 A new order from \"Coffee\" is published"));
+
+    // Skip the next one
+    let new_oid = new.id.unwrap();
+    h.click(OWNER, &notice, &format!("rp skip {new_oid}")).await.unwrap();
+    assert!(h.last_sent_to(owner_cid).text.ends_with("next on Fri 22 Jul"));
+    h.clock.set(Utc.ymd(2022, 7, 15).and_hms(6, 0, 0));
+    crate::ui::recurring::create_due(h.bot.clone(), h.db.clone()).await.unwrap();
+    assert_eq!(2, orders(&h).await.len());
+
+    // And stop the series
+    h.click(OWNER, &notice, &format!("rp stop {new_oid}")).await.unwrap();
+    assert_eq!("\"Coffee\" won't make orders anymore",
+               h.last_sent_to(owner_cid).text);
+    h.clock.set(Utc.ymd(2022, 7, 22).and_hms(6, 0, 0));
+    crate::ui::recurring::create_due(h.bot.clone(), h.db.clone()).await.unwrap();
+    assert_eq!(2, orders(&h).await.len());
+}
+
+#[tokio::test]
+async fn test_shopping_list() {
+    let mut h = setup().await;
+    let owner_cid = OWNER.private_chat();
+    let courier_cid = COURIER.private_chat();
+
+    h.send_text(OWNER, owner_cid, "/new_order").await.unwrap();
+    h.send_text(OWNER, owner_cid, "Groceries").await.unwrap();
+    h.send_text(OWNER, owner_cid, "2 x Milk 450 @ SAS\nBread 300")
+        .await.unwrap();
+    h.send_text(OWNER, owner_cid, "500").await.unwrap();
+    h.send_text(OWNER, owner_cid, "Fresh, please").await.unwrap();
+    let created = h.api.sent().into_iter().rev()
+        .find(|m| m.text.starts_with("New Order is created!"))
+        .expect("order is not created");
+    assert!(created.text.contains("▫️ 2 × Milk: 450 AMD each at SAS\n▫️ Bread: 300 AMD"));
+    assert!(created.text.contains("Item cost: 1200 AMD"));
+    let publish = created.buttons.iter()
+        .find(|b| b.starts_with("oa publish "))
+        .cloned()
+        .unwrap();
+    let oid: u64 = publish.rsplit(' ').next().unwrap().parse().unwrap();
+    h.click(OWNER, &created, &publish).await.unwrap();
+
+    // Only the courier gets the list
+    let list = format!("it list {oid}");
+    assert!(!h.last_sent_to(GROUP).buttons.contains(&list));
+    let assign = format!("oa assign_to_me {oid}");
+    let public = h.last_with_button(GROUP, &assign);
+    h.click(COURIER, &public, &assign).await.unwrap();
+    let assigned = h.last_with_button(courier_cid, &list);
+    h.click(COURIER, &assigned, &list).await.unwrap();
+    let shopping = h.last_sent_to(courier_cid);
+    assert!(shopping.text.starts_with("🛒 Shopping list of <b>Groceries</b>"));
+    assert_eq!(vec![format!("it tick {oid} 0"), format!("it paid {oid} 0"),
+                    format!("it tick {oid} 1"), format!("it paid {oid} 1")],
+               shopping.buttons);
+
+    // Tick the milk off and pay for the bread
+    h.click(COURIER, &shopping, &format!("it tick {oid} 0")).await.unwrap();
+    let edited = h.api.edited().into_iter().rev()
+        .find(|m| m.chat_id == courier_cid.0)
+        .unwrap();
+    assert!(edited.text.contains("✅ 2 × Milk"));
+    h.click(COURIER, &shopping, &format!("it paid {oid} 1")).await.unwrap();
+    assert_eq!("How much have you paid for Bread, in Armenian Drams?",
+               h.last_sent_to(courier_cid).text);
+    h.send_text(COURIER, courier_cid, "350").await.unwrap();
+    assert!(h.last_sent_to(courier_cid).text
+            .contains("✅ Bread: 300 AMD, paid 350 AMD"));
+
+    let order = h.db.clone().get_order(GROUP, crate::order::OrderId(oid))
+        .await.unwrap().unwrap();
+    assert!(order.items.iter().all(|i| i.bought));
+    assert_eq!(Some(350), crate::order::item::paid(&order.items));
+
+    // The owner can't tick items
+    h.click(OWNER, &shopping, &format!("it tick {oid} 1")).await.unwrap();
+    let order = h.db.clone().get_order(GROUP, crate::order::OrderId(oid))
+        .await.unwrap().unwrap();
+    assert!(order.items[1].bought);
+}
+
+#[tokio::test]
+async fn test_balance_ledger() {
+    let mut h = setup().await;
+    let owner_cid = OWNER.private_chat();
+    let courier_cid = COURIER.private_chat();
+
+    h.send_text(OWNER, owner_cid, "/balance").await.unwrap();
+    assert_eq!("You don't owe anybody and nobody owes you",
+               h.last_sent_to(owner_cid).text);
+
+    // A confirmed delivery becomes a debt of the owner
+    let oid = create_assigned_order(&mut h).await;
+    mark_as_delivered(&mut h, oid).await;
+    let confirm = format!("oa confirm_delivery {oid}");
+    let msg = h.last_with_button(owner_cid, &confirm);
+    h.click(OWNER, &msg, &confirm).await.unwrap();
+
+    h.send_text(COURIER, courier_cid, "/balance").await.unwrap();
+    assert!(h.last_sent_to(courier_cid).text.contains("owes you 5500 AMD"));
+    h.send_text(OWNER, owner_cid, "/balance").await.unwrap();
+    let balance = h.last_sent_to(owner_cid);
+    assert!(balance.text.contains("You owe"));
+    assert!(balance.text.contains("5500 AMD"));
+    let paid = format!("lg paid {} 0", GROUP.0);
+    assert_eq!(Some("✅ Repaid: Coffee beans, 5500 AMD".to_string()),
+               balance.button_text(&paid));
+
+    // The owner repays, the courier says they haven't got it
+    h.click(OWNER, &balance, &paid).await.unwrap();
+    let edited = h.api.edited().into_iter().rev()
+        .find(|m| m.chat_id == owner_cid.0)
+        .unwrap();
+    assert_eq!("You don't owe anybody and nobody owes you", edited.text);
+    let undo = format!("lg undo {} 1", GROUP.0);
+    let msg = h.last_with_button(courier_cid, &undo);
+    assert!(msg.text.contains("has marked the debt for <b>Coffee beans</b>, \
+5500 AMD as repaid"));
+    h.click(COURIER, &msg, &undo).await.unwrap();
+    assert!(h.last_sent_to(owner_cid).text.contains("isn't repaid yet"));
+
+    // Nothing is removed, the debt is open again
+    let entries = h.db.clone().ledger(GROUP).await.unwrap();
+    assert_eq!(3, entries.len());
+    assert_eq!(std::collections::BTreeMap::from([(UserId(OWNER.id), 5500)]),
+               crate::ledger::balances(&entries, UserId(COURIER.id)));
+
+    // Only the sides of the debt can repay it
+    h.click(ADMIN, &balance, &paid).await.unwrap();
+    assert_eq!(3, h.db.clone().ledger(GROUP).await.unwrap().len());
+
+    // Two clicks at once repay it only once
+    let now = h.db.now();
+    let repay = move |entries: &[crate::ledger::Entry]|
+        crate::ledger::repay(entries, 0, UserId(OWNER.id), now);
+    let (mut first, mut second) = (h.db.clone(), h.db.clone());
+    let (first, second) = tokio::join!(
+        first.add_checked_ledger_entry(GROUP, repay),
+        second.add_checked_ledger_entry(GROUP, repay));
+    assert_eq!(1, [first.unwrap(), second.unwrap()].iter()
+               .filter(|added| added.is_ok()).count());
+    assert_eq!(4, h.db.clone().ledger(GROUP).await.unwrap().len());
+
+    // A wrong debt is canceled by the courier when the owner asks
+    let oid = create_assigned_order(&mut h).await;
+    mark_as_delivered(&mut h, oid).await;
+    let confirm = format!("oa confirm_delivery {oid}");
+    let msg = h.last_with_button(owner_cid, &confirm);
+    h.click(OWNER, &msg, &confirm).await.unwrap();
+    h.send_text(OWNER, owner_cid, "/balance").await.unwrap();
+    let balance = h.last_sent_to(owner_cid);
+    let wrong = format!("lg wrong {} 4", GROUP.0);
+    assert_eq!(Some("❌ Wrong".to_string()), balance.button_text(&wrong));
+    h.click(OWNER, &balance, &wrong).await.unwrap();
+    assert_eq!(5, h.db.clone().ledger(GROUP).await.unwrap().len());
+    let cancel = format!("lg cancel {} 4", GROUP.0);
+    let msg = h.last_with_button(courier_cid, &cancel);
+    assert!(msg.text.contains("says the debt for <b>Coffee beans</b>, \
+5500 AMD is wrong"));
+    h.click(COURIER, &msg, &cancel).await.unwrap();
+    assert!(h.last_sent_to(owner_cid).text.contains("has canceled the debt"));
+    let entries = h.db.clone().ledger(GROUP).await.unwrap();
+    assert_eq!(6, entries.len());
+    assert!(crate::ledger::balances(&entries, UserId(OWNER.id)).is_empty());
+    assert!(crate::ledger::open_debts(&entries, UserId(OWNER.id)).is_empty());
+}
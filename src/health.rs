@@ -0,0 +1,314 @@
+//! Health and readiness checks for whatever supervises the bot
+//!
+//! Serves two endpoints over plain HTTP:
+//!   /health  always 200 while the process is alive
+//!   /ready   200 if the Db answers, the dialogue storage is connected and
+//!            Telegram `getUpdates` succeeded recently, 503 otherwise
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicI64, Ordering};
+use std::time::Duration;
+
+use futures::stream::{self, Stream, StreamExt};
+use serde::Serialize;
+use teloxide::{
+    prelude::*,
+    payloads::GetUpdates,
+    requests::HasPayload,
+    types::AllowedUpdate,
+    dispatching::{
+        stop_token::{AsyncStopToken, AsyncStopFlag},
+        update_listeners::{StatefulListener, UpdateListener},
+    },
+    RequestError,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::OnceCell;
+
+use crate::error::Error;
+use crate::{Db, Offset};
+
+/// Address the health server listens on, unless overridden by `HEALTH_ADDR`
+const DEFAULT_HEALTH_ADDR: &str = "127.0.0.1:8080";
+
+/// Long polling timeout for `getUpdates`
+const POLLING_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// We're not ready if `getUpdates` didn't succeed for this long.
+/// Must be comfortably longer than `POLLING_TIMEOUT`.
+const MAX_POLL_AGE_SECS: i64 = 60;
+
+/// The Db isn't ready if it doesn't answer a ping this fast
+const DB_PING_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Shared state the health server reports on
+///
+/// Cheap to clone, all clones see the same state
+#[derive(Clone, Default)]
+pub struct Health {
+    inner: Arc<Inner>,
+}
+
+#[derive(Default)]
+struct Inner {
+    /// Set once `Db::new` succeeded
+    db: OnceCell<Db>,
+
+    /// Set once the dialogue storage is connected
+    storage_ready: AtomicBool,
+
+    /// Unix timestamp of the last successful `getUpdates`, 0 if never
+    last_poll_ok: AtomicI64,
+}
+
+#[derive(Debug, Serialize)]
+struct Readiness {
+    ready: bool,
+    db: bool,
+    storage: bool,
+    /// Seconds since the last successful `getUpdates`, None if never
+    last_poll_secs_ago: Option<i64>,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn db_connected(&self, db: Db) {
+        if self.inner.db.set(db).is_err() {
+            log::warn!("health: db is already set");
+        }
+    }
+
+    pub fn storage_connected(&self) {
+        self.inner.storage_ready.store(true, Ordering::Relaxed);
+    }
+
+    fn poll_succeeded(&self) {
+        let now = Offset::now().timestamp();
+        self.inner.last_poll_ok.store(now, Ordering::Relaxed);
+    }
+
+    async fn readiness(&self) -> Readiness {
+        let db = match self.inner.db.get() {
+            Some(db) => {
+                let mut db = db.clone();
+                match tokio::time::timeout(DB_PING_TIMEOUT, db.ping()).await {
+                    Ok(Ok(())) => true,
+                    Ok(Err(e)) => {
+                        log::warn!("health: db ping failed: {e:?}");
+                        false
+                    },
+                    Err(_) => {
+                        log::warn!("health: db ping timed out");
+                        false
+                    },
+                }
+            },
+            None => false,
+        };
+        let storage = self.inner.storage_ready.load(Ordering::Relaxed);
+
+        let last_poll_ok = self.inner.last_poll_ok.load(Ordering::Relaxed);
+        let last_poll_secs_ago = if last_poll_ok == 0 {
+            None
+        } else {
+            Some(Offset::now().timestamp() - last_poll_ok)
+        };
+        let polling = matches!(last_poll_secs_ago,
+                               Some(ago) if ago <= MAX_POLL_AGE_SECS);
+
+        Readiness {
+            ready: db && storage && polling,
+            db,
+            storage,
+            last_poll_secs_ago,
+        }
+    }
+}
+
+/// Starts the health server in the background
+pub async fn spawn_server(health: Health) -> Result<(), Error> {
+    let addr = std::env::var("HEALTH_ADDR")
+        .unwrap_or_else(|_| DEFAULT_HEALTH_ADDR.to_string());
+    let listener = TcpListener::bind(&addr).await?;
+    log::info!("Health server is listening on {addr}");
+
+    tokio::spawn(async move {
+        loop {
+            let (stream, _peer) = match listener.accept().await {
+                Ok(conn) => conn,
+                Err(e) => {
+                    log::warn!("health: accept failed: {e:?}");
+                    continue;
+                }
+            };
+            let health = health.clone();
+            tokio::spawn(async move {
+                if let Err(e) = handle_conn(health, stream).await {
+                    log::debug!("health: connection error: {e:?}");
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+/// Handles a single request, we only care about the request line
+async fn handle_conn(health: Health, mut stream: TcpStream) -> Result<(), Error> {
+    let mut buf = [0u8; 1024];
+    let n = stream.read(&mut buf).await?;
+    let req = String::from_utf8_lossy(&buf[..n]);
+    let mut request_line = req.lines().next().unwrap_or("").split(' ');
+    let method = request_line.next().unwrap_or("");
+    let path = request_line.next().unwrap_or("");
+
+    let (status, body) = match (method, path) {
+        ("GET", "/health") => ("200 OK", "{\"alive\":true}".to_string()),
+        ("GET", "/ready") => {
+            let readiness = health.readiness().await;
+            let status = if readiness.ready {
+                "200 OK"
+            } else {
+                "503 Service Unavailable"
+            };
+            (status, serde_json::to_string(&readiness)?)
+        },
+        _ => ("404 Not Found", "{}".to_string()),
+    };
+
+    let resp = format!("HTTP/1.1 {status}\r
+Content-Type: application/json\r
+Content-Length: {}\r
+Connection: close\r
+\r
+{body}", body.len());
+    stream.write_all(resp.as_bytes()).await?;
+    stream.shutdown().await?;
+    Ok(())
+}
+
+struct PollingState {
+    bot: AutoSend<Bot>,
+    health: Health,
+    offset: i32,
+    allowed_updates: Option<Vec<AllowedUpdate>>,
+    flag: AsyncStopFlag,
+    token: AsyncStopToken,
+}
+
+/// Long polling update listener that records successful `getUpdates`
+/// calls in `health`
+///
+/// Works like teloxide's `polling_default`, which doesn't let us see
+/// requests that returned no updates.
+pub fn polling(
+    bot: AutoSend<Bot>,
+    health: Health,
+) -> impl UpdateListener<RequestError> {
+    let (token, flag) = AsyncStopToken::new_pair();
+    let state = PollingState {
+        bot,
+        health,
+        offset: 0,
+        allowed_updates: None,
+        flag,
+        token,
+    };
+
+    fn stream(
+        st: &mut PollingState,
+    ) -> impl Stream<Item = Result<Update, RequestError>> + Send + '_ {
+        stream::unfold(st, |st| async move {
+            if st.flag.is_stopped() {
+                // Acknowledge updates we've already handled
+                let mut req = st.bot.get_updates();
+                *req.payload_mut() = GetUpdates {
+                    offset: Some(st.offset),
+                    timeout: Some(0),
+                    limit: Some(1),
+                    allowed_updates: st.allowed_updates.take(),
+                };
+                if let Err(e) = req.await {
+                    log::warn!("polling: could not acknowledge updates: {e:?}");
+                }
+                return None;
+            }
+
+            let mut req = st.bot.get_updates();
+            *req.payload_mut() = GetUpdates {
+                offset: Some(st.offset),
+                timeout: Some(POLLING_TIMEOUT.as_secs() as u32),
+                limit: None,
+                allowed_updates: st.allowed_updates.clone(),
+            };
+
+            match req.await {
+                Ok(updates) => {
+                    st.health.poll_succeeded();
+                    if let Some(upd) = updates.last() {
+                        st.offset = upd.id + 1;
+                    }
+                    let updates: Vec<_> =
+                        updates.into_iter().map(Ok).collect();
+                    Some((stream::iter(updates), st))
+                },
+                Err(e) => Some((stream::iter(vec![Err(e)]), st)),
+            }
+        })
+        .flatten()
+    }
+
+    StatefulListener::new_with_hints(
+        state,
+        stream,
+        |st: &mut PollingState| st.token.clone(),
+        Some(|st: &mut PollingState,
+              allowed: &mut dyn Iterator<Item = AllowedUpdate>| {
+            st.allowed_updates = Some(allowed.collect());
+        }),
+        Some(|_: &PollingState| Some(POLLING_TIMEOUT)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Sends `path` to a health server of `health`, returns the response
+    async fn get(health: &Health, path: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await.unwrap();
+        let (server, _peer) = listener.accept().await.unwrap();
+        let handled = tokio::spawn(handle_conn(health.clone(), server));
+        client.write_all(format!("GET {path} HTTP/1.1\r\n\r\n").as_bytes())
+            .await.unwrap();
+        let mut resp = String::new();
+        client.read_to_string(&mut resp).await.unwrap();
+        handled.await.unwrap().unwrap();
+        resp
+    }
+
+    #[tokio::test]
+    async fn test_endpoints() {
+        let health = Health::new();
+        assert!(get(&health, "/health").await.starts_with("HTTP/1.1 200 OK"));
+        assert!(get(&health, "/nope").await.starts_with("HTTP/1.1 404"));
+
+        // Nothing is connected yet
+        let resp = get(&health, "/ready").await;
+        assert!(resp.starts_with("HTTP/1.1 503"));
+        assert!(resp.ends_with("{\"ready\":false,\"db\":false,\"storage\":false,\
+\"last_poll_secs_ago\":null}"));
+
+        health.storage_connected();
+        health.poll_succeeded();
+        let resp = get(&health, "/ready").await;
+        assert!(resp.starts_with("HTTP/1.1 503"));
+        assert!(resp.contains("\"storage\":true"));
+        assert!(!resp.contains("\"last_poll_secs_ago\":null"));
+    }
+}
@@ -0,0 +1,301 @@
+//! Who owes whom in a public chat
+//!
+//! The ledger of a chat is a list of entries that are never changed or
+//! removed. Each entry is double-entry: it debits one member and credits
+//! another one with the same amount, so balances of a chat always add up
+//! to zero. An entry is referred to by its position in the list.
+//!
+//! A confirmed delivery with a settled amount records a debt of the owner
+//! to the courier, together with the confirmation. A repayment records the
+//! opposite entry, and a mistake is corrected by reversing the entry rather
+//! than removing it: either side reverses a repayment, the courier reverses
+//! a debt.
+
+use std::collections::BTreeMap;
+
+use serde::{Serialize, Deserialize};
+use teloxide::types::UserId;
+
+use crate::DateTime;
+use crate::order::{Order, OrderId, Status};
+use crate::privacy;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum EntryKind {
+    /// The owner owes the courier for a delivery
+    Delivery,
+    /// A debt is repaid
+    Repayment,
+    /// Cancels an entry that was recorded by mistake
+    Reversal,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Entry {
+    pub kind: EntryKind,
+    pub at: DateTime,
+    /// Who has recorded it
+    pub by: UserId,
+    /// Owes `amount_in_drams` more to `credit` after this entry
+    pub debit: UserId,
+    pub credit: UserId,
+    pub amount_in_drams: u64,
+    /// Order the debt is for
+    pub order_id: Option<OrderId>,
+    /// What the order is, so the ledger makes sense without it
+    pub note: String,
+    /// Position of the entry this one repays or reverses
+    pub refers_to: Option<usize>,
+}
+
+impl Entry {
+    /// Debt of the owner to the courier for a delivered order,
+    /// None if there's nothing to owe
+    pub fn delivery(order: &Order, at: DateTime) -> Option<Entry> {
+        let settlement = order.settlement.as_ref()?;
+        let (_assigned_at, courier, _user) = order.assigned.as_ref()?;
+        let owner = order.customer.id;
+        let amount_in_drams = settlement.owed_in_drams(order.markup_in_drams);
+        let anonymous = |uid: UserId| uid == privacy::ANONYMOUS_USER_ID;
+        if amount_in_drams == 0 || owner == *courier
+            || anonymous(owner) || anonymous(*courier)
+        {
+            return None
+        }
+        Some(Entry {
+            kind: EntryKind::Delivery,
+            at,
+            by: owner,
+            debit: owner,
+            credit: *courier,
+            amount_in_drams,
+            order_id: order.id,
+            note: order.name.clone(),
+            refers_to: None,
+        })
+    }
+
+    /// Debt an action that has turned `prev` into `order` makes, if it has
+    /// confirmed the delivery
+    pub fn new_debt(prev: &Order, order: &Order, at: DateTime) -> Option<Entry> {
+        let confirmed = |o: &Order| o.status() == Status::DeliveryConfirmed;
+        if confirmed(prev) || !confirmed(order) {
+            return None
+        }
+        Entry::delivery(order, at)
+    }
+
+    /// The opposite entry to `entries[idx]`
+    fn opposite(entry: &Entry, idx: usize, kind: EntryKind, by: UserId,
+                at: DateTime) -> Entry {
+        Entry {
+            kind,
+            at,
+            by,
+            debit: entry.credit,
+            credit: entry.debit,
+            amount_in_drams: entry.amount_in_drams,
+            order_id: entry.order_id,
+            note: entry.note.clone(),
+            refers_to: Some(idx),
+        }
+    }
+
+    pub fn involves(&self, uid: UserId) -> bool {
+        self.debit == uid || self.credit == uid
+    }
+
+    /// The other side of the entry for `uid`
+    pub fn counterparty(&self, uid: UserId) -> UserId {
+        if self.debit == uid { self.credit } else { self.debit }
+    }
+}
+
+/// Tells if an entry has reversed `entries[idx]`
+fn is_reversed(entries: &[Entry], idx: usize) -> bool {
+    entries.iter()
+        .any(|e| e.kind == EntryKind::Reversal && e.refers_to == Some(idx))
+}
+
+/// Tells if the debt `entries[idx]` is repaid, reversed debts never are
+fn is_repaid(entries: &[Entry], idx: usize) -> bool {
+    !is_reversed(entries, idx) && entries.iter().enumerate()
+        .any(|(ii, e)| e.kind == EntryKind::Repayment
+             && e.refers_to == Some(idx)
+             && !is_reversed(entries, ii))
+}
+
+/// Finds entry `idx` that `uid` takes part in
+fn find(entries: &[Entry], idx: usize, uid: UserId) -> Result<&Entry, String> {
+    match entries.get(idx) {
+        Some(entry) if entry.involves(uid) => Ok(entry),
+        _ => Err("There's no such entry in your ledger".to_string()),
+    }
+}
+
+/// Entry that marks the debt `entries[idx]` as repaid, either side can
+/// record it
+pub fn repay(
+    entries: &[Entry],
+    idx: usize,
+    by: UserId,
+    at: DateTime,
+) -> Result<Entry, String> {
+    let debt = find(entries, idx, by)?;
+    if debt.kind != EntryKind::Delivery {
+        return Err("There's no such debt in your ledger".to_string())
+    }
+    if is_reversed(entries, idx) {
+        return Err("This debt is canceled".to_string())
+    }
+    if is_repaid(entries, idx) {
+        return Err("This debt is already repaid".to_string())
+    }
+    Ok(Entry::opposite(debt, idx, EntryKind::Repayment, by, at))
+}
+
+/// Entry that reverses the repayment or the debt `entries[idx]` recorded
+/// by mistake. Either side can reverse a repayment, only the courier can
+/// cancel a debt, and only while it isn't repaid.
+pub fn reverse(
+    entries: &[Entry],
+    idx: usize,
+    by: UserId,
+    at: DateTime,
+) -> Result<Entry, String> {
+    let entry = find(entries, idx, by)?;
+    match entry.kind {
+        EntryKind::Repayment => (),
+        EntryKind::Delivery if by != entry.credit => {
+            return Err("Only who you owe can cancel this debt".to_string())
+        },
+        EntryKind::Delivery if is_repaid(entries, idx) => {
+            return Err("This debt is repaid, say it isn't first".to_string())
+        },
+        EntryKind::Delivery => (),
+        EntryKind::Reversal => {
+            return Err("A correction can't be reversed".to_string())
+        },
+    }
+    if is_reversed(entries, idx) {
+        return Err("This entry is already reversed".to_string())
+    }
+    Ok(Entry::opposite(entry, idx, EntryKind::Reversal, by, at))
+}
+
+/// How much each member owes `uid`, negative if `uid` owes them,
+/// members that are even are left out
+pub fn balances(entries: &[Entry], uid: UserId) -> BTreeMap<UserId, i64> {
+    let mut balances: BTreeMap<UserId, i64> = BTreeMap::new();
+    for entry in entries.iter().filter(|e| e.involves(uid)) {
+        // Prices are capped by `MAX_PRICE_IN_DRAMS`, debts are far from it
+        let amount = i64::try_from(entry.amount_in_drams).unwrap_or(i64::MAX);
+        let signed = if entry.credit == uid { amount } else { -amount };
+        let balance = balances.entry(entry.counterparty(uid)).or_default();
+        *balance = balance.saturating_add(signed);
+    }
+    balances.retain(|_uid, balance| *balance != 0);
+    balances
+}
+
+/// Debts `uid` owes or is owed that aren't repaid or canceled, with their
+/// positions
+pub fn open_debts(entries: &[Entry], uid: UserId) -> Vec<(usize, &Entry)> {
+    entries.iter().enumerate()
+        .filter(|(_ii, e)| e.kind == EntryKind::Delivery && e.involves(uid))
+        .filter(|(ii, _e)| !is_repaid(entries, *ii)
+                && !is_reversed(entries, *ii))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeZone, Utc};
+
+    fn debt(debit: u64, credit: u64, amount_in_drams: u64) -> Entry {
+        Entry {
+            kind: EntryKind::Delivery,
+            at: Utc.ymd(2022, 7, 1).and_hms(12, 0, 0),
+            by: UserId(debit),
+            debit: UserId(debit),
+            credit: UserId(credit),
+            amount_in_drams,
+            order_id: None,
+            note: "Coffee".to_string(),
+            refers_to: None,
+        }
+    }
+
+    #[test]
+    fn test_balances() {
+        let now = Utc.ymd(2022, 7, 2).and_hms(12, 0, 0);
+        let mut entries = vec![debt(1, 2, 5700), debt(1, 2, 300), debt(2, 1, 1000)];
+        assert_eq!(BTreeMap::from([(UserId(1), 5000)]),
+                   balances(&entries, UserId(2)));
+        assert_eq!(BTreeMap::from([(UserId(2), -5000)]),
+                   balances(&entries, UserId(1)));
+        assert!(balances(&entries, UserId(3)).is_empty());
+
+        // Both sides can repay, but only once
+        assert!(repay(&entries, 0, UserId(3), now).is_err());
+        let repayment = repay(&entries, 0, UserId(2), now).unwrap();
+        assert_eq!((UserId(2), UserId(1), Some(0)),
+                   (repayment.debit, repayment.credit, repayment.refers_to));
+        entries.push(repayment);
+        assert!(repay(&entries, 0, UserId(1), now).is_err());
+        assert_eq!(vec![1, 2], open_debts(&entries, UserId(1)).iter()
+                   .map(|(ii, _e)| *ii).collect::<Vec<usize>>());
+        assert_eq!(BTreeMap::from([(UserId(2), 700)]),
+                   balances(&entries, UserId(1)));
+
+        // A mistake is corrected with another entry
+        assert!(reverse(&entries, 0, UserId(1), now).is_err());
+        entries.push(reverse(&entries, 3, UserId(1), now).unwrap());
+        assert!(reverse(&entries, 3, UserId(2), now).is_err());
+        assert_eq!(3, open_debts(&entries, UserId(1)).len());
+        assert_eq!(BTreeMap::from([(UserId(2), -5000)]),
+                   balances(&entries, UserId(1)));
+        entries.push(repay(&entries, 0, UserId(1), now).unwrap());
+        assert_eq!(2, open_debts(&entries, UserId(1)).len());
+
+        // Every entry is double-entry
+        let total: i64 = [1, 2].iter()
+            .flat_map(|uid| balances(&entries, UserId(*uid)).into_values())
+            .sum();
+        assert_eq!(0, total);
+    }
+
+    #[test]
+    fn test_reverse_debt() {
+        let now = Utc.ymd(2022, 7, 2).and_hms(12, 0, 0);
+        let mut entries = vec![debt(1, 2, 5700), debt(1, 2, 300)];
+
+        // Only the courier cancels a debt, once
+        assert!(reverse(&entries, 0, UserId(1), now).is_err());
+        let reversal = reverse(&entries, 0, UserId(2), now).unwrap();
+        assert_eq!((EntryKind::Reversal, UserId(2), UserId(1), Some(0)),
+                   (reversal.kind, reversal.debit, reversal.credit,
+                    reversal.refers_to));
+        entries.push(reversal);
+        assert!(reverse(&entries, 0, UserId(2), now).is_err());
+        assert!(reverse(&entries, 2, UserId(2), now).is_err());
+        assert_eq!(BTreeMap::from([(UserId(2), -300)]),
+                   balances(&entries, UserId(1)));
+
+        // A canceled debt is neither open nor can be repaid
+        assert_eq!(vec![1], open_debts(&entries, UserId(1)).iter()
+                   .map(|(ii, _e)| *ii).collect::<Vec<usize>>());
+        assert!(!is_repaid(&entries, 0));
+        assert!(repay(&entries, 0, UserId(1), now).is_err());
+
+        // A repaid debt is only canceled once the repayment is reversed
+        entries.push(repay(&entries, 1, UserId(1), now).unwrap());
+        assert!(reverse(&entries, 1, UserId(2), now).is_err());
+        entries.push(reverse(&entries, 3, UserId(2), now).unwrap());
+        entries.push(reverse(&entries, 1, UserId(2), now).unwrap());
+        assert!(open_debts(&entries, UserId(1)).is_empty());
+        assert!(balances(&entries, UserId(1)).is_empty());
+        assert!(balances(&entries, UserId(2)).is_empty());
+    }
+}
@@ -1,18 +1,138 @@
 // Mostly copied from pretty_env_logger https://github.com/seanmonstar/pretty-env-logger
 
+use std::cell::RefCell;
 use std::fmt;
+use std::future::Future;
 use std::sync::atomic::{Ordering, AtomicUsize};
 use env_logger::{
     fmt::{Color, Style, StyledValue},
     Builder,
 };
 use log::Level;
+use serde::Serialize;
+use teloxide::{
+    prelude::*,
+    dispatching::{DpHandlerDescription, UpdateHandler},
+    dptree::{HandlerDescription, di::{DependencyMap, DependencySupplier}},
+};
+use crate::error::Error;
+use crate::order::{ActionKind, OrderId};
+use crate::Offset;
 
 // pub extern crate env_logger;
 // extern crate log;
 
+tokio::task_local! {
+    /// Context of the update that is being handled right now
+    static CONTEXT: RefCell<Context>;
+}
+
+/// Fields that are added to every log line produced while handling
+/// a single `Update`
+///
+/// `update_id` doubles as the correlation id
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Context {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    update_id: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    chat_id: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    order_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    action: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    handler: Option<&'static str>,
+}
+
+impl Context {
+    fn from_update(update: &Update) -> Self {
+        Context {
+            update_id: Some(update.id),
+            chat_id: update.chat().map(|c| c.id.0),
+            user_id: update.user().map(|u| u.id.0),
+            ..Default::default()
+        }
+    }
+}
+
+/// A line in JSON log format
+#[derive(Serialize)]
+struct JsonLine<'a> {
+    ts: String,
+    level: &'static str,
+    target: &'a str,
+    msg: String,
+    #[serde(flatten)]
+    context: Context,
+}
+
+/// Runs `fut` with a log context of `update`
+pub async fn scope<F: Future>(update: &Update, fut: F) -> F::Output {
+    let ctx = Context::from_update(update);
+    CONTEXT.scope(RefCell::new(ctx), fut).await
+}
+
+/// Handler that makes everything chained after it log with the context
+/// of the update it handles
+pub fn update_scope() -> UpdateHandler<Error> {
+    dptree::from_fn_with_description(
+        DpHandlerDescription::entry(),
+        |deps: DependencyMap, cont| async move {
+            let update: std::sync::Arc<Update> = deps.get();
+            scope(&update, cont(deps)).await
+        })
+}
+
+fn modify_context<F: FnOnce(&mut Context)>(f: F) {
+    // Outside of update handling there is no context, which is fine
+    let _ = CONTEXT.try_with(|ctx| f(&mut ctx.borrow_mut()));
+}
+
+fn current_context() -> Context {
+    CONTEXT.try_with(|ctx| ctx.borrow().clone()).unwrap_or_default()
+}
+
+/// Which handler is handling the current update
+pub fn set_handler(name: &'static str) {
+    modify_context(|ctx| ctx.handler = Some(name));
+}
+
+/// Which order the current update is about
+pub fn set_order(oid: OrderId) {
+    modify_context(|ctx| ctx.order_id = Some(oid.0));
+}
+
+/// Which order action the current update is performing
+pub fn set_action(kind: ActionKind) {
+    modify_context(|ctx| ctx.action = Some(kind.id()));
+}
+
+/// Log line format, chosen with `LOG_FORMAT` environment variable
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Colored human readable text, the default
+    Text,
+    /// One JSON object per line, with fields of the update context
+    Json,
+}
+
+impl Format {
+    fn from_env() -> Format {
+        match std::env::var("LOG_FORMAT").as_deref() {
+            Ok("json") => Format::Json,
+            _ => Format::Text,
+        }
+    }
+}
+
 pub fn init() {
-    let mut builder = formatted_builder();
+    let mut builder = match Format::from_env() {
+        Format::Text => formatted_builder(),
+        Format::Json => json_builder(),
+    };
 
     if let Ok(s) = ::std::env::var("RUST_LOG") {
         builder.parse_filters(&s);
@@ -49,6 +169,31 @@ pub fn formatted_builder() -> Builder {
 
     builder
 }
+/// Returns a `env_logger::Builder` that writes one JSON object per line
+pub fn json_builder() -> Builder {
+    let mut builder = Builder::new();
+
+    builder.format(|f, record| {
+        use std::io::Write;
+
+        let line = json_line(record).map_err(std::io::Error::from)?;
+        writeln!(f, "{line}")
+    });
+
+    builder
+}
+
+/// `record` with the context of the current update as a JSON object
+fn json_line(record: &log::Record) -> serde_json::Result<String> {
+    serde_json::to_string(&JsonLine {
+        ts: Offset::now().to_rfc3339(),
+        level: record.level().as_str(),
+        target: record.target(),
+        msg: record.args().to_string(),
+        context: current_context(),
+    })
+}
+
 struct Padded<T> {
     value: T,
     width: usize,
@@ -81,3 +226,53 @@ fn colored_level<'a>(style: &'a mut Style, level: Level) -> StyledValue<'a, &'st
         Level::Error => style.set_color(Color::Red).value("ERROR"),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn line(msg: &str) -> Value {
+        let line = json_line(&log::Record::builder()
+            .args(format_args!("{msg}"))
+            .level(Level::Info)
+            .target("dili")
+            .build()).unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_json_line_has_update_context() {
+        // Teloxide only parses updates from text
+        let update: Update = serde_json::from_str(&json!({
+            "update_id": 42,
+            "message": {
+                "message_id": 1,
+                "date": 0,
+                "chat": {"id": 7, "type": "private", "first_name": "User7"},
+                "from": {"id": 7, "is_bot": false, "first_name": "User7"},
+                "text": "/start",
+            },
+        }).to_string()).unwrap();
+        let logged = scope(&update, async {
+            set_handler("test");
+            set_order(OrderId(5));
+            set_action(ActionKind::Publish);
+            line("publishing")
+        }).await;
+        assert_eq!("INFO", logged["level"]);
+        assert_eq!("publishing", logged["msg"]);
+        assert_eq!(42, logged["update_id"]);
+        assert_eq!(7, logged["chat_id"]);
+        assert_eq!(7, logged["user_id"]);
+        assert_eq!(5, logged["order_id"]);
+        assert_eq!("publish", logged["action"]);
+        assert_eq!("test", logged["handler"]);
+
+        // Outside of an update there's nothing to add
+        let logged = line("idle");
+        assert_eq!("idle", logged["msg"]);
+        assert!(logged.get("update_id").is_none());
+        assert!(logged.get("order_id").is_none());
+    }
+}
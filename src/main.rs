@@ -16,6 +16,22 @@ mod markup;
 mod ui;
 mod data_gathering;
 mod logger;
+mod clock;
+mod health;
+mod moderation;
+mod rate_limit;
+mod outbox;
+mod digest;
+mod settings;
+mod privacy;
+mod collection;
+mod archive;
+mod stats;
+mod template;
+mod recurrence;
+mod ledger;
+#[cfg(all(test, feature = "mem_db"))]
+mod e2e;
 
 use db::Db;
 use crate::error::Error;
@@ -49,6 +65,7 @@ async fn handle_callback_query(
     dialogue: MyDialogue,
     mut db: Db,
 ) -> HandlerResult {
+    logger::set_handler("handle_callback_query");
     log::info!("-> handle_callback_query");
     data_gathering::collect_data_from_cq(&mut db, q.clone()).await?;
     log::debug!("   query: {q:?}");
@@ -88,8 +105,32 @@ query: {q:?}", q.data);
     }
     let data = q.data.clone().unwrap();
 
-    let _is_handled =  ui::order_action::try_handle_query(
-        bot, db, dialogue, q, &data).await?;
+    let is_handled = ui::order_action::try_handle_query(
+        bot.clone(), db.clone(), dialogue.clone(), q.clone(), &data).await?;
+    let is_handled = is_handled || ui::cancel_order::try_handle_query(
+        bot.clone(), db.clone(), dialogue.clone(), q.clone(), &data).await?;
+    let is_handled = is_handled || ui::report_order::try_handle_query(
+        bot.clone(), db.clone(), dialogue.clone(), q.clone(), &data).await?;
+    let is_handled = is_handled || ui::new_order::try_handle_query(
+        bot.clone(), db.clone(), dialogue.clone(), q.clone(), &data).await?;
+    let is_handled = is_handled || ui::template::try_handle_query(
+        bot.clone(), db.clone(), dialogue.clone(), q.clone(), &data).await?;
+    let is_handled = is_handled || ui::recurring::try_handle_query(
+        bot.clone(), db.clone(), dialogue.clone(), q.clone(), &data).await?;
+    let is_handled = is_handled || ui::items::try_handle_query(
+        bot.clone(), db.clone(), dialogue.clone(), q.clone(), &data).await?;
+    let is_handled = is_handled || ui::settlement::try_handle_query(
+        bot.clone(), db.clone(), dialogue.clone(), q.clone(), &data).await?;
+    let is_handled = is_handled || ui::ledger::try_handle_query(
+        bot.clone(), db.clone(), q.clone(), &data).await?;
+    let is_handled = is_handled || ui::settings::try_handle_query(
+        bot.clone(), db.clone(), q.clone(), &data).await?;
+    let is_handled = is_handled || ui::privacy::try_handle_query(
+        bot.clone(), db.clone(), q.clone(), &data).await?;
+    if !is_handled {
+        ui::moderation::try_handle_query(
+            bot, db, dialogue, q, &data).await?;
+    }
 
     Ok(())
 }
@@ -105,10 +146,22 @@ pub fn schema() -> UpdateHandler<Error> {
         Update::filter_callback_query()
             .endpoint(handle_callback_query);
 
-    dialogue::enter::<Update, ErasedStorage<State>, State, _>()
+    logger::update_scope()
+        .chain(dialogue::enter::<Update, ErasedStorage<State>, State, _>())
         .branch(dptree::filter_async(collect_data_handler))
+        .branch(ui::limits::schema())
         .branch(dptree::case![State::NewOrder(no)]
                 .branch(ui::new_order::schema()))
+        .branch(dptree::case![State::CancelOrder(state)]
+                .branch(ui::cancel_order::schema()))
+        .branch(dptree::case![State::Dispute(state)]
+                .branch(ui::dispute::schema()))
+        .branch(dptree::case![State::Template(state)]
+                .branch(ui::template::schema()))
+        .branch(dptree::case![State::Items(state)]
+                .branch(ui::items::schema()))
+        .branch(dptree::case![State::Settlement(state)]
+                .branch(ui::settlement::schema()))
         .branch(message_handler)
         .branch(callback_query_handler)
         .branch(dptree::entry())
@@ -119,7 +172,11 @@ async fn main() -> Result<(), Error> {
     logger::init();
     log::info!("Starting bot...");
 
-    let db = Db::new().await?;
+    let health = health::Health::new();
+    health::spawn_server(health.clone()).await?;
+
+    let db = Db::new(clock::Clock::System).await?;
+    health.db_connected(db.clone());
 
     let bot = init_bot()?.auto_send();
 
@@ -127,10 +184,23 @@ async fn main() -> Result<(), Error> {
     let storage: MyStorage =
         RedisStorage::open(REDIS_URL, dialogue::serializer::Json)
             .await?.erase();
+    health.storage_connected();
+
+    let limiter = rate_limit::RateLimiter::new();
+    tokio::spawn(ui::digest::run(bot.clone(), db.clone()));
+    tokio::spawn(ui::quiet_hours::run(bot.clone(), db.clone()));
+    tokio::spawn(collection::run(db.clone()));
+    tokio::spawn(archive::run(db.clone()));
+    tokio::spawn(ui::recurring::run(bot.clone(), db.clone()));
+
+    let listener = health::polling(bot.clone(), health);
     Dispatcher::builder(bot, schema())
-        .dependencies(dptree::deps![storage, db])
+        .dependencies(dptree::deps![storage, db, limiter])
         .build()//  .setup_ctrlc_handler()
-        .dispatch()
+        .dispatch_with_listener(
+            listener,
+            LoggingErrorHandler::with_custom_text(
+                "An error from the update listener"))
         .await;
 
     Ok(())
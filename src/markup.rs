@@ -1,7 +1,8 @@
 use teloxide::types::{User, UserId};
 use chrono::Duration;
 use askama_escape::{escape, Html, Escaped};
-use crate::{DateTime, Offset};
+use crate::DateTime;
+use crate::settings::Currency;
 use std::borrow::Cow;
 use std::fmt::Display;
 
@@ -15,6 +16,15 @@ pub fn format_amd(amd: u64) -> String {
     format!("{amd} AMD")
 }
 
+/// Like `format_amd` but also shows roughly how much it is in `currency`
+pub fn format_price(amd: u64, currency: Currency) -> String {
+    if amd == 0 || currency == Currency::Amd {
+        return format_amd(amd)
+    }
+    let converted = amd as f64 / currency.drams_per_unit();
+    format!("{amd} AMD (≈ {converted:.2} {})", currency.code())
+}
+
 pub fn format_username(user: &User) -> String {
     let first_name = &user.first_name;
     let name = if let Some(last_name) = &user.last_name {
@@ -39,6 +49,11 @@ pub fn user_url(uid: UserId) -> String {
     format!("tg://user?id={uid}")
 }
 
+/// Opens the bot with the public profile of `uid`
+pub fn profile_url(bot_username: &str, uid: UserId) -> String {
+    format!("https://t.me/{bot_username}?start=profile_{uid}")
+}
+
 pub fn user_link(user: &User) -> String {
     let url = user_url(user.id);
     let name = format_username(user);
@@ -50,8 +65,8 @@ pub fn escape_html(s: &str) -> Escaped<'_, Html> {
     escape(s, Html)
 }
 
-pub fn time_ago(t: DateTime) -> String {
-    let now = Offset::now();
+/// How long ago (or from now) `t` is, as seen at `now`
+pub fn time_ago(t: DateTime, now: DateTime) -> String {
     let dur = t.signed_duration_since(now);
     if dur.is_zero() {
         return "right now".to_string();
@@ -115,7 +130,8 @@ pub fn human_positive_duration(dur: Duration) -> String {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use chrono::Duration;
+    use chrono::{Duration, TimeZone};
+    use chrono::offset::Utc;
 
     #[test]
     fn test_humanize_positive_duration() {
@@ -127,4 +143,27 @@ mod tests {
                    human_positive_duration(Duration::minutes(1)
                                            + Duration::seconds(5)));
     }
+
+    #[test]
+    fn test_humanize_positive_duration_boundaries() {
+        let h = human_positive_duration;
+        assert_eq!("just now",       h(Duration::zero()));
+        assert_eq!("few seconds",    h(Duration::seconds(30)));
+        assert_eq!("about a minute", h(Duration::seconds(31)));
+        assert_eq!("1 minute",       h(Duration::seconds(60)));
+        assert_eq!("59 minutes",     h(Duration::minutes(60) - Duration::seconds(1)));
+        assert_eq!("1 hour",         h(Duration::hours(1)));
+        assert_eq!("23 hours",       h(Duration::days(1) - Duration::seconds(1)));
+        assert_eq!("1 day",          h(Duration::days(1)));
+        assert_eq!("6 days",         h(Duration::weeks(1) - Duration::seconds(1)));
+        assert_eq!("1 week",         h(Duration::weeks(1)));
+    }
+
+    #[test]
+    fn test_time_ago() {
+        let now = Utc.ymd(2022, 7, 1).and_hms(12, 0, 0);
+        assert_eq!("right now", time_ago(now, now));
+        assert_eq!("3 days ago", time_ago(now - Duration::days(3), now));
+        assert_eq!("2 hours from now", time_ago(now + Duration::hours(2), now));
+    }
 }
@@ -0,0 +1,56 @@
+use teloxide::types::UserId;
+use serde::{Serialize, Deserialize};
+
+use crate::order::{ActionKind, OrderId};
+use crate::DateTime;
+
+/// How long we trust the list of chat admins before asking Telegram again
+pub const ADMINS_CACHE_TTL_SECS: i64 = 10 * 60;
+
+/// How many distinct members have to report an order to hide it
+/// until an admin looks at it, unless `REPORTS_TO_HIDE` says otherwise
+pub const DEFAULT_REPORTS_TO_HIDE: usize = 3;
+
+pub fn reports_to_hide() -> usize {
+    match std::env::var("REPORTS_TO_HIDE") {
+        Ok(s) => s.parse().unwrap_or_else(|e| {
+            log::warn!("REPORTS_TO_HIDE={s:?}: {e:?}");
+            DEFAULT_REPORTS_TO_HIDE
+        }),
+        Err(_) => DEFAULT_REPORTS_TO_HIDE,
+    }
+}
+
+/// Admins of a public chat as Telegram told us at `fetched_at`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ChatAdmins {
+    pub fetched_at: DateTime,
+    pub admins: Vec<UserId>,
+}
+
+impl ChatAdmins {
+    pub fn is_fresh(&self, now: DateTime) -> bool {
+        (now - self.fetched_at).num_seconds() < ADMINS_CACHE_TTL_SECS
+    }
+}
+
+/// Something an admin did using their admin powers
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Moderation {
+    /// Performed an action on somebody else's order
+    Order { order_id: OrderId, action: ActionKind },
+
+    /// Doesn't let the user post orders in the chat
+    Ban(UserId),
+
+    /// Lets the user post orders again
+    Unban(UserId),
+}
+
+/// Entry of a public chat's moderation log
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ModerationRecord {
+    pub admin: UserId,
+    pub at: DateTime,
+    pub moderation: Moderation,
+}
@@ -6,16 +6,31 @@ mod action;
 mod status;
 mod role;
 mod action_error;
+mod dispute;
+mod report;
+mod draft;
+mod settlement;
+pub mod transition;
+pub mod item;
 pub use status::Status;
 pub use role::Role;
 pub use action::Action;
 pub use action_kind::ActionKind;
 pub use action_error::ActionError;
-use crate::utils::dumb_intersection;
-use crate::Offset;
+pub use transition::Transition;
+pub use dispute::{Dispute, Resolution};
+pub use report::{Report, ReportReason};
+pub use draft::{Draft, DraftField};
+pub use item::{Item, ItemUpdate};
+pub use settlement::Settlement;
 use crate::DateTime;
+use crate::privacy;
 use serde::{Serialize, Deserialize};
 
+/// Users can't enter a price, a reward or a cost above that, so sums of
+/// a few of them never overflow and always fit in debts
+pub const MAX_PRICE_IN_DRAMS: u64 = 1_000_000_000_000;
+
 #[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord,
          Serialize, Deserialize)]
 #[repr(transparent)]
@@ -36,10 +51,14 @@ impl fmt::Display for OrderId {
 // - note / description
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(from = "StoredOrder")]
 pub struct Order {
     /// Id of this order, None if not persisted in the database
     pub id: Option<OrderId>,
 
+    /// Current status, only changed by `perform_action`
+    pub status: Status,
+
     pub name: String,
 
     /// Original description message
@@ -47,9 +66,12 @@ pub struct Order {
     /// We probably don't need it, we just need the text, but whatever
     pub description_text: String,
 
-    /// Roughly how much it is
+    /// Roughly how much it is, the estimate of all items if there are any
     pub price_in_drams: u64,
 
+    /// Shopping list, empty if it's just one thing
+    pub items: Vec<Item>,
+
     /// How much extra the customer is willing to pay
     pub markup_in_drams: u64,
 
@@ -69,14 +91,130 @@ pub struct Order {
     /// When and by whom it was delivered, if it was
     pub delivered: Option<(UserId, Option<User>, DateTime)>,
 
+    /// What the courier has actually paid, they tell it when they
+    /// mark the order as delivered
+    pub settlement: Option<Settlement>,
+
     /// When the delivery was confirmed, None if it's not
     pub delivery_confirmed_at: Option<DateTime>,
 
     /// When it was canceled, if it was
     pub canceled_at: Option<DateTime>,
+
+    /// Why it was canceled, if the owner told us
+    pub cancel_reason: Option<String>,
+
+    /// All disputes about the delivery, the last one is unresolved
+    /// while the order is `Disputed`
+    pub disputes: Vec<Dispute>,
+
+    /// Members that think something is wrong with the order
+    pub reports: Vec<Report>,
+
+    /// Name of the owner's template that made it on schedule
+    pub series: Option<String>,
+}
+
+/// Order as it's stored in the database
+///
+/// Orders stored before `status` was explicit don't have it,
+/// so we infer it from the other fields
+#[derive(Deserialize)]
+struct StoredOrder {
+    id: Option<OrderId>,
+    status: Option<Status>,
+    name: String,
+    description_text: String,
+    price_in_drams: u64,
+    #[serde(default)]
+    items: Vec<Item>,
+    markup_in_drams: u64,
+    created_at: DateTime,
+    published_at: Option<DateTime>,
+    customer: User,
+    assigned: Option<(DateTime, UserId, Option<User>)>,
+    delivered: Option<(UserId, Option<User>, DateTime)>,
+    #[serde(default)]
+    settlement: Option<Settlement>,
+    delivery_confirmed_at: Option<DateTime>,
+    canceled_at: Option<DateTime>,
+    cancel_reason: Option<String>,
+    #[serde(default)]
+    disputes: Vec<Dispute>,
+    #[serde(default)]
+    reports: Vec<Report>,
+    #[serde(default)]
+    series: Option<String>,
+}
+
+impl StoredOrder {
+    const fn legacy_status(&self) -> Status {
+        if self.canceled_at.is_some() { return Status::Unpublished }
+        if self.delivery_confirmed_at.is_some() {
+            return Status::DeliveryConfirmed }
+        if self.delivered.is_some() { return Status::MarkedAsDelivered }
+        if self.assigned.is_some() { return Status::Assigned }
+        if self.published_at.is_some() { return Status::Published }
+
+        Status::Unpublished
+    }
+}
+
+impl From<StoredOrder> for Order {
+    fn from(o: StoredOrder) -> Order {
+        let status = o.status.unwrap_or_else(|| o.legacy_status());
+        Order {
+            id: o.id,
+            status,
+            name: o.name,
+            description_text: o.description_text,
+            price_in_drams: o.price_in_drams,
+            items: o.items,
+            markup_in_drams: o.markup_in_drams,
+            created_at: o.created_at,
+            published_at: o.published_at,
+            customer: o.customer,
+            assigned: o.assigned,
+            delivered: o.delivered,
+            settlement: o.settlement,
+            delivery_confirmed_at: o.delivery_confirmed_at,
+            canceled_at: o.canceled_at,
+            cancel_reason: o.cancel_reason,
+            disputes: o.disputes,
+            reports: o.reports,
+            series: o.series,
+        }
+    }
 }
 
 impl Order {
+    /// New unpublished order of `customer`
+    pub fn new(draft: Draft, customer: User, created_at: DateTime) -> Order {
+        let Draft { name, price_in_drams, items, markup_in_drams,
+                    description_text } = draft;
+        Order {
+            id: None,
+            status: Status::Unpublished,
+            name,
+            description_text,
+            price_in_drams,
+            items,
+            markup_in_drams,
+            created_at,
+            published_at: None,
+            customer,
+            assigned: None,
+            delivered: None,
+            settlement: None,
+            delivery_confirmed_at: None,
+            canceled_at: None,
+            cancel_reason: None,
+            disputes: Vec::new(),
+            reports: Vec::new(),
+            series: None,
+        }
+    }
+
     /// Returns true if is assigned and not completed yet
     pub fn is_active_assignment(&self) -> bool {
         match self.status() {
@@ -86,6 +224,41 @@ impl Order {
         }
     }
 
+    /// The courier ticks off an item or tells what they've paid for it
+    pub fn update_item(
+        &mut self,
+        uid: UserId,
+        idx: usize,
+        update: ItemUpdate,
+    ) -> Result<(), ActionError> {
+        if self.role(uid) != Role::Assignee {
+            return Err(ActionError::NotPermitted)
+        }
+        if !self.is_active_assignment() {
+            return Err(ActionError::NotAvailable)
+        }
+        let item = self.items.get_mut(idx).ok_or(ActionError::NotAvailable)?;
+        item.update(update);
+        Ok(())
+    }
+
+    /// What the courier has most likely paid: what they've told for
+    /// items and the estimate of the rest
+    pub fn expected_cost_in_drams(&self) -> u64 {
+        if self.items.is_empty() {
+            return self.price_in_drams
+        }
+        self.items.iter()
+            .map(|i| i.actual_price_in_drams.unwrap_or_else(|| i.estimate()))
+            .fold(0, u64::saturating_add)
+    }
+
+    /// What the order has cost, the estimate until the courier tells us
+    pub fn cost_in_drams(&self) -> u64 {
+        self.settlement.as_ref()
+            .map_or(self.price_in_drams, |s| s.actual_cost_in_drams)
+    }
+
     fn role(&self, uid: UserId) -> Role {
         if self.customer.id == uid {
             return Role::Owner;
@@ -101,98 +274,299 @@ impl Order {
     }
 
     pub const fn status(&self) -> Status {
-        if self.canceled_at.is_some() { return Status::Unpublished }
-        if self.delivery_confirmed_at.is_some() {
-            return Status::DeliveryConfirmed }
-        if self.delivered.is_some() { return Status::MarkedAsDelivered }
-        if self.assigned.is_some() { return Status::Assigned }
-        if self.published_at.is_some() { return Status::Published }
-
-        Status::Unpublished
+        self.status
     }
 
-    /// Actions that are available to order in its current state
-    pub const fn available_actions(&self) -> &'static [ActionKind] {
-        match self.status() {
-            Status::Unpublished =>
-                &[ActionKind::Publish, ActionKind::Delete],
-            Status::Published =>
-                &[ActionKind::AssignToMe, ActionKind::Cancel],
-            Status::Assigned => &[
-                ActionKind::Unassign,
-                ActionKind::MarkAsDelivered,
-                ActionKind::ConfirmDelivery
-            ],
-            Status::MarkedAsDelivered =>
-                &[ActionKind::ConfirmDelivery],
-            Status::DeliveryConfirmed =>
-                &[ActionKind::Delete],
-        }
+    /// Actions that `role` can perform in the current status
+    ///
+    /// `uid` is None if we don't know who's going to perform them
+    fn role_actions(&self, role: Role, uid: Option<UserId>) -> Vec<ActionKind> {
+        transition::from(self.status)
+            .filter(|t| t.roles.contains(&role))
+            .filter(|t| self.check_condition(uid, t.action).is_ok())
+            .map(|t| t.action)
+            .collect()
     }
 
     pub fn user_actions(
         &self,
         actor: UserId,
     ) -> Vec<ActionKind> {
-        let role = self.role(actor);
-        let available_actions = self.available_actions();
-        let allowed_actions = role.allowed_actions();
-
-        dumb_intersection(allowed_actions, available_actions)
+        self.role_actions(self.role(actor), Some(actor))
     }
 
     pub fn public_actions(&self) -> Vec<ActionKind> {
-        let available_actions = self.available_actions();
-        let allowed_actions = Role::UnrelatedUser.allowed_actions();
+        self.role_actions(Role::UnrelatedUser, None)
+    }
+
+    /// Actions an admin of the order's public chat can perform as an admin
+    pub fn admin_actions(&self) -> Vec<ActionKind> {
+        self.role_actions(Role::ChatAdmin, None)
+    }
+
+    /// Checks what the transition table can't express
+    ///
+    /// `uid` is None if we don't know who's going to act
+    fn check_condition(
+        &self,
+        uid: Option<UserId>,
+        kind: ActionKind,
+    ) -> Result<(), ActionError> {
+        match kind {
+            ActionKind::Report => match uid {
+                Some(uid) if self.pending_reports().any(|r| r.by == uid) =>
+                    Err(ActionError::AlreadyReported),
+                _ => Ok(()),
+            },
+            ActionKind::DismissReports
+                if self.pending_reports().next().is_none() =>
+                    Err(ActionError::NotAvailable),
+            _ => Ok(()),
+        }
+    }
+
+    /// Reports admins haven't looked at yet
+    pub fn pending_reports(&self) -> impl Iterator<Item = &Report> {
+        self.reports.iter().filter(|r| !r.reviewed)
+    }
+
+    /// Hidden orders aren't listed until an admin reviews the reports
+    pub fn is_hidden(&self, reports_to_hide: usize) -> bool {
+        let mut reporters: Vec<UserId> =
+            self.pending_reports().map(|r| r.by).collect();
+        reporters.sort_by_key(|uid| uid.0);
+        reporters.dedup();
+        reporters.len() >= reports_to_hide
+    }
+
+    /// True if `uid` could only perform `kind` as an admin of the chat
+    pub fn is_moderation(&self, uid: UserId, kind: ActionKind) -> bool {
+        match transition::find(self.status, kind) {
+            Some(t) => ! t.roles.contains(&self.role(uid)),
+            None => false,
+        }
+    }
+
+    /// The dispute that's waiting for an admin, if there's one
+    pub fn open_dispute(&self) -> Option<&Dispute> {
+        self.disputes.last().filter(|d| d.resolution.is_none())
+    }
+
+    /// When the order was delivered or canceled, None while it's not
+    /// done with
+    pub fn completed_at(&self) -> Option<DateTime> {
+        match self.status {
+            Status::DeliveryConfirmed => self.delivery_confirmed_at,
+            Status::Unpublished => self.canceled_at,
+            _ => None,
+        }
+    }
+
+    /// Cancels the order unless it's done with, deleted orders are
+    /// archived as canceled ones
+    pub fn cancel_deleted(&mut self, now: DateTime) {
+        if self.completed_at().is_none() {
+            self.status = Status::Unpublished;
+            self.canceled_at = Some(now);
+        }
+    }
 
-        dumb_intersection(allowed_actions, available_actions)
+    /// Tells if `uid` has created the order or is delivering it
+    pub fn involves(&self, uid: UserId) -> bool {
+        self.role(uid) != Role::UnrelatedUser
     }
 
-    pub fn is_action_permitted(&self, uid: UserId, action: &Action) -> bool {
-        let allowed = self.user_actions(uid);
-        allowed.into_iter().any(|a| a == action.kind)
+    /// Tells if `uid` is anywhere in the order: they've created it, are
+    /// delivering it, have reported it or opened or resolved its dispute
+    pub fn mentions(&self, uid: UserId) -> bool {
+        self.involves(uid)
+            || self.reports.iter().any(|r| r.by == uid)
+            || self.disputes.iter().any(|d| d.opened_by == uid
+                || d.resolution.as_ref().is_some_and(|r| r.by == uid))
     }
 
-    /// Performs `action` and returns previous status
+    /// Replaces `uid` everywhere in the order with an anonymous user,
+    /// so the order still makes sense to the other side
+    pub fn forget_user(&mut self, uid: UserId) {
+        let anon = privacy::anonymous_user();
+        if self.customer.id == uid {
+            self.customer = anon.clone();
+        }
+        if let Some((_when, id, user)) = &mut self.assigned {
+            if *id == uid {
+                *id = anon.id;
+                *user = None;
+            }
+        }
+        if let Some((id, user, _when)) = &mut self.delivered {
+            if *id == uid {
+                *id = anon.id;
+                *user = None;
+            }
+        }
+        for dispute in self.disputes.iter_mut() {
+            if dispute.opened_by == uid {
+                dispute.opened_by = anon.id;
+            }
+            if let Some(resolution) = &mut dispute.resolution {
+                if resolution.by == uid {
+                    resolution.by = anon.id;
+                }
+            }
+        }
+        for report in self.reports.iter_mut().filter(|r| r.by == uid) {
+            report.by = anon.id;
+        }
+    }
+
+    /// Returns the transition `uid` would make by performing `kind`
+    ///
+    /// `is_admin` tells if `uid` is an admin of the order's public chat,
+    /// the order can't know it
+    pub fn check_action(
+        &self,
+        uid: UserId,
+        is_admin: bool,
+        kind: ActionKind,
+    ) -> Result<&'static Transition, ActionError> {
+        let t = transition::find(self.status, kind)
+            .ok_or(ActionError::NotAvailable)?;
+        let permitted = t.roles.contains(&self.role(uid))
+            || (is_admin && t.roles.contains(&Role::ChatAdmin));
+        if ! permitted {
+            return Err(ActionError::NotPermitted)
+        }
+        self.check_condition(Some(uid), kind)?;
+        Ok(t)
+    }
+
+    fn review_reports(&mut self) {
+        for report in self.reports.iter_mut() {
+            report.reviewed = true;
+        }
+    }
+
+    /// Performs `action` at time `now` and returns previous status
     ///
     /// Note: shouldn't be called with `Delete` action, which should
     /// be handled by the database instead
     pub fn perform_action(
         &mut self,
         user: User,
-        action: &Action
+        is_admin: bool,
+        action: &Action,
+        now: DateTime,
     ) -> Result<Status, ActionError> {
         let uid = user.id;
-        if ! self.is_action_permitted(uid, action) {
-            return Err(ActionError::NotPermitted)
-        }
+        let transition = self.check_action(uid, is_admin, action.kind)?;
+        let next_status = match transition.to {
+            Some(status) => status,
+            None => panic!("should be handled by the database"),
+        };
 
         let prev_status = self.status();
 
         match action.kind {
             ActionKind::Publish => {
                 self.canceled_at = None;
-                self.published_at = Some(Offset::now());
+                self.cancel_reason = None;
+                self.published_at = Some(now);
             },
             ActionKind::Cancel => {
-                self.canceled_at = Some(Offset::now());
+                self.canceled_at = Some(now);
+                self.cancel_reason = action.note.clone();
+                self.assigned = None;
+                self.delivered = None;
+                self.settlement = None;
             },
             ActionKind::AssignToMe => {
-                self.assigned = Some((Offset::now(), uid, Some(user)));
+                self.assigned = Some((now, uid, Some(user)));
             },
             ActionKind::Unassign => {
                 self.assigned = None;
+                self.delivered = None;
+                self.settlement = None;
             },
             ActionKind::MarkAsDelivered => {
-                self.delivered = Some((uid, None, Offset::now()));
+                self.delivered = Some((uid, None, now));
+                if let Some(settlement) = &action.settlement {
+                    self.settlement = Some(settlement.clone());
+                }
             },
             ActionKind::ConfirmDelivery => {
-                self.delivery_confirmed_at = Some(Offset::now())
+                self.delivery_confirmed_at = Some(now)
             },
             ActionKind::Delete => {
                 panic!("should be handled by the database")
             },
+            ActionKind::Unpublish => {
+                self.review_reports();
+                self.canceled_at = Some(now);
+                self.cancel_reason = action.note.clone();
+                self.assigned = None;
+                self.delivered = None;
+                self.settlement = None;
+            },
+            ActionKind::ForceUnassign => {
+                self.assigned = None;
+                self.delivered = None;
+                self.settlement = None;
+            },
+            ActionKind::Report => {
+                let reason = action.note.as_deref()
+                    .and_then(ReportReason::maybe_from_id);
+                if reason.is_none() {
+                    log::warn!("Unknown report reason {:?}", action.note);
+                }
+                self.reports.push(Report {
+                    by: uid,
+                    at: now,
+                    reason: reason.unwrap_or(ReportReason::Inappropriate),
+                    reviewed: false,
+                });
+            },
+            ActionKind::DismissReports => {
+                self.review_reports();
+            },
+            ActionKind::OpenDispute => {
+                self.disputes.push(Dispute {
+                    opened_by: uid,
+                    opened_at: now,
+                    note: action.note.clone().unwrap_or_default(),
+                    resolution: None,
+                });
+            },
+            ActionKind::ResolveDelivered
+            | ActionKind::ResolveRepublish
+            | ActionKind::ResolveCancel => {
+                if let Some(dispute) = self.disputes.last_mut() {
+                    dispute.resolution = Some(Resolution {
+                        by: uid,
+                        at: now,
+                        action: action.kind,
+                    });
+                }
+                match action.kind {
+                    ActionKind::ResolveDelivered => {
+                        self.delivery_confirmed_at = Some(now);
+                    },
+                    ActionKind::ResolveRepublish => {
+                        self.published_at = Some(now);
+                        self.assigned = None;
+                        self.delivered = None;
+                        self.settlement = None;
+                    },
+                    _ => {
+                        self.canceled_at = Some(now);
+                        self.cancel_reason = action.note.clone();
+                        self.assigned = None;
+                        self.delivered = None;
+                        self.settlement = None;
+                    },
+                }
+            },
         }
+        self.status = next_status;
 
         Ok(prev_status)
     }
@@ -201,11 +575,18 @@ impl Order {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::clock::Clock;
+    use chrono::{Duration, TimeZone};
+    use proptest::prelude::*;
 
     fn mk_customer() -> teloxide::types::User {
+        mk_user(1)
+    }
+
+    fn mk_user(id: u64) -> teloxide::types::User {
         teloxide::types::User {
-            id: teloxide::types::UserId(1),
-            first_name: "firstname".into(),
+            id: teloxide::types::UserId(id),
+            first_name: format!("user{id}"),
             last_name: None,
             username: None,
             is_bot: false,
@@ -213,6 +594,277 @@ mod tests {
         }
     }
 
+    fn mk_order() -> Order {
+        Order {
+            id: Some(OrderId(1)),
+            status: Status::Unpublished,
+            name: "ordername".to_string(),
+            price_in_drams: 0,
+            items: Vec::new(),
+            markup_in_drams: 0,
+            description_text: "order description".to_string(),
+            created_at: chrono::offset::Utc.ymd(2022, 7, 1).and_hms(12, 0, 0),
+            canceled_at: None,
+            cancel_reason: None,
+            disputes: Vec::new(),
+            reports: Vec::new(),
+            delivered: None,
+            settlement: None,
+            published_at: None,
+            customer: mk_customer(),
+            assigned: None,
+            delivery_confirmed_at: None,
+            series: None,
+        }
+    }
+
+    /// Checks that fields of the order agree with its status
+    fn check_consistency(o: &Order) -> Result<(), String> {
+        let published = o.published_at.is_some();
+        let assigned = o.assigned.is_some();
+        let delivered = o.delivered.is_some();
+        let confirmed = o.delivery_confirmed_at.is_some();
+        let canceled = o.canceled_at.is_some();
+
+        let ok = match o.status {
+            Status::Unpublished =>
+                !assigned && !delivered && !confirmed,
+            Status::Published =>
+                published && !canceled && !assigned && !delivered && !confirmed,
+            Status::Assigned =>
+                published && !canceled && assigned && !delivered && !confirmed,
+            Status::MarkedAsDelivered =>
+                published && !canceled && assigned && delivered && !confirmed,
+            Status::Disputed =>
+                published && !canceled && assigned && delivered && !confirmed,
+            Status::DeliveryConfirmed =>
+                published && !canceled && assigned && confirmed,
+        };
+        if !ok {
+            return Err(format!("inconsistent {:?} order: {o:?}", o.status))
+        }
+        if o.cancel_reason.is_some() && !canceled {
+            return Err("order has a cancel reason but isn't canceled".to_string())
+        }
+        if (o.status == Status::Disputed) != o.open_dispute().is_some() {
+            return Err("open dispute doesn't match the status".to_string())
+        }
+
+        if let Some((_, assignee, _)) = &o.assigned {
+            if *assignee == o.customer.id {
+                return Err("order is assigned to its owner".to_string())
+            }
+            if let Some((deliverer, _, _)) = &o.delivered {
+                if deliverer != assignee {
+                    return Err("order is delivered by not assignee".to_string())
+                }
+            }
+        }
+        Ok(())
+    }
+
+    const ALL_ACTIONS: &[ActionKind] = &[
+        ActionKind::Publish,
+        ActionKind::Cancel,
+        ActionKind::AssignToMe,
+        ActionKind::Unassign,
+        ActionKind::MarkAsDelivered,
+        ActionKind::ConfirmDelivery,
+        ActionKind::Delete,
+        ActionKind::OpenDispute,
+        ActionKind::ResolveDelivered,
+        ActionKind::ResolveRepublish,
+        ActionKind::ResolveCancel,
+        ActionKind::Unpublish,
+        ActionKind::ForceUnassign,
+        ActionKind::Report,
+        ActionKind::DismissReports,
+    ];
+
+    /// Users in the property test are admins of the chat if it's true
+    fn is_admin(uid: UserId) -> bool {
+        uid == UserId(4)
+    }
+
+    proptest! {
+        #[test]
+        fn prop_actions_keep_order_consistent(
+            steps in prop::collection::vec(
+                (0..ALL_ACTIONS.len(), 1..5u64), 0..40)
+        ) {
+            let clock = Clock::fixed(chrono::offset::Utc.ymd(2022, 7, 1).and_hms(12, 0, 0));
+            let mut order = mk_order();
+
+            for (action_idx, uid) in steps {
+                let kind = ALL_ACTIONS[action_idx];
+                let user = mk_user(uid);
+                let admin = is_admin(user.id);
+                let offered = order.user_actions(user.id).contains(&kind)
+                    || (admin && order.admin_actions().contains(&kind));
+                let checked = order.check_action(user.id, admin, kind);
+                prop_assert_eq!(offered, checked.is_ok());
+
+                // Deletion is done by the database
+                if kind == ActionKind::Delete { continue }
+
+                clock.advance(Duration::minutes(1));
+                let before = serde_json::to_string(&order).unwrap();
+                let res = order.perform_action(
+                    user, admin, &Action {
+                        kind, order_id: OrderId(1), note: None, settlement: None,
+                    }, clock.now());
+                match (res, checked) {
+                    (Ok(prev), Ok(t)) => {
+                        prop_assert_eq!(t.from, prev);
+                        prop_assert_eq!(t.to, Some(order.status()));
+                    },
+                    (Err(_), Err(_)) => {
+                        let after = serde_json::to_string(&order).unwrap();
+                        prop_assert_eq!(before, after);
+                    },
+                    (res, checked) => prop_assert!(false,
+                        "perform_action {res:?} disagrees with check_action {checked:?}"),
+                }
+                prop_assert_eq!(Ok(()), check_consistency(&order));
+
+                let stored: Order = serde_json::from_str(
+                    &serde_json::to_string(&order).unwrap()).unwrap();
+                prop_assert_eq!(order.status(), stored.status());
+            }
+        }
+    }
+
+    #[test]
+    fn test_legacy_status_is_inferred() {
+        let mut order = mk_order();
+        order.published_at = Some(order.created_at);
+        order.assigned = Some((order.created_at, UserId(2), None));
+        let mut json = serde_json::to_value(&order).unwrap();
+        json.as_object_mut().unwrap().remove("status");
+
+        let stored: Order = serde_json::from_value(json).unwrap();
+        assert_eq!(Status::Assigned, stored.status());
+    }
+
+    #[test]
+    fn test_reports_hide_order() {
+        let clock = Clock::fixed(chrono::offset::Utc.ymd(2022, 7, 1).and_hms(12, 0, 0));
+        let mut order = mk_order();
+        order.perform_action(mk_user(1), false, &Action {
+            kind: ActionKind::Publish, order_id: OrderId(1),
+            note: None, settlement: None,
+        }, clock.now()).unwrap();
+
+        let report = |order: &mut Order, uid: u64| order.perform_action(
+            mk_user(uid), false, &Action {
+                kind: ActionKind::Report,
+                order_id: OrderId(1),
+                note: Some("spam".to_string()),
+                settlement: None,
+            }, clock.now());
+        report(&mut order, 2).unwrap();
+        assert!(matches!(report(&mut order, 2), Err(ActionError::AlreadyReported)));
+        assert!(!order.user_actions(UserId(2)).contains(&ActionKind::Report));
+        assert!(!order.is_hidden(2));
+
+        report(&mut order, 3).unwrap();
+        assert!(order.is_hidden(2));
+        assert_eq!(ReportReason::Spam, order.reports[1].reason);
+
+        order.perform_action(mk_user(4), true, &Action {
+            kind: ActionKind::DismissReports, order_id: OrderId(1),
+            note: None, settlement: None,
+        }, clock.now()).unwrap();
+        assert!(!order.is_hidden(2));
+        assert!(!order.admin_actions().contains(&ActionKind::DismissReports));
+        assert_eq!(Status::Published, order.status());
+    }
+
+    #[test]
+    fn test_forget_user() {
+        let clock = Clock::fixed(chrono::offset::Utc.ymd(2022, 7, 1).and_hms(12, 0, 0));
+        let mut order = mk_order();
+        let act = |order: &mut Order, kind: ActionKind, uid: u64| {
+            order.perform_action(mk_user(uid), false, &Action {
+                kind, order_id: OrderId(1),
+                note: Some("late".to_string()), settlement: None,
+            }, clock.now()).unwrap();
+        };
+        act(&mut order, ActionKind::Publish, 1);
+        act(&mut order, ActionKind::Report, 3);
+        act(&mut order, ActionKind::AssignToMe, 2);
+        act(&mut order, ActionKind::MarkAsDelivered, 2);
+        act(&mut order, ActionKind::OpenDispute, 2);
+
+        assert!(order.mentions(UserId(3)));
+        assert!(!order.involves(UserId(3)));
+        order.forget_user(UserId(3));
+        assert!(!order.mentions(UserId(3)));
+
+        order.forget_user(UserId(2));
+        assert!(!order.involves(UserId(2)));
+        assert!(order.involves(UserId(1)));
+        assert_eq!(Some(privacy::ANONYMOUS_USER_ID), order.assigned.as_ref().map(|a| a.1));
+        assert_eq!(Some(privacy::ANONYMOUS_USER_ID), order.delivered.as_ref().map(|d| d.0));
+        assert_eq!(privacy::ANONYMOUS_USER_ID, order.disputes[0].opened_by);
+        assert_eq!(privacy::ANONYMOUS_USER_ID, order.reports[0].by);
+        check_consistency(&order).unwrap();
+
+        order.forget_user(UserId(1));
+        assert_eq!("Deleted user", order.customer.first_name);
+        assert!(!order.involves(UserId(1)));
+    }
+
+    #[test]
+    fn test_settle() {
+        let clock = Clock::fixed(chrono::offset::Utc.ymd(2022, 7, 1).and_hms(12, 0, 0));
+        let mut order = mk_order();
+        order.price_in_drams = 1000;
+        order.markup_in_drams = 200;
+        let act = |order: &mut Order, kind: ActionKind, uid: u64| {
+            order.perform_action(mk_user(uid), false, &Action {
+                kind, order_id: OrderId(1), note: None, settlement: None,
+            }, clock.now())
+        };
+        let settlement = Settlement {
+            actual_cost_in_drams: 1100,
+            receipt: None,
+            at: clock.now(),
+        };
+        let deliver = |order: &mut Order, uid: u64| {
+            order.perform_action(mk_user(uid), false, &Action {
+                kind: ActionKind::MarkAsDelivered,
+                order_id: OrderId(1),
+                note: None,
+                settlement: Some(settlement.clone()),
+            }, clock.now())
+        };
+        act(&mut order, ActionKind::Publish, 1).unwrap();
+        assert!(matches!(deliver(&mut order, 2), Err(ActionError::NotAvailable)));
+        act(&mut order, ActionKind::AssignToMe, 2).unwrap();
+        assert!(matches!(deliver(&mut order, 1), Err(ActionError::NotPermitted)));
+        assert_eq!(None, order.settlement);
+        assert_eq!(1000, order.expected_cost_in_drams());
+
+        deliver(&mut order, 2).unwrap();
+        assert_eq!(1100, order.cost_in_drams());
+
+        // The next courier tells their own
+        order.perform_action(mk_user(4), true, &Action {
+            kind: ActionKind::ForceUnassign, order_id: OrderId(1),
+            note: None, settlement: None,
+        }, clock.now()).unwrap();
+        assert_eq!(None, order.settlement);
+        assert_eq!(1000, order.cost_in_drams());
+
+        act(&mut order, ActionKind::AssignToMe, 3).unwrap();
+        deliver(&mut order, 3).unwrap();
+        assert_eq!(Some(1300), order.settlement.as_ref()
+                   .map(|s| s.owed_in_drams(order.markup_in_drams)));
+        let huge = Settlement { actual_cost_in_drams: u64::MAX, ..settlement };
+        assert_eq!(u64::MAX, huge.owed_in_drams(order.markup_in_drams));
+    }
+
     #[test]
     fn test_order_status_changes() {
         let publisher = User {
@@ -233,27 +885,36 @@ mod tests {
         };
 
         let oid = OrderId(1);
+        let clock = Clock::fixed(chrono::offset::Utc.ymd(2022, 7, 1).and_hms(12, 0, 0));
 
         let customer = mk_customer();
         let order = Order {
             id: Some(oid),
+            status: Status::Unpublished,
             name: "ordername".to_string(),
             price_in_drams: 0,
+            items: Vec::new(),
             markup_in_drams: 0,
             description_text: "order description".to_string(),
-            created_at: chrono::offset::Utc::now(),
+            created_at: clock.now(),
             canceled_at: None,
+            cancel_reason: None,
+            disputes: Vec::new(),
+            reports: Vec::new(),
             delivered: None,
+            settlement: None,
             published_at: None,
             customer,
             assigned: None,
             delivery_confirmed_at: None,
+            series: None,
         };
 
         let act = |order: &mut Order, action: ActionKind, actor: User, expected_status: Status| {
-            order.perform_action(actor, &Action {
-                kind: action, order_id: oid,
-            }).unwrap();
+            clock.advance(Duration::hours(1));
+            order.perform_action(actor, false, &Action {
+                kind: action, order_id: oid, note: None, settlement: None,
+            }, clock.now()).unwrap();
             assert_eq!(expected_status, order.status());
         };
 
@@ -265,6 +926,14 @@ mod tests {
             act(&mut order, ActionKind::AssignToMe,      assignee.clone(),  Status::Assigned);
             act(&mut order, ActionKind::MarkAsDelivered, assignee.clone(),  Status::MarkedAsDelivered);
             act(&mut order, ActionKind::ConfirmDelivery, publisher.clone(), Status::DeliveryConfirmed);
+
+            let published_at = order.published_at.unwrap();
+            let assigned_at = order.assigned.as_ref().unwrap().0;
+            let delivered_at = order.delivered.as_ref().unwrap().2;
+            assert_eq!(Duration::hours(1), published_at - order.created_at);
+            assert_eq!(Duration::hours(1), assigned_at - published_at);
+            assert_eq!(Duration::hours(1), delivered_at - assigned_at);
+            assert_eq!(Some(clock.now()), order.delivery_confirmed_at);
         }
 
         // happy path, but confirmed without marking as delivered
@@ -1,11 +1,18 @@
 
-use crate::order::{ActionKind, OrderId};
+use crate::order::{ActionKind, OrderId, Settlement};
 
 /// ActionKind for specific order
 #[derive(Clone, Debug)]
 pub struct Action {
     pub order_id: OrderId,
     pub kind: ActionKind,
+
+    /// Free text the user gave along with the action, like a cancellation
+    /// reason. It's never a part of the button data.
+    pub note: Option<String>,
+
+    /// What the courier has paid, given along with `MarkAsDelivered`
+    pub settlement: Option<Settlement>,
 }
 
 impl Action {
@@ -40,7 +47,7 @@ impl Action {
         if args.next().is_some() { return None }
 
         let kind = ActionKind::maybe_from_id(kind)?;
-        Some(Action { kind, order_id })
+        Some(Action { kind, order_id, note: None, settlement: None })
     }
 }
 
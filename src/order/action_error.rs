@@ -10,6 +10,18 @@ pub enum ActionError {
     /// User is not allowed to perform that action
     NotPermitted,
 
+    /// The action can't be performed on the order in its current status
+    NotAvailable,
+
+    /// Admins of the chat don't let the user post orders
+    Banned,
+
+    /// User already has as many published orders as the chat allows
+    TooManyOpenOrders(usize),
+
+    /// User has reported the order and admins haven't looked at it yet
+    AlreadyReported,
+
     /// Some other technical error
     Other
 }
@@ -25,6 +37,21 @@ Either you clicked on a stale message or it's a bug (oh noes!)")
             ActionError::NotPermitted => {
                 write!(f, "You are not permitted to perform this action")
             },
+            ActionError::NotAvailable => {
+                write!(f, "This action is not available for the order anymore")
+            },
+            ActionError::Banned => {
+                write!(f, "Admins of the chat don't allow you to post orders")
+            },
+            ActionError::TooManyOpenOrders(limit) => {
+                write!(f, "Sorry, you can have at most {limit} published \
+orders in this chat. Please wait until some of them are delivered \
+or cancel the ones you don't need anymore")
+            },
+            ActionError::AlreadyReported => {
+                write!(f, "You've already reported this order, \
+admins of the chat will look at it")
+            },
             ActionError::Other => { write!(f, "Some error occured") }
         }
     }
@@ -1,5 +1,7 @@
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+use serde::{Serialize, Deserialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum ActionKind {
     /// Show this order in the list of all orders
     Publish,
@@ -19,32 +21,72 @@ pub enum ActionKind {
     /// As the owner we confirm that it's delivered
     ConfirmDelivery,
 
-    /// Delete it completely
+    /// Move it to the archive, canceling it unless it's done with
     Delete,
+
+    /// Say that something went wrong with the delivery
+    OpenDispute,
+
+    /// As a chat admin decide that it's delivered after all
+    ResolveDelivered,
+
+    /// As a chat admin decide that somebody else should deliver it
+    ResolveRepublish,
+
+    /// As a chat admin decide that it's not going to be delivered
+    ResolveCancel,
+
+    /// As a chat admin hide somebody else's order
+    Unpublish,
+
+    /// As a chat admin take the order from whoever is delivering it
+    ForceUnassign,
+
+    /// Tell admins that something is wrong with the order
+    Report,
+
+    /// As a chat admin decide that reported order is fine
+    DismissReports,
 }
 
 impl ActionKind {
     pub const fn human_name(&self) -> &'static str {
         match self {
-            ActionKind::Publish         => "Publish this order",
-            ActionKind::Cancel          => "Cancel this order",
-            ActionKind::AssignToMe      => "Take this order",
-            ActionKind::Unassign        => "Unassign this order",
-            ActionKind::MarkAsDelivered => "Mark as delivered",
-            ActionKind::ConfirmDelivery => "Confirm that I've received the items",
-            ActionKind::Delete          => "Delete this order",
+            ActionKind::Publish          => "Publish this order",
+            ActionKind::Cancel           => "Cancel this order",
+            ActionKind::AssignToMe       => "Take this order",
+            ActionKind::Unassign         => "Unassign this order",
+            ActionKind::MarkAsDelivered  => "Mark as delivered",
+            ActionKind::ConfirmDelivery  => "Confirm that I've received the items",
+            ActionKind::Delete           => "Delete this order",
+            ActionKind::OpenDispute      => "Something is wrong",
+            ActionKind::ResolveDelivered => "It's delivered",
+            ActionKind::ResolveRepublish => "Publish it again",
+            ActionKind::ResolveCancel    => "Cancel it",
+            ActionKind::Unpublish        => "Unpublish",
+            ActionKind::ForceUnassign    => "Unassign the courier",
+            ActionKind::Report           => "Report",
+            ActionKind::DismissReports   => "It's fine, show it",
         }
     }
 
     pub const fn id(&self) -> &'static str {
         match self {
-            ActionKind::Publish         => "publish",
-            ActionKind::Cancel          => "cancel",
-            ActionKind::AssignToMe      => "assign_to_me",
-            ActionKind::Unassign        => "unassign",
-            ActionKind::MarkAsDelivered => "mark_as_delivered",
-            ActionKind::ConfirmDelivery => "confirm_delivery",
-            ActionKind::Delete          => "delete",
+            ActionKind::Publish          => "publish",
+            ActionKind::Cancel           => "cancel",
+            ActionKind::AssignToMe       => "assign_to_me",
+            ActionKind::Unassign         => "unassign",
+            ActionKind::MarkAsDelivered  => "mark_as_delivered",
+            ActionKind::ConfirmDelivery  => "confirm_delivery",
+            ActionKind::Delete           => "delete",
+            ActionKind::OpenDispute      => "open_dispute",
+            ActionKind::ResolveDelivered => "resolve_delivered",
+            ActionKind::ResolveRepublish => "resolve_republish",
+            ActionKind::ResolveCancel    => "resolve_cancel",
+            ActionKind::Unpublish        => "unpublish",
+            ActionKind::ForceUnassign    => "force_unassign",
+            ActionKind::Report           => "report",
+            ActionKind::DismissReports   => "dismiss_reports",
         }
     }
 
@@ -59,6 +101,14 @@ impl ActionKind {
             "mark_as_delivered" => Some(ActionKind::MarkAsDelivered),
             "confirm_delivery"  => Some(ActionKind::ConfirmDelivery),
             "delete"            => Some(ActionKind::Delete),
+            "open_dispute"      => Some(ActionKind::OpenDispute),
+            "resolve_delivered" => Some(ActionKind::ResolveDelivered),
+            "resolve_republish" => Some(ActionKind::ResolveRepublish),
+            "resolve_cancel"    => Some(ActionKind::ResolveCancel),
+            "unpublish"         => Some(ActionKind::Unpublish),
+            "force_unassign"    => Some(ActionKind::ForceUnassign),
+            "report"            => Some(ActionKind::Report),
+            "dismiss_reports"   => Some(ActionKind::DismissReports),
             _other              => None
         }
     }
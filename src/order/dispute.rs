@@ -0,0 +1,28 @@
+use teloxide::types::UserId;
+use serde::{Serialize, Deserialize};
+
+use crate::order::ActionKind;
+use crate::DateTime;
+
+/// Owner or assignee says the delivery went wrong
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Dispute {
+    pub opened_by: UserId,
+    pub opened_at: DateTime,
+
+    /// What went wrong, in the words of whoever opened it
+    pub note: String,
+
+    /// None while an admin hasn't looked at it
+    pub resolution: Option<Resolution>,
+}
+
+/// How a chat admin has resolved a dispute
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Resolution {
+    pub by: UserId,
+    pub at: DateTime,
+
+    /// One of the `Resolve*` actions
+    pub action: ActionKind,
+}
@@ -0,0 +1,68 @@
+use serde::{Serialize, Deserialize};
+
+use super::{Item, Order};
+
+/// What the owner tells us to create an order
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Draft {
+    pub name: String,
+    pub price_in_drams: u64,
+    /// Shopping list, `price_in_drams` is its estimate
+    #[serde(default)]
+    pub items: Vec<Item>,
+    pub markup_in_drams: u64,
+    pub description_text: String,
+}
+
+/// Fields of a draft the owner can change one by one
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DraftField {
+    Name,
+    /// Either a price or a shopping list
+    Price,
+    Markup,
+    Description,
+}
+
+impl DraftField {
+    pub const ALL: &'static [DraftField] = &[
+        DraftField::Name,
+        DraftField::Price,
+        DraftField::Markup,
+        DraftField::Description,
+    ];
+
+    pub const fn human_name(&self) -> &'static str {
+        match self {
+            DraftField::Name        => "Name",
+            DraftField::Price       => "Price or items",
+            DraftField::Markup      => "Reward",
+            DraftField::Description => "Description",
+        }
+    }
+
+    pub const fn id(&self) -> &'static str {
+        match self {
+            DraftField::Name        => "name",
+            DraftField::Price       => "price",
+            DraftField::Markup      => "markup",
+            DraftField::Description => "description",
+        }
+    }
+
+    pub fn maybe_from_id(s: &str) -> Option<DraftField> {
+        Self::ALL.iter().copied().find(|field| field.id() == s)
+    }
+}
+
+impl From<&Order> for Draft {
+    fn from(order: &Order) -> Draft {
+        Draft {
+            name: order.name.clone(),
+            price_in_drams: order.price_in_drams,
+            items: order.items.iter().map(Item::fresh).collect(),
+            markup_in_drams: order.markup_in_drams,
+            description_text: order.description_text.clone(),
+        }
+    }
+}
@@ -0,0 +1,172 @@
+use serde::{Serialize, Deserialize};
+
+use crate::order::MAX_PRICE_IN_DRAMS;
+
+/// One line of a shopping list
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Item {
+    pub name: String,
+    pub quantity: u32,
+    /// Roughly how much one of them is
+    pub price_in_drams: u64,
+    /// Where to get it, if the owner cares
+    pub shop: Option<String>,
+    /// The courier has got it
+    #[serde(default)]
+    pub bought: bool,
+    /// How much the courier has paid for all of them
+    #[serde(default)]
+    pub actual_price_in_drams: Option<u64>,
+}
+
+/// What the courier does with an item
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ItemUpdate {
+    /// Ticks it off or back
+    Toggle,
+    /// Tells how much they've paid, which also ticks it off
+    Paid(u64),
+}
+
+impl Item {
+    /// Estimated price of all of them, parsed items never overflow
+    pub fn estimate(&self) -> u64 {
+        self.price_in_drams.saturating_mul(self.quantity as u64)
+    }
+
+    /// The same item that nobody has bought yet
+    pub fn fresh(&self) -> Item {
+        Item { bought: false, actual_price_in_drams: None, ..self.clone() }
+    }
+
+    pub fn update(&mut self, update: ItemUpdate) {
+        match update {
+            ItemUpdate::Toggle => self.bought = !self.bought,
+            ItemUpdate::Paid(price) => {
+                self.bought = true;
+                self.actual_price_in_drams = Some(price);
+            },
+        }
+    }
+}
+
+/// Estimated price of all items
+pub fn estimate(items: &[Item]) -> u64 {
+    items.iter().map(Item::estimate).fold(0, u64::saturating_add)
+}
+
+/// What the courier has paid for items so far, None if nothing yet
+pub fn paid(items: &[Item]) -> Option<u64> {
+    items.iter()
+        .filter_map(|i| i.actual_price_in_drams)
+        .reduce(u64::saturating_add)
+}
+
+/// Parses a line like `2 x Milk 450 @ SAS`: two of Milk, 450 drams each,
+/// from SAS. The quantity and the shop are optional.
+fn parse_item(line: &str) -> Result<Item, String> {
+    let (line, shop) = match line.split_once('@') {
+        Some((line, shop)) if !shop.trim().is_empty() =>
+            (line, Some(shop.trim().to_string())),
+        Some(_) => return Err("there's no shop after @".to_string()),
+        None => (line, None),
+    };
+    let mut words: Vec<&str> = line.split_whitespace().collect();
+    let quantity = match words.as_slice() {
+        [n, "x" | "×", ..] => {
+            let quantity = n.parse().ok().filter(|n| *n > 0)
+                .ok_or_else(|| format!("{n} isn't a quantity"))?;
+            words.drain(..2);
+            quantity
+        },
+        _ => 1,
+    };
+    let price_in_drams: u64 = words.pop()
+        .and_then(|price| price.parse().ok())
+        .ok_or("it doesn't end with a price in drams")?;
+    let total = price_in_drams.checked_mul(quantity as u64);
+    if !total.is_some_and(|total| total <= MAX_PRICE_IN_DRAMS) {
+        return Err("that's too expensive".to_string())
+    }
+    if words.is_empty() {
+        return Err("what is it?".to_string())
+    }
+    Ok(Item {
+        name: words.join(" "),
+        quantity,
+        price_in_drams,
+        shop,
+        bought: false,
+        actual_price_in_drams: None,
+    })
+}
+
+/// Parses a shopping list, one item per line, empty lines are skipped
+pub fn parse_items(text: &str) -> Result<Vec<Item>, String> {
+    let items = text.lines()
+        .enumerate()
+        .filter(|(_n, line)| !line.trim().is_empty())
+        .map(|(n, line)| parse_item(line)
+             .map_err(|e| format!("line {}: {e}", n + 1)))
+        .collect::<Result<Vec<Item>, String>>()?;
+    if items.is_empty() {
+        return Err("the list is empty".to_string())
+    }
+    if estimate(&items) > MAX_PRICE_IN_DRAMS {
+        return Err("the list is too expensive".to_string())
+    }
+    Ok(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_items() {
+        let items = parse_items("2 x Milk 450 @ SAS\n\nFresh bread 300\n")
+            .unwrap();
+        assert_eq!(2, items.len());
+        assert_eq!(Item {
+            name: "Milk".to_string(),
+            quantity: 2,
+            price_in_drams: 450,
+            shop: Some("SAS".to_string()),
+            bought: false,
+            actual_price_in_drams: None,
+        }, items[0]);
+        assert_eq!("Fresh bread", items[1].name);
+        assert_eq!(1, items[1].quantity);
+        assert_eq!(None, items[1].shop);
+        assert_eq!(1200, estimate(&items));
+
+        assert_eq!(Err("line 2: it doesn't end with a price in drams".to_string()),
+                   parse_items("Milk 450\nBread"));
+        assert!(parse_items("0 x Milk 450").is_err());
+        assert!(parse_items("2 x 450").is_err());
+        assert!(parse_items("Milk 450 @").is_err());
+        assert!(parse_items(" \n").is_err());
+
+        let max = MAX_PRICE_IN_DRAMS;
+        assert_eq!(Err("line 1: that's too expensive".to_string()),
+                   parse_items(&format!("2 x Milk {max}")));
+        assert_eq!(Err("line 1: that's too expensive".to_string()),
+                   parse_items(&format!("2 x Milk {}", u64::MAX)));
+        assert!(parse_items(&format!("Milk {max}")).is_ok());
+        assert_eq!(Err("the list is too expensive".to_string()),
+                   parse_items(&format!("Milk {max}\nBread 1")));
+    }
+
+    #[test]
+    fn test_paid() {
+        let mut items = parse_items("2 x Milk 450\nBread 300").unwrap();
+        assert_eq!(None, paid(&items));
+        items[0].update(ItemUpdate::Paid(1000));
+        assert!(items[0].bought);
+        assert_eq!(Some(1000), paid(&items));
+        items[1].update(ItemUpdate::Toggle);
+        assert!(items[1].bought);
+        assert_eq!(Some(1000), paid(&items));
+        assert_eq!(items[0].fresh(), parse_items("2 x Milk 450").unwrap()[0]);
+    }
+}
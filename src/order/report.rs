@@ -0,0 +1,56 @@
+use teloxide::types::UserId;
+use serde::{Serialize, Deserialize};
+
+use crate::DateTime;
+
+/// What's wrong with the order according to whoever reported it
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ReportReason {
+    Spam,
+    Scam,
+    Inappropriate,
+}
+
+impl ReportReason {
+    pub const ALL: &'static [ReportReason] = &[
+        ReportReason::Spam,
+        ReportReason::Scam,
+        ReportReason::Inappropriate,
+    ];
+
+    pub const fn human_name(&self) -> &'static str {
+        match self {
+            ReportReason::Spam          => "Spam",
+            ReportReason::Scam          => "Scam",
+            ReportReason::Inappropriate => "Inappropriate",
+        }
+    }
+
+    pub const fn id(&self) -> &'static str {
+        match self {
+            ReportReason::Spam          => "spam",
+            ReportReason::Scam          => "scam",
+            ReportReason::Inappropriate => "inappropriate",
+        }
+    }
+
+    pub fn maybe_from_id<S: AsRef<str>>(s: S) -> Option<ReportReason> {
+        match s.as_ref() {
+            "spam"          => Some(ReportReason::Spam),
+            "scam"          => Some(ReportReason::Scam),
+            "inappropriate" => Some(ReportReason::Inappropriate),
+            _other          => None,
+        }
+    }
+}
+
+/// A member has flagged the order for admins to look at
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Report {
+    pub by: UserId,
+    pub at: DateTime,
+    pub reason: ReportReason,
+
+    /// Set once an admin has looked at the order
+    pub reviewed: bool,
+}
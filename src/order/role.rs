@@ -1,29 +1,9 @@
-
-use crate::order::ActionKind;
-
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Role {
     Owner,
     Assignee,
     UnrelatedUser,
-}
 
-impl Role {
-    pub const fn allowed_actions(self) -> &'static [ActionKind] {
-        match self {
-            Role::Owner => &[
-                ActionKind::Publish,
-                ActionKind::Unassign, // Not sure about this
-                ActionKind::Cancel,
-                ActionKind::ConfirmDelivery,
-                ActionKind::Delete
-            ],
-            Role::Assignee => &[
-                ActionKind::Unassign,
-                ActionKind::MarkAsDelivered
-            ],
-            Role::UnrelatedUser => &[ActionKind::AssignToMe],
-        }
-    }
+    /// Administrator of the public chat the order is in
+    ChatAdmin,
 }
-
@@ -0,0 +1,19 @@
+use serde::{Serialize, Deserialize};
+
+use crate::DateTime;
+
+/// What the courier has actually paid for the order
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Settlement {
+    pub actual_cost_in_drams: u64,
+    /// Telegram file id of the receipt photo, if they've sent one
+    pub receipt: Option<String>,
+    pub at: DateTime,
+}
+
+impl Settlement {
+    /// What the owner owes the courier for the order with `markup_in_drams`
+    pub const fn owed_in_drams(&self, markup_in_drams: u64) -> u64 {
+        self.actual_cost_in_drams.saturating_add(markup_in_drams)
+    }
+}
@@ -1,12 +1,15 @@
 
 use std::fmt;
+use serde::{Serialize, Deserialize};
 
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Status {
     Unpublished,
     Published,
     Assigned,
     MarkedAsDelivered,
+    /// Owner and assignee disagree on the delivery, an admin decides
+    Disputed,
     DeliveryConfirmed,
 }
 
@@ -17,6 +20,7 @@ impl Status {
             Status::Published         => "Published",
             Status::Assigned          => "Assigned",
             Status::MarkedAsDelivered => "Marked as delivered",
+            Status::Disputed          => "Disputed",
             Status::DeliveryConfirmed => "Delivered",
         }
     }
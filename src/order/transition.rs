@@ -0,0 +1,66 @@
+use crate::order::{ActionKind, Role, Status};
+
+/// A move of an order from one status to another
+#[derive(Clone, Copy, Debug)]
+pub struct Transition {
+    pub from: Status,
+    pub action: ActionKind,
+
+    /// Status after the action, None if the order is deleted
+    pub to: Option<Status>,
+
+    /// Who is allowed to do it
+    pub roles: &'static [Role],
+}
+
+const fn t(
+    from: Status,
+    action: ActionKind,
+    to: Option<Status>,
+    roles: &'static [Role],
+) -> Transition {
+    Transition { from, action, to, roles }
+}
+
+/// All allowed moves, everything that isn't here is forbidden
+pub const TRANSITIONS: &[Transition] = {
+    use Status::*;
+    use ActionKind::*;
+    use Role::*;
+    &[
+        t(Unpublished,       Publish,          Some(Published),         &[Owner]),
+        t(Unpublished,       Delete,           None,                    &[Owner, ChatAdmin]),
+        t(Published,         AssignToMe,       Some(Assigned),          &[UnrelatedUser]),
+        t(Published,         Cancel,           Some(Unpublished),       &[Owner]),
+        t(Published,         Unpublish,        Some(Unpublished),       &[ChatAdmin]),
+        t(Published,         Delete,           None,                    &[ChatAdmin]),
+        t(Published,         Report,           Some(Published),         &[UnrelatedUser]),
+        t(Published,         DismissReports,   Some(Published),         &[ChatAdmin]),
+        t(Assigned,          Cancel,           Some(Unpublished),       &[Owner]),
+        t(Assigned,          Unassign,         Some(Published),         &[Owner, Assignee]),
+        t(Assigned,          MarkAsDelivered,  Some(MarkedAsDelivered), &[Assignee]),
+        t(Assigned,          ConfirmDelivery,  Some(DeliveryConfirmed), &[Owner]),
+        t(Assigned,          Unpublish,        Some(Unpublished),       &[ChatAdmin]),
+        t(Assigned,          ForceUnassign,    Some(Published),         &[ChatAdmin]),
+        t(Assigned,          Delete,           None,                    &[ChatAdmin]),
+        t(MarkedAsDelivered, ConfirmDelivery,  Some(DeliveryConfirmed), &[Owner]),
+        t(MarkedAsDelivered, OpenDispute,      Some(Disputed),          &[Owner, Assignee]),
+        t(MarkedAsDelivered, ForceUnassign,    Some(Published),         &[ChatAdmin]),
+        t(MarkedAsDelivered, Delete,           None,                    &[ChatAdmin]),
+        t(Disputed,          ResolveDelivered, Some(DeliveryConfirmed), &[ChatAdmin]),
+        t(Disputed,          ResolveRepublish, Some(Published),         &[ChatAdmin]),
+        t(Disputed,          ResolveCancel,    Some(Unpublished),       &[ChatAdmin]),
+        t(Disputed,          Delete,           None,                    &[ChatAdmin]),
+        t(DeliveryConfirmed, Delete,           None,                    &[Owner, ChatAdmin]),
+    ]
+};
+
+/// Finds the transition for `action` from `from` status
+pub fn find(from: Status, action: ActionKind) -> Option<&'static Transition> {
+    TRANSITIONS.iter().find(|t| t.from == from && t.action == action)
+}
+
+/// Transitions available from `from` status
+pub fn from(from: Status) -> impl Iterator<Item = &'static Transition> {
+    TRANSITIONS.iter().filter(move |t| t.from == from)
+}
@@ -0,0 +1,105 @@
+//! Sending requests to Telegram without tripping its flood control
+//!
+//! Requests to the same chat wait for each other in a queue, so a loop
+//! of messages keeps its order and a `RetryAfter` for one of them holds
+//! back the rest instead of failing them too. Network errors are retried
+//! with a backoff, failures we can't retry are logged and returned.
+
+use std::collections::HashMap;
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::Duration;
+
+use teloxide::{
+    RequestError,
+    payloads::{SendMessage, DeleteMessage, EditMessageText, PinChatMessage,
+               SendDocument, SendPhoto},
+    requests::{Output, Request},
+    types::Recipient,
+};
+
+/// How many times we try to send a request before giving up
+const MAX_ATTEMPTS: u32 = 5;
+
+/// Backoff after the first network error, doubled after each next one
+const FIRST_BACKOFF: Duration = Duration::from_millis(500);
+
+/// Queues of chats we're sending something to, `()` is held by whoever
+/// is sending to the chat now
+static QUEUES: LazyLock<Mutex<HashMap<Recipient, Arc<tokio::sync::Mutex<()>>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Payloads of requests that go to a chat
+pub trait ChatPayload {
+    fn chat(&self) -> &Recipient;
+}
+
+impl ChatPayload for SendMessage {
+    fn chat(&self) -> &Recipient { &self.chat_id }
+}
+
+impl ChatPayload for DeleteMessage {
+    fn chat(&self) -> &Recipient { &self.chat_id }
+}
+
+impl ChatPayload for EditMessageText {
+    fn chat(&self) -> &Recipient { &self.chat_id }
+}
+
+impl ChatPayload for PinChatMessage {
+    fn chat(&self) -> &Recipient { &self.chat_id }
+}
+
+impl ChatPayload for SendDocument {
+    fn chat(&self) -> &Recipient { &self.chat_id }
+}
+
+impl ChatPayload for SendPhoto {
+    fn chat(&self) -> &Recipient { &self.chat_id }
+}
+
+fn queue(chat: &Recipient) -> Arc<tokio::sync::Mutex<()>> {
+    let mut queues = QUEUES.lock().unwrap_or_else(|e| e.into_inner());
+    // Nobody is waiting for queues we hold the only reference to
+    queues.retain(|_chat, q| Arc::strong_count(q) > 1);
+    queues.entry(chat.clone()).or_default().clone()
+}
+
+/// Sends `req` after everything queued for its chat before it
+pub async fn send<R>(req: R) -> Result<Output<R>, RequestError>
+where
+    R: Request<Err = RequestError>,
+    R::Payload: ChatPayload,
+{
+    let chat = req.payload_ref().chat().clone();
+    let queue = queue(&chat);
+    let _turn = queue.lock().await;
+
+    let mut backoff = FIRST_BACKOFF;
+    let mut attempt = 1;
+    loop {
+        let err = match req.send_ref().await {
+            Ok(output) => return Ok(output),
+            Err(err) => err,
+        };
+        let wait = match &err {
+            RequestError::RetryAfter(wait) => *wait,
+            RequestError::Network(_) => {
+                let wait = backoff;
+                backoff *= 2;
+                wait
+            },
+            _ => {
+                log::warn!("outbox: sending to {chat} failed: {err:?}");
+                return Err(err)
+            },
+        };
+        if attempt >= MAX_ATTEMPTS {
+            log::error!("outbox: giving up sending to {chat} after \
+{attempt} attempts: {err:?}");
+            return Err(err)
+        }
+        log::warn!("outbox: retrying to send to {chat} in {wait:?}: {err:?}");
+        tokio::time::sleep(wait).await;
+        attempt += 1;
+    }
+}
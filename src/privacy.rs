@@ -0,0 +1,86 @@
+use serde::Serialize;
+use teloxide::types::{ChatId, User, UserId};
+
+use crate::ledger;
+use crate::order::{Dispute, Order, OrderId, Report, Status};
+use crate::settings::{HeldNotification, UserSettings};
+use crate::template::Template;
+use crate::DateTime;
+
+/// Who forgotten users become in orders of other people
+pub const ANONYMOUS_USER_ID: UserId = UserId(0);
+
+pub fn anonymous_user() -> User {
+    User {
+        id: ANONYMOUS_USER_ID,
+        is_bot: false,
+        first_name: "Deleted user".to_string(),
+        last_name: None,
+        username: None,
+        language_code: None,
+    }
+}
+
+/// Everything we store about a user, `/my_data` sends it as JSON
+#[derive(Debug, Serialize)]
+pub struct UserData {
+    pub exported_at: DateTime,
+    /// As Telegram has last shown the user to us
+    pub profile: Option<User>,
+    pub settings: UserSettings,
+    /// Notifications waiting for the end of quiet hours
+    pub held_notifications: Vec<HeldNotification>,
+    pub templates: Vec<Template>,
+    pub chats: Vec<ChatData>,
+}
+
+/// What we store about a user in one public chat
+#[derive(Debug, Serialize)]
+pub struct ChatData {
+    pub id: ChatId,
+    pub name: String,
+    pub digest_subscriber: bool,
+    pub banned: bool,
+    /// Orders the user has created
+    pub orders: Vec<Order>,
+    /// Orders the user has delivered or is delivering
+    pub assignments: Vec<Order>,
+    /// Messages in the user's private chat where we've shown these orders
+    pub order_messages: Vec<OrderMessage>,
+    /// Debts the user owes or is owed and their repayments
+    pub ledger: Vec<ledger::Entry>,
+    /// Reports the user has filed on orders
+    pub reports: Vec<FiledReport>,
+    /// Disputes the user has resolved as an admin of the chat
+    pub resolved_disputes: Vec<ResolvedDispute>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FiledReport {
+    pub order_id: OrderId,
+    pub report: Report,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ResolvedDispute {
+    pub order_id: OrderId,
+    pub dispute: Dispute,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OrderMessage {
+    pub order_id: OrderId,
+    pub chat_id: ChatId,
+    pub message_id: i32,
+}
+
+/// Orders that need the user, so we can't forget them yet
+pub fn is_active(order: &Order) -> bool {
+    match order.status() {
+        Status::Published         => true,
+        Status::Assigned          => true,
+        Status::MarkedAsDelivered => true,
+        Status::Disputed          => true,
+        _ => false,
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+use teloxide::types::UserId;
+use serde::{Serialize, Deserialize};
+
+use crate::order::{Order, Status};
+use crate::DateTime;
+
+/// Limits admins of a public chat can change with `/limits`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChatLimits {
+    /// How many orders a user can create in a day
+    pub orders_per_day: usize,
+
+    /// How many orders of a user can be published at once
+    pub open_orders: usize,
+
+    /// How many buttons a user can click in a second
+    pub callbacks_per_sec: usize,
+}
+
+impl Default for ChatLimits {
+    fn default() -> Self {
+        ChatLimits {
+            orders_per_day: 10,
+            open_orders: 5,
+            callbacks_per_sec: 5,
+        }
+    }
+}
+
+impl ChatLimits {
+    /// Names of the limits as `/limits` shows and accepts them
+    pub const NAMES: &'static [&'static str] = &[
+        "orders_per_day",
+        "open_orders",
+        "callbacks_per_sec",
+    ];
+
+    pub fn get(&self, name: &str) -> Option<usize> {
+        match name {
+            "orders_per_day"    => Some(self.orders_per_day),
+            "open_orders"       => Some(self.open_orders),
+            "callbacks_per_sec" => Some(self.callbacks_per_sec),
+            _other              => None,
+        }
+    }
+
+    /// Fails if there's no limit called `name` or `value` is 0,
+    /// which would stop the chat from working at all
+    pub fn set(&mut self, name: &str, value: usize) -> Result<(), String> {
+        if value == 0 {
+            return Err(format!("{name} must be at least 1"))
+        }
+        match name {
+            "orders_per_day"    => self.orders_per_day = value,
+            "open_orders"       => self.open_orders = value,
+            "callbacks_per_sec" => self.callbacks_per_sec = value,
+            _other              => return Err(format!("There's no {name} limit")),
+        }
+        Ok(())
+    }
+
+    /// Tells if a user who has created orders at `created` can create
+    /// one more, deleted orders count too
+    pub fn can_create_order(&self, created: &[DateTime], now: DateTime) -> bool {
+        let day_ago = now - chrono::Duration::days(1);
+        let today = created.iter().filter(|at| **at > day_ago).count();
+        today < self.orders_per_day
+    }
+
+    /// Tells if a user who has submitted `orders` can publish one more
+    pub fn can_publish_order(&self, orders: &[Order]) -> bool {
+        let open = orders.iter()
+            .filter(|o| o.status() == Status::Published)
+            .count();
+        open < self.open_orders
+    }
+}
+
+/// Remembers when users clicked buttons recently
+///
+/// It's kept in memory rather than in the db, because it's checked
+/// for every click and it's fine to forget it on restart
+#[derive(Clone, Debug, Default)]
+pub struct RateLimiter {
+    clicks: Arc<Mutex<BTreeMap<UserId, VecDeque<DateTime>>>>,
+}
+
+impl RateLimiter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records a click of `uid` at `now` unless they've already made
+    /// `per_sec` clicks in the last second, returns whether it's recorded
+    pub fn try_click(&self, uid: UserId, per_sec: usize, now: DateTime) -> bool {
+        let mut clicks = match self.clicks.lock() {
+            Ok(clicks) => clicks,
+            Err(e) => {
+                log::warn!("RateLimiter lock: {e:?}");
+                return true
+            }
+        };
+        let second_ago = now - chrono::Duration::seconds(1);
+        clicks.retain(|_uid, times| {
+            times.retain(|t| *t > second_ago);
+            !times.is_empty()
+        });
+
+        let times = clicks.entry(uid).or_default();
+        if times.len() >= per_sec {
+            return false
+        }
+        times.push_back(now);
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_clicks_are_limited_per_second() {
+        let limiter = RateLimiter::new();
+        let now = chrono::offset::Utc.ymd(2022, 7, 1).and_hms(12, 0, 0);
+        let (alice, bob) = (UserId(1), UserId(2));
+
+        assert!(limiter.try_click(alice, 2, now));
+        assert!(limiter.try_click(alice, 2, now));
+        assert!(!limiter.try_click(alice, 2, now));
+        assert!(limiter.try_click(bob, 2, now));
+
+        let later = now + chrono::Duration::milliseconds(1500);
+        assert!(limiter.try_click(alice, 2, later));
+    }
+
+    #[test]
+    fn test_limits() {
+        let mut limits = ChatLimits::default();
+        assert!(limits.set("open_orders", 0).is_err());
+        assert!(limits.set("nope", 1).is_err());
+        assert_eq!(Ok(()), limits.set("orders_per_day", 2));
+
+        let now = chrono::offset::Utc.ymd(2022, 7, 1).and_hms(12, 0, 0);
+        let created = [now - chrono::Duration::days(2), now];
+        assert!(limits.can_create_order(&created, now));
+        let created = [now - chrono::Duration::hours(2), now];
+        assert!(!limits.can_create_order(&created, now));
+    }
+}
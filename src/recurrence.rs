@@ -0,0 +1,193 @@
+//! Templates that make a new order by themselves on schedule
+//!
+//! A recurrence repeats every one to `MAX_EVERY_WEEKS` weeks on a weekday
+//! at the owner's local time. Orders it makes remember the template
+//! as their series, so the owner can skip the next one or stop it.
+
+use chrono::{Datelike, Duration, NaiveTime, Weekday};
+use serde::{Serialize, Deserialize};
+
+use crate::DateTime;
+
+pub const MAX_EVERY_WEEKS: u32 = 4;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recurrence {
+    pub every_weeks: u32,
+    pub weekday: Weekday,
+    /// Local time of the owner
+    pub at: NaiveTime,
+    /// Time zone of the owner when they set it
+    pub utc_offset_mins: i32,
+    /// Publish orders right away instead of leaving it to the owner
+    pub publish: bool,
+    /// When the next order is made
+    pub next_at: DateTime,
+}
+
+/// What the owner asks with `/repeat`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Request {
+    /// Make orders from the template `name` on schedule
+    Set {
+        name: String,
+        every_weeks: u32,
+        weekday: Weekday,
+        at: NaiveTime,
+        publish: bool,
+    },
+    /// Don't make the next order of the series
+    Skip { name: String },
+    /// Don't make orders of the series anymore
+    Stop { name: String },
+}
+
+impl Recurrence {
+    /// Starts with the first time after `now`
+    pub fn new(
+        every_weeks: u32,
+        weekday: Weekday,
+        at: NaiveTime,
+        utc_offset_mins: i32,
+        publish: bool,
+        now: DateTime,
+    ) -> Recurrence {
+        let offset = Duration::minutes(utc_offset_mins as i64);
+        let local_now = now + offset;
+        let days = (7 + weekday.num_days_from_monday() as i64
+            - local_now.weekday().num_days_from_monday() as i64) % 7;
+        let mut next_at = (local_now.date() + Duration::days(days))
+            .and_time(at).unwrap() - offset;
+        if next_at <= now {
+            next_at = next_at + Duration::weeks(1);
+        }
+        Recurrence { every_weeks, weekday, at, utc_offset_mins, publish, next_at }
+    }
+
+    pub fn is_due(&self, now: DateTime) -> bool {
+        self.next_at <= now
+    }
+
+    /// Moves to the next time after `now`, times that have passed
+    /// while we weren't running are skipped
+    pub fn advance(&mut self, now: DateTime) {
+        loop {
+            self.next_at = self.next_at + Duration::weeks(self.every_weeks as i64);
+            if self.next_at > now {
+                break
+            }
+        }
+    }
+
+    /// Like "every 2 weeks on Friday at 10:00"
+    pub fn describe(&self) -> String {
+        let every = match self.every_weeks {
+            1 => "every week".to_string(),
+            n => format!("every {n} weeks"),
+        };
+        let publish = if self.publish { ", published right away" } else { "" };
+        format!("{every} on {} at {}{publish}",
+                weekday_name(self.weekday), self.at.format("%H:%M"))
+    }
+}
+
+const fn weekday_name(weekday: Weekday) -> &'static str {
+    match weekday {
+        Weekday::Mon => "Monday",
+        Weekday::Tue => "Tuesday",
+        Weekday::Wed => "Wednesday",
+        Weekday::Thu => "Thursday",
+        Weekday::Fri => "Friday",
+        Weekday::Sat => "Saturday",
+        Weekday::Sun => "Sunday",
+    }
+}
+
+fn parse_every(s: &str) -> Option<u32> {
+    let weeks = match s {
+        "weekly" => 1,
+        _ => s.strip_suffix('w')?.parse().ok()?,
+    };
+    (1..=MAX_EVERY_WEEKS).contains(&weeks).then_some(weeks)
+}
+
+/// Parses arguments of `/repeat`, like `weekly fri 10:00 Coffee`,
+/// `2w fri 10:00 publish Coffee`, `skip Coffee` or `stop Coffee`
+pub fn parse(args: &str) -> Result<Request, String> {
+    let name_from = |words: &[&str]| -> Result<String, String> {
+        match words.join(" ") {
+            name if name.is_empty() =>
+                Err("Which template? Write its name at the end".to_string()),
+            name => Ok(name),
+        }
+    };
+    let words: Vec<&str> = args.split_whitespace().collect();
+    match words.as_slice() {
+        ["skip", name @ ..] => Ok(Request::Skip { name: name_from(name)? }),
+        ["stop", name @ ..] => Ok(Request::Stop { name: name_from(name)? }),
+        [every, weekday, at, rest @ ..] => {
+            let every_weeks = parse_every(every).ok_or_else(|| format!(
+                "Say weekly or 2w up to {MAX_EVERY_WEEKS}w instead of {every}"))?;
+            let weekday: Weekday = weekday.parse()
+                .map_err(|_| format!("{weekday} isn't a day of the week"))?;
+            let at = NaiveTime::parse_from_str(at, "%H:%M")
+                .map_err(|_| format!("{at} isn't a time like 10:00"))?;
+            let (publish, name) = match rest {
+                ["publish", name @ ..] => (true, name),
+                name => (false, name),
+            };
+            Ok(Request::Set { name: name_from(name)?, every_weeks, weekday,
+                              at, publish })
+        },
+        _ => Err("I don't understand that".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use chrono::offset::Utc;
+
+    #[test]
+    fn test_next_at() {
+        // It's Friday
+        let now = Utc.ymd(2022, 7, 1).and_hms(12, 0, 0);
+        let ten = NaiveTime::from_hms(10, 0, 0);
+
+        // 10:00 in UTC+4 has passed today, so it's the next Friday
+        let mut r = Recurrence::new(2, Weekday::Fri, ten, 4 * 60, false, now);
+        assert_eq!(Utc.ymd(2022, 7, 8).and_hms(6, 0, 0), r.next_at);
+        assert!(!r.is_due(now));
+
+        // Missed times are skipped
+        let later = Utc.ymd(2022, 7, 30).and_hms(0, 0, 0);
+        assert!(r.is_due(later));
+        r.advance(later);
+        assert_eq!(Utc.ymd(2022, 8, 5).and_hms(6, 0, 0), r.next_at);
+
+        let r = Recurrence::new(1, Weekday::Sat, ten, 0, false, now);
+        assert_eq!(Utc.ymd(2022, 7, 2).and_hms(10, 0, 0), r.next_at);
+        assert_eq!("every week on Saturday at 10:00", r.describe());
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(Ok(Request::Set {
+            name: "Coffee beans".to_string(),
+            every_weeks: 2,
+            weekday: Weekday::Fri,
+            at: NaiveTime::from_hms(10, 30, 0),
+            publish: true,
+        }), parse("2w friday 10:30 publish Coffee beans"));
+        assert!(matches!(parse("weekly Mon 9:00 Pills"),
+                         Ok(Request::Set { every_weeks: 1, publish: false, .. })));
+        assert_eq!(Ok(Request::Stop { name: "Pills".to_string() }),
+                   parse("stop Pills"));
+        assert!(parse("5w fri 10:00 Pills").is_err());
+        assert!(parse("weekly someday 10:00 Pills").is_err());
+        assert!(parse("weekly fri 10:00").is_err());
+        assert!(parse("skip").is_err());
+        assert!(parse("").is_err());
+    }
+}
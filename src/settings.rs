@@ -0,0 +1,232 @@
+use serde::{Serialize, Deserialize};
+use teloxide::types::ChatId;
+use chrono::Timelike;
+
+use crate::DateTime;
+use crate::order::OrderId;
+
+/// What a user has chosen in the settings menu
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UserSettings {
+    /// Public chat to use when the user is in several of them
+    #[serde(default)]
+    pub default_chat: Option<ChatId>,
+
+    /// Send orders published in the user's chats to them privately
+    #[serde(default)]
+    pub new_orders: bool,
+
+    /// Private notifications wait or don't make a sound at this time
+    #[serde(default)]
+    pub quiet_hours: Option<QuietHours>,
+
+    /// Time zone of the user, quiet hours are in their local time
+    ///
+    /// Settings saved before there were time zones have quiet hours
+    /// in UTC, so they default to 0 rather than `DEFAULT_UTC_OFFSET_MINS`
+    #[serde(default)]
+    pub utc_offset_mins: i32,
+
+    /// Prices are also shown in this currency in private chats
+    #[serde(default)]
+    pub currency: Currency,
+
+    /// Leave the user out of the couriers leaderboard of `/stats`
+    #[serde(default)]
+    pub hide_in_stats: bool,
+}
+
+/// Most users are in Armenia
+pub const DEFAULT_UTC_OFFSET_MINS: i32 = 4 * 60;
+
+impl Default for UserSettings {
+    fn default() -> Self {
+        UserSettings {
+            default_chat: None,
+            new_orders: false,
+            quiet_hours: None,
+            utc_offset_mins: DEFAULT_UTC_OFFSET_MINS,
+            currency: Currency::default(),
+            hide_in_stats: false,
+        }
+    }
+}
+
+impl UserSettings {
+    /// Time zones users choose from, in minutes
+    pub const MIN_UTC_OFFSET_MINS: i32 = -12 * 60;
+    pub const MAX_UTC_OFFSET_MINS: i32 = 14 * 60;
+
+    /// Tells if it's quiet hours for the user at `now`
+    pub fn is_quiet(&self, now: DateTime) -> bool {
+        let local = now + chrono::Duration::minutes(self.utc_offset_mins as i64);
+        self.quiet_hours.is_some_and(|q| q.contains(local))
+    }
+
+    /// Moves the user's time zone by `hours`, keeping it in range
+    pub fn shift_utc_offset(&mut self, hours: i32) {
+        self.utc_offset_mins = (self.utc_offset_mins + hours * 60)
+            .clamp(Self::MIN_UTC_OFFSET_MINS, Self::MAX_UTC_OFFSET_MINS);
+    }
+
+    /// Time zone as people write it, like UTC+04:00
+    pub fn format_utc_offset(&self) -> String {
+        let sign = if self.utc_offset_mins < 0 { '-' } else { '+' };
+        let mins = self.utc_offset_mins.abs();
+        format!("UTC{sign}{:02}:{:02}", mins / 60, mins % 60)
+    }
+}
+
+/// Notification that waits for the end of the user's quiet hours
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct HeldNotification {
+    pub pcid: ChatId,
+
+    /// The order is shown as it is when the notification is sent,
+    /// None if it's deleted
+    pub order_id: Option<OrderId>,
+
+    /// Shown above the order, HTML
+    pub text: String,
+
+    pub held_at: DateTime,
+}
+
+/// Hours of the day in the user's time zone, `from` is included and `to`
+/// is not, so 23..8 means from 23:00 till 08:00
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct QuietHours {
+    pub from: u32,
+    pub to: u32,
+}
+
+impl QuietHours {
+    /// Quiet hours users choose from
+    pub const PRESETS: &'static [QuietHours] = &[
+        QuietHours { from: 22, to: 7 },
+        QuietHours { from: 23, to: 8 },
+        QuietHours { from: 0,  to: 9 },
+    ];
+
+    pub fn contains(&self, t: DateTime) -> bool {
+        let hour = t.hour();
+        if self.from <= self.to {
+            self.from <= hour && hour < self.to
+        } else {
+            self.from <= hour || hour < self.to
+        }
+    }
+
+    /// The preset after `current`, None after the last one
+    pub fn next_preset(current: Option<QuietHours>) -> Option<QuietHours> {
+        let pos = current.and_then(|c| Self::PRESETS.iter().position(|p| *p == c));
+        match pos {
+            None => Self::PRESETS.first().copied(),
+            Some(pos) => Self::PRESETS.get(pos + 1).copied(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Currency {
+    #[default]
+    Amd,
+    Usd,
+    Eur,
+    Rub,
+}
+
+impl Currency {
+    pub const ALL: &'static [Currency] = &[
+        Currency::Amd,
+        Currency::Usd,
+        Currency::Eur,
+        Currency::Rub,
+    ];
+
+    pub const fn code(self) -> &'static str {
+        match self {
+            Currency::Amd => "AMD",
+            Currency::Usd => "USD",
+            Currency::Eur => "EUR",
+            Currency::Rub => "RUB",
+        }
+    }
+
+    /// Roughly how many drams one unit costs
+    ///
+    /// The built-in rates go stale, `DRAMS_PER_USD` and alike override them
+    pub fn drams_per_unit(self) -> f64 {
+        let default = match self {
+            Currency::Amd => return 1.0,
+            Currency::Usd => 400.0,
+            Currency::Eur => 410.0,
+            Currency::Rub => 7.0,
+        };
+        let var = format!("DRAMS_PER_{}", self.code());
+        match std::env::var(&var) {
+            Ok(s) => match s.parse() {
+                Ok(rate) if rate > 0.0 => rate,
+                _ => {
+                    log::warn!("{var}={s:?} is not an exchange rate");
+                    default
+                },
+            },
+            Err(_) => default,
+        }
+    }
+
+    pub fn next(self) -> Currency {
+        let pos = Self::ALL.iter().position(|c| *c == self).unwrap_or(0);
+        Self::ALL[(pos + 1) % Self::ALL.len()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_settings_without_time_zone_are_in_utc() {
+        let settings: UserSettings = serde_json::from_str(
+            r#"{"quiet_hours":{"from":23,"to":8}}"#).unwrap();
+        assert_eq!(0, settings.utc_offset_mins);
+        assert_eq!(DEFAULT_UTC_OFFSET_MINS, UserSettings::default().utc_offset_mins);
+    }
+
+    #[test]
+    fn test_quiet_hours_wrap_around_midnight() {
+        let at = |h| chrono::offset::Utc.ymd(2022, 7, 1).and_hms(h, 30, 0);
+        let night = QuietHours { from: 23, to: 8 };
+        assert!(night.contains(at(23)));
+        assert!(night.contains(at(0)));
+        assert!(night.contains(at(7)));
+        assert!(!night.contains(at(8)));
+        assert!(!night.contains(at(22)));
+
+        let mut settings = UserSettings {
+            quiet_hours: Some(night),
+            utc_offset_mins: 0,
+            ..UserSettings::default()
+        };
+        assert!(!settings.is_quiet(at(20)));
+        settings.shift_utc_offset(4);
+        assert_eq!("UTC+04:00", settings.format_utc_offset());
+        assert!(settings.is_quiet(at(20)));
+        assert!(!settings.is_quiet(at(4)));
+        settings.shift_utc_offset(-100);
+        assert_eq!("UTC-12:00", settings.format_utc_offset());
+
+        let morning = QuietHours { from: 0, to: 9 };
+        assert!(morning.contains(at(0)));
+        assert!(!morning.contains(at(23)));
+
+        let mut quiet = None;
+        for preset in QuietHours::PRESETS {
+            quiet = QuietHours::next_preset(quiet);
+            assert_eq!(Some(*preset), quiet);
+        }
+        assert_eq!(None, QuietHours::next_preset(quiet));
+    }
+}
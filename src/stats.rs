@@ -0,0 +1,197 @@
+use std::collections::BTreeMap;
+
+use chrono::Duration;
+use teloxide::types::UserId;
+
+use crate::order::{Order, Status};
+use crate::DateTime;
+
+/// Periods `/stats` can cover
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Period {
+    Day,
+    #[default]
+    Week,
+    Month,
+    All,
+}
+
+impl Period {
+    pub const ALL: &'static [Period] = &[
+        Period::Day,
+        Period::Week,
+        Period::Month,
+        Period::All,
+    ];
+
+    pub const fn id(self) -> &'static str {
+        match self {
+            Period::Day   => "day",
+            Period::Week  => "week",
+            Period::Month => "month",
+            Period::All   => "all",
+        }
+    }
+
+    pub fn maybe_from_id(s: &str) -> Option<Period> {
+        Self::ALL.iter().copied().find(|period| period.id() == s)
+    }
+
+    /// When the period that ends at `now` has started, None for all time
+    pub fn since(self, now: DateTime) -> Option<DateTime> {
+        match self {
+            Period::Day   => Some(now - Duration::days(1)),
+            Period::Week  => Some(now - Duration::weeks(1)),
+            Period::Month => Some(now - Duration::days(30)),
+            Period::All   => None,
+        }
+    }
+}
+
+/// What happened to orders of a public chat during a period
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Stats {
+    pub created: usize,
+    pub published: usize,
+    pub delivered: usize,
+    pub canceled: usize,
+
+    /// From publishing to a courier taking it, of orders taken during
+    /// the period
+    pub median_to_assign: Option<Duration>,
+
+    /// From a courier taking it to the owner confirming delivery,
+    /// of orders delivered during the period
+    pub median_to_deliver: Option<Duration>,
+
+    /// Prices of items of delivered orders, without rewards
+    pub delivered_value_in_drams: u64,
+
+    /// Couriers and how many orders they've delivered, most first
+    pub couriers: Vec<(UserId, usize)>,
+}
+
+fn median(mut durations: Vec<Duration>) -> Option<Duration> {
+    durations.sort();
+    let mid = durations.len() / 2;
+    match durations.len() {
+        0 => None,
+        len if len % 2 == 1 => Some(durations[mid]),
+        _ => Some((durations[mid - 1] + durations[mid]) / 2),
+    }
+}
+
+impl Stats {
+    /// Stats of `orders`, live and archived ones, since `since`
+    pub fn new(orders: &[Order], since: Option<DateTime>) -> Stats {
+        let during = |t: Option<DateTime>| match (t, since) {
+            (Some(t), Some(since)) => t >= since,
+            (Some(_), None) => true,
+            (None, _) => false,
+        };
+        let assigned_at = |o: &Order| o.assigned.as_ref().map(|a| a.0);
+        let delivered: Vec<&Order> = orders.iter()
+            .filter(|o| o.status() == Status::DeliveryConfirmed)
+            .filter(|o| during(o.delivery_confirmed_at))
+            .collect();
+
+        let mut couriers: BTreeMap<UserId, usize> = BTreeMap::new();
+        for (_at, uid, _user) in delivered.iter().filter_map(|o| o.assigned.as_ref()) {
+            *couriers.entry(*uid).or_default() += 1;
+        }
+        let mut couriers: Vec<(UserId, usize)> = couriers.into_iter().collect();
+        // Stable, so couriers with as many deliveries stay in order of ids
+        couriers.sort_by_key(|(_uid, delivered)| std::cmp::Reverse(*delivered));
+
+        Stats {
+            created: orders.iter().filter(|o| during(Some(o.created_at))).count(),
+            published: orders.iter().filter(|o| during(o.published_at)).count(),
+            delivered: delivered.len(),
+            canceled: orders.iter().filter(|o| during(o.canceled_at)).count(),
+            median_to_assign: median(orders.iter()
+                .filter(|o| during(assigned_at(o)))
+                .filter_map(|o| Some(assigned_at(o)? - o.published_at?))
+                .collect()),
+            median_to_deliver: median(delivered.iter()
+                .filter_map(|o| Some(o.delivery_confirmed_at? - assigned_at(o)?))
+                .collect()),
+            delivered_value_in_drams: delivered.iter()
+                .map(|o| o.cost_in_drams())
+                .sum(),
+            couriers,
+        }
+    }
+}
+
+/// What a user has done in a public chat, shown in their profile
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct UserStats {
+    /// Created and not taken by a courier yet, canceled ones aren't counted
+    pub open: usize,
+    /// Created and being delivered
+    pub being_delivered: usize,
+    /// Created and delivered
+    pub completed: usize,
+    /// Taken by the user and not delivered yet
+    pub delivering: usize,
+    /// Delivered by the user
+    pub delivered: usize,
+    /// Disputes of orders the user has created or delivered
+    pub disputes: usize,
+}
+
+impl UserStats {
+    /// Stats of `uid` from orders of one public chat
+    pub fn new(uid: UserId, orders: &[Order]) -> UserStats {
+        let mut stats = UserStats::default();
+        for order in orders {
+            let is_owner = order.customer.id == uid;
+            let is_courier = order.assigned.as_ref()
+                .is_some_and(|(_at, courier, _user)| *courier == uid);
+            if !is_owner && !is_courier {
+                continue
+            }
+            let (mine, theirs) = match order.status() {
+                Status::Unpublished if order.canceled_at.is_some() =>
+                    (None, None),
+                Status::Unpublished | Status::Published =>
+                    (Some(&mut stats.open), None),
+                Status::Assigned | Status::MarkedAsDelivered
+                    | Status::Disputed =>
+                    (Some(&mut stats.being_delivered), Some(&mut stats.delivering)),
+                Status::DeliveryConfirmed =>
+                    (Some(&mut stats.completed), Some(&mut stats.delivered)),
+            };
+            if let (true, Some(count)) = (is_owner, mine) {
+                *count += 1;
+            }
+            if let (true, Some(count)) = (is_courier, theirs) {
+                *count += 1;
+            }
+            stats.disputes += order.disputes.len();
+        }
+        stats
+    }
+
+    pub fn add(&mut self, other: UserStats) {
+        self.open += other.open;
+        self.being_delivered += other.being_delivered;
+        self.completed += other.completed;
+        self.delivering += other.delivering;
+        self.delivered += other.delivered;
+        self.disputes += other.disputes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_median() {
+        let mins = |m: &[i64]| m.iter().map(|m| Duration::minutes(*m)).collect();
+        assert_eq!(None, median(mins(&[])));
+        assert_eq!(Some(Duration::minutes(5)), median(mins(&[9, 5, 1])));
+        assert_eq!(Some(Duration::minutes(4)), median(mins(&[9, 5, 3, 1])));
+    }
+}
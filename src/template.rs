@@ -0,0 +1,68 @@
+//! Orders users save to create them again later
+//!
+//! People reorder the same things over and over, a template keeps
+//! everything but who and when, so a new order starts prefilled from it.
+
+use serde::{Serialize, Deserialize};
+
+use crate::order::Draft;
+use crate::recurrence::Recurrence;
+
+/// How many templates a user can have
+pub const MAX_TEMPLATES: usize = 20;
+
+/// Names are shown on buttons, so they better be short
+pub const MAX_NAME_LEN: usize = 40;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Template {
+    /// Unique among templates of the user
+    pub name: String,
+    pub draft: Draft,
+    /// Makes orders by itself when set
+    #[serde(default)]
+    pub recurrence: Option<Recurrence>,
+}
+
+/// Identifies the template named `name` in buttons, it doesn't change
+/// when other templates are added or deleted
+///
+/// A name may not fit in callback data, so it's an FNV-1a hash of it
+pub fn key(name: &str) -> u64 {
+    name.bytes().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ b as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Checks a name the user has written, returns it trimmed
+pub fn check_name(name: &str) -> Result<&str, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("The name is empty, please write one".to_string())
+    }
+    if name.chars().count() > MAX_NAME_LEN {
+        return Err(format!("That's too long, please keep it under \
+{MAX_NAME_LEN} characters"))
+    }
+    Ok(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_name() {
+        assert_eq!(Ok("Coffee"), check_name("  Coffee\n"));
+        assert!(check_name(" ").is_err());
+        assert!(check_name(&"a".repeat(MAX_NAME_LEN + 1)).is_err());
+        assert!(check_name(&"ա".repeat(MAX_NAME_LEN)).is_ok());
+    }
+
+    #[test]
+    fn test_key() {
+        assert_eq!(0xcbf29ce484222325, key(""));
+        assert_eq!(0xaf63dc4c8601ec8c, key("a"));
+        assert_ne!(key("Coffee"), key("Coffeе"));
+    }
+}
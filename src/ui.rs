@@ -4,7 +4,7 @@ use teloxide::{
 };
 use serde::{Serialize, Deserialize};
 
-use std::sync::Arc;
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
 
 mod collect_data;
@@ -24,9 +24,26 @@ pub use list_active_orders::list_active_orders;
 pub mod commands;
 pub mod order;
 pub mod order_action;
+pub mod cancel_order;
+pub mod dispute;
+pub mod report_order;
+pub mod moderation;
+pub mod limits;
+pub mod board;
+pub mod digest;
+pub mod archive;
+pub mod stats;
+pub mod settings;
+pub mod quiet_hours;
+pub mod privacy;
 pub mod say_hello;
 pub mod help;
 pub mod me;
+pub mod template;
+pub mod recurring;
+pub mod items;
+pub mod settlement;
+pub mod ledger;
 
 
 use crate::error::Error;
@@ -37,6 +54,7 @@ pub type MyDialogue = Dialogue<State, dialogue::ErasedStorage<State>>;
 pub type MyStorage = Arc<dialogue::ErasedStorage<State>>;
 
 use crate::data_gathering;
+use crate::outbox;
 pub const TEMP_MSG_TIMEOUT_MS: u64 = 60_000;
 pub const TEMP_MSG_TIMEOUT: std::time::Duration =
     std::time::Duration::from_millis(TEMP_MSG_TIMEOUT_MS);
@@ -48,7 +66,12 @@ pub const TEMP_MSG_FAST_TIMEOUT: std::time::Duration =
 pub enum State {
     #[default]
     Start,
-    NewOrder(new_order::State)
+    NewOrder(new_order::State),
+    CancelOrder(cancel_order::State),
+    Dispute(dispute::State),
+    Template(template::State),
+    Items(items::State),
+    Settlement(settlement::State),
 }
 
 pub async fn pcid_or_err(bot: &AutoSend<Bot>, db: &mut crate::Db,
@@ -59,25 +82,38 @@ pub async fn pcid_or_err(bot: &AutoSend<Bot>, db: &mut crate::Db,
         Ok(pcid) => Ok(pcid),
         Err(e) => {
             log::warn!("-> handle_callback_query pcid: {e:?}");
-            bot.send_message(dialogue.chat_id(), format!("{e}")).await?;
+            outbox::send(bot.send_message(dialogue.chat_id(), format!("{e}")))
+                .await?;
             Err(format!("{e:?}").into())
         }
     }
 }
 
+static BOT_USERNAME: OnceLock<String> = OnceLock::new();
+
+/// Username of the bot, Telegram is asked only once
+pub async fn bot_username(bot: &AutoSend<Bot>) -> Result<String, Error> {
+    if let Some(username) = BOT_USERNAME.get() {
+        return Ok(username.clone())
+    }
+    let me = bot.get_me().await?;
+    let username = me.user.username.clone().ok_or("the bot has no username")?;
+    Ok(BOT_USERNAME.get_or_init(|| username).clone())
+}
+
 pub async fn text_msg(
     duration: Option<Duration>,
     bot: AutoSend<Bot>,
     cid: ChatId,
     text: &str,
 ) -> Result<(), Error> {
-    let msg = bot.send_message(cid, text).await?;
+    let msg = outbox::send(bot.send_message(cid, text)).await?;
     if let Some(duration) = duration {
         tokio::spawn(async move {
             log::debug!("deleting temp msg");
             tokio::time::sleep(duration).await;
             // we don't care if we couldn't delete the message
-            let _ = bot.delete_message(cid, msg.id).await;
+            let _ = outbox::send(bot.delete_message(cid, msg.id)).await;
         });
     }
     Ok(())
@@ -90,13 +126,13 @@ pub async fn html_msg(
     text: &str,
 ) -> Result<(), Error> {
     let bot = bot.parse_mode(teloxide::types::ParseMode::Html);
-    let msg = bot.send_message(cid, text).await?;
+    let msg = outbox::send(bot.send_message(cid, text)).await?;
     if let Some(duration) = duration {
         tokio::spawn(async move {
             log::debug!("deleting temp msg");
             tokio::time::sleep(duration).await;
             // we don't care if we couldn't delete the message
-            let _ = bot.delete_message(cid, msg.id).await;
+            let _ = outbox::send(bot.delete_message(cid, msg.id)).await;
         });
     }
     Ok(())
@@ -0,0 +1,93 @@
+//! Archive of completed orders of a public chat
+//!
+//! `/archive` tells how many orders are archived, admins destroy
+//! an archived order for good with `/archive delete <order id>`.
+
+use teloxide::{
+    prelude::*,
+    types::ChatKind,
+};
+
+use crate::archive;
+use crate::error::Error;
+use crate::Db;
+use crate::order::{OrderId, Status};
+use crate::ui::{self, HandlerResult};
+use crate::ui::commands::Command;
+use crate::{data_gathering, outbox};
+
+async fn format_archive(db: &mut Db, pcid: ChatId) -> Result<String, Error> {
+    let orders = db.archived_orders(pcid).await?;
+    let delivered = orders.iter()
+        .filter(|o| o.status() == Status::DeliveryConfirmed)
+        .count();
+    let canceled = orders.iter()
+        .filter(|o| o.canceled_at.is_some())
+        .count();
+    let when = match archive::archive_after() {
+        Some(after) => format!("Orders are archived {} days after they're \
+delivered or canceled, or when they're deleted.", after.num_days()),
+        None => "Archiving is turned off, orders are only archived when \
+they're deleted.".to_string(),
+    };
+    Ok(format!("Archive of the chat: {} orders, {delivered} of them delivered \
+and {canceled} canceled.\n{when}\n\n\
+Admins can delete an archived order for good like this: {} delete 42",
+        orders.len(), Command::Archive(String::new())))
+}
+
+/// Shows the archive of the user's public chat or, if they're an admin,
+/// deletes an archived order
+pub async fn handle_command(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    msg: &Message,
+    args: &str,
+) -> HandlerResult {
+    log::info!("-> archive::handle_command {args:?}");
+    let cid = msg.chat.id;
+    let uid = match msg.from() {
+        Some(user) => user.id,
+        None => {
+            log::warn!("/archive: no user in msg {msg:?}");
+            return Ok(())
+        },
+    };
+    let pcid = if let ChatKind::Public(_) = msg.chat.kind {
+        msg.chat.id
+    } else {
+        let chats = data_gathering::user_public_chats(&mut db, uid).await?;
+        match chats.as_slice() {
+            [(pcid, _name)] => *pcid,
+            _ => {
+                outbox::send(bot.send_message(cid,
+                    "Please send it to the public chat you want the archive \
+of")).await?;
+                return Ok(())
+            },
+        }
+    };
+
+    let mut args = args.split_whitespace();
+    let text = match (args.next(), args.next().map(str::parse), args.next()) {
+        (None, _, _) => format_archive(&mut db, pcid).await?,
+        (Some("delete"), Some(Ok(oid)), None) => {
+            if ! data_gathering::is_chat_admin(&bot, &mut db, pcid, uid).await {
+                ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT), bot, cid,
+                    "Only admins of the chat can delete archived orders")
+                    .await?;
+                return Ok(())
+            }
+            let oid = OrderId(oid);
+            if db.delete_archived_order(pcid, uid, oid).await? {
+                format!("Archived order {oid} is deleted")
+            } else {
+                format!("There's no archived order {oid}")
+            }
+        },
+        _ => format!("I don't understand that.\n\n{}",
+                     format_archive(&mut db, pcid).await?),
+    };
+    ui::text_msg(Some(ui::TEMP_MSG_TIMEOUT), bot, cid, &text).await?;
+    Ok(())
+}
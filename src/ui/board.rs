@@ -0,0 +1,84 @@
+//! Pinned message in a public chat that lists published orders
+//!
+//! The board is edited in place whenever the list might change. If the
+//! message is gone, because somebody deleted it, we send and pin a new one.
+
+use teloxide::{
+    prelude::*,
+    types::{MessageId, ParseMode},
+    ApiError, RequestError,
+};
+
+use crate::error::Error;
+use crate::Db;
+use crate::order::{Order, Status};
+use crate::{markup, moderation, outbox};
+use crate::DateTime;
+
+const TITLE: &str = "📋 Open orders";
+
+pub fn format_line(order: &Order, now: DateTime) -> String {
+    let name = markup::bold(markup::escape_html(&order.name).to_string());
+    let price = markup::format_amd(order.price_in_drams);
+    let reward = if order.markup_in_drams > 0 {
+        format!(", reward {}", markup::format_amd(order.markup_in_drams))
+    } else {
+        "".to_string()
+    };
+    let age = order.published_at
+        .map(|t| format!(", published {}", markup::time_ago(t, now)))
+        .unwrap_or_default();
+    format!("{name}: {price}{reward}{age}")
+}
+
+fn format(orders: &[Order], now: DateTime) -> String {
+    if orders.is_empty() {
+        return format!("{TITLE}\n\nNo open orders right now")
+    }
+    let lines: Vec<String> = orders.iter()
+        .map(|o| format_line(o, now))
+        .collect();
+    format!("{TITLE}\n\n{}", lines.join("\n"))
+}
+
+/// Re-renders the board of `pcid` from its published orders
+pub async fn update(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    pcid: ChatId,
+) -> Result<(), Error> {
+    log::info!("-> board::update {pcid}");
+    let reports_to_hide = moderation::reports_to_hide();
+    let mut orders: Vec<Order> = db.orders_by_status(pcid, Status::Published)
+        .await?
+        .into_iter()
+        .filter(|o| !o.is_hidden(reports_to_hide))
+        .collect();
+    orders.sort_by_key(|o| o.published_at);
+    let text = format(&orders, db.now());
+
+    let bot = bot.parse_mode(ParseMode::Html);
+    if let Some(mid) = db.board_msg_id(pcid).await? {
+        let edited = outbox::send(
+            bot.edit_message_text(pcid, mid.message_id, &text)).await;
+        match edited {
+            Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) =>
+                return Ok(()),
+            Err(RequestError::Api(ApiError::MessageToEditNotFound))
+            | Err(RequestError::Api(ApiError::MessageIdInvalid)) =>
+                log::info!("board of {pcid} is gone, sending a new one"),
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let msg = outbox::send(bot.send_message(pcid, text)).await?;
+    db.set_board_msg_id(pcid, MessageId { message_id: msg.id }).await?;
+    let pinned = outbox::send(
+        bot.pin_chat_message(pcid, msg.id).disable_notification(true)).await;
+    if let Err(e) = pinned {
+        // Admins of the chat might not let us pin messages, the board
+        // is still useful without that
+        log::warn!("could not pin the board of {pcid}: {e:?}");
+    }
+    Ok(())
+}
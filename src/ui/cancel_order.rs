@@ -0,0 +1,209 @@
+//! Asking the owner why they cancel an order
+//!
+//! Clicking `Cancel` under an order doesn't cancel it right away, first
+//! the owner picks one of the preset reasons, writes their own one,
+//! or says they'd rather not tell.
+
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup},
+    dispatching::UpdateHandler,
+};
+use serde::{Serialize, Deserialize};
+
+use crate::error::Error;
+use crate::Db;
+use crate::order::{self, ActionError, ActionKind, OrderId};
+use crate::ui::{self, HandlerResult, MyDialogue};
+use crate::logger;
+use crate::outbox;
+
+const BTN_DATA_PREFIX: &str = "oc";
+
+/// Reasons the owner can pick instead of writing one: (id, text)
+const PRESET_REASONS: &[(&str, &str)] = &[
+    ("plans_changed", "My plans have changed"),
+    ("not_needed",    "I don't need it anymore"),
+    ("got_it",        "I've got it elsewhere"),
+    ("too_long",      "It's taking too long"),
+];
+
+/// Button id for writing a reason in a message
+const OWN_REASON: &str = "own";
+
+/// Button id for canceling without a reason
+const NO_REASON: &str = "none";
+
+/// Telegram allows much longer messages, but it's a reason, not a letter
+const MAX_REASON_LEN: usize = 500;
+
+/// Waiting for the owner to write why they cancel the order
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct State {
+    pub pcid: ChatId,
+    pub order_id: OrderId,
+}
+
+pub fn schema() -> UpdateHandler<Error> {
+    Update::filter_message()
+        .endpoint(receive_reason)
+}
+
+fn button(reason_id: &str, oid: OrderId, text: &str) -> InlineKeyboardButton {
+    let data = format!("{BTN_DATA_PREFIX} {reason_id} {oid}");
+    InlineKeyboardButton::callback(text.to_string(), data)
+}
+
+fn reasons_keyboard(oid: OrderId) -> InlineKeyboardMarkup {
+    let mut rows: Vec<Vec<InlineKeyboardButton>> = PRESET_REASONS.iter()
+        .map(|(id, text)| vec![button(id, oid, text)])
+        .collect();
+    rows.push(vec![
+        button(OWN_REASON, oid, "Write my own reason"),
+        button(NO_REASON, oid, "No reason"),
+    ]);
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// Asks `uid` why they cancel the order, if they're allowed to cancel it
+pub async fn ask_reason(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    dialogue: MyDialogue,
+    uid: UserId,
+    pcid: ChatId,
+    oid: OrderId,
+) -> HandlerResult {
+    log::info!("-> ask_reason {oid}");
+    let order = db.get_order(pcid, oid).await?;
+    let checked = match &order {
+        Some(order) => order.check_action(uid, false, ActionKind::Cancel)
+            .map(|_| ()),
+        None => Err(ActionError::OrderNotFound(oid)),
+    };
+    if let Err(e) = checked {
+        log::warn!("ask_reason {uid} {pcid} => {e:?}");
+        ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT),
+                     bot, dialogue.chat_id(), &format!("{e}")).await?;
+        return Ok(())
+    }
+
+    outbox::send(
+        bot.send_message(dialogue.chat_id(), "Why are you canceling the order?")
+            .reply_markup(reasons_keyboard(oid))).await?;
+    Ok(())
+}
+
+/// If it's a cancellation reason query then handle it and return `true`,
+/// otherwise just return `false`
+pub async fn try_handle_query(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    dialogue: MyDialogue,
+    q: CallbackQuery,
+    data: &str,
+) -> Result<bool, Error> {
+    let mut args = data.split(' ');
+    if args.next() != Some(BTN_DATA_PREFIX) {
+        return Ok(false)
+    }
+    let reason_id = args.next();
+    let oid = args.next().and_then(|oid| oid.parse().ok()).map(OrderId);
+    let (reason_id, oid) = match (reason_id, oid, args.next()) {
+        (Some(reason_id), Some(oid), None) => (reason_id, oid),
+        _ => {
+            log::warn!("cancel_order: malformed data {data:?}");
+            return Ok(true)
+        }
+    };
+    logger::set_handler("cancel_order");
+    logger::set_order(oid);
+    log::info!("-> cancel_order::try_handle_query {reason_id} {oid}");
+
+    let pcid = ui::pcid_or_err(&bot, &mut db, &q, &dialogue).await;
+    if pcid.is_err() {
+        // We've already told the user what's wrong
+        return Ok(true)
+    }
+    let pcid = pcid.unwrap();
+
+    let note = match reason_id {
+        OWN_REASON => {
+            dialogue.update(ui::State::CancelOrder(
+                State { pcid, order_id: oid })).await?;
+            outbox::send(bot.send_message(dialogue.chat_id(),
+                             "Write why you're canceling the order")).await?;
+            if let Some(msg) = q.message {
+                outbox::send(bot.delete_message(msg.chat.id, msg.id)).await?;
+            }
+            return Ok(true)
+        },
+        NO_REASON => None,
+        _ => match PRESET_REASONS.iter().find(|(id, _)| *id == reason_id) {
+            Some((_id, text)) => Some(text.to_string()),
+            None => {
+                log::warn!("cancel_order: unknown reason {reason_id:?}");
+                return Ok(true)
+            }
+        },
+    };
+
+    let action = order::Action {
+        kind: ActionKind::Cancel,
+        order_id: oid,
+        note,
+        settlement: None,
+    };
+    ui::order_action::handle_order_action(
+        bot.clone(), q.from, false, pcid, action, db, dialogue).await?;
+
+    // The reason doesn't matter anymore whether it's canceled or not
+    if let Some(msg) = q.message {
+        outbox::send(bot.delete_message(msg.chat.id, msg.id)).await?;
+    }
+    Ok(true)
+}
+
+async fn receive_reason(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    db: Db,
+    dialogue: MyDialogue,
+    state: State,
+) -> HandlerResult {
+    logger::set_handler("cancel_order::receive_reason");
+    logger::set_order(state.order_id);
+    log::info!("-> receive_reason {state:?}");
+
+    let text = msg.text().map(str::trim).unwrap_or("");
+    if text.is_empty() {
+        outbox::send(bot.send_message(dialogue.chat_id(),
+            "Please write why you're canceling the order in a message"))
+            .await?;
+        return Ok(())
+    }
+    if text.chars().count() > MAX_REASON_LEN {
+        outbox::send(bot.send_message(dialogue.chat_id(),
+            format!("That's too long, please keep it under \
+{MAX_REASON_LEN} characters"))).await?;
+        return Ok(())
+    }
+
+    let user = msg.from();
+    if user.is_none() {
+        log::warn!("receive_reason No user in msg {msg:?}");
+        return Err(format!("No user is msg {msg:?}").into());
+    }
+    let user = user.unwrap().clone();
+
+    dialogue.exit().await?;
+    let action = order::Action {
+        kind: ActionKind::Cancel,
+        order_id: state.order_id,
+        note: Some(text.to_string()),
+        settlement: None,
+    };
+    ui::order_action::handle_order_action(
+        bot, user, false, state.pcid, action, db, dialogue).await?;
+    Ok(())
+}
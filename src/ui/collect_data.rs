@@ -1,6 +1,7 @@
 use crate::db::Db;
 use crate::ui::HandlerResult;
-use crate::data_gathering;
+use crate::data_gathering::{self, remember_user};
+use crate::collection::{self, Source};
 use teloxide::{
     prelude::*,
     types::UpdateKind,
@@ -18,13 +19,18 @@ pub async fn collect_data(
     update: Update,
 ) -> HandlerResult {
     log::debug!("update: {:?}", update);
+    let policy = collection::policy();
+    match Source::of(&update.kind) {
+        Some(source) if policy.records(source) => {},
+        _ => return Ok(()),
+    }
     let ret = match update.kind {
         UpdateKind::Message(m) => collect_data_from_msg(db.clone(), m).await,
         UpdateKind::EditedMessage(m) => collect_data_from_msg(db.clone(), m).await,
         UpdateKind::ChannelPost(m) => collect_data_from_msg(db.clone(), m).await,
         UpdateKind::EditedChannelPost(m) => collect_data_from_msg(db.clone(), m).await,
-        UpdateKind::InlineQuery(q) => db.update_user(q.from).await,
-        UpdateKind::ChosenInlineResult(cir) => db.update_user(cir.from).await,
+        UpdateKind::InlineQuery(q) => remember_user(&mut db, &q.from).await,
+        UpdateKind::ChosenInlineResult(cir) => remember_user(&mut db, &cir.from).await,
         UpdateKind::CallbackQuery(cq) => {
             if let Some(msg) = cq.message {
                 collect_data_from_msg(db.clone(), msg).await
@@ -32,32 +38,34 @@ pub async fn collect_data(
                 Ok(())
             }
         },
-        UpdateKind::ShippingQuery(sq) => db.update_user(sq.from).await,
-        UpdateKind::PreCheckoutQuery(pcq) => db.update_user(pcq.from).await,
+        UpdateKind::ShippingQuery(sq) => remember_user(&mut db, &sq.from).await,
+        UpdateKind::PreCheckoutQuery(pcq) => remember_user(&mut db, &pcq.from).await,
         UpdateKind::Poll(_poll) => Ok(()),
-        UpdateKind::PollAnswer(pa) => db.update_user(pa.user).await,
+        UpdateKind::PollAnswer(pa) => remember_user(&mut db, &pa.user).await,
         UpdateKind::MyChatMember(cmu) => {
             db.update_chat(cmu.chat).await?;
-            db.update_user(cmu.from).await?;
-            db.update_user(cmu.new_chat_member.user).await?;
+            remember_user(&mut db, &cmu.from).await?;
+            remember_user(&mut db, &cmu.new_chat_member.user).await?;
             Ok(())
         }
         UpdateKind::ChatMember(cmu) => {
             db.update_chat(cmu.chat).await?;
-            db.update_user(cmu.from).await?;
-            db.update_user(cmu.new_chat_member.user).await?;
+            remember_user(&mut db, &cmu.from).await?;
+            remember_user(&mut db, &cmu.new_chat_member.user).await?;
             Ok(())
         }
         UpdateKind::ChatJoinRequest(cjr) => {
             db.update_chat(cjr.chat).await?;
-            db.update_user(cjr.from).await?;
+            remember_user(&mut db, &cjr.from).await?;
             Ok(())
         }
         UpdateKind::Error(_) => Ok(()),
     };
 
-    db.debug_stats().await?;
+    // It's three queries, not worth it on every update unless debugging
+    if log::log_enabled!(log::Level::Debug) {
+        db.debug_stats().await?;
+    }
 
     ret
 }
-
@@ -1,6 +1,8 @@
 use crate::Db;
 use crate::ui::{self, HandlerResult};
 use crate::MyDialogue;
+use crate::logger;
+use crate::outbox;
 use teloxide::{
     prelude::*,
     utils::command::BotCommands,
@@ -12,7 +14,7 @@ use std::fmt;
           description = "These commands are supported:")]
 pub enum Command {
     #[command(description = "Start here")]
-    Start,
+    Start(String),
     #[command(description = "Show main menu")]
     Menu,
     #[command(description = "Show how to use me")]
@@ -21,20 +23,47 @@ pub enum Command {
     NewOrder,
     #[command(description = "Get the bot to know you")]
     Hello,
-    #[command(description = "What the bot knows about you (mostly for debugging)")]
+    #[command(description = "Your profile: chats, orders and deliveries")]
     Me,
+    #[command(description = "Your order templates, create an order from one")]
+    Templates,
+    #[command(description = "Make orders from a template on schedule, like: weekly fri 10:00 Coffee")]
+    Repeat(String),
+    #[command(description = "What you owe and are owed in your chats")]
+    Balance,
+    #[command(description = "Show limits of the chat, admins can change them")]
+    Limits(String),
+    #[command(description = "Daily digest of orders: subscribe, or set its time if you're an admin")]
+    Digest(String),
+    #[command(description = "Archived orders of the chat, admins can delete them")]
+    Archive(String),
+    #[command(description = "Stats and top couriers of the chat: day, week, month or all")]
+    Stats(String),
+    #[command(description = "Get everything the bot knows about you as a file")]
+    MyData,
+    #[command(description = "Ask the bot to forget you")]
+    ForgetMe,
 }
 
 impl Command {
     /// Function for printing a command
     pub const fn cmd(&self) -> &'static str {
         match self {
-            Command::Start    => "/start",
+            Command::Start(_) => "/start",
             Command::Menu     => "/menu",
             Command::Help     => "/help",
             Command::NewOrder => "/new_order",
             Command::Hello    => "/hello",
             Command::Me       => "/me",
+            Command::Templates => "/templates",
+            Command::Repeat(_) => "/repeat",
+            Command::Balance  => "/balance",
+            Command::Limits(_) => "/limits",
+            Command::Digest(_) => "/digest",
+            Command::Archive(_) => "/archive",
+            Command::Stats(_) => "/stats",
+            Command::MyData   => "/my_data",
+            Command::ForgetMe => "/forget_me",
         }
     }
 }
@@ -52,29 +81,68 @@ pub async fn handle_command(
     command: Command,
     db: Db,
 ) -> HandlerResult {
+    logger::set_handler("handle_command");
+    log::info!("-> handle_command {command}");
     let msg_id = msg.id;
     let user = msg.from();
     let cid = msg.chat.id;
     match command {
-        Command::Start    => { ui::main_menu::main_menu(bot.clone(), cid).await? },
+        Command::Start(args) => {
+            let is_handled = ui::me::try_handle_start(
+                bot.clone(), db, cid, user, &args).await?;
+            if !is_handled {
+                ui::main_menu::main_menu(bot.clone(), cid).await?
+            }
+        },
         Command::Menu     => { ui::main_menu::main_menu(bot.clone(), cid).await? },
         Command::Hello    => { ui::say_hello::say_hello(bot.clone(), cid, msg.from()).await? },
-        Command::Help     => { bot.clone().send_message(cid, ui::help::help()).await?; },
+        Command::Help     => {
+            outbox::send(bot.clone().send_message(cid, ui::help::help()))
+                .await?;
+        },
         Command::Me       => { ui::me::send_me(bot.clone(), db, cid, user).await?; },
+        Command::Templates => {
+            ui::template::send_templates(bot.clone(), db, user).await?
+        },
+        Command::Repeat(args) => {
+            ui::recurring::handle_command(bot.clone(), db, &msg, &args).await?
+        },
+        Command::Balance  => {
+            ui::ledger::send_balance(bot.clone(), db, user).await?
+        },
+        Command::Limits(args) => {
+            ui::limits::handle_command(bot.clone(), db, &msg, &args).await?
+        },
+        Command::Digest(args) => {
+            ui::digest::handle_command(bot.clone(), db, &msg, &args).await?
+        },
+        Command::Archive(args) => {
+            ui::archive::handle_command(bot.clone(), db, &msg, &args).await?
+        },
+        Command::Stats(args) => {
+            ui::stats::handle_command(bot.clone(), db, &msg, &args).await?
+        },
+        Command::MyData   => {
+            ui::privacy::send_my_data(bot.clone(), db, cid, user).await?
+        },
+        Command::ForgetMe => {
+            ui::privacy::ask_forget_me(bot.clone(), db, user).await?
+        },
         Command::NewOrder => {
             if let Some(user) = user {
                 ui::new_order::start(
                     bot.clone(), db, dialogue, cid, user.id).await?
             } else {
                 log::warn!("/new_order: could get user from msg {msg:?}");
-                bot.send_message(cid, "We don't know who you are. Thanks Telegram!
-Anyway, please try again and it should work.").await?;
+                outbox::send(bot.send_message(cid,
+                    "We don't know who you are. Thanks Telegram!
+Anyway, please try again and it should work.")).await?;
             }
         }
     }
 
     // Delete the command message after the command is handled
-    bot.delete_message(cid, msg_id).await?;
+    outbox::send(bot.delete_message(cid, msg_id)).await?;
     Ok(())
 }
 
@@ -0,0 +1,212 @@
+//! Daily digest of orders for members that mute the group
+//!
+//! A background task checks every minute which public chats are due
+//! for their digest, posts it there and sends it to subscribers.
+//! `/digest` shows the settings, lets members subscribe and admins
+//! change the time or turn it off.
+
+use std::time::Duration;
+
+use chrono::NaiveTime;
+use teloxide::{
+    prelude::*,
+    types::{ChatKind, ParseMode},
+};
+
+use crate::error::Error;
+use crate::Db;
+use crate::digest::{Digest, DigestSettings};
+use crate::order::{Order, Status};
+use crate::ui::{self, HandlerResult};
+use crate::ui::commands::Command;
+use crate::{data_gathering, markup, moderation, outbox};
+use crate::DateTime;
+
+/// How often we look for digests to send
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+fn format_delivered(order: &Order, now: DateTime) -> String {
+    let name = markup::bold(markup::escape_html(&order.name).to_string());
+    let when = order.delivery_confirmed_at
+        .map(|t| format!(", delivered {}", markup::time_ago(t, now)))
+        .unwrap_or_default();
+    format!("{name}{when}")
+}
+
+fn format_section(title: &str, lines: Vec<String>) -> Option<String> {
+    if lines.is_empty() {
+        return None
+    }
+    Some(format!("{} ({})\n{}", markup::bold(title), lines.len(),
+                 lines.join("\n")))
+}
+
+fn format(chat_name: &str, digest: &Digest, now: DateTime) -> String {
+    let sections: Vec<String> = [
+        format_section("🆕 New orders", digest.new.iter()
+            .map(|o| ui::board::format_line(o, now)).collect()),
+        format_section("⏳ Still waiting for a courier", digest.waiting.iter()
+            .map(|o| ui::board::format_line(o, now)).collect()),
+        format_section("✅ Delivered", digest.delivered.iter()
+            .map(|o| format_delivered(o, now)).collect()),
+    ].into_iter().flatten().collect();
+    format!("📰 Daily digest of {}\n\n{}",
+            markup::bold(markup::escape_html(chat_name).to_string()),
+            sections.join("\n\n"))
+}
+
+/// Sends the digest of `pcid` to the chat and its subscribers,
+/// unless there's nothing to tell
+pub async fn send(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    pcid: ChatId,
+    chat_name: &str,
+) -> HandlerResult {
+    log::info!("-> digest::send {pcid}");
+    let now = db.now();
+    let reports_to_hide = moderation::reports_to_hide();
+    let published = db.orders_by_status(pcid, Status::Published).await?
+        .into_iter()
+        .filter(|o| !o.is_hidden(reports_to_hide))
+        .collect();
+    let delivered = db.orders_by_status(pcid, Status::DeliveryConfirmed)
+        .await?;
+    let digest = Digest::new(published, delivered, now);
+    if digest.is_empty() {
+        log::info!("digest of {pcid} is empty, not sending it");
+        return Ok(())
+    }
+
+    let text = format(chat_name, &digest, now);
+    let bot = bot.parse_mode(ParseMode::Html);
+    outbox::send(bot.send_message(pcid, &text)).await?;
+    for uid in db.digest_subscribers(pcid).await? {
+        let quiet = db.user_settings(uid).await?.is_quiet(now);
+        let sent = outbox::send(bot.send_message(uid, &text)
+            .disable_notification(quiet)).await;
+        // They might have blocked the bot, that's no reason to not send
+        // the digest to the rest
+        if let Err(e) = sent {
+            log::warn!("could not send the digest of {pcid} to {uid}: {e:?}");
+        }
+    }
+    Ok(())
+}
+
+/// Sends digests of all public chats that are due for one
+pub async fn send_due(bot: AutoSend<Bot>, mut db: Db) -> Result<(), Error> {
+    let now = db.now();
+    for (pcid, name) in db.public_chats().await? {
+        let mut settings = db.digest_settings(pcid).await?;
+        if !settings.is_due(now) {
+            continue
+        }
+        if let Err(e) = send(bot.clone(), db.clone(), pcid, &name).await {
+            log::error!("digest::send_due {pcid}: {e:?}");
+        }
+        // Even if it failed, trying again every minute won't help
+        settings.last_sent = Some(now);
+        db.set_digest_settings(pcid, &settings).await?;
+    }
+    Ok(())
+}
+
+/// Sends digests when they're due, forever
+pub async fn run(bot: AutoSend<Bot>, db: Db) {
+    loop {
+        if let Err(e) = send_due(bot.clone(), db.clone()).await {
+            log::error!("digest::run: {e:?}");
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+fn format_settings(settings: &DigestSettings, is_subscribed: bool) -> String {
+    let cmd = Command::Digest(String::new());
+    let when = if settings.enabled {
+        format!("Every day at {} UTC", settings.at.format("%H:%M"))
+    } else {
+        "Off".to_string()
+    };
+    let subscribed = if is_subscribed {
+        format!("You get it in private. Stop it like this: {cmd} unsubscribe")
+    } else {
+        format!("Get it in private like this: {cmd} subscribe")
+    };
+    format!("Daily digest of the chat: {when}\n{subscribed}\n\n\
+Admins can change the time like this: {cmd} 09:00, or turn it off: {cmd} off")
+}
+
+/// Shows digest settings of the user's public chat, subscribes the user
+/// or, if they're an admin, changes when the digest is sent
+pub async fn handle_command(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    msg: &Message,
+    args: &str,
+) -> HandlerResult {
+    log::info!("-> digest::handle_command {args:?}");
+    let cid = msg.chat.id;
+    let uid = match msg.from() {
+        Some(user) => user.id,
+        None => {
+            log::warn!("/digest: no user in msg {msg:?}");
+            return Ok(())
+        },
+    };
+    let pcid = if let ChatKind::Public(_) = msg.chat.kind {
+        msg.chat.id
+    } else {
+        let chats = data_gathering::user_public_chats(&mut db, uid).await?;
+        match chats.as_slice() {
+            [(pcid, _name)] => *pcid,
+            _ => {
+                outbox::send(bot.send_message(cid,
+                    "Please send it to the public chat you want the digest \
+of")).await?;
+                return Ok(())
+            },
+        }
+    };
+
+    let mut settings = db.digest_settings(pcid).await?;
+    let args = args.trim();
+    match args {
+        "subscribe" | "unsubscribe" => {
+            db.set_digest_subscription(pcid, uid, args == "subscribe")
+                .await?;
+        },
+        "" => {},
+        _ => {
+            if ! data_gathering::is_chat_admin(&bot, &mut db, pcid, uid).await {
+                ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT), bot, cid,
+                    "Only admins of the chat can change its digest").await?;
+                return Ok(())
+            }
+            if args == "off" {
+                settings.enabled = false;
+            } else if let Ok(at) = NaiveTime::parse_from_str(args, "%H:%M") {
+                settings.enabled = true;
+                settings.at = at;
+                // Count from now, so a time that has passed today doesn't
+                // send a digest right away
+                settings.last_sent = Some(db.now());
+            } else {
+                let subscribed = db.digest_subscribers(pcid).await?
+                    .contains(&uid);
+                ui::text_msg(Some(ui::TEMP_MSG_TIMEOUT), bot, cid,
+                    &format!("I don't understand that.\n\n{}",
+                             format_settings(&settings, subscribed))).await?;
+                return Ok(())
+            }
+            db.set_digest_settings(pcid, &settings).await?;
+            log::info!("{uid} has changed digest of {pcid}: {settings:?}");
+        },
+    }
+
+    let subscribed = db.digest_subscribers(pcid).await?.contains(&uid);
+    ui::text_msg(Some(ui::TEMP_MSG_TIMEOUT), bot, cid,
+                 &format_settings(&settings, subscribed)).await?;
+    Ok(())
+}
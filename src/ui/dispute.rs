@@ -0,0 +1,106 @@
+//! Disputes about deliveries
+//!
+//! Owner or assignee of an order that's marked as delivered can open
+//! a dispute with a note saying what went wrong. Admins of the public
+//! chat see open disputes in `ui::moderation` and resolve them.
+
+use teloxide::{
+    prelude::*,
+    dispatching::UpdateHandler,
+};
+use serde::{Serialize, Deserialize};
+
+use crate::error::Error;
+use crate::Db;
+use crate::order::{self, ActionError, ActionKind, OrderId};
+use crate::ui::{self, HandlerResult, MyDialogue};
+use crate::logger;
+use crate::outbox;
+
+/// Telegram allows much longer messages, but an admin has to read it
+const MAX_NOTE_LEN: usize = 1000;
+
+/// Waiting for a note that says what went wrong
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct State {
+    pub pcid: ChatId,
+    pub order_id: OrderId,
+}
+
+pub fn schema() -> UpdateHandler<Error> {
+    Update::filter_message()
+        .endpoint(receive_note)
+}
+
+/// Asks `uid` what went wrong, if they're allowed to open a dispute
+pub async fn ask_note(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    dialogue: MyDialogue,
+    uid: UserId,
+    pcid: ChatId,
+    oid: OrderId,
+) -> HandlerResult {
+    log::info!("-> ask_note {oid}");
+    let order = db.get_order(pcid, oid).await?;
+    let checked = match &order {
+        Some(order) => order.check_action(uid, false, ActionKind::OpenDispute)
+            .map(|_| ()),
+        None => Err(ActionError::OrderNotFound(oid)),
+    };
+    if let Err(e) = checked {
+        log::warn!("ask_note {uid} {pcid} => {e:?}");
+        ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT),
+                     bot, dialogue.chat_id(), &format!("{e}")).await?;
+        return Ok(())
+    }
+
+    dialogue.update(ui::State::Dispute(State { pcid, order_id: oid })).await?;
+    outbox::send(bot.send_message(dialogue.chat_id(), "What went wrong? \
+Admins of the chat will read it and decide what to do with the order."))
+        .await?;
+    Ok(())
+}
+
+async fn receive_note(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    db: Db,
+    dialogue: MyDialogue,
+    state: State,
+) -> HandlerResult {
+    logger::set_handler("dispute::receive_note");
+    logger::set_order(state.order_id);
+    log::info!("-> receive_note {state:?}");
+
+    let text = msg.text().map(str::trim).unwrap_or("");
+    if text.is_empty() {
+        outbox::send(bot.send_message(dialogue.chat_id(),
+            "Please write what went wrong in a message")).await?;
+        return Ok(())
+    }
+    if text.chars().count() > MAX_NOTE_LEN {
+        outbox::send(bot.send_message(dialogue.chat_id(),
+            format!("That's too long, please keep it under \
+{MAX_NOTE_LEN} characters"))).await?;
+        return Ok(())
+    }
+
+    let user = msg.from();
+    if user.is_none() {
+        log::warn!("receive_note No user in msg {msg:?}");
+        return Err(format!("No user is msg {msg:?}").into());
+    }
+    let user = user.unwrap().clone();
+
+    dialogue.exit().await?;
+    let action = order::Action {
+        kind: ActionKind::OpenDispute,
+        order_id: state.order_id,
+        note: Some(text.to_string()),
+        settlement: None,
+    };
+    ui::order_action::handle_order_action(
+        bot, user, false, state.pcid, action, db, dialogue).await?;
+    Ok(())
+}
@@ -0,0 +1,211 @@
+//! Shopping list of an order for its courier
+//!
+//! Orders with items have a "Shopping list" button for their courier.
+//! The list has a button to tick each item off and one to tell how much
+//! they've paid for it, which ticks it off too.
+
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode},
+    dispatching::UpdateHandler,
+};
+use serde::{Serialize, Deserialize};
+
+use crate::error::Error;
+use crate::Db;
+use crate::order::{ItemUpdate, Order, OrderId};
+use crate::ui::{self, HandlerResult, MyDialogue};
+use crate::{logger, markup, outbox};
+
+const BTN_DATA_PREFIX: &str = "it";
+
+/// Waiting for the courier to write how much they've paid for an item
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct State {
+    pub pcid: ChatId,
+    pub order_id: OrderId,
+    pub idx: usize,
+}
+
+pub fn schema() -> UpdateHandler<Error> {
+    Update::filter_message()
+        .endpoint(receive_paid)
+}
+
+fn is_courier(order: &Order, uid: UserId) -> bool {
+    order.assigned.as_ref().is_some_and(|(_at, courier, _user)| *courier == uid)
+}
+
+/// A button to the shopping list if `cid` is a private chat
+/// of the courier that is delivering the order
+pub fn list_button(order: &Order, cid: ChatId) -> Option<InlineKeyboardButton> {
+    let oid = order.id?;
+    let is_for_courier = cid.is_user()
+        && is_courier(order, UserId(cid.0 as u64))
+        && order.is_active_assignment();
+    (is_for_courier && !order.items.is_empty()).then(|| {
+        InlineKeyboardButton::callback("🛒 Shopping list".to_string(),
+                                       format!("{BTN_DATA_PREFIX} list {oid}"))
+    })
+}
+
+async fn format_list(
+    db: &mut Db,
+    cid: ChatId,
+    order: &Order,
+) -> Result<(String, InlineKeyboardMarkup), Error> {
+    let oid = order.id.ok_or("order has no id")?;
+    let currency = db.user_settings(UserId(cid.0 as u64)).await?.currency;
+    let text = format!("🛒 Shopping list of {}\n\n{}\n\nClick an item when \
+you've got it, 💵 to tell how much you've paid for it",
+        markup::bold(markup::escape_html(&order.name).to_string()),
+        ui::order::format_items(&order.items, currency));
+    let keyboard = InlineKeyboardMarkup::new(order.items.iter().enumerate()
+        .map(|(ii, item)| {
+            let check = if item.bought { "✅" } else { "▫️" };
+            vec![
+                InlineKeyboardButton::callback(
+                    format!("{check} {}", item.name),
+                    format!("{BTN_DATA_PREFIX} tick {oid} {ii}")),
+                InlineKeyboardButton::callback(
+                    "💵".to_string(),
+                    format!("{BTN_DATA_PREFIX} paid {oid} {ii}")),
+            ]
+        }));
+    Ok((text, keyboard))
+}
+
+/// Sends the shopping list of `order` to `cid` or edits the message
+/// `msg_id` that shows it
+async fn send_list(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    cid: ChatId,
+    order: &Order,
+    msg_id: Option<i32>,
+) -> HandlerResult {
+    let (text, keyboard) = format_list(&mut db, cid, order).await?;
+    let bot = bot.parse_mode(ParseMode::Html);
+    match msg_id {
+        Some(msg_id) => {
+            outbox::send(bot.edit_message_text(cid, msg_id, text)
+                .reply_markup(keyboard)).await?;
+        },
+        None => {
+            outbox::send(bot.send_message(cid, text).reply_markup(keyboard))
+                .await?;
+        },
+    }
+    Ok(())
+}
+
+/// If it's a shopping list query then handle it and return `true`,
+/// otherwise just return `false`
+pub async fn try_handle_query(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    dialogue: MyDialogue,
+    q: CallbackQuery,
+    data: &str,
+) -> Result<bool, Error> {
+    let mut args = data.split(' ');
+    if args.next() != Some(BTN_DATA_PREFIX) {
+        return Ok(false)
+    }
+    let what = args.next();
+    let oid = args.next().and_then(|oid| oid.parse().ok()).map(OrderId);
+    let idx: Option<usize> = args.next().and_then(|idx| idx.parse().ok());
+    let (what, oid, idx) = match (what, oid, idx, args.next()) {
+        (Some("list"), Some(oid), None, None) => ("list", oid, 0),
+        (Some(what @ ("tick" | "paid")), Some(oid), Some(idx), None) =>
+            (what, oid, idx),
+        _ => {
+            log::warn!("items: malformed data {data:?}");
+            return Ok(true)
+        }
+    };
+    logger::set_handler("items");
+    logger::set_order(oid);
+    log::info!("-> items::try_handle_query {what} {idx}");
+
+    let pcid = match ui::pcid_or_err(&bot, &mut db, &q, &dialogue).await {
+        Ok(pcid) => pcid,
+        // We've already told the user what's wrong
+        Err(_) => return Ok(true),
+    };
+    let uid = q.from.id;
+    let cid = dialogue.chat_id();
+    match what {
+        "list" => match db.get_order(pcid, oid).await? {
+            Some(order) if is_courier(&order, uid) =>
+                send_list(bot, db, cid, &order, None).await?,
+            _ => ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT), bot, cid,
+                              "This order is gone").await?,
+        },
+        "tick" => match db.update_item(pcid, uid, oid, idx, ItemUpdate::Toggle)
+            .await
+        {
+            Ok(order) => {
+                let msg_id = q.message.map(|msg| msg.id);
+                send_list(bot, db, cid, &order, msg_id).await?;
+            },
+            Err(e) => ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT), bot, cid,
+                                   &e.to_string()).await?,
+        },
+        _ => {
+            let item = db.get_order(pcid, oid).await?
+                .filter(|order| is_courier(order, uid))
+                .and_then(|order| order.items.get(idx).cloned());
+            match item {
+                Some(item) => {
+                    dialogue.update(ui::State::Items(
+                        State { pcid, order_id: oid, idx })).await?;
+                    outbox::send(bot.send_message(cid, format!("How much \
+have you paid for {}, in Armenian Drams?", item.name))).await?;
+                },
+                None => ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT), bot,
+                                     cid, "This item is gone").await?,
+            }
+        },
+    }
+    Ok(true)
+}
+
+async fn receive_paid(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    mut db: Db,
+    dialogue: MyDialogue,
+    state: State,
+) -> HandlerResult {
+    logger::set_handler("items::receive_paid");
+    logger::set_order(state.order_id);
+    log::info!("-> receive_paid {state:?}");
+    let cid = dialogue.chat_id();
+    let uid = match msg.from() {
+        Some(user) => user.id,
+        None => {
+            log::warn!("receive_paid No user in msg {msg:?}");
+            return Err(format!("No user is msg {msg:?}").into());
+        },
+    };
+    let paid = match ui::new_order::parse_price(msg.text().unwrap_or("")) {
+        Ok(paid) => paid,
+        Err(e) => {
+            outbox::send(bot.send_message(cid,
+                format!("I don't understand the price - {e}, please try again")))
+                .await?;
+            return Ok(())
+        },
+    };
+
+    dialogue.exit().await?;
+    let State { pcid, order_id, idx } = state;
+    match db.update_item(pcid, uid, order_id, idx, ItemUpdate::Paid(paid)).await {
+        Ok(order) => send_list(bot, db, cid, &order, None).await?,
+        Err(e) => {
+            outbox::send(bot.send_message(cid, e.to_string())).await?;
+        },
+    }
+    Ok(())
+}
@@ -0,0 +1,252 @@
+//! Balances between members of public chats
+//!
+//! `/balance` shows what the user owes and is owed in each of their chats,
+//! with a button to mark every open debt as repaid. The other side gets
+//! a button to say it isn't, which reverses the repayment. A wrong debt is
+//! canceled by the courier, the owner can only ask them to.
+
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode, User},
+};
+
+use crate::error::Error;
+use crate::Db;
+use crate::ledger::{self, Entry};
+use crate::ui::{self, HandlerResult};
+use crate::ui::commands::Command;
+use crate::{logger, markup, outbox, privacy, utils};
+
+const BTN_DATA_PREFIX: &str = "lg";
+
+async fn user_link(db: &mut Db, uid: UserId) -> Result<String, Error> {
+    Ok(match db.get_user(uid).await? {
+        Some(user) => markup::user_link(&user),
+        None => markup::user_link(&privacy::anonymous_user()),
+    })
+}
+
+/// Balances of `uid` in all their chats and buttons to repay open debts
+async fn format_balance(
+    db: &mut Db,
+    uid: UserId,
+) -> Result<(String, InlineKeyboardMarkup), Error> {
+    let currency = db.user_settings(uid).await?.currency;
+    let price = |drams: u64| markup::format_price(drams, currency);
+    let mut sections = Vec::new();
+    let mut rows = Vec::new();
+    for (pcid, name) in db.user_public_chats(uid).await? {
+        let entries = db.ledger(pcid).await?;
+        let balances = ledger::balances(&entries, uid);
+        if balances.is_empty() {
+            continue
+        }
+        let mut lines = vec![format!("💰 {}",
+            markup::bold(markup::escape_html(&name).to_string()))];
+        for (other, balance) in balances {
+            let link = user_link(db, other).await?;
+            let amount = price(balance.unsigned_abs());
+            lines.push(if balance > 0 {
+                format!("{link} owes you {amount}")
+            } else {
+                format!("You owe {link} {amount}")
+            });
+        }
+        sections.push(lines.join("\n"));
+
+        for (idx, debt) in ledger::open_debts(&entries, uid) {
+            let text = format!("✅ Repaid: {}, {}", debt.note,
+                               price(debt.amount_in_drams));
+            let (wrong, what) = if debt.credit == uid {
+                ("❌ Cancel", "cancel")
+            } else {
+                ("❌ Wrong", "wrong")
+            };
+            rows.push(vec![
+                InlineKeyboardButton::callback(
+                    text, format!("{BTN_DATA_PREFIX} paid {pcid} {idx}")),
+                InlineKeyboardButton::callback(
+                    wrong, format!("{BTN_DATA_PREFIX} {what} {pcid} {idx}")),
+            ]);
+        }
+    }
+    let text = if sections.is_empty() {
+        "You don't owe anybody and nobody owes you".to_string()
+    } else {
+        let hint = if rows.is_empty() {
+            ""
+        } else {
+            "\n\nClick a debt once it's repaid, either of you can do it. \
+A wrong debt is canceled by who's owed."
+        };
+        format!("{}{hint}", sections.join("\n\n"))
+    };
+    Ok((text, InlineKeyboardMarkup::new(rows)))
+}
+
+/// Sends `user` their balances privately
+pub async fn send_balance(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    user: Option<&User>,
+) -> HandlerResult {
+    log::info!("-> ledger::send_balance");
+    let uid = match user {
+        Some(user) => user.id,
+        None => return Ok(()),
+    };
+    let (text, keyboard) = format_balance(&mut db, uid).await?;
+    outbox::send(bot.parse_mode(ParseMode::Html)
+        .send_message(utils::uid_to_cid(uid), text)
+        .reply_markup(keyboard)).await?;
+    Ok(())
+}
+
+/// What a user has done to a debt
+enum Notice {
+    Repaid,
+    NotRepaid,
+    Canceled,
+    Wrong,
+}
+
+/// Tells the other side of `entry` what `user` has done to its debt,
+/// `buttons` are shown under the message
+async fn notify_counterparty(
+    bot: AutoSend<Bot>,
+    db: &mut Db,
+    user: &User,
+    entry: &Entry,
+    notice: Notice,
+    buttons: Vec<InlineKeyboardButton>,
+) -> HandlerResult {
+    let other = entry.counterparty(user.id);
+    let settings = db.user_settings(other).await?;
+    let link = markup::user_link(user);
+    let debt = format!("the debt for {}, {}",
+        markup::bold(markup::escape_html(&entry.note).to_string()),
+        markup::format_price(entry.amount_in_drams, settings.currency));
+    let text = match notice {
+        Notice::Repaid => format!("{link} has marked {debt} as repaid"),
+        Notice::NotRepaid => format!("{link} says {debt} isn't repaid yet"),
+        Notice::Canceled => format!("{link} has canceled {debt}"),
+        Notice::Wrong => format!("{link} says {debt} is wrong, cancel it \
+if it is"),
+    };
+    let sent = outbox::send(bot.parse_mode(ParseMode::Html)
+        .send_message(utils::uid_to_cid(other),
+                      format!("{text}. See {}", Command::Balance))
+        .reply_markup(InlineKeyboardMarkup::new(vec![buttons]))
+        .disable_notification(settings.is_quiet(db.now()))).await;
+    if let Err(e) = sent {
+        // They might have blocked the bot
+        log::warn!("Could not tell {other} about a ledger entry: {e:?}");
+    }
+    Ok(())
+}
+
+/// Asks whom `user` owes the open debt `entries[idx]` to cancel it
+async fn ask_to_cancel(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    user: &User,
+    pcid: ChatId,
+    idx: usize,
+) -> HandlerResult {
+    let cid = utils::uid_to_cid(user.id);
+    let entries = db.ledger(pcid).await?;
+    let debt = ledger::open_debts(&entries, user.id).into_iter()
+        .find(|(ii, debt)| *ii == idx && debt.debit == user.id);
+    let debt = match debt {
+        Some((_idx, debt)) => debt,
+        None => {
+            ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT), bot, cid,
+                         "There's no such debt in your ledger").await?;
+            return Ok(())
+        },
+    };
+    let cancel = InlineKeyboardButton::callback(
+        "❌ Cancel the debt".to_string(),
+        format!("{BTN_DATA_PREFIX} cancel {pcid} {idx}"));
+    notify_counterparty(bot.clone(), &mut db, user, debt, Notice::Wrong,
+                        vec![cancel]).await?;
+    ui::text_msg(Some(ui::TEMP_MSG_TIMEOUT), bot, cid,
+                 "Okay, I've asked them to cancel it").await?;
+    Ok(())
+}
+
+/// If it's a ledger query then handle it and return `true`,
+/// otherwise just return `false`
+pub async fn try_handle_query(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    q: CallbackQuery,
+    data: &str,
+) -> Result<bool, Error> {
+    let mut args = data.split(' ');
+    if args.next() != Some(BTN_DATA_PREFIX) {
+        return Ok(false)
+    }
+    let what = args.next();
+    let pcid = args.next().and_then(|pcid| pcid.parse().ok()).map(ChatId);
+    let idx: Option<usize> = args.next().and_then(|idx| idx.parse().ok());
+    let (what, pcid, idx) = match (what, pcid, idx, args.next()) {
+        (Some(what @ ("paid" | "undo" | "cancel" | "wrong")), Some(pcid),
+         Some(idx), None) => (what, pcid, idx),
+        _ => {
+            log::warn!("ledger: malformed data {data:?}");
+            return Ok(true)
+        }
+    };
+    logger::set_handler("ledger");
+    log::info!("-> ledger::try_handle_query {data}");
+
+    let user = q.from;
+    let cid = utils::uid_to_cid(user.id);
+    if what == "wrong" {
+        ask_to_cancel(bot, db, &user, pcid, idx).await?;
+        return Ok(true)
+    }
+    let (uid, now, repay) = (user.id, db.now(), what == "paid");
+    let added = db.add_checked_ledger_entry(pcid, move |entries| if repay {
+        ledger::repay(entries, idx, uid, now)
+    } else {
+        ledger::reverse(entries, idx, uid, now)
+    }).await?;
+    let (new_idx, entry) = match added {
+        Ok(added) => added,
+        Err(e) => {
+            ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT), bot, cid, &e).await?;
+            return Ok(true)
+        },
+    };
+    log::info!("{}: {:?} of entry {idx} in {pcid}", user.id, entry.kind);
+
+    if what == "undo" {
+        notify_counterparty(bot.clone(), &mut db, &user, &entry,
+                            Notice::NotRepaid, Vec::new()).await?;
+        if let Some(msg) = q.message {
+            outbox::send(bot.edit_message_text(msg.chat.id, msg.id,
+                "Okay, the debt is open again")).await?;
+        }
+        return Ok(true)
+    }
+    if repay {
+        let undo = InlineKeyboardButton::callback(
+            "↩️ It isn't repaid".to_string(),
+            format!("{BTN_DATA_PREFIX} undo {pcid} {new_idx}"));
+        notify_counterparty(bot.clone(), &mut db, &user, &entry,
+                            Notice::Repaid, vec![undo]).await?;
+    } else {
+        notify_counterparty(bot.clone(), &mut db, &user, &entry,
+                            Notice::Canceled, Vec::new()).await?;
+    }
+    // Show what's left
+    if let Some(msg) = q.message {
+        let (text, keyboard) = format_balance(&mut db, user.id).await?;
+        outbox::send(bot.parse_mode(ParseMode::Html)
+            .edit_message_text(msg.chat.id, msg.id, text)
+            .reply_markup(keyboard)).await?;
+    }
+    Ok(true)
+}
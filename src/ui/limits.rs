@@ -0,0 +1,151 @@
+//! Rate limits of public chats
+//!
+//! Button clicks are limited before any other handler sees them, order
+//! creation and publishing are limited where orders are created and
+//! published. Admins of a chat see and change its limits with `/limits`.
+
+use teloxide::{
+    prelude::*,
+    types::ChatKind,
+    dispatching::UpdateHandler,
+};
+
+use crate::error::Error;
+use crate::Db;
+use crate::rate_limit::{ChatLimits, RateLimiter};
+use crate::ui::{self, HandlerResult};
+use crate::ui::commands::Command;
+use crate::{data_gathering, logger};
+use crate::outbox;
+
+/// Catches clicks of users who click faster than their chat allows
+pub fn schema() -> UpdateHandler<Error> {
+    Update::filter_callback_query()
+        .chain(dptree::filter_async(is_over_limit))
+        .endpoint(slow_down)
+}
+
+/// Limits of the public chat where `q` comes from
+///
+/// Clicks in private chats count against the user's public chat,
+/// if we can't tell which one it is then the default limits apply
+async fn query_limits(db: &mut Db, q: &CallbackQuery) -> ChatLimits {
+    let pcid = match &q.message {
+        Some(msg) if matches!(msg.chat.kind, ChatKind::Public(_)) =>
+            Some(msg.chat.id),
+        _ => match data_gathering::user_public_chats(db, q.from.id).await {
+            Ok(chats) if chats.len() == 1 => Some(chats[0].0),
+            Ok(_) => None,
+            Err(e) => {
+                log::warn!("query_limits {}: {e:?}", q.from.id);
+                None
+            },
+        },
+    };
+    match pcid {
+        Some(pcid) => db.chat_limits(pcid).await.unwrap_or_else(|e| {
+            log::warn!("query_limits {pcid}: {e:?}");
+            ChatLimits::default()
+        }),
+        None => ChatLimits::default(),
+    }
+}
+
+async fn is_over_limit(
+    q: CallbackQuery,
+    mut db: Db,
+    limiter: RateLimiter,
+) -> bool {
+    let limits = query_limits(&mut db, &q).await;
+    !limiter.try_click(q.from.id, limits.callbacks_per_sec, db.now())
+}
+
+async fn slow_down(bot: AutoSend<Bot>, q: CallbackQuery) -> HandlerResult {
+    logger::set_handler("limits::slow_down");
+    log::info!("-> slow_down {} is clicking too fast", q.from.id);
+    bot.answer_callback_query(q.id)
+        .text("You're clicking too fast, please wait a second and try again")
+        .await?;
+    Ok(())
+}
+
+fn format_limits(limits: &ChatLimits) -> String {
+    let lines: Vec<String> = ChatLimits::NAMES.iter()
+        .map(|name| format!("{name}: {}", limits.get(name).unwrap_or(0)))
+        .collect();
+    format!("Limits of the chat:\n{}\n\nAdmins can change them like this: \
+{} open_orders 3", lines.join("\n"), Command::Limits(String::new()))
+}
+
+/// Shows limits of the user's public chat, or changes one if `args` are
+/// a name and a value and the user is an admin of the chat
+pub async fn handle_command(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    msg: &Message,
+    args: &str,
+) -> HandlerResult {
+    log::info!("-> limits::handle_command {args:?}");
+    let cid = msg.chat.id;
+    let uid = match msg.from() {
+        Some(user) => user.id,
+        None => {
+            log::warn!("/limits: no user in msg {msg:?}");
+            return Ok(())
+        },
+    };
+    let pcid = if let ChatKind::Public(_) = msg.chat.kind {
+        msg.chat.id
+    } else {
+        let chats = data_gathering::user_public_chats(&mut db, uid).await?;
+        match chats.as_slice() {
+            [(pcid, _name)] => *pcid,
+            _ => {
+                outbox::send(bot.send_message(cid,
+                    "Please send it to the public chat you want to see \
+the limits of")).await?;
+                return Ok(())
+            },
+        }
+    };
+
+    let mut limits = db.chat_limits(pcid).await?;
+    let args: Vec<&str> = args.split_whitespace().collect();
+    if args.is_empty() {
+        ui::text_msg(Some(ui::TEMP_MSG_TIMEOUT), bot, cid,
+                     &format_limits(&limits)).await?;
+        return Ok(())
+    }
+
+    if ! data_gathering::is_chat_admin(&bot, &mut db, pcid, uid).await {
+        ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT), bot, cid,
+                     "Only admins of the chat can change its limits").await?;
+        return Ok(())
+    }
+    let value = match args.as_slice() {
+        [name, value] if limits.get(name).is_some() =>
+            value.parse().ok().map(|value: usize| (*name, value)),
+        _ => None,
+    };
+    match value {
+        Some((name, value)) => match limits.set(name, value) {
+            Ok(()) => {
+                db.set_chat_limits(pcid, &limits).await?;
+                log::info!("{uid} has changed limits of {pcid}: {limits:?}");
+                ui::text_msg(Some(ui::TEMP_MSG_TIMEOUT), bot, cid,
+                             &format_limits(&limits)).await?;
+            },
+            Err(e) => {
+                ui::text_msg(Some(ui::TEMP_MSG_TIMEOUT), bot, cid,
+                             &format!("{e}.\n\n{}", format_limits(&limits)))
+                    .await?;
+            },
+        },
+        None => {
+            ui::text_msg(Some(ui::TEMP_MSG_TIMEOUT), bot, cid,
+                &format!("I don't understand that.\n\n{}",
+                         format_limits(&limits))).await?;
+        },
+    }
+    Ok(())
+}
@@ -2,6 +2,7 @@ use teloxide::prelude::*;
 use crate::{Db, Chat};
 use crate::ui::{self, HandlerResult};
 use crate::order;
+use crate::moderation;
 
 pub async fn list_active_orders(
     bot: AutoSend<Bot>,
@@ -12,8 +13,13 @@ pub async fn list_active_orders(
 ) -> HandlerResult {
     log::info!("-> list_active_orders");
     let cid = chat.id;
-    let orders = db.clone()
-        .orders_by_status(pcid, order::Status::Published).await?;
+    // Reported orders wait for an admin to look at them
+    let reports_to_hide = moderation::reports_to_hide();
+    let orders: Vec<_> = db.clone()
+        .orders_by_status(pcid, order::Status::Published).await?
+        .into_iter()
+        .filter(|o| !o.is_hidden(reports_to_hide))
+        .collect();
     if orders.is_empty() {
         ui::text_msg(Some(ui::TEMP_MSG_TIMEOUT), bot, cid, "No active orders").await?;
     } else {
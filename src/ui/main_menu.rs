@@ -1,6 +1,7 @@
 use crate::db::Db;
 use crate::ui::{self, HandlerResult, MyDialogue};
 use crate::error::Error;
+use crate::outbox;
 use teloxide::{
     prelude::*,
     payloads::SendMessageSetters,
@@ -16,6 +17,8 @@ pub enum MainMenuItem {
     ShowMyOrders,
     MyAssignments,
     NewOrder,
+    Moderation,
+    Settings,
 }
 
 impl MainMenuItem {
@@ -25,6 +28,8 @@ impl MainMenuItem {
             MainMenuItem::ShowMyOrders     => "Orders I've created 😺",
             MainMenuItem::MyAssignments    => "Orders I'm delivering 🔄",
             MainMenuItem::NewOrder         => "New Order 🤘",
+            MainMenuItem::Moderation       => "Moderation (for admins) 🛡",
+            MainMenuItem::Settings         => "Settings ⚙️",
         }
     }
 
@@ -34,6 +39,8 @@ impl MainMenuItem {
             MainMenuItem::ShowMyOrders     => "show_my_orders",
             MainMenuItem::MyAssignments    => "my_assignments",
             MainMenuItem::NewOrder         => "new_order",
+            MainMenuItem::Moderation       => "moderation",
+            MainMenuItem::Settings         => "settings",
         }
     }
 
@@ -41,7 +48,9 @@ impl MainMenuItem {
         &[ MainMenuItem::ListActiveOrders,
            MainMenuItem::ShowMyOrders,
            MainMenuItem::MyAssignments,
-           MainMenuItem::NewOrder ]
+           MainMenuItem::NewOrder,
+           MainMenuItem::Moderation,
+           MainMenuItem::Settings ]
     }
 
     pub const fn public_items() -> &'static [Self] {
@@ -55,6 +64,8 @@ impl MainMenuItem {
           "show_my_orders"     => Some(MainMenuItem::ShowMyOrders),
           "my_assignments"     => Some(MainMenuItem::MyAssignments),
           "new_order"          => Some(MainMenuItem::NewOrder),
+          "moderation"         => Some(MainMenuItem::Moderation),
+          "settings"           => Some(MainMenuItem::Settings),
           _ => None
         }
     }
@@ -77,8 +88,8 @@ pub async fn main_menu(
     };
     let items = main_menu_items.iter().map(|item| item.kbd_button());
 
-    bot.send_message(cid, "Choose your destiny")
-        .reply_markup(inline_rows_kbd(items))
+    outbox::send(bot.send_message(cid, "Choose your destiny")
+        .reply_markup(inline_rows_kbd(items)))
         .await?;
 
     Ok(())
@@ -120,7 +131,7 @@ pub async fn send_menu_link(
     cid: ChatId,
 ) -> HandlerResult {
     log::debug!("send_menu_link");
-    bot.send_message(cid, "Open menu like this: /menu").await?;
+    outbox::send(bot.send_message(cid, "Open menu like this: /menu")).await?;
     Ok(())
 }
 
@@ -136,7 +147,7 @@ pub async fn handle_item(
     let cid = dialogue.chat_id();
     log::info!("main_menu = {menu_item:?}");
     if let Some(msg) = &q.message {
-        bot.delete_message(cid, msg.id).await?;
+        outbox::send(bot.delete_message(cid, msg.id)).await?;
     }
     match menu_item {
         MainMenuItem::NewOrder => {
@@ -159,7 +170,16 @@ pub async fn handle_item(
             ui::list_my_assignments(
                 bot.clone(), db, pcid, chat, uid, dialogue).await?;
             send_menu_link(bot, cid).await?;
-        }
+        },
+        MainMenuItem::Moderation => {
+            let pcid = ui::pcid_or_err(&bot, &mut db, q, &dialogue).await?;
+            ui::moderation::show(
+                bot.clone(), db, pcid, uid, cid).await?;
+            send_menu_link(bot, cid).await?;
+        },
+        MainMenuItem::Settings => {
+            ui::settings::show(bot, db, cid, uid).await?;
+        },
     }
     Ok(())
 }
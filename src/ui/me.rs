@@ -1,12 +1,56 @@
+//! Profile of a user
+//!
+//! `/me` shows the user their own profile with buttons to their lists,
+//! links under orders open a public profile of the order's owner.
+
 use crate::Db;
 use teloxide::{
     prelude::*,
-    types::{User},
+    types::{InlineKeyboardMarkup, ParseMode, User},
 };
+use crate::error::Error;
 use crate::HandlerResult;
+use crate::stats::UserStats;
+use crate::ui::main_menu::MainMenuItem;
+use crate::{data_gathering, markup, outbox};
 use std::fmt::Write;
 
-/// Shows basic information about the user
+/// What the deep link of a public profile starts with, see
+/// `markup::profile_url`
+const PROFILE_START_PREFIX: &str = "profile_";
+
+/// Roles of a user in a public chat, from the most important one
+fn format_role(is_admin: bool, is_banned: bool) -> &'static str {
+    match (is_admin, is_banned) {
+        (true, _)      => "admin",
+        (false, true)  => "banned from posting orders",
+        (false, false) => "member",
+    }
+}
+
+/// Stats of `uid` in public chats `pcids`
+async fn user_stats(
+    db: &mut Db,
+    uid: UserId,
+    pcids: &[ChatId],
+) -> Result<UserStats, Error> {
+    let mut stats = UserStats::default();
+    for pcid in pcids.iter().copied() {
+        let orders = db.orders_involving(pcid, uid).await?;
+        stats.add(UserStats::new(uid, &orders));
+    }
+    Ok(stats)
+}
+
+fn format_stats(stats: &UserStats) -> String {
+    format!("Orders created: {} open, {} being delivered, {} completed\n\
+As a courier: {} delivering now, {} delivered\n\
+Disputes: {}",
+        stats.open, stats.being_delivered, stats.completed,
+        stats.delivering, stats.delivered, stats.disputes)
+}
+
+/// Shows the profile of the user with buttons to their lists
 pub async fn send_me(
     bot: AutoSend<Bot>,
     mut db: Db,
@@ -14,8 +58,8 @@ pub async fn send_me(
     user: Option<&User>
 ) -> HandlerResult {
     if user.is_none() {
-        bot.send_message(cid, "I don't know who sent this message. Thanks, Telegram!")
-            .await?;
+        outbox::send(bot.send_message(cid,
+            "I don't know who sent this message. Thanks, Telegram!")).await?;
         return Ok(())
     }
     let user = user.unwrap();
@@ -23,16 +67,103 @@ pub async fn send_me(
     let pub_chats: Vec<(ChatId, String)> =
         db.user_public_chats(user.id).await?;
 
-    let mut ret = "I know that you're in the following chats:\n\n".to_string();
+    let mut ret = format!("👤 {}\n\n",
+                          markup::bold(markup::user_link(user)));
     if pub_chats.is_empty() {
         ret.push_str("Actually I don't see you in any chat. \
-Try saying /hello to a public chat I'm in");
+Try saying /hello to a public chat I'm in\n");
+    } else {
+        ret.push_str("Your chats:\n");
     }
-
-    for (_cid, name) in pub_chats.into_iter() {
-        writeln!(&mut ret, " - {name}")?;
+    for (pcid, name) in pub_chats.iter().cloned() {
+        let is_admin = data_gathering::is_chat_admin(
+            &bot, &mut db, pcid, user.id).await;
+        let is_banned = db.is_banned(pcid, user.id).await?;
+        writeln!(&mut ret, " - {}, {}", markup::escape_html(&name),
+                 format_role(is_admin, is_banned))?;
     }
-    bot.send_message(cid, ret).await?;
+
+    let pcids: Vec<ChatId> = pub_chats.iter().map(|(pcid, _)| *pcid).collect();
+    let stats = user_stats(&mut db, user.id, &pcids).await?;
+    let settings = db.user_settings(user.id).await?;
+    let quiet = match settings.quiet_hours {
+        Some(q) => format!("{:02}:00-{:02}:00 {}", q.from, q.to,
+                           settings.format_utc_offset()),
+        None => "off".to_string(),
+    };
+    write!(&mut ret, "\n{}\n\nQuiet hours: {quiet}\nPrices also in: {}",
+           format_stats(&stats), settings.currency.code())?;
+
+    let items = [
+        MainMenuItem::ShowMyOrders,
+        MainMenuItem::MyAssignments,
+        MainMenuItem::ListActiveOrders,
+        MainMenuItem::Settings,
+    ];
+    let keyboard = InlineKeyboardMarkup::new(
+        items.iter().map(|item| [item.kbd_button()]));
+    outbox::send(bot.parse_mode(ParseMode::Html).send_message(cid, ret)
+        .reply_markup(keyboard)).await?;
 
     Ok(())
 }
+
+/// Shows what `viewer` may see about `uid`: stats only count chats they
+/// share, users that hide from stats show only their name, and users
+/// without a shared chat aren't shown at all
+pub async fn send_public_profile(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    cid: ChatId,
+    viewer: UserId,
+    uid: UserId,
+) -> HandlerResult {
+    log::info!("-> send_public_profile {uid} to {viewer}");
+    let viewer_chats = db.user_public_chats(viewer).await?;
+    let shared: Vec<ChatId> = db.user_public_chats(uid).await?.into_iter()
+        .map(|(pcid, _name)| pcid)
+        .filter(|pcid| viewer_chats.iter().any(|(c, _name)| c == pcid))
+        .collect();
+    // Strangers can't tell whether the user exists
+    let user = match db.get_user(uid).await? {
+        Some(user) if !shared.is_empty() => user,
+        _ => {
+            outbox::send(bot.send_message(cid,
+                "I don't know this user anymore")).await?;
+            return Ok(())
+        },
+    };
+    let mut ret = format!("👤 {}\n", markup::bold(markup::user_link(&user)));
+    if !db.user_settings(uid).await?.hide_in_stats {
+        let stats = user_stats(&mut db, uid, &shared).await?;
+        write!(&mut ret, "\nIn your chats:\n{}", format_stats(&stats))?;
+    }
+    outbox::send(bot.parse_mode(ParseMode::Html).send_message(cid, ret))
+        .await?;
+    Ok(())
+}
+
+/// Handles `/start` with arguments of a deep link,
+/// returns false if they aren't ours
+pub async fn try_handle_start(
+    bot: AutoSend<Bot>,
+    db: Db,
+    cid: ChatId,
+    viewer: Option<&User>,
+    args: &str,
+) -> Result<bool, Error> {
+    let uid = match args.strip_prefix(PROFILE_START_PREFIX)
+        .and_then(|uid| uid.parse().ok())
+    {
+        Some(uid) => UserId(uid),
+        None => return Ok(false),
+    };
+    match viewer {
+        Some(viewer) => send_public_profile(bot, db, cid, viewer.id, uid).await?,
+        None => {
+            outbox::send(bot.send_message(cid,
+                "I don't know who sent this message. Thanks, Telegram!")).await?;
+        },
+    }
+    Ok(true)
+}
@@ -0,0 +1,189 @@
+//! Admin view of a public chat
+//!
+//! Admins of the chat see open disputes, reported orders, all orders
+//! that are in progress with the actions only admins can do, users banned
+//! from posting orders and what other admins did recently.
+
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup},
+};
+
+use crate::Db;
+use crate::error::Error;
+use crate::order::{Order, Status};
+use crate::moderation::{Moderation, ModerationRecord};
+use crate::ui::{self, HandlerResult, MyDialogue};
+use crate::{data_gathering, logger, markup};
+use crate::outbox;
+
+const BAN_PREFIX: &str = "mban";
+const UNBAN_PREFIX: &str = "munban";
+
+/// How many moderation log entries we show
+const LOG_ENTRIES: usize = 5;
+
+/// Statuses of orders admins might want to do something about
+const IN_PROGRESS: &[Status] = &[
+    Status::Published,
+    Status::Assigned,
+    Status::MarkedAsDelivered,
+];
+
+fn ban_button(order: &Order) -> InlineKeyboardButton {
+    InlineKeyboardButton::callback(
+        "Ban the owner".to_string(),
+        format!("{BAN_PREFIX} {}", order.customer.id))
+}
+
+async fn user_name(db: &mut Db, uid: UserId) -> Result<String, Error> {
+    Ok(match db.get_user(uid).await? {
+        Some(user) => markup::user_link(&user),
+        None => format!("{uid}"),
+    })
+}
+
+async fn format_record(
+    db: &mut Db,
+    record: &ModerationRecord,
+) -> Result<String, Error> {
+    let admin = user_name(db, record.admin).await?;
+    let what = match &record.moderation {
+        Moderation::Order { order_id, action } =>
+            format!("{} order {order_id}", action.id()),
+        Moderation::Ban(uid) => format!("banned {}", user_name(db, *uid).await?),
+        Moderation::Unban(uid) =>
+            format!("unbanned {}", user_name(db, *uid).await?),
+    };
+    let when = markup::time_ago(record.at, db.now());
+    Ok(format!("{admin} {what} {when}"))
+}
+
+/// Shows the admin view of `pcid` to `uid` in `cid`
+pub async fn show(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    pcid: ChatId,
+    uid: UserId,
+    cid: ChatId,
+) -> HandlerResult {
+    log::info!("-> moderation::show {pcid}");
+    if ! data_gathering::is_chat_admin(&bot, &mut db, pcid, uid).await {
+        outbox::send(
+            bot.send_message(cid, "Only admins of the chat can moderate it"))
+            .await?;
+        return Ok(())
+    }
+
+    let mut shown = 0;
+    for order in db.orders_by_status(pcid, Status::Disputed).await? {
+        ui::order::send_admin_message(
+            db.clone(), &order, bot.clone(), Vec::new(), cid,
+            Some("Dispute ⚖️")).await?;
+        shown += 1;
+    }
+    for status in IN_PROGRESS {
+        for order in db.orders_by_status(pcid, *status).await? {
+            let reports = order.pending_reports().count();
+            let prefix = (reports > 0)
+                .then(|| format!("Reported {reports} times 🚩"));
+            ui::order::send_admin_message(
+                db.clone(), &order, bot.clone(), vec![ban_button(&order)],
+                cid, prefix).await?;
+            shown += 1;
+        }
+    }
+
+    let banned = db.banned_users(pcid).await?;
+    if !banned.is_empty() {
+        let mut rows = Vec::with_capacity(banned.len());
+        for uid in banned {
+            let name = match db.get_user(uid).await? {
+                Some(user) => markup::format_username(&user),
+                None => format!("{uid}"),
+            };
+            rows.push(vec![InlineKeyboardButton::callback(
+                format!("Unban {name}"), format!("{UNBAN_PREFIX} {uid}"))]);
+        }
+        outbox::send(bot.send_message(cid, "Users that can't post orders:")
+            .reply_markup(InlineKeyboardMarkup::new(rows))).await?;
+        shown += 1;
+    }
+
+    let log = db.moderation_log(pcid).await?;
+    if !log.is_empty() {
+        let mut lines = Vec::with_capacity(LOG_ENTRIES);
+        for record in log.iter().rev().take(LOG_ENTRIES) {
+            lines.push(format_record(&mut db, record).await?);
+        }
+        let text = format!("Recently:\n{}", lines.join("\n"));
+        ui::html_msg(None, bot.clone(), cid, &text).await?;
+    }
+
+    if shown == 0 {
+        outbox::send(
+            bot.send_message(cid, "Nothing to moderate, everybody's happy"))
+            .await?;
+    }
+    Ok(())
+}
+
+/// If it's a ban or unban query then handle it and return `true`,
+/// otherwise just return `false`
+pub async fn try_handle_query(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    dialogue: MyDialogue,
+    q: CallbackQuery,
+    data: &str,
+) -> Result<bool, Error> {
+    let (ban, uid) = match data.split_once(' ') {
+        Some((BAN_PREFIX, uid))   => (true, uid),
+        Some((UNBAN_PREFIX, uid)) => (false, uid),
+        _ => return Ok(false),
+    };
+    let uid = match uid.parse() {
+        Ok(uid) => UserId(uid),
+        Err(e) => {
+            log::warn!("moderation: malformed data {data:?}: {e:?}");
+            return Ok(true)
+        }
+    };
+    logger::set_handler("moderation");
+    log::info!("-> moderation::try_handle_query ban={ban} {uid}");
+
+    let pcid = ui::pcid_or_err(&bot, &mut db, &q, &dialogue).await;
+    if pcid.is_err() {
+        // We've already told the user what's wrong
+        return Ok(true)
+    }
+    let pcid = pcid.unwrap();
+
+    let admin = q.from.id;
+    let cid = dialogue.chat_id();
+    if ! data_gathering::is_chat_admin(&bot, &mut db, pcid, admin).await {
+        ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT), bot, cid,
+                     "Only admins of the chat can moderate it").await?;
+        return Ok(true)
+    }
+
+    let name = user_name(&mut db, uid).await?;
+    if ban {
+        if data_gathering::is_chat_admin(&bot, &mut db, pcid, uid).await {
+            ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT), bot, cid,
+                         "Admins can't be banned").await?;
+            return Ok(true)
+        }
+        db.ban_user(pcid, admin, uid).await?;
+        ui::html_msg(Some(ui::TEMP_MSG_TIMEOUT), bot, cid,
+                     &format!("{name} can't post orders anymore")).await?;
+    } else {
+        db.unban_user(pcid, admin, uid).await?;
+        if let Some(msg) = q.message {
+            outbox::send(bot.delete_message(msg.chat.id, msg.id)).await?;
+        }
+        ui::html_msg(Some(ui::TEMP_MSG_TIMEOUT), bot, cid,
+                     &format!("{name} can post orders again")).await?;
+    }
+    Ok(true)
+}
@@ -1,6 +1,6 @@
 use teloxide::{
     prelude::*,
-    types::User,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, ParseMode, User},
     dispatching::UpdateHandler,
 };
 
@@ -12,14 +12,28 @@ use std::num::IntErrorKind;
 use crate::error::Error;
 use crate::MyDialogue;
 use crate::db::Db;
-use crate::order::Order;
+use crate::order::{item, Order, ActionError, Draft, DraftField, Item};
+use crate::order::MAX_PRICE_IN_DRAMS;
 use crate::ui;
 use crate::ui::commands::Command;
 use crate::utils;
-use crate::Offset;
+use crate::logger;
+use crate::outbox;
+use crate::data_gathering;
+use crate::collection;
+use crate::markup;
+use crate::settings::Currency;
 
 type HandlerResult = Result<(), Error>;
 
+const BTN_DATA_PREFIX: &str = "nd";
+
+/// Button id for creating the order from the draft
+const CREATE: &str = "create";
+
+/// Button id for dropping the draft
+const CANCEL: &str = "cancel";
+
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub enum State {
     #[default]
@@ -27,11 +41,22 @@ pub enum State {
     ReceivedName {
         name: String },
     ReceivedPrice {
-        name: String, price: u64, },
+        name: String, price: u64,
+        #[serde(default)]
+        items: Vec<Item>, },
     ReceivedMarkup {
-        name: String, price: u64, markup: u64, },
+        name: String, price: u64, markup: u64,
+        #[serde(default)]
+        items: Vec<Item>, },
     ReceivedDescription {
         name: String, price: u64, markup: u64, description: String },
+    /// Prefilled from a past order or a template, the owner changes
+    /// what they need and creates it
+    Draft {
+        draft: Draft },
+    /// Waiting for a new value of one field of the draft
+    EditingDraft {
+        draft: Draft, field: DraftField },
 }
 
 pub fn schema() -> UpdateHandler<Error> {
@@ -41,10 +66,14 @@ pub fn schema() -> UpdateHandler<Error> {
                 .endpoint(receive_name))
         .branch(dptree::case![State::ReceivedName  { name }]
                 .endpoint(receive_price))
-        .branch(dptree::case![State::ReceivedPrice { name, price }]
+        .branch(dptree::case![State::ReceivedPrice { name, price, items }]
                 .endpoint(receive_markup))
-        .branch(dptree::case![State::ReceivedMarkup  { name, price, markup }]
-                .endpoint(receive_description));
+        .branch(dptree::case![State::ReceivedMarkup  { name, price, markup, items }]
+                .endpoint(receive_description))
+        .branch(dptree::case![State::Draft { draft }]
+                .endpoint(remind_about_draft))
+        .branch(dptree::case![State::EditingDraft { draft, field }]
+                .endpoint(receive_draft_field));
 
     dptree::entry()
         .branch(message_handler)
@@ -65,22 +94,22 @@ pub async fn start(
 ) -> HandlerResult {
     if cid.is_user() {
         // Make sure user's in a public chat before asking them anything
-        let _ = pub_chat_or_bail(bot.clone(), dialogue.clone(), db, cid, uid)
-            .await?;
+        let _ = pub_chat_or_bail(
+            bot.clone(), dialogue.clone(), db.clone(), cid, uid).await?;
 
         dialogue.update(
             ui::State::NewOrder(ui::new_order::State::default())).await?;
         ui::new_order::send_initial_message(
-            bot.clone(), cid).await?;
+            bot.clone(), db, cid).await?;
     } else {
         // It was clicied in a public chat, so:
         //
         // 1. Send a private message suggesting to start a new order
         // 2. Send back a public message suggesting to check
         //    private messages. We later delete this message.
-        bot.send_message(utils::uid_to_cid(uid),
+        outbox::send(bot.send_message(utils::uid_to_cid(uid),
         format!("Create new order here with {} command",
-                Command::NewOrder)).await?;
+                Command::NewOrder))).await?;
 
         ui::text_msg(
             Some(ui::TEMP_MSG_TIMEOUT), bot, cid,
@@ -89,11 +118,13 @@ pub async fn start(
     Ok(())
 }
 
-/// Send the first message of the dialogue for creating new order.
+/// Send the first message of the dialogue for creating new order,
+/// with buttons to start from a template if the user has any.
 ///
 /// Must be sent only in a private chat
 async fn send_initial_message(
     bot: AutoSend<Bot>,
+    mut db: Db,
     cid: ChatId)
 -> HandlerResult {
     if !cid.is_user() {
@@ -102,7 +133,15 @@ message in a public chat {cid}");
         log::warn!("{}", msg);
         return Err(msg.into())
     }
-    bot.send_message(cid, "What do you want?").await?;
+    let templates = db.user_templates(UserId(cid.0 as u64)).await?;
+    if templates.is_empty() {
+        outbox::send(bot.send_message(cid, "What do you want?")).await?;
+    } else {
+        outbox::send(bot.send_message(cid,
+            "What do you want? Write it or start from one of your templates:")
+            .reply_markup(ui::template::templates_keyboard(&templates)))
+            .await?;
+    }
     Ok(())
 }
 
@@ -111,12 +150,13 @@ async fn receive_name(
     msg: Message,
     dialogue: MyDialogue,
 ) -> HandlerResult {
+    logger::set_handler("new_order::receive_name");
     log::info!("-> receive_name");
 
     if msg.text().is_none() {
-        bot.send_message(dialogue.chat_id(), "You haven't written your \
-order's name. Please try again. Just write a message containing \
-the name of your order").await?;
+        outbox::send(bot.send_message(dialogue.chat_id(),
+            "You haven't written your order's name. Please try again. \
+Just write a message containing the name of your order")).await?;
         return Ok(())
     }
     let text = msg.text().unwrap();
@@ -133,9 +173,14 @@ async fn ask_for_price(
     bot: AutoSend<Bot>,
     dialogue: MyDialogue,
 ) -> HandlerResult {
-    bot.send_message(dialogue.chat_id(),
+    outbox::send(bot.send_message(dialogue.chat_id(),
                      "How much is it in Armenian Drams? \
-A rough estimate is enough. Say 0 if it's already paid for").await?;
+A rough estimate is enough. Say 0 if it's already paid for.
+
+Or send a shopping list, one item per line like
+2 x Milk 450 @ SAS
+for two of Milk, 450 each, from SAS. The quantity and the shop \
+are optional.")).await?;
     Ok(())
 }
 
@@ -145,28 +190,28 @@ async fn receive_price(
     dialogue: MyDialogue,
     name: String,
 ) -> HandlerResult {
+    logger::set_handler("new_order::receive_price");
     log::info!("-> receive_price {name}");
 
     if msg.text().is_none() {
-        bot.send_message(dialogue.chat_id(),
-        "Please send me the price of this order, I've reecived nothing")
+        outbox::send(bot.send_message(dialogue.chat_id(),
+        "Please send me the price of this order, I've reecived nothing"))
             .await?;
         return Ok(())
     }
     let text = msg.text().unwrap();
 
-    let price = parse_price(text);
-    if let Err(e) = price {
-        bot.send_message(dialogue.chat_id(),
-            format!("I don't understand the price - {e}, please try again"))
-            .await?;
-        return Ok(())
-    }
-    let price = price.unwrap();
+    let (price, items) = match parse_price_or_items(text) {
+        Ok(price_items) => price_items,
+        Err(e) => {
+            outbox::send(bot.send_message(dialogue.chat_id(), e)).await?;
+            return Ok(())
+        },
+    };
 
     ask_for_markup(bot, dialogue.clone()).await?;
     change_state(
-        dialogue, State::ReceivedPrice { name, price }).await?;
+        dialogue, State::ReceivedPrice { name, price, items }).await?;
 
     Ok(())
 }
@@ -175,9 +220,9 @@ async fn ask_for_markup(
     bot: AutoSend<Bot>,
     dialogue: MyDialogue,
 ) -> HandlerResult {
-    bot.send_message(dialogue.chat_id(),
+    outbox::send(bot.send_message(dialogue.chat_id(),
                      "How much (Drams) will you offer for the delivery?
-It is completely optional, say 0 for no markup.").await?;
+It is completely optional, say 0 for no markup.")).await?;
     Ok(())
 }
 
@@ -185,30 +230,31 @@ async fn receive_markup(
     bot: AutoSend<Bot>,
     msg: Message,
     dialogue: MyDialogue,
-    name_price: (String, u64),
+    name_price_items: (String, u64, Vec<Item>),
 ) -> HandlerResult {
-    log::info!("-> receive_markup {name_price:?}");
+    logger::set_handler("new_order::receive_markup");
+    log::info!("-> receive_markup {name_price_items:?}");
     if msg.text().is_none() {
-        bot.send_message(dialogue.chat_id(),
+        outbox::send(bot.send_message(dialogue.chat_id(),
         "Please send me how much above the item price are you \
-willing to pay for the delivery, I've reecived nothing").await?;
+willing to pay for the delivery, I've reecived nothing")).await?;
         return Ok(())
     }
     let text = msg.text().unwrap();
 
     let markup = parse_price(text);
     if let Err(e) = markup {
-        bot.send_message(dialogue.chat_id(),
-            format!("I don't understand the price - {e}, please try again"))
+        outbox::send(bot.send_message(dialogue.chat_id(),
+            format!("I don't understand the price - {e}, please try again")))
             .await?;
         return Ok(())
     }
     let markup = markup.unwrap();
 
     ask_for_description(bot, dialogue.clone()).await?;
-    let (name, price) = name_price;
+    let (name, price, items) = name_price_items;
     change_state(
-        dialogue, State::ReceivedMarkup { name, price, markup }).await?;
+        dialogue, State::ReceivedMarkup { name, price, markup, items }).await?;
 
     Ok(())
 }
@@ -217,9 +263,9 @@ async fn ask_for_description(
     bot: AutoSend<Bot>,
     dialogue: MyDialogue,
 ) -> HandlerResult {
-    bot.send_message(dialogue.chat_id(),
+    outbox::send(bot.send_message(dialogue.chat_id(),
                      "Write some details of the item you want delivered, \
-where to get it from and other important details.").await?;
+where to get it from and other important details.")).await?;
     Ok(())
 }
 
@@ -228,38 +274,235 @@ async fn receive_description(
     dialogue: MyDialogue,
     db: Db,
     msg: Message,
-    name_price_markup: (String, u64, u64),
+    name_price_markup_items: (String, u64, u64, Vec<Item>),
 ) -> HandlerResult {
-    log::info!("-> receive_description {name_price_markup:?}");
+    logger::set_handler("new_order::receive_description");
+    log::info!("-> receive_description {name_price_markup_items:?}");
     if msg.text().is_none() {
-        bot.send_message(dialogue.chat_id(),
+        outbox::send(bot.send_message(dialogue.chat_id(),
         "Please write a description.
-We don't allow photos or videos right now. Sorry!").await?;
+We don't allow photos or videos right now. Sorry!")).await?;
         return Ok(())
     }
     let description_text = msg.text().unwrap().to_string();
 
-    let (name, price_in_drams, markup_in_drams) = name_price_markup;
+    let (name, price_in_drams, markup_in_drams, items) =
+        name_price_markup_items;
     let user = msg.from();
     if user.is_none() {
         log::warn!("receive_price No user in msg {msg:?}");
         return Err(format!("No user is msg {msg:?}").into());
     }
     let user = user.unwrap();
-    let order_data = OrderData {
-        name, price_in_drams, markup_in_drams, description_text,
+    let draft = Draft {
+        name, price_in_drams, items, markup_in_drams, description_text,
     };
     finish_creating_order(
-        bot, db, dialogue, user, order_data).await?;
+        bot, db, dialogue, user, draft).await?;
 
     Ok(())
 }
 
-struct OrderData {
-    name: String,
-    price_in_drams: u64,
-    markup_in_drams: u64,
-    description_text: String,
+fn draft_keyboard() -> InlineKeyboardMarkup {
+    let button = |id: &str, text: String| InlineKeyboardButton::callback(
+        text, format!("{BTN_DATA_PREFIX} {id}"));
+    let mut rows: Vec<Vec<InlineKeyboardButton>> = DraftField::ALL
+        .chunks(2)
+        .map(|fields| fields.iter()
+             .map(|f| button(f.id(), format!("✏️ {}", f.human_name())))
+             .collect())
+        .collect();
+    rows.push(vec![
+        button(CREATE, "✅ Create".to_string()),
+        button(CANCEL, "Cancel".to_string()),
+    ]);
+    InlineKeyboardMarkup::new(rows)
+}
+
+fn format_draft(draft: &Draft, currency: Currency) -> String {
+    let reward = if draft.markup_in_drams > 0 {
+        markup::format_price(draft.markup_in_drams, currency)
+    } else {
+        "none".to_string()
+    };
+    let items = if draft.items.is_empty() {
+        "".to_string()
+    } else {
+        format!("\n\n{}", ui::order::format_items(&draft.items, currency))
+    };
+    format!("{}\n\n{}{items}\n\nItem cost: {}\nReward: {reward}",
+            markup::bold(markup::escape_html(&draft.name).to_string()),
+            markup::escape_html(&draft.description_text),
+            markup::format_price(draft.price_in_drams, currency))
+}
+
+async fn send_draft(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    cid: ChatId,
+    draft: &Draft,
+) -> HandlerResult {
+    let settings = db.user_settings(UserId(cid.0 as u64)).await?;
+    let text = format!("Here's your new order, change what you need \
+and create it:\n\n{}", format_draft(draft, settings.currency));
+    outbox::send(bot.parse_mode(ParseMode::Html).send_message(cid, text)
+        .reply_markup(draft_keyboard())).await?;
+    Ok(())
+}
+
+/// Start a new order prefilled from `draft`, only the fields the owner
+/// wants to change are asked again
+///
+/// Must be called only in a private chat
+pub async fn start_draft(
+    bot: AutoSend<Bot>,
+    db: Db,
+    dialogue: MyDialogue,
+    uid: UserId,
+    draft: Draft,
+) -> HandlerResult {
+    log::info!("-> start_draft {draft:?}");
+    let cid = dialogue.chat_id();
+    let _ = pub_chat_or_bail(
+        bot.clone(), dialogue.clone(), db.clone(), cid, uid).await?;
+    change_state(dialogue, State::Draft { draft: draft.clone() }).await?;
+    send_draft(bot, db, cid, &draft).await
+}
+
+async fn ask_for_field(
+    bot: AutoSend<Bot>,
+    dialogue: MyDialogue,
+    field: DraftField,
+) -> HandlerResult {
+    match field {
+        DraftField::Name => {
+            outbox::send(bot.send_message(dialogue.chat_id(),
+                                          "What do you want?")).await?;
+        },
+        DraftField::Price       => ask_for_price(bot, dialogue).await?,
+        DraftField::Markup      => ask_for_markup(bot, dialogue).await?,
+        DraftField::Description => ask_for_description(bot, dialogue).await?,
+    }
+    Ok(())
+}
+
+/// If it's a query of a draft's button then handle it and return `true`,
+/// otherwise just return `false`
+pub async fn try_handle_query(
+    bot: AutoSend<Bot>,
+    db: Db,
+    dialogue: MyDialogue,
+    q: CallbackQuery,
+    data: &str,
+) -> Result<bool, Error> {
+    let mut args = data.split(' ');
+    if args.next() != Some(BTN_DATA_PREFIX) {
+        return Ok(false)
+    }
+    let button = match (args.next(), args.next()) {
+        (Some(button), None) => button,
+        _ => {
+            log::warn!("new_order: malformed data {data:?}");
+            return Ok(true)
+        }
+    };
+    logger::set_handler("new_order::draft");
+    log::info!("-> new_order::try_handle_query {button}");
+
+    // The draft is shown again after every change
+    if let Some(msg) = &q.message {
+        outbox::send(bot.delete_message(msg.chat.id, msg.id)).await?;
+    }
+    let draft = match dialogue.get().await? {
+        Some(ui::State::NewOrder(State::Draft { draft }))
+        | Some(ui::State::NewOrder(State::EditingDraft { draft, .. })) => draft,
+        _ => {
+            ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT), bot,
+                dialogue.chat_id(), &format!("This order is gone, please \
+start a new one with {}", Command::NewOrder)).await?;
+            return Ok(true)
+        },
+    };
+
+    match button {
+        CREATE => {
+            finish_creating_order(bot, db, dialogue, &q.from, draft).await?;
+        },
+        CANCEL => {
+            let cid = dialogue.chat_id();
+            exit_dialogue(dialogue).await?;
+            outbox::send(bot.send_message(cid, "Okay, I won't create it"))
+                .await?;
+            ui::main_menu::send_menu_link(bot, cid).await?;
+        },
+        _ => match DraftField::maybe_from_id(button) {
+            Some(field) => {
+                ask_for_field(bot, dialogue.clone(), field).await?;
+                change_state(dialogue, State::EditingDraft { draft, field })
+                    .await?;
+            },
+            None => log::warn!("new_order: unknown button {button:?}"),
+        },
+    }
+    Ok(true)
+}
+
+async fn remind_about_draft(
+    bot: AutoSend<Bot>,
+    dialogue: MyDialogue,
+) -> HandlerResult {
+    outbox::send(bot.send_message(dialogue.chat_id(),
+        "Use the buttons under your new order to change, create \
+or cancel it")).await?;
+    Ok(())
+}
+
+async fn receive_draft_field(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    db: Db,
+    dialogue: MyDialogue,
+    draft_field: (Draft, DraftField),
+) -> HandlerResult {
+    logger::set_handler("new_order::receive_draft_field");
+    let (mut draft, field) = draft_field;
+    log::info!("-> receive_draft_field {field:?}");
+
+    let text = match msg.text() {
+        Some(text) => text,
+        None => {
+            outbox::send(bot.send_message(dialogue.chat_id(),
+                "Please send it in a text message")).await?;
+            return Ok(())
+        },
+    };
+    match field {
+        DraftField::Name        => draft.name = text.to_string(),
+        DraftField::Description => draft.description_text = text.to_string(),
+        DraftField::Price => match parse_price_or_items(text) {
+            Ok((price, items)) => {
+                draft.price_in_drams = price;
+                draft.items = items;
+            },
+            Err(e) => {
+                outbox::send(bot.send_message(dialogue.chat_id(), e)).await?;
+                return Ok(())
+            },
+        },
+        DraftField::Markup => match parse_price(text) {
+            Ok(markup) => draft.markup_in_drams = markup,
+            Err(e) => {
+                outbox::send(bot.send_message(dialogue.chat_id(),
+                    format!("I don't understand the price - {e}, \
+please try again"))).await?;
+                return Ok(())
+            },
+        },
+    }
+
+    let cid = dialogue.chat_id();
+    change_state(dialogue, State::Draft { draft: draft.clone() }).await?;
+    send_draft(bot, db, cid, &draft).await
 }
 
 /// Gets a public chat or leaves the dialogue
@@ -270,28 +513,48 @@ async fn pub_chat_or_bail(
     cid: ChatId,
     uid: UserId,
 ) -> Result<(ChatId, String), Error> {
-    let pub_chats = db.user_public_chats(uid).await?;
+    let pub_chats = data_gathering::user_public_chats(&mut db, uid).await?;
 
     if pub_chats.len() == 1 {
+        let pcid = pub_chats[0].0;
+        if db.is_banned(pcid, uid).await? {
+            let log_msg = format!("User {uid} is banned in {pcid}");
+            log::info!("{log_msg}");
+            outbox::send(bot.send_message(cid, ActionError::Banned.to_string()))
+                .await?;
+            exit_dialogue(dialogue).await?;
+            return Err(log_msg.into());
+        }
+        let limits = db.chat_limits(pcid).await?;
+        let created = db.order_creations(pcid, uid).await?;
+        if !limits.can_create_order(&created, db.now()) {
+            let log_msg = format!("User {uid} created too many orders in {pcid}");
+            log::info!("{log_msg}");
+            outbox::send(bot.send_message(cid, format!("Sorry, you can create \
+at most {} orders a day in this chat. Please try again tomorrow.",
+                limits.orders_per_day))).await?;
+            exit_dialogue(dialogue).await?;
+            return Err(log_msg.into());
+        }
         return Ok(pub_chats[0].clone());
     }
 
     if pub_chats.is_empty() {
         let log_msg = "User {uid} is not in any pub chat";
         log::warn!("{log_msg}");
-        bot.send_message(cid,
+        outbox::send(bot.send_message(cid,
             format!("I don't see you in any public chats.
-Try sending {} to the public chat I'm in.", Command::Hello)).await?;
+Try sending {} to the public chat I'm in.", Command::Hello))).await?;
         exit_dialogue(dialogue).await?;
         ui::main_menu::send_menu_link(bot, cid).await?;
         return Err(log_msg.into());
     }
 
-    bot.send_message(cid,
-        format!("You're in multiple public chats {} and \
-we don't support it yet", pub_chats.len())).await?;
-    let msg = "TODO: Support multiple pub chats uid = {uid}";
-    log::warn!("{msg}");
+    outbox::send(bot.send_message(cid,
+        format!("You're in {} public chats, please choose the one \
+for your orders in Settings ⚙️ of the menu", pub_chats.len()))).await?;
+    let msg = format!("User {uid} is in multiple pub chats");
+    log::info!("{msg}");
     exit_dialogue(dialogue).await?;
     ui::main_menu::send_menu_link(bot, cid).await?;
     Err(msg.into())
@@ -302,27 +565,13 @@ async fn finish_creating_order(
     mut db: Db,
     dialogue: MyDialogue,
     user: &User,
-    order_data: OrderData,
+    draft: Draft,
 ) -> HandlerResult {
-    let OrderData { name, price_in_drams,
-        markup_in_drams, description_text } = order_data;
-    log::info!("-> finish_creating_order {name} \
-{price_in_drams} {markup_in_drams}");
-
-    let mut order = Order {
-        id: None,
-        name,
-        description_text,
-        price_in_drams,
-        markup_in_drams,
-        created_at: Offset::now(),
-        published_at: None,
-        customer: user.clone(),
-        assigned: None,
-        delivered: None,
-        delivery_confirmed_at: None,
-        canceled_at: None,
-    };
+    log::info!("-> finish_creating_order {} {} {}", draft.name,
+               draft.price_in_drams, draft.markup_in_drams);
+
+    let mut order = Order::new(
+        draft, collection::policy().minimise(user), db.now());
     let uid = user.id;
 
     let cid = dialogue.chat_id();
@@ -330,6 +579,8 @@ async fn finish_creating_order(
         bot.clone(), dialogue.clone(), db.clone(), cid, uid).await?;
     let pcid = pub_chat.0;
     let oid = db.add_order(pcid, &mut order).await?;
+    logger::set_order(oid);
+    log::info!("Order created in {pcid}");
     let mut order = order;
     order.id = Some(oid);
 
@@ -353,10 +604,31 @@ async fn exit_dialogue(dialogue: MyDialogue) -> HandlerResult {
     Ok(())
 }
 
+/// Either a price or a shopping list and its estimate,
+/// the error is what we tell the user
+fn parse_price_or_items(text: &str) -> Result<(u64, Vec<Item>), String> {
+    let price_err = match parse_price(text) {
+        Ok(price) => return Ok((price, Vec::new())),
+        Err(e) => e,
+    };
+    // A single word was meant to be a price
+    if text.split_whitespace().nth(1).is_none() {
+        return Err(format!("I don't understand the price - {price_err}, \
+please try again"))
+    }
+    match item::parse_items(text) {
+        Ok(items) => Ok((item::estimate(&items), items)),
+        Err(e) => Err(format!("I don't understand the list - {e}, \
+please try again")),
+    }
+}
+
 /// Transform int parse error into something more price-speccific
-fn parse_price(text: &str) -> Result<u64, Error> {
+pub fn parse_price(text: &str) -> Result<u64, Error> {
     let price: Result<u64, ParseIntError> = text.parse();
     match price {
+        Ok(price) if price > MAX_PRICE_IN_DRAMS =>
+            Err("ain't no one got that much money".into()),
         Ok(price) => Ok(price),
         Err(e) => {
             let e = match e.kind() {
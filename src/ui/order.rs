@@ -4,18 +4,30 @@ use teloxide::{
 };
 
 use crate::error::Error;
-use crate::order::{Order, Action, Status};
+use crate::order::{item, Item, Order, Action, ActionKind, Status};
 use crate::markup::{self, time_ago};
-use crate::Db;
+use crate::{Db, DateTime};
+use crate::outbox;
+use crate::privacy;
+use crate::ui;
+use crate::settings::{Currency, UserSettings};
 
-fn format_status(order: &Order) -> String {
+fn format_status(order: &Order, now: DateTime) -> String {
     match order.status() {
-        Status::Unpublished => "Not published".to_string(),
+        Status::Unpublished => match (order.canceled_at, &order.cancel_reason) {
+            (None, _) => "Not published".to_string(),
+            (Some(when), None) =>
+                format!("Canceled {}", time_ago(when, now)),
+            (Some(when), Some(reason)) =>
+                format!("Canceled {}: {}", time_ago(when, now),
+                        markup::escape_html(reason)),
+        },
         Status::Published =>
-            format!("Published {}", time_ago(order.published_at.unwrap())),
+            format!("Published {}",
+                    time_ago(order.published_at.unwrap(), now)),
         Status::Assigned => {
             let (when, _id, who) = order.assigned.as_ref().unwrap();
-            let when = time_ago(*when);
+            let when = time_ago(*when, now);
             let to_whom = if let Some(user) = who {
                 format!(" to {} ", markup::user_link(user))
             } else {
@@ -26,11 +38,16 @@ fn format_status(order: &Order) -> String {
         },
         Status::MarkedAsDelivered => {
             let (_uid, _u, when) = order.delivered.as_ref().unwrap();
-            format!("Marked as deliered {}", time_ago(*when))
+            format!("Marked as deliered {}", time_ago(*when, now))
+        },
+        Status::Disputed => {
+            let dispute = order.open_dispute().unwrap();
+            format!("Disputed {}: {}", time_ago(dispute.opened_at, now),
+                    markup::escape_html(&dispute.note))
         },
         Status::DeliveryConfirmed => {
             let when = order.delivery_confirmed_at.unwrap();
-            format!("Delivered {}", time_ago(when))
+            format!("Delivered {}", time_ago(when, now))
         }
     }
 }
@@ -44,28 +61,75 @@ fn format_description(order: &Order) -> String {
     markup::escape_html(&order.description_text).to_string()
 }
 
-fn format(order: &Order) -> String {
+/// Shopping list, one item per line
+pub fn format_items(items: &[Item], currency: Currency) -> String {
+    items.iter().map(|i| {
+        let check = if i.bought { "✅" } else { "▫️" };
+        let (quantity, each) = match i.quantity {
+            1 => ("".to_string(), ""),
+            n => (format!("{n} × "), " each"),
+        };
+        let shop = i.shop.as_ref()
+            .map(|shop| format!(" at {}", markup::escape_html(shop)))
+            .unwrap_or_default();
+        let paid = i.actual_price_in_drams
+            .map(|paid| format!(", paid {}", markup::format_price(paid, currency)))
+            .unwrap_or_default();
+        format!("{check} {quantity}{}: {}{each}{shop}{paid}",
+                markup::escape_html(&i.name),
+                markup::format_price(i.price_in_drams, currency))
+    }).collect::<Vec<String>>().join("\n")
+}
+
+fn format(
+    order: &Order,
+    now: DateTime,
+    currency: Currency,
+    bot_username: &str,
+) -> String {
     let name        = format_name(order);
     let description = format_description(order);
-    let status      = format_status(order);
-    let user_link   = markup::user_link(&order.customer);
-    let price = markup::format_amd(order.price_in_drams);
+    let status      = format_status(order, now);
+    let mut user_link = markup::user_link(&order.customer);
+    if order.customer.id != privacy::ANONYMOUS_USER_ID {
+        let url = markup::profile_url(bot_username, order.customer.id);
+        user_link = format!("{user_link} ({})", markup::link(url, "profile"));
+    }
+    let price = markup::format_price(order.price_in_drams, currency);
+    let items = if order.items.is_empty() {
+        "".to_string()
+    } else {
+        format!("\n\n{}", format_items(&order.items, currency))
+    };
+    let paid = match (&order.settlement, item::paid(&order.items)) {
+        (Some(settlement), _) => format!("\nActually paid: {}",
+            markup::format_price(settlement.actual_cost_in_drams, currency)),
+        (None, Some(paid)) => format!("\nPaid so far: {}",
+                                      markup::format_price(paid, currency)),
+        (None, None) => "".to_string(),
+    };
 
     let markup = if order.markup_in_drams > 0 {
         format!("\nReward: {}",
-                markup::format_amd(order.markup_in_drams))
+                markup::format_price(order.markup_in_drams, currency))
     } else {
         "".to_string()
     };
 
+    let series = match &order.series {
+        Some(series) => format!("\n🔁 Repeats from \"{}\"",
+                                markup::escape_html(series)),
+        None => "".to_string(),
+    };
+
     let text = format!("\
 {name}
-By {user_link}
+By {user_link}{series}
 {status}
 
-{description}
+{description}{items}
 
-Item cost: {price}{markup}
+Item cost: {price}{paid}{markup}
 ");
     text
 }
@@ -80,14 +144,68 @@ Item cost: {price}{markup}
 /// prefix: Prepend the order itself with this text
 ///         Note that it is rendered as HTML
 pub async fn send_message<S: AsRef<str>>(
-    mut db: Db,
+    db: Db,
     order: &Order,
     bot: AutoSend<Bot>,
     for_uid: Option<UserId>,
     to_chat_id: ChatId,
     prefix: Option<S>,
 ) -> Result<Message, Error> {
-    let mut text = format(order);
+    let actions = match for_uid {
+        Some(uid) => order.user_actions(uid),
+        None      => order.public_actions(),
+    };
+    send_with_actions(
+        db, order, bot, actions, Vec::new(), to_chat_id, prefix).await
+}
+
+/// Like `send_message` for the owner of the order,
+/// `extra` buttons are shown in a row below the order actions
+pub async fn send_owner_message<S: AsRef<str>>(
+    db: Db,
+    order: &Order,
+    bot: AutoSend<Bot>,
+    uid: UserId,
+    extra: Vec<InlineKeyboardButton>,
+    to_chat_id: ChatId,
+    prefix: Option<S>,
+) -> Result<Message, Error> {
+    let actions = order.user_actions(uid);
+    send_with_actions(db, order, bot, actions, extra, to_chat_id, prefix).await
+}
+
+/// Like `send_message` but shows what a chat admin can do with the order
+///
+/// `extra` buttons are shown in a row below the order actions
+pub async fn send_admin_message<S: AsRef<str>>(
+    db: Db,
+    order: &Order,
+    bot: AutoSend<Bot>,
+    extra: Vec<InlineKeyboardButton>,
+    to_chat_id: ChatId,
+    prefix: Option<S>,
+) -> Result<Message, Error> {
+    let actions = order.admin_actions();
+    send_with_actions(db, order, bot, actions, extra, to_chat_id, prefix).await
+}
+
+async fn send_with_actions<S: AsRef<str>>(
+    mut db: Db,
+    order: &Order,
+    bot: AutoSend<Bot>,
+    actions: Vec<ActionKind>,
+    mut extra: Vec<InlineKeyboardButton>,
+    to_chat_id: ChatId,
+    prefix: Option<S>,
+) -> Result<Message, Error> {
+    // Private chats show the order the way their user likes it
+    let settings = if to_chat_id.is_user() {
+        db.user_settings(UserId(to_chat_id.0 as u64)).await?
+    } else {
+        UserSettings::default()
+    };
+    let bot_username = ui::bot_username(&bot).await?;
+    let mut text = format(order, db.now(), settings.currency, &bot_username);
     if let Some(prefix) = prefix {
         let prefix = prefix.as_ref();
         text = format!("{prefix}\n\n{text}");
@@ -96,19 +214,19 @@ pub async fn send_message<S: AsRef<str>>(
     let order_id = order.id
         .ok_or("Could not make action for order without id")?;
 
-    let actions = match for_uid {
-        Some(uid) => order.user_actions(uid),
-        None      => order.public_actions(),
-    };
-
     let actions: Vec<Action> =
         actions.into_iter()
-        .map(|action| Action { kind: action, order_id })
+        .map(|kind| Action { kind, order_id, note: None, settlement: None })
         .collect();
-    let buttons = actions_keyboard_markup(&actions);
+    let mut buttons = actions_keyboard_markup(&actions);
+    extra.extend(ui::items::list_button(order, to_chat_id));
+    if !extra.is_empty() {
+        buttons = buttons.append_row(extra);
+    }
     let bot = bot.parse_mode(teloxide::types::ParseMode::Html);
-    let msg: Message = bot.send_message(to_chat_id, text)
-        .reply_markup(buttons).await?;
+    let msg: Message = outbox::send(bot.send_message(to_chat_id, text)
+        .reply_markup(buttons)
+        .disable_notification(settings.is_quiet(db.now()))).await?;
     let msg_id = MessageId { message_id: msg.id };
     db.add_msg_id(order_id, to_chat_id, msg_id).await?;
     Ok(msg)
@@ -125,3 +243,45 @@ fn actions_keyboard_markup(actions: &[Action]) -> InlineKeyboardMarkup {
     InlineKeyboardMarkup::new(rows)
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+    use teloxide::types::User;
+    use chrono::offset::Utc;
+
+    #[test]
+    fn test_format_published_status() {
+        let now = Utc.ymd(2022, 7, 1).and_hms(12, 0, 0);
+        let order = Order {
+            id: Some(crate::order::OrderId(1)),
+            status: Status::Published,
+            name: "ordername".to_string(),
+            description_text: "order description".to_string(),
+            price_in_drams: 0,
+            items: Vec::new(),
+            markup_in_drams: 0,
+            created_at: now - Duration::days(4),
+            published_at: Some(now - Duration::days(3)),
+            customer: User {
+                id: UserId(1),
+                first_name: "firstname".into(),
+                last_name: None,
+                username: None,
+                is_bot: false,
+                language_code: None,
+            },
+            assigned: None,
+            delivered: None,
+            settlement: None,
+            delivery_confirmed_at: None,
+            canceled_at: None,
+            cancel_reason: None,
+            disputes: Vec::new(),
+            reports: Vec::new(),
+            series: None,
+        };
+        assert_eq!("Published 3 days ago", format_status(&order, now));
+    }
+}
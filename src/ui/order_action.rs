@@ -1,14 +1,19 @@
 use teloxide::{
     prelude::*,
-    types::{User, MessageId},
+    types::{InputFile, MessageId, ParseMode, User},
 };
 use crate::order::{self, Order, OrderId, ActionKind};
 use crate::Db;
 use crate::error::Error;
 use crate::ui::{self, MyDialogue};
+use crate::ui::commands::Command;
+use crate::ledger;
 use crate::markup;
 use crate::utils;
 use crate::data_gathering;
+use crate::logger;
+use crate::moderation;
+use crate::outbox;
 
 /// If it's an order query then handle it and return `true`,
 /// otherwise just return `false`
@@ -24,12 +29,16 @@ pub async fn try_handle_query(
         return Ok(false)
     }
     let action = action.unwrap();
+    logger::set_handler("order_action");
+    logger::set_order(action.order_id);
+    logger::set_action(action.kind);
 
     log::info!("  got order action from callback query {action:?}");
     let pcid = data_gathering::pub_chat_id_from_cq(&mut db, q.clone()).await;
     if let Err(e) = pcid {
         log::warn!("-> handle_unknown_callback_query pcid: {e:?}");
-        bot.send_message(dialogue.chat_id(), format!("{e}")).await?;
+        outbox::send(bot.send_message(dialogue.chat_id(), format!("{e}")))
+            .await?;
         // Returning true because it's a right kind of query,
         // we just failed handling it
         return Ok(true)
@@ -37,8 +46,35 @@ pub async fn try_handle_query(
     let pcid = pcid.unwrap();
 
     let user = q.from;
+    if action.kind == ActionKind::Cancel {
+        // Ask why first, `ui::cancel_order` cancels it after that
+        ui::cancel_order::ask_reason(
+            bot, db, dialogue, user.id, pcid, action.order_id).await?;
+        return Ok(true)
+    }
+    if action.kind == ActionKind::OpenDispute {
+        // Same here, `ui::dispute` opens it once we know what's wrong
+        ui::dispute::ask_note(
+            bot, db, dialogue, user.id, pcid, action.order_id).await?;
+        return Ok(true)
+    }
+    if action.kind == ActionKind::MarkAsDelivered {
+        // `ui::settlement` marks it once we know what the courier has paid
+        ui::settlement::ask_cost(
+            bot, db, dialogue, user.id, pcid, action.order_id).await?;
+        return Ok(true)
+    }
+    if action.kind == ActionKind::Report {
+        // And `ui::report_order` reports it once we know what's wrong
+        ui::report_order::ask_reason(
+            bot, db, dialogue.chat_id(), user.id, pcid, action.order_id).await?;
+        return Ok(true)
+    }
+
+    let is_admin =
+        data_gathering::is_chat_admin(&bot, &mut db, pcid, user.id).await;
     let changed = ui::order_action::handle_order_action(
-        bot.clone(), user, pcid, action, db, dialogue).await?;
+        bot.clone(), user, is_admin, pcid, action, db, dialogue).await?;
 
     if changed {
         if q.message.is_none() {
@@ -50,7 +86,7 @@ pub async fn try_handle_query(
         // messege showing the order
         let msg = q.message.unwrap();
         log::info!("deleting old order message {}", msg.id);
-        bot.delete_message(msg.chat.id, msg.id).await?;
+        outbox::send(bot.delete_message(msg.chat.id, msg.id)).await?;
     }
     Ok(true)
 }
@@ -59,9 +95,12 @@ pub async fn try_handle_query(
 ///
 /// Returns true if order is changed and we need to delete the old message
 /// to avoid confusion
-async fn handle_order_action(
+///
+/// `is_admin` tells if `user` is an admin of `pcid`
+pub async fn handle_order_action(
     bot: AutoSend<Bot>,
     user: User,
+    is_admin: bool,
     pcid: ChatId,
     action: order::Action,
     mut db: Db,
@@ -79,13 +118,15 @@ async fn handle_order_action(
             db.order_msg_ids(oid).await?;
 
         for (cid, mid) in msgs.into_iter() {
-            if let Err(e) = bot.delete_message(cid, mid.message_id).await {
+            let deleted =
+                outbox::send(bot.delete_message(cid, mid.message_id)).await;
+            if let Err(e) = deleted {
                 log::warn!("could not delete order message ({cid}, {mid:?}): {e:?}")
             }
         }
     }
 
-    let res = db.perform_action(user, pcid, action).await;
+    let res = db.perform_action(user.clone(), is_admin, pcid, action).await;
     log::info!("db.perform_action => {res:?}");
     if let Err(e) = res {
         log::warn!("handle_order_action perform_action({uid}, {pcid}) => {e:?}");
@@ -95,21 +136,74 @@ async fn handle_order_action(
                      bot, cid, &format!("{e}")).await?;
         return Ok(false)
     }
-    let (prev_status, order) = res.unwrap();
+    let (prev, order) = res.unwrap();
+    let prev_status = prev.status();
+
+    let on_board = |o: &Order| o.status() == order::Status::Published;
+    if on_board(&prev) || order.as_ref().is_some_and(on_board) {
+        let updated = ui::board::update(bot.clone(), db.clone(), pcid).await;
+        if let Err(e) = updated {
+            log::warn!("could not update the board of {pcid}: {e:?}");
+        }
+    }
+
+    // Reports don't change the status, so they don't fit the match below
+    match (action_type, &order) {
+        (ActionKind::Report, Some(order)) => {
+            order_reported_notifications(
+                db, bot, utils::uid_to_cid(uid), pcid, &user, order).await?;
+            return Ok(false)
+        },
+        (ActionKind::DismissReports, Some(_)) => {
+            ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT), bot,
+                         dialogue.chat_id(), "Done").await?;
+            log::info!("Reports of {oid} are dismissed");
+            return Ok(true)
+        },
+        _ => {},
+    }
+
+    let resolves_dispute =
+        prev_status == order::Status::Disputed && order.is_some();
+    if !resolves_dispute && prev.is_moderation(uid, action_type) {
+        moderation_notifications(
+            db, bot, dialogue.chat_id(), pcid, &prev, order.as_ref(),
+            action_type).await?;
+        log::info!("Moderation: {prev_status} + {action_type:?}");
+        // Deleted orders' messages are already gone
+        return Ok(order.is_some())
+    }
 
     if order.is_none() {
-        bot.send_message(dialogue.chat_id(), "Deleted the order").await?;
+        outbox::send(bot.send_message(dialogue.chat_id(),
+            "Moved the order to the archive, only admins of the chat can \
+delete it for good")).await?;
         return Ok(false)
     }
     let order = order.unwrap();
     let new_status = order.status();
 
+    // The db has recorded it with the confirmation
+    let debt = ledger::Entry::new_debt(&prev, &order, db.now());
+
+    if prev_status == order::Status::Disputed {
+        dispute_resolved_notifications(
+            db, bot, dialogue.chat_id(), pcid, &prev, &order).await?;
+        log::info!("Dispute resolved: {action_type:?} -> {new_status}");
+        return Ok(true)
+    }
+
     match new_status {
         order::Status::Unpublished => {
-            bot.send_message(
+            outbox::send(bot.send_message(
                 dialogue.chat_id(),
-                "The order is unpublished. Now it's not shown to anybody.")
+                "The order is unpublished. Now it's not shown to anybody."))
                 .await?;
+
+            if let Some((_when, assignee_id, _user)) = &prev.assigned {
+                order_canceled_notification(
+                    db, bot, *assignee_id, pcid, &order).await?;
+            }
         },
         order::Status::Published => {
             order_published_notifications(
@@ -122,30 +216,40 @@ async fn handle_order_action(
         },
         order::Status::MarkedAsDelivered => {
             // Send message to the chat in which it was marked as delivered
-            bot.send_message(dialogue.chat_id(),
+            outbox::send(bot.send_message(dialogue.chat_id(),
             "Order is marked as delivered. It will be closed after \
-the publisher confirms they've received it.").await?;
+the publisher confirms they've received it.")).await?;
 
             // Send message to the owner asking to confirm delivery
             let assignee_link = get_assignee_link(db.clone(), &order).await?;
-            let msg = format!("{assignee_link} marked order as delivered. \
-Please confirm it.");
-
-            let priv_chat_id: ChatId = ChatId(order.customer.id.0 as i64);
-            ui::order::send_message(
-                db, &order, bot, Some(uid), priv_chat_id, Some(msg)).await?;
-
+            let owner_id = order.customer.id;
+            let currency = db.user_settings(owner_id).await?.currency;
+            let msg = match ui::settlement::format_settlement(&order, currency) {
+                Some(summary) => format!("{assignee_link} marked order as \
+delivered. {summary}. Please confirm it."),
+                None => format!("{assignee_link} marked order as delivered. \
+Please confirm it."),
+            };
+            send_receipt(db.clone(), bot.clone(), owner_id, &order).await?;
+
+            ui::quiet_hours::send_or_hold(
+                db, bot, owner_id, pcid, &order, msg).await?;
 
         },
+        order::Status::Disputed => {
+            dispute_opened_notifications(db, bot, uid, pcid, &order).await?;
+        },
         order::Status::DeliveryConfirmed => {
-            delivery_confirmed_notifications(db, bot, &order).await?;
+            delivery_confirmed_notifications(
+                db, bot, pcid, &order, debt.as_ref()).await?;
         },
     }
 
     // bot.send_message(dialogue.chat_id(),
     //     format!("Changed status to {new_status}")).await?;
     log::info!("Order status update: {prev_status} + {action_type:?} -> \
-{new_status}    {order:?}");
+{new_status}");
+    log::debug!("Updated order: {order:?}");
 
     Ok(prev_status != new_status)
 }
@@ -176,9 +280,32 @@ pub async fn order_published_notifications(
     }
 
     // Send notification to public chat
-    ui::order::send_message(db, order, bot, None, pcid,
+    ui::order::send_message(db.clone(), order, bot.clone(), None, pcid,
                             Some("New order is published")).await?;
 
+    new_order_notifications(db, bot, pcid, order).await
+}
+
+/// Sends a published order privately to members of the chat that want it,
+/// except its owner
+async fn new_order_notifications(
+    mut db: Db,
+    bot: AutoSend<Bot>,
+    pcid: ChatId,
+    order: &Order,
+) -> Result<(), Error> {
+    for uid in db.chat_members(pcid).await? {
+        if uid == order.customer.id || !db.user_settings(uid).await?.new_orders {
+            continue
+        }
+        let sent = ui::order::send_message(
+            db.clone(), order, bot.clone(), Some(uid), utils::uid_to_cid(uid),
+            Some("New order is published")).await;
+        if let Err(e) = sent {
+            // They might have blocked the bot
+            log::warn!("Could not send new order to {uid}: {e:?}");
+        }
+    }
     Ok(())
 }
 pub async fn order_assigned_notifications(
@@ -220,23 +347,219 @@ your order! Please send them a message to discuss the details.");
     Ok(())
 }
 
-pub async fn delivery_confirmed_notifications(
+/// Tells the former assignee that the owner has canceled the order
+pub async fn order_canceled_notification(
     db: Db,
     bot: AutoSend<Bot>,
+    assignee_id: UserId,
+    pcid: ChatId,
     order: &Order,
 ) -> Result<(), Error> {
-    // Send message to the assignee
+    let owner_link = markup::user_link(&order.customer);
+    let mut msg = format!("{owner_link} has canceled the order \
+you were going to deliver.");
+    if let Some(reason) = &order.cancel_reason {
+        msg = format!("{msg}\nReason: {}", markup::escape_html(reason));
+    }
+
+    ui::quiet_hours::send_or_hold(
+        db, bot, assignee_id, pcid, order, msg).await?;
+    Ok(())
+}
+
+/// Tells the owner and the assignee what an admin did to their order
+///
+/// `order` is None if the admin has deleted it
+pub async fn moderation_notifications(
+    db: Db,
+    bot: AutoSend<Bot>,
+    admin_cid: ChatId,
+    pcid: ChatId,
+    prev: &Order,
+    order: Option<&Order>,
+    kind: ActionKind,
+) -> Result<(), Error> {
+    let what = match kind {
+        ActionKind::Delete        => "deleted",
+        ActionKind::Unpublish     => "unpublished",
+        ActionKind::ForceUnassign => "unassigned the courier from",
+        _                         => "changed",
+    };
+    let msg = format!("An admin of the chat has {what} the order");
+
+    let mut uids = vec![prev.customer.id];
+    if let Some((_when, assignee_id, _user)) = &prev.assigned {
+        uids.push(*assignee_id);
+    }
+    for uid in uids {
+        if let Some(order) = order {
+            ui::quiet_hours::send_or_hold(
+                db.clone(), bot.clone(), uid, pcid, order, &msg).await?;
+        } else {
+            let name = markup::bold(markup::escape_html(&prev.name).to_string());
+            ui::quiet_hours::html_or_hold(
+                db.clone(), bot.clone(), uid, pcid, &format!("{msg} {name}"))
+                .await?;
+        }
+    }
+
+    if let Some(order) = order.filter(|o| o.status() == order::Status::Published) {
+        ui::order::send_message(
+            db, order, bot.clone(), None, pcid,
+            Some("The order is looking for a courier again")).await?;
+    }
+    ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT), bot, admin_cid, "Done")
+        .await?;
+    Ok(())
+}
+
+/// Thanks the reporter and sends the order to admins of the chat privately
+pub async fn order_reported_notifications(
+    mut db: Db,
+    bot: AutoSend<Bot>,
+    reporter_cid: ChatId,
+    pcid: ChatId,
+    reporter: &User,
+    order: &Order,
+) -> Result<(), Error> {
+    ui::text_msg(Some(ui::TEMP_MSG_TIMEOUT), bot.clone(), reporter_cid,
+        "Thanks, admins of the chat will look at the order").await?;
+
+    let reason = order.reports.last()
+        .map(|r| r.reason.human_name())
+        .unwrap_or("something");
+    let pending = order.pending_reports().count();
+    let reporter_link = markup::user_link(reporter);
+    let mut msg = format!("{reporter_link} has reported the order as \
+{reason}. Pending reports: {pending}");
+    if order.is_hidden(moderation::reports_to_hide()) {
+        msg = format!("{msg}\nThe order is hidden until you look at it");
+    }
+
+    for admin in data_gathering::chat_admins(&bot, &mut db, pcid).await {
+        let cid = utils::uid_to_cid(admin);
+        let sent = ui::order::send_admin_message(
+            db.clone(), order, bot.clone(), Vec::new(), cid, Some(&msg)).await;
+        if let Err(e) = sent {
+            // The admin might have never talked to the bot
+            log::warn!("Could not notify admin {admin} about a report: {e:?}");
+        }
+    }
+    Ok(())
+}
+
+/// Tells both sides that the dispute is open
+pub async fn dispute_opened_notifications(
+    db: Db,
+    bot: AutoSend<Bot>,
+    opened_by: UserId,
+    pcid: ChatId,
+    order: &Order,
+) -> Result<(), Error> {
+    let owner_id = order.customer.id;
     let assignee_id = order.assigned.as_ref().unwrap().1;
-    let assignee_cid = utils::uid_to_cid(assignee_id);
+
+    let other_id = if opened_by == owner_id { assignee_id } else { owner_id };
+    ui::quiet_hours::send_or_hold(
+        db.clone(), bot.clone(), other_id, pcid, order,
+        "There's a dispute about your order. \
+Admins of the chat will decide what to do with it.").await?;
+
     ui::order::send_message(
-        db.clone(), order, bot.clone(), Some(assignee_id), assignee_cid,
-        Some("Order delivery is confirmed! Thank you!")).await?;
+        db, order, bot, Some(opened_by), utils::uid_to_cid(opened_by),
+        Some("You've opened a dispute. \
+Admins of the chat will decide what to do with the order.")).await?;
+    Ok(())
+}
 
-    // Send message to the owner
+/// Tells both sides and the admin how the dispute is resolved
+///
+/// `prev` is the order before the resolution, while it was still assigned
+pub async fn dispute_resolved_notifications(
+    db: Db,
+    bot: AutoSend<Bot>,
+    admin_cid: ChatId,
+    pcid: ChatId,
+    prev: &Order,
+    order: &Order,
+) -> Result<(), Error> {
+    let outcome = match order.status() {
+        order::Status::DeliveryConfirmed => "the order is delivered",
+        order::Status::Published => "the order is published again",
+        _ => "the order is canceled",
+    };
+    let msg = format!("The dispute is resolved, {outcome}");
+
+    // Disputed orders are assigned, but the stored one may be broken
+    let assignee_id = prev.assigned.as_ref().map(|(_at, uid, _user)| *uid);
+    let uids = std::iter::once(order.customer.id).chain(assignee_id);
+    for uid in uids {
+        ui::quiet_hours::send_or_hold(
+            db.clone(), bot.clone(), uid, pcid, order, &msg).await?;
+    }
+
+    if order.status() == order::Status::Published {
+        ui::order::send_message(
+            db, order, bot.clone(), None, pcid,
+            Some("The order is published again")).await?;
+    }
+    ui::text_msg(Some(ui::TEMP_MSG_TIMEOUT), bot, admin_cid, &msg).await?;
+    Ok(())
+}
+
+/// Sends the owner the photo of the receipt if the courier has sent one
+async fn send_receipt(
+    mut db: Db,
+    bot: AutoSend<Bot>,
+    owner_id: UserId,
+    order: &Order,
+) -> Result<(), Error> {
+    let receipt = order.settlement.as_ref().and_then(|s| s.receipt.clone());
+    if let Some(receipt) = receipt {
+        let quiet = db.user_settings(owner_id).await?.is_quiet(db.now());
+        let name = markup::bold(markup::escape_html(&order.name).to_string());
+        let caption = format!("Receipt for {name}");
+        outbox::send(bot.send_photo(utils::uid_to_cid(owner_id),
+                                    InputFile::file_id(receipt))
+            .caption(caption)
+            .parse_mode(ParseMode::Html)
+            .disable_notification(quiet)).await?;
+    }
+    Ok(())
+}
+
+/// Tells both sides the delivery is confirmed and what the owner owes
+/// the courier for it, if anything
+pub async fn delivery_confirmed_notifications(
+    mut db: Db,
+    bot: AutoSend<Bot>,
+    pcid: ChatId,
+    order: &Order,
+    debt: Option<&ledger::Entry>,
+) -> Result<(), Error> {
     let owner_id = order.customer.id;
+    let assignee_id = order.assigned.as_ref().unwrap().1;
+    let mut owner_msg = "Order delivery is confirmed!".to_string();
+    let mut assignee_msg = "Order delivery is confirmed! Thank you!".to_string();
+    if let Some(debt) = debt {
+        let price = |currency| markup::format_price(debt.amount_in_drams, currency);
+        let currency = db.user_settings(owner_id).await?.currency;
+        owner_msg = format!("{owner_msg} You owe the courier {}, see {}",
+                            price(currency), Command::Balance);
+        let currency = db.user_settings(assignee_id).await?.currency;
+        assignee_msg = format!("{assignee_msg} You're owed {} for it, see {}",
+                               price(currency), Command::Balance);
+    }
+
+    // Send message to the assignee
+    ui::quiet_hours::send_or_hold(
+        db.clone(), bot.clone(), assignee_id, pcid, order, &assignee_msg)
+        .await?;
+
+    // Send message to the owner
     let owner_cid = utils::uid_to_cid(owner_id);
-    ui::text_msg(Some(ui::TEMP_MSG_TIMEOUT), bot, owner_cid,
-        "Order delivery is confirmed!").await?;
+    ui::text_msg(Some(ui::TEMP_MSG_TIMEOUT), bot, owner_cid, &owner_msg)
+        .await?;
 
     Ok(())
 }
@@ -0,0 +1,205 @@
+//! What users can do with the data we have about them
+//!
+//! `/my_data` sends everything we store about the user as a JSON file,
+//! `/forget_me` removes it after the user confirms. Orders other people
+//! have a part in are kept, with the user shown as a deleted one.
+
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, InputFile, User},
+};
+
+use crate::error::Error;
+use crate::Db;
+use crate::order::Order;
+use crate::privacy::{
+    self, ChatData, FiledReport, OrderMessage, ResolvedDispute, UserData,
+};
+use crate::ui::HandlerResult;
+use crate::{logger, outbox, utils};
+
+const BTN_DATA_PREFIX: &str = "fm";
+
+const FILE_NAME: &str = "my_data.json";
+
+async fn collect(db: &mut Db, user: &User) -> Result<UserData, Error> {
+    let uid = user.id;
+    let private_chat = utils::uid_to_cid(uid);
+    let mut chats = Vec::new();
+    for (pcid, name) in db.user_public_chats(uid).await? {
+        let (orders, assignments): (Vec<Order>, Vec<Order>) =
+            db.orders_involving(pcid, uid).await?.into_iter()
+            .partition(|o| o.customer.id == uid);
+        let mut order_messages = Vec::new();
+        for order in orders.iter().chain(assignments.iter()) {
+            let oid = match order.id {
+                Some(oid) => oid,
+                None => continue,
+            };
+            for (cid, mid) in db.order_msg_ids(oid).await? {
+                if cid == private_chat {
+                    order_messages.push(OrderMessage {
+                        order_id: oid,
+                        chat_id: cid,
+                        message_id: mid.message_id,
+                    });
+                }
+            }
+        }
+        let mut reports = Vec::new();
+        let mut resolved_disputes = Vec::new();
+        for order in db.orders_mentioning(pcid, uid).await? {
+            let oid = match order.id {
+                Some(oid) => oid,
+                None => continue,
+            };
+            reports.extend(order.reports.into_iter()
+                .filter(|r| r.by == uid)
+                .map(|report| FiledReport { order_id: oid, report }));
+            resolved_disputes.extend(order.disputes.into_iter()
+                .filter(|d| d.resolution.as_ref().is_some_and(|r| r.by == uid))
+                .map(|dispute| ResolvedDispute { order_id: oid, dispute }));
+        }
+        chats.push(ChatData {
+            id: pcid,
+            name,
+            digest_subscriber: db.digest_subscribers(pcid).await?
+                .contains(&uid),
+            banned: db.is_banned(pcid, uid).await?,
+            orders,
+            assignments,
+            order_messages,
+            ledger: db.ledger(pcid).await?.into_iter()
+                .filter(|entry| entry.involves(uid))
+                .collect(),
+            reports,
+            resolved_disputes,
+        });
+    }
+    Ok(UserData {
+        exported_at: db.now(),
+        profile: db.get_user(uid).await?,
+        settings: db.user_settings(uid).await?,
+        held_notifications: db.held_notifications(uid).await?,
+        templates: db.user_templates(uid).await?,
+        chats,
+    })
+}
+
+/// Sends everything we know about `user` to them privately as a file
+pub async fn send_my_data(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    cid: ChatId,
+    user: Option<&User>,
+) -> HandlerResult {
+    log::info!("-> privacy::send_my_data");
+    let user = match user {
+        Some(user) => user,
+        None => {
+            outbox::send(bot.send_message(cid,
+                "I don't know who sent this message. Thanks, Telegram!"))
+                .await?;
+            return Ok(())
+        },
+    };
+    let data = collect(&mut db, user).await?;
+    let json = serde_json::to_vec_pretty(&data)?;
+    outbox::send(bot.send_document(utils::uid_to_cid(user.id),
+        InputFile::memory(json).file_name(FILE_NAME))).await?;
+    Ok(())
+}
+
+/// Orders of `uid` that can't go on without them
+async fn active_orders(db: &mut Db, uid: UserId) -> Result<usize, Error> {
+    let mut count = 0;
+    for (pcid, _name) in db.user_public_chats(uid).await? {
+        count += db.orders_involving(pcid, uid).await?
+            .iter()
+            .filter(|o| privacy::is_active(o))
+            .count();
+    }
+    Ok(count)
+}
+
+fn active_orders_text(count: usize) -> String {
+    format!("You have {count} orders that aren't finished yet. Please \
+finish or cancel them first, so nobody waits for you forever.")
+}
+
+/// Asks `user` if they really want to be forgotten
+pub async fn ask_forget_me(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    user: Option<&User>,
+) -> HandlerResult {
+    log::info!("-> privacy::ask_forget_me");
+    let uid = match user {
+        Some(user) => user.id,
+        None => return Ok(()),
+    };
+    let cid = utils::uid_to_cid(uid);
+    let active = active_orders(&mut db, uid).await?;
+    if active > 0 {
+        outbox::send(bot.send_message(cid, active_orders_text(active)))
+            .await?;
+        return Ok(())
+    }
+    let keyboard = InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback(
+            "🗑 Yes, forget me", format!("{BTN_DATA_PREFIX} yes")),
+        InlineKeyboardButton::callback(
+            "No", format!("{BTN_DATA_PREFIX} no")),
+    ]]);
+    outbox::send(bot.send_message(cid, "I'll delete everything I know about \
+you: your profile, settings, templates, chats and orders.\n\n\
+Orders other people have delivered for you or you've delivered for them \
+stay, but they will show a deleted user instead of you. Bans and debts \
+between you and others stay too.\n\n\
+Are you sure?").reply_markup(keyboard)).await?;
+    Ok(())
+}
+
+/// If it's a forget me query then handle it and return `true`,
+/// otherwise just return `false`
+pub async fn try_handle_query(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    q: CallbackQuery,
+    data: &str,
+) -> Result<bool, Error> {
+    let mut args = data.split(' ');
+    if args.next() != Some(BTN_DATA_PREFIX) {
+        return Ok(false)
+    }
+    let confirmed = match (args.next(), args.next()) {
+        (Some("yes"), None) => true,
+        (Some("no"), None) => false,
+        _ => {
+            log::warn!("forget_me: malformed data {data:?}");
+            return Ok(true)
+        }
+    };
+    logger::set_handler("forget_me");
+    log::info!("-> privacy::try_handle_query {confirmed}");
+
+    let uid = q.from.id;
+    let text = if !confirmed {
+        "Good, I still remember you".to_string()
+    } else {
+        // Something could have been ordered since we asked
+        let active = active_orders(&mut db, uid).await?;
+        if active > 0 {
+            active_orders_text(active)
+        } else {
+            db.forget_user(uid).await?;
+            log::info!("{uid} is forgotten");
+            "Done, I've forgotten you. If you talk to me again, I'll \
+remember you from then on.".to_string()
+        }
+    };
+    if let Some(msg) = q.message {
+        outbox::send(bot.edit_message_text(msg.chat.id, msg.id, text)).await?;
+    }
+    Ok(true)
+}
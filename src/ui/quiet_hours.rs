@@ -0,0 +1,159 @@
+//! Private notifications that wait for the end of users' quiet hours
+//!
+//! Notifications that can wait are stored in the db during quiet hours,
+//! a background task sends them once the user's quiet hours are over.
+//! Urgent ones and subscriptions are sent right away but silently.
+
+use std::time::Duration;
+
+use teloxide::prelude::*;
+use teloxide::{ApiError, RequestError};
+
+use crate::error::Error;
+use crate::Db;
+use crate::order::Order;
+use crate::settings::HeldNotification;
+use crate::ui;
+use crate::utils;
+
+/// How often we look for quiet hours that are over
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Held notifications that still fail to send after that many days are
+/// dropped
+const MAX_HELD_DAYS: i64 = 2;
+
+/// Stores `held` if it's quiet hours for `uid`, returns whether it did
+async fn hold_if_quiet(
+    db: &mut Db,
+    uid: UserId,
+    held: HeldNotification,
+) -> Result<bool, Error> {
+    if !db.user_settings(uid).await?.is_quiet(db.now()) {
+        return Ok(false)
+    }
+    log::info!("holding a notification for {uid} till quiet hours end");
+    db.hold_notification(uid, &held).await?;
+    Ok(true)
+}
+
+/// Sends `order` to `uid` privately with `text` above it, or keeps it
+/// until their quiet hours end
+pub async fn send_or_hold<S: AsRef<str>>(
+    mut db: Db,
+    bot: AutoSend<Bot>,
+    uid: UserId,
+    pcid: ChatId,
+    order: &Order,
+    text: S,
+) -> Result<(), Error> {
+    let held = HeldNotification {
+        pcid,
+        order_id: order.id,
+        text: text.as_ref().to_string(),
+        held_at: db.now(),
+    };
+    if !hold_if_quiet(&mut db, uid, held).await? {
+        ui::order::send_message(
+            db, order, bot, Some(uid), utils::uid_to_cid(uid), Some(text))
+            .await?;
+    }
+    Ok(())
+}
+
+/// Like `send_or_hold` for a notification without an order, `text` is HTML
+pub async fn html_or_hold(
+    mut db: Db,
+    bot: AutoSend<Bot>,
+    uid: UserId,
+    pcid: ChatId,
+    text: &str,
+) -> Result<(), Error> {
+    let held = HeldNotification {
+        pcid,
+        order_id: None,
+        text: text.to_string(),
+        held_at: db.now(),
+    };
+    if !hold_if_quiet(&mut db, uid, held).await? {
+        ui::html_msg(None, bot, utils::uid_to_cid(uid), text).await?;
+    }
+    Ok(())
+}
+
+async fn send_held(
+    mut db: Db,
+    bot: AutoSend<Bot>,
+    uid: UserId,
+    held: HeldNotification,
+) -> Result<(), Error> {
+    let cid = utils::uid_to_cid(uid);
+    let order = match held.order_id {
+        Some(oid) => db.get_order(held.pcid, oid).await?,
+        None => None,
+    };
+    match order {
+        // The order is shown as it is now, with the actions it has now
+        Some(order) => {
+            ui::order::send_message(
+                db, &order, bot, Some(uid), cid, Some(&held.text)).await?;
+        },
+        None => ui::html_msg(None, bot, cid, &held.text).await?,
+    }
+    Ok(())
+}
+
+/// Whether sending to the user would fail the same way next time
+fn is_permanent(e: &Error) -> bool {
+    matches!(
+        e.downcast_ref::<RequestError>(),
+        Some(RequestError::Api(
+            ApiError::BotBlocked
+            | ApiError::UserDeactivated
+            | ApiError::CantInitiateConversation
+            | ApiError::ChatNotFound
+        ))
+    )
+}
+
+/// Sends held notifications of users whose quiet hours are over
+pub async fn flush(bot: AutoSend<Bot>, mut db: Db) -> Result<(), Error> {
+    let now = db.now();
+    for uid in db.users_with_held_notifications().await? {
+        if db.user_settings(uid).await?.is_quiet(now) {
+            continue
+        }
+        let held = db.take_held_notifications(uid).await?;
+        log::info!("quiet hours of {uid} are over, sending {} notifications",
+                   held.len());
+        for next in held {
+            let sent = send_held(db.clone(), bot.clone(), uid, next.clone()).await;
+            let e = match sent {
+                Ok(()) => continue,
+                Err(e) => e,
+            };
+            if is_permanent(&e) {
+                log::warn!("dropping a held notification to {uid}, \
+they can't get it: {e:?}");
+            } else if now - next.held_at > chrono::Duration::days(MAX_HELD_DAYS) {
+                log::warn!("dropping a held notification to {uid} held since \
+{}: {e:?}", next.held_at);
+            } else {
+                log::warn!("could not send a held notification to {uid}, \
+will try again: {e:?}");
+                db.hold_notification(uid, &next).await?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Sends held notifications when quiet hours end, forever
+pub async fn run(bot: AutoSend<Bot>, db: Db) {
+    loop {
+        if let Err(e) = flush(bot.clone(), db.clone()).await {
+            log::error!("quiet_hours::run: {e:?}");
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
@@ -0,0 +1,301 @@
+//! Orders made from templates on schedule
+//!
+//! `/repeat weekly fri 10:00 Coffee` makes the template "Coffee" create
+//! an order every Friday, `publish` before the name publishes it right
+//! away. A background task checks every minute which series are due.
+//! The owner gets every new order with buttons to skip the next one
+//! or stop the series, `/repeat skip` and `/repeat stop` do the same.
+
+use std::time::Duration;
+
+use teloxide::{
+    prelude::*,
+    types::InlineKeyboardButton,
+};
+
+use crate::error::Error;
+use crate::Db;
+use crate::order::{self, ActionError, ActionKind, Order, OrderId};
+use crate::recurrence::{self, Recurrence, Request};
+use crate::template::Template;
+use crate::ui::{self, HandlerResult, MyDialogue};
+use crate::ui::commands::Command;
+use crate::{collection, data_gathering, logger, markup, outbox, utils};
+
+/// How often we look for series that are due
+const CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+const BTN_DATA_PREFIX: &str = "rp";
+
+fn series_buttons(oid: OrderId) -> Vec<InlineKeyboardButton> {
+    let button = |text: &str, what: &str| InlineKeyboardButton::callback(
+        text.to_string(), format!("{BTN_DATA_PREFIX} {what} {oid}"));
+    vec![
+        button("⏭ Skip the next one", "skip"),
+        button("⏹ Stop the series", "stop"),
+    ]
+}
+
+/// Like "every week on Friday at 10:00, next on Fri 8 Jul"
+pub fn format_recurrence(r: &Recurrence) -> String {
+    let local = r.next_at + chrono::Duration::minutes(r.utc_offset_mins as i64);
+    format!("{}, next on {}", r.describe(), local.format("%a %-d %b"))
+}
+
+fn usage() -> String {
+    let cmd = Command::Repeat(String::new());
+    format!("Make orders from one of your {} on schedule, in your time zone:\n\
+{cmd} weekly fri 10:00 Coffee\n\
+{cmd} 2w mon 09:30 publish Pills\n\n\
+Skip the next order with {cmd} skip Coffee, or stop it with {cmd} stop Coffee",
+        Command::Templates)
+}
+
+/// What happens when the owner asks to skip or stop a series
+async fn skip_or_stop(
+    db: &mut Db,
+    uid: UserId,
+    name: &str,
+    stop: bool,
+) -> Result<String, Error> {
+    let mut template = match db.user_templates(uid).await?.into_iter()
+        .find(|t| t.name == name && t.recurrence.is_some())
+    {
+        Some(template) => template,
+        None => return Ok(format!("\"{name}\" doesn't make orders by itself")),
+    };
+    let text = if stop {
+        template.recurrence = None;
+        format!("\"{name}\" won't make orders anymore")
+    } else {
+        let now = db.now();
+        let recurrence = template.recurrence.as_mut().unwrap();
+        recurrence.advance(now);
+        format!("The next order of \"{name}\" is skipped, it's {}",
+                format_recurrence(recurrence))
+    };
+    db.save_template(uid, &template).await?;
+    log::info!("{uid}: {text}");
+    Ok(text)
+}
+
+/// Sets, skips or stops series of the user's templates
+pub async fn handle_command(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    msg: &Message,
+    args: &str,
+) -> HandlerResult {
+    log::info!("-> recurring::handle_command {args:?}");
+    let uid = match msg.from() {
+        Some(user) => user.id,
+        None => {
+            log::warn!("/repeat: no user in msg {msg:?}");
+            return Ok(())
+        },
+    };
+    let cid = utils::uid_to_cid(uid);
+    if args.trim().is_empty() {
+        let series: Vec<String> = db.user_templates(uid).await?.iter()
+            .filter_map(|t| Some(format!(" - {}: {}", t.name,
+                                         format_recurrence(t.recurrence.as_ref()?))))
+            .collect();
+        let text = if series.is_empty() {
+            usage()
+        } else {
+            format!("Your templates make orders:\n{}\n\n{}",
+                    series.join("\n"), usage())
+        };
+        outbox::send(bot.send_message(cid, text)).await?;
+        return Ok(())
+    }
+
+    let text = match recurrence::parse(args) {
+        Err(e) => format!("{e}\n\n{}", usage()),
+        Ok(Request::Skip { name }) =>
+            skip_or_stop(&mut db, uid, &name, false).await?,
+        Ok(Request::Stop { name }) =>
+            skip_or_stop(&mut db, uid, &name, true).await?,
+        Ok(Request::Set { name, every_weeks, weekday, at, publish }) => {
+            match db.user_templates(uid).await?.into_iter()
+                .find(|t| t.name == name)
+            {
+                None => format!("You have no template \"{name}\", \
+see {}", Command::Templates),
+                Some(mut template) => {
+                    let offset = db.user_settings(uid).await?.utc_offset_mins;
+                    let recurrence = Recurrence::new(
+                        every_weeks, weekday, at, offset, publish, db.now());
+                    let text = format!("\"{name}\" will make an order {}",
+                                       format_recurrence(&recurrence));
+                    template.recurrence = Some(recurrence);
+                    db.save_template(uid, &template).await?;
+                    log::info!("{uid}: {text}");
+                    text
+                },
+            }
+        },
+    };
+    outbox::send(bot.send_message(cid, text)).await?;
+    Ok(())
+}
+
+/// Tells the owner why their series couldn't make an order
+async fn notify_failed(
+    bot: AutoSend<Bot>,
+    uid: UserId,
+    template: &Template,
+    why: &str,
+) -> HandlerResult {
+    outbox::send(bot.send_message(utils::uid_to_cid(uid),
+        format!("🔁 I couldn't make an order from \"{}\": {why}",
+                template.name))).await?;
+    Ok(())
+}
+
+/// Makes an order of the series, publishes it if the owner wants it
+/// and sends it to them
+async fn create(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    uid: UserId,
+    template: &Template,
+) -> HandlerResult {
+    log::info!("-> recurring::create {uid} {:?}", template.name);
+    let user = db.get_user(uid).await?.ok_or("no such user")?;
+    let pcid = match data_gathering::user_public_chats(&mut db, uid).await?
+        .as_slice()
+    {
+        [(pcid, _name)] => *pcid,
+        [] => return notify_failed(bot, uid, template,
+                                   "I don't see you in any public chats").await,
+        _ => return notify_failed(bot, uid, template, "please choose the \
+chat for your orders in Settings ⚙️ of the menu").await,
+    };
+    if db.is_banned(pcid, uid).await? {
+        return notify_failed(bot, uid, template,
+                             &ActionError::Banned.to_string()).await
+    }
+    let limits = db.chat_limits(pcid).await?;
+    let created = db.order_creations(pcid, uid).await?;
+    if !limits.can_create_order(&created, db.now()) {
+        return notify_failed(bot, uid, template, &format!("you can create \
+at most {} orders a day in this chat", limits.orders_per_day)).await
+    }
+
+    let mut order = Order::new(template.draft.clone(),
+                               collection::policy().minimise(&user), db.now());
+    order.series = Some(template.name.clone());
+    let oid = db.add_order(pcid, &mut order).await?;
+    logger::set_order(oid);
+    order.id = Some(oid);
+    let name = markup::escape_html(&template.name);
+    let mut prefix = format!("🔁 A new order from \"{name}\" is created");
+
+    let publish = template.recurrence.as_ref().is_some_and(|r| r.publish);
+    if publish {
+        let action = order::Action {
+            kind: ActionKind::Publish,
+            order_id: oid,
+            note: None,
+            settlement: None,
+        };
+        match db.perform_action(user, false, pcid, action).await {
+            Ok((_prev, Some(published))) => {
+                order = published;
+                prefix = format!("🔁 A new order from \"{name}\" is published");
+                if let Err(e) = ui::board::update(bot.clone(), db.clone(), pcid)
+                    .await
+                {
+                    log::warn!("could not update the board of {pcid}: {e:?}");
+                }
+                // The owner gets it below with buttons of the series
+                ui::order_action::order_published_notifications(
+                    db.clone(), bot.clone(), pcid, pcid, &order).await?;
+            },
+            Ok((_prev, None)) => return Err("published order is gone".into()),
+            Err(e) => {
+                log::info!("could not publish {oid}: {e:?}");
+                prefix = format!("{prefix}, but I couldn't publish it: {}",
+                                 markup::escape_html(&e.to_string()));
+            },
+        }
+    }
+    ui::order::send_owner_message(db, &order, bot, uid, series_buttons(oid),
+                                  utils::uid_to_cid(uid), Some(prefix)).await?;
+    Ok(())
+}
+
+/// Makes orders of all series that are due
+pub async fn create_due(bot: AutoSend<Bot>, mut db: Db) -> Result<(), Error> {
+    let now = db.now();
+    for (uid, mut template) in db.recurring_templates().await? {
+        let recurrence = template.recurrence.as_mut().unwrap();
+        if !recurrence.is_due(now) {
+            continue
+        }
+        recurrence.advance(now);
+        // Even if it fails, trying again every minute won't help
+        db.save_template(uid, &template).await?;
+        if let Err(e) = create(bot.clone(), db.clone(), uid, &template).await {
+            log::error!("recurring::create_due {uid} {:?}: {e:?}",
+                        template.name);
+        }
+    }
+    Ok(())
+}
+
+/// Makes orders of series when they're due, forever
+pub async fn run(bot: AutoSend<Bot>, db: Db) {
+    loop {
+        if let Err(e) = create_due(bot.clone(), db.clone()).await {
+            log::error!("recurring::run: {e:?}");
+        }
+        tokio::time::sleep(CHECK_INTERVAL).await;
+    }
+}
+
+/// If it's a query of a series' button then handle it and return `true`,
+/// otherwise just return `false`
+pub async fn try_handle_query(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    dialogue: MyDialogue,
+    q: CallbackQuery,
+    data: &str,
+) -> Result<bool, Error> {
+    let mut args = data.split(' ');
+    if args.next() != Some(BTN_DATA_PREFIX) {
+        return Ok(false)
+    }
+    let what = args.next();
+    let oid = args.next().and_then(|oid| oid.parse().ok()).map(OrderId);
+    let (stop, oid) = match (what, oid, args.next()) {
+        (Some("skip"), Some(oid), None) => (false, oid),
+        (Some("stop"), Some(oid), None) => (true, oid),
+        _ => {
+            log::warn!("recurring: malformed data {data:?}");
+            return Ok(true)
+        }
+    };
+    logger::set_handler("recurring");
+    logger::set_order(oid);
+    log::info!("-> recurring::try_handle_query {data}");
+
+    let pcid = match ui::pcid_or_err(&bot, &mut db, &q, &dialogue).await {
+        Ok(pcid) => pcid,
+        // We've already told the user what's wrong
+        Err(_) => return Ok(true),
+    };
+    let uid = q.from.id;
+    let series = db.get_order(pcid, oid).await?
+        .filter(|o| o.customer.id == uid)
+        .and_then(|o| o.series);
+    let text = match series {
+        Some(name) => skip_or_stop(&mut db, uid, &name, stop).await?,
+        None => "This order is gone".to_string(),
+    };
+    ui::text_msg(Some(ui::TEMP_MSG_TIMEOUT), bot, dialogue.chat_id(), &text)
+        .await?;
+    Ok(true)
+}
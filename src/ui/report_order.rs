@@ -0,0 +1,115 @@
+//! Reporting orders to admins of the chat
+//!
+//! Clicking `Report` under an order asks what's wrong with it first,
+//! the report is stored once the member picks a reason. Both happen in
+//! the member's private chat, so the group doesn't see who reports what.
+//! The picker carries the public chat in its buttons, so the member's
+//! private dialogue isn't touched.
+
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup},
+};
+
+use crate::error::Error;
+use crate::Db;
+use crate::order::{self, ActionError, ActionKind, OrderId, ReportReason};
+use crate::ui::{self, HandlerResult, MyDialogue};
+use crate::logger;
+use crate::outbox;
+use crate::utils;
+
+const BTN_DATA_PREFIX: &str = "or";
+
+fn reasons_keyboard(pcid: ChatId, oid: OrderId) -> InlineKeyboardMarkup {
+    let rows = ReportReason::ALL.iter()
+        .map(|reason| vec![InlineKeyboardButton::callback(
+            reason.human_name().to_string(),
+            format!("{BTN_DATA_PREFIX} {} {pcid} {oid}", reason.id()))])
+        .collect::<Vec<_>>();
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// Asks `uid` in their private chat what's wrong with the order,
+/// if they're allowed to report it
+///
+/// `cid` is where they've clicked `Report`, they're told there if they
+/// haven't started a private chat with the bot yet
+pub async fn ask_reason(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    cid: ChatId,
+    uid: UserId,
+    pcid: ChatId,
+    oid: OrderId,
+) -> HandlerResult {
+    log::info!("-> report_order::ask_reason {oid}");
+    let private_cid = utils::uid_to_cid(uid);
+    let order = db.get_order(pcid, oid).await?;
+    let checked = match &order {
+        Some(order) => order.check_action(uid, false, ActionKind::Report)
+            .map(|_| ()),
+        None => Err(ActionError::OrderNotFound(oid)),
+    };
+    let sent = match checked {
+        Ok(()) => outbox::send(
+            bot.send_message(private_cid, "What's wrong with the order?")
+                .reply_markup(reasons_keyboard(pcid, oid))).await
+            .map(|_| ()).map_err(Error::from),
+        Err(e) => {
+            log::warn!("report_order::ask_reason {uid} {pcid} => {e:?}");
+            ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT),
+                         bot.clone(), private_cid, &format!("{e}")).await
+        },
+    };
+    if let Err(e) = sent {
+        log::warn!("report_order::ask_reason can't message {uid}: {e:?}");
+        ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT), bot, cid,
+            "To report an order, open a private chat with me and press \
+Start first, then click Report again").await?;
+    }
+    Ok(())
+}
+
+/// If it's a report reason query then handle it and return `true`,
+/// otherwise just return `false`
+pub async fn try_handle_query(
+    bot: AutoSend<Bot>,
+    db: Db,
+    dialogue: MyDialogue,
+    q: CallbackQuery,
+    data: &str,
+) -> Result<bool, Error> {
+    let mut args = data.split(' ');
+    if args.next() != Some(BTN_DATA_PREFIX) {
+        return Ok(false)
+    }
+    let reason = args.next().and_then(ReportReason::maybe_from_id);
+    let pcid = args.next().and_then(|pcid| pcid.parse().ok()).map(ChatId);
+    let oid = args.next().and_then(|oid| oid.parse().ok()).map(OrderId);
+    let (reason, pcid, oid) = match (reason, pcid, oid, args.next()) {
+        (Some(reason), Some(pcid), Some(oid), None) => (reason, pcid, oid),
+        _ => {
+            log::warn!("report_order: malformed data {data:?}");
+            return Ok(true)
+        }
+    };
+    logger::set_handler("report_order");
+    logger::set_order(oid);
+    log::info!("-> report_order::try_handle_query {reason:?} {oid}");
+
+    let action = order::Action {
+        kind: ActionKind::Report,
+        order_id: oid,
+        note: Some(reason.id().to_string()),
+        settlement: None,
+    };
+    ui::order_action::handle_order_action(
+        bot.clone(), q.from, false, pcid, action, db, dialogue).await?;
+
+    // Reported or not, the reason is picked
+    if let Some(msg) = q.message {
+        outbox::send(bot.delete_message(msg.chat.id, msg.id)).await?;
+    }
+    Ok(true)
+}
@@ -6,6 +6,7 @@ use crate::HandlerResult;
 use crate::ui;
 use crate::utils;
 use crate::markup;
+use crate::outbox;
 use std::time::Duration;
 
 /// Delete our "hello" sent in a public that after this amount
@@ -17,8 +18,9 @@ pub async fn say_hello(
     user: Option<&User>,
 ) -> HandlerResult {
     if cid.is_user() {
-        bot.send_message(cid, "Send this message in a public chat, \
-so I know you're there.").await?;
+        outbox::send(bot.send_message(cid,
+            "Send this message in a public chat, so I know you're there."))
+            .await?;
         return Ok(());
     }
 
@@ -30,24 +32,26 @@ so I know you're there.").await?;
                     .parse_mode(teloxide::types::ParseMode::Html);
                 let mention = markup::user_link(user);
                 let msg = format!("{mention} See you in a private chat!");
-                let sent: Message = bot.send_message(cid, msg).await?;
+                let sent: Message =
+                    outbox::send(bot.send_message(cid, msg)).await?;
                 sent
             };
             // Now say hello in a private chat
             let help = ui::help::help();
             let msg =
                 format!("Hi there! Here is how you can talk to me:\n{help}");
-            bot.send_message(utils::uid_to_cid(user.id), msg).await?;
+            outbox::send(bot.send_message(utils::uid_to_cid(user.id), msg))
+                .await?;
             sent
         } else {
-            bot.send_message(cid, "Hi there!").await?
+            outbox::send(bot.send_message(cid, "Hi there!")).await?
         };
 
     let msg_id = sent.id;
     tokio::spawn(async move {
         tokio::time::sleep(OUR_HELLO_DEL_TIMEOUT).await;
         // I don't care if it fails, it's no biggie
-        let _ = bot.delete_message(cid, msg_id).await;
+        let _ = outbox::send(bot.delete_message(cid, msg_id)).await;
     });
 
     Ok(())
@@ -0,0 +1,211 @@
+//! Settings menu of a user
+//!
+//! Every button changes one setting and the menu is edited in place
+//! to show the new value.
+
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup},
+    ApiError, RequestError,
+};
+
+use crate::error::Error;
+use crate::Db;
+use crate::settings::{QuietHours, UserSettings};
+use crate::ui::HandlerResult;
+use crate::logger;
+use crate::outbox;
+
+const BTN_DATA_PREFIX: &str = "st";
+
+const TEXT: &str = "Settings ⚙️\n\nTap a button to change it";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Item {
+    DefaultChat,
+    NewOrders,
+    Digests,
+    QuietHours,
+    EarlierTimeZone,
+    LaterTimeZone,
+    Currency,
+    Stats,
+}
+
+impl Item {
+    const ALL: &'static [Item] = &[
+        Item::DefaultChat,
+        Item::NewOrders,
+        Item::Digests,
+        Item::QuietHours,
+        Item::EarlierTimeZone,
+        Item::LaterTimeZone,
+        Item::Currency,
+        Item::Stats,
+    ];
+
+    const fn id(self) -> &'static str {
+        match self {
+            Item::DefaultChat     => "chat",
+            Item::NewOrders       => "new_orders",
+            Item::Digests         => "digests",
+            Item::QuietHours      => "quiet",
+            Item::EarlierTimeZone => "tz_minus",
+            Item::LaterTimeZone   => "tz_plus",
+            Item::Currency        => "currency",
+            Item::Stats           => "stats",
+        }
+    }
+
+    fn maybe_from_id(s: &str) -> Option<Item> {
+        Self::ALL.iter().copied().find(|item| item.id() == s)
+    }
+}
+
+/// Settings together with what's stored elsewhere but shown here
+struct Shown {
+    settings: UserSettings,
+    chats: Vec<(ChatId, String)>,
+    digests: bool,
+}
+
+async fn load(db: &mut Db, uid: UserId) -> Result<Shown, Error> {
+    let settings = db.user_settings(uid).await?;
+    let chats = db.user_public_chats(uid).await?;
+    let mut digests = false;
+    for (pcid, _name) in chats.iter() {
+        digests = digests || db.digest_subscribers(*pcid).await?.contains(&uid);
+    }
+    Ok(Shown { settings, chats, digests })
+}
+
+fn on_off(on: bool) -> &'static str {
+    if on { "on" } else { "off" }
+}
+
+fn button_text(item: Item, shown: &Shown) -> String {
+    let settings = &shown.settings;
+    match item {
+        Item::DefaultChat => {
+            let chat = shown.chats.iter()
+                .find(|(pcid, _name)| Some(*pcid) == settings.default_chat)
+                .map(|(_pcid, name)| name.as_str())
+                .unwrap_or("not chosen");
+            format!("💬 Chat for orders: {chat}")
+        },
+        Item::NewOrders =>
+            format!("🔔 New orders in private: {}", on_off(settings.new_orders)),
+        Item::Digests =>
+            format!("📰 Daily digests in private: {}", on_off(shown.digests)),
+        Item::QuietHours => match settings.quiet_hours {
+            Some(q) => format!("🌙 Quiet hours: {:02}:00-{:02}:00",
+                               q.from, q.to),
+            None => "🌙 Quiet hours: off".to_string(),
+        },
+        Item::EarlierTimeZone =>
+            format!("🕒 Time zone: {} ➖", settings.format_utc_offset()),
+        Item::LaterTimeZone => "➕".to_string(),
+        Item::Currency =>
+            format!("💱 Show prices also in: {}", settings.currency.code()),
+        Item::Stats =>
+            format!("🏆 Show me in chat stats: {}", on_off(!settings.hide_in_stats)),
+    }
+}
+
+fn keyboard(shown: &Shown) -> InlineKeyboardMarkup {
+    let button = |item: Item| InlineKeyboardButton::callback(
+        button_text(item, shown), format!("{BTN_DATA_PREFIX} {}", item.id()));
+    let mut rows: Vec<Vec<InlineKeyboardButton>> = Vec::new();
+    for item in Item::ALL {
+        match item {
+            // Time zone is changed with two buttons in one row
+            Item::EarlierTimeZone => rows.push(vec![
+                button(Item::EarlierTimeZone), button(Item::LaterTimeZone)]),
+            Item::LaterTimeZone => {},
+            _ => rows.push(vec![button(*item)]),
+        }
+    }
+    InlineKeyboardMarkup::new(rows)
+}
+
+/// Shows the settings menu of `uid` in `cid`
+pub async fn show(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    cid: ChatId,
+    uid: UserId,
+) -> HandlerResult {
+    log::info!("-> settings::show {uid}");
+    let shown = load(&mut db, uid).await?;
+    outbox::send(bot.send_message(cid, TEXT).reply_markup(keyboard(&shown)))
+        .await?;
+    Ok(())
+}
+
+/// Changes `item` to its next value
+async fn change(db: &mut Db, uid: UserId, item: Item) -> HandlerResult {
+    let Shown { mut settings, chats, digests } = load(db, uid).await?;
+    match item {
+        Item::DefaultChat => {
+            // Goes through user's chats and then back to none
+            let pos = chats.iter()
+                .position(|(pcid, _name)| Some(*pcid) == settings.default_chat);
+            let next = match pos {
+                Some(pos) => pos + 1,
+                None => 0,
+            };
+            settings.default_chat = chats.get(next).map(|(pcid, _name)| *pcid);
+        },
+        Item::NewOrders => settings.new_orders = !settings.new_orders,
+        Item::Digests => {
+            for (pcid, _name) in chats.iter() {
+                db.set_digest_subscription(*pcid, uid, !digests).await?;
+            }
+        },
+        Item::QuietHours =>
+            settings.quiet_hours = QuietHours::next_preset(settings.quiet_hours),
+        Item::EarlierTimeZone => settings.shift_utc_offset(-1),
+        Item::LaterTimeZone => settings.shift_utc_offset(1),
+        Item::Currency => settings.currency = settings.currency.next(),
+        Item::Stats => settings.hide_in_stats = !settings.hide_in_stats,
+    }
+    log::info!("{uid} has changed {item:?}: {settings:?}");
+    db.set_user_settings(uid, &settings).await
+}
+
+/// If it's a settings query then handle it and return `true`,
+/// otherwise just return `false`
+pub async fn try_handle_query(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    q: CallbackQuery,
+    data: &str,
+) -> Result<bool, Error> {
+    let mut args = data.split(' ');
+    if args.next() != Some(BTN_DATA_PREFIX) {
+        return Ok(false)
+    }
+    let item = match (args.next().and_then(Item::maybe_from_id), args.next()) {
+        (Some(item), None) => item,
+        _ => {
+            log::warn!("settings: malformed data {data:?}");
+            return Ok(true)
+        }
+    };
+    logger::set_handler("settings");
+    log::info!("-> settings::try_handle_query {item:?}");
+
+    let uid = q.from.id;
+    change(&mut db, uid, item).await?;
+    let shown = load(&mut db, uid).await?;
+    if let Some(msg) = q.message {
+        let edited = outbox::send(bot.edit_message_text(msg.chat.id, msg.id, TEXT)
+            .reply_markup(keyboard(&shown))).await;
+        match edited {
+            // Like time zone that can't go further
+            Ok(_) | Err(RequestError::Api(ApiError::MessageNotModified)) => {},
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(true)
+}
@@ -0,0 +1,206 @@
+//! Asking the courier what they've actually paid
+//!
+//! Clicking `Mark as delivered` doesn't mark the order right away, first
+//! the courier writes how much they've paid, optionally as a caption of
+//! a photo of the receipt, or says it's what we expected. The owner gets
+//! the actual cost and how much they owe with the confirmation prompt.
+
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, User},
+    dispatching::UpdateHandler,
+};
+use serde::{Serialize, Deserialize};
+
+use crate::error::Error;
+use crate::Db;
+use crate::order::{self, ActionError, ActionKind, Order, OrderId, Settlement};
+use crate::settings::Currency;
+use crate::ui::{self, HandlerResult, MyDialogue};
+use crate::{logger, markup, outbox};
+
+const BTN_DATA_PREFIX: &str = "dl";
+
+/// Waiting for the courier to write what they've paid
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct State {
+    pub pcid: ChatId,
+    pub order_id: OrderId,
+}
+
+pub fn schema() -> UpdateHandler<Error> {
+    Update::filter_message()
+        .endpoint(receive_cost)
+}
+
+/// Like "Paid 5200 AMD + 500 AMD reward = 5700 AMD to pay the courier",
+/// None until the courier tells what they've paid
+pub fn format_settlement(order: &Order, currency: Currency) -> Option<String> {
+    let settlement = order.settlement.as_ref()?;
+    let price = |drams| markup::format_price(drams, currency);
+    let mut text = format!("Paid {}", price(settlement.actual_cost_in_drams));
+    if order.markup_in_drams > 0 {
+        text = format!("{text} + {} reward", price(order.markup_in_drams));
+    }
+    Some(format!("{text} = {} to pay the courier",
+                 price(settlement.owed_in_drams(order.markup_in_drams))))
+}
+
+/// Asks `uid` what they've paid, if they're allowed to mark the order
+/// as delivered
+pub async fn ask_cost(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    dialogue: MyDialogue,
+    uid: UserId,
+    pcid: ChatId,
+    oid: OrderId,
+) -> HandlerResult {
+    log::info!("-> ask_cost {oid}");
+    let checked = match db.get_order(pcid, oid).await? {
+        Some(order) => order.check_action(uid, false, ActionKind::MarkAsDelivered)
+            .map(|_| order),
+        None => Err(ActionError::OrderNotFound(oid)),
+    };
+    let order = match checked {
+        Ok(order) => order,
+        Err(e) => {
+            log::warn!("ask_cost {uid} {pcid} => {e:?}");
+            ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT),
+                         bot, dialogue.chat_id(), &format!("{e}")).await?;
+            return Ok(())
+        },
+    };
+
+    let currency = db.user_settings(uid).await?.currency;
+    let expected = markup::format_price(order.expected_cost_in_drams(), currency);
+    let keyboard = InlineKeyboardMarkup::new(vec![vec![
+        InlineKeyboardButton::callback(format!("As expected: {expected}"),
+                                       format!("{BTN_DATA_PREFIX} same {oid}")),
+    ]]);
+    dialogue.update(ui::State::Settlement(State { pcid, order_id: oid }))
+        .await?;
+    outbox::send(bot.send_message(dialogue.chat_id(), "How much have you \
+actually paid for the order, in Armenian Drams? If you have the receipt, \
+send its photo with the amount as the caption.")
+        .reply_markup(keyboard)).await?;
+    Ok(())
+}
+
+/// Marks the order as delivered along with what the courier has paid
+async fn settle_and_deliver(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    dialogue: MyDialogue,
+    user: User,
+    state: State,
+    cost: Option<u64>,
+    receipt: Option<String>,
+) -> HandlerResult {
+    dialogue.exit().await?;
+    let State { pcid, order_id: oid } = state;
+    let cost = match cost {
+        Some(cost) => cost,
+        None => match db.get_order(pcid, oid).await? {
+            Some(order) => order.expected_cost_in_drams(),
+            None => 0,
+        },
+    };
+    let settlement = Settlement {
+        actual_cost_in_drams: cost,
+        receipt,
+        at: db.now(),
+    };
+    let action = order::Action {
+        kind: ActionKind::MarkAsDelivered,
+        order_id: oid,
+        note: None,
+        settlement: Some(settlement),
+    };
+    ui::order_action::handle_order_action(
+        bot, user, false, pcid, action, db, dialogue).await?;
+    Ok(())
+}
+
+/// If it's a query to deliver the order at the expected cost then handle
+/// it and return `true`, otherwise just return `false`
+pub async fn try_handle_query(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    dialogue: MyDialogue,
+    q: CallbackQuery,
+    data: &str,
+) -> Result<bool, Error> {
+    let mut args = data.split(' ');
+    if args.next() != Some(BTN_DATA_PREFIX) {
+        return Ok(false)
+    }
+    let what = args.next();
+    let oid = args.next().and_then(|oid| oid.parse().ok()).map(OrderId);
+    let oid = match (what, oid, args.next()) {
+        (Some("same"), Some(oid), None) => oid,
+        _ => {
+            log::warn!("settlement: malformed data {data:?}");
+            return Ok(true)
+        }
+    };
+    logger::set_handler("settlement");
+    logger::set_order(oid);
+    log::info!("-> settlement::try_handle_query {data}");
+
+    let pcid = match ui::pcid_or_err(&bot, &mut db, &q, &dialogue).await {
+        Ok(pcid) => pcid,
+        // We've already told the user what's wrong
+        Err(_) => return Ok(true),
+    };
+    let state = State { pcid, order_id: oid };
+    settle_and_deliver(bot.clone(), db, dialogue, q.from, state, None, None)
+        .await?;
+
+    // The question is answered whether it's delivered or not
+    if let Some(msg) = q.message {
+        outbox::send(bot.delete_message(msg.chat.id, msg.id)).await?;
+    }
+    Ok(true)
+}
+
+async fn receive_cost(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    db: Db,
+    dialogue: MyDialogue,
+    state: State,
+) -> HandlerResult {
+    logger::set_handler("settlement::receive_cost");
+    logger::set_order(state.order_id);
+    log::info!("-> receive_cost {state:?}");
+
+    // Telegram sends a few sizes of the photo, the last one is the largest
+    let receipt = msg.photo()
+        .and_then(|sizes| sizes.last())
+        .map(|size| size.file_id.clone());
+    let text = msg.text().or_else(|| msg.caption()).map(str::trim).unwrap_or("");
+    let cost = match ui::new_order::parse_price(text) {
+        Ok(cost) => cost,
+        Err(e) => {
+            let text = if receipt.is_some() && text.is_empty() {
+                "Please send the receipt again with the amount you've paid \
+as the caption".to_string()
+            } else {
+                format!("I don't understand the amount - {e}, please try again")
+            };
+            outbox::send(bot.send_message(dialogue.chat_id(), text)).await?;
+            return Ok(())
+        },
+    };
+
+    let user = msg.from();
+    if user.is_none() {
+        log::warn!("receive_cost No user in msg {msg:?}");
+        return Err(format!("No user is msg {msg:?}").into());
+    }
+    let user = user.unwrap().clone();
+
+    settle_and_deliver(bot, db, dialogue, user, state, Some(cost), receipt)
+        .await
+}
@@ -1,5 +1,6 @@
 use crate::Db;
 use crate::ui::{self, HandlerResult, MyDialogue, State};
+use crate::outbox;
 use teloxide::{
     prelude::*,
     types::Chat,
@@ -16,18 +17,27 @@ pub async fn show_my_orders(
     log::info!("-> show_my_orders");
     let orders = db.clone().orders_submitted_by_user(pcid, uid).await?;
     if orders.is_empty() {
-        bot.send_message(dialogue.chat_id(), "You have no current orders")
+        outbox::send(
+            bot.send_message(dialogue.chat_id(), "You have no current orders"))
             .await?;
     } else {
-        bot.send_message(dialogue.chat_id(), "Your orders:").await?;
-        let uid = match chat.is_private() {
-            true => Some(uid),
-            false => None,
-        };
+        outbox::send(bot.send_message(dialogue.chat_id(), "Your orders:"))
+            .await?;
         let msg: Option<&str> = None;
         for order in orders.iter() {
-            ui::order::send_message(
-                db.clone(), order, bot.clone(), uid, chat.id, msg).await?;
+            match (chat.is_private(), order.id) {
+                // Owners can order the same thing again
+                (true, Some(oid)) => {
+                    ui::order::send_owner_message(
+                        db.clone(), order, bot.clone(), uid,
+                        ui::template::order_buttons(oid), chat.id, msg).await?;
+                },
+                _ => {
+                    ui::order::send_message(
+                        db.clone(), order, bot.clone(), None, chat.id, msg)
+                        .await?;
+                },
+            }
         }
     }
     dialogue.update(State::Start).await?;
@@ -0,0 +1,116 @@
+//! Statistics of a public chat and its top couriers
+//!
+//! `/stats` covers the last week, `/stats day`, `/stats month` and
+//! `/stats all` cover other periods. Users that hide themselves in
+//! settings aren't on the leaderboard.
+
+use std::fmt::Write;
+
+use teloxide::{
+    prelude::*,
+    types::{ChatKind, ParseMode},
+};
+
+use crate::error::Error;
+use crate::Db;
+use crate::privacy;
+use crate::stats::{Period, Stats};
+use crate::ui::{self, HandlerResult};
+use crate::ui::commands::Command;
+use crate::{markup, outbox};
+
+/// How many couriers the leaderboard shows
+const TOP_COURIERS: usize = 5;
+
+fn format_period(period: Period) -> &'static str {
+    match period {
+        Period::Day   => "the last day",
+        Period::Week  => "the last week",
+        Period::Month => "the last 30 days",
+        Period::All   => "all time",
+    }
+}
+
+fn format_median(dur: Option<chrono::Duration>) -> String {
+    dur.map(markup::human_positive_duration)
+        .unwrap_or_else(|| "—".to_string())
+}
+
+async fn format(
+    db: &mut Db,
+    stats: &Stats,
+    period: Period,
+) -> Result<String, Error> {
+    let mut text = format!("📊 Stats of the chat for {}\n\n\
+Created: {}\nPublished: {}\nDelivered: {}\nCanceled: {}\n\n\
+Median time to find a courier: {}\n\
+Median time to deliver: {}\n\
+Items delivered worth: {}\n",
+        format_period(period),
+        stats.created, stats.published, stats.delivered, stats.canceled,
+        format_median(stats.median_to_assign),
+        format_median(stats.median_to_deliver),
+        markup::format_amd(stats.delivered_value_in_drams));
+
+    let mut top = Vec::new();
+    for (uid, delivered) in stats.couriers.iter() {
+        if top.len() == TOP_COURIERS {
+            break
+        }
+        if *uid == privacy::ANONYMOUS_USER_ID
+            || db.user_settings(*uid).await?.hide_in_stats
+        {
+            continue
+        }
+        if let Some(user) = db.get_user(*uid).await? {
+            top.push((user, *delivered));
+        }
+    }
+    if !top.is_empty() {
+        writeln!(&mut text, "\n🏆 {}", markup::bold("Top couriers"))?;
+        for (place, (user, delivered)) in top.iter().enumerate() {
+            writeln!(&mut text, "{}. {} — {delivered}", place + 1,
+                     markup::user_link(user))?;
+        }
+    }
+    write!(&mut text, "\nOther periods: {cmd} day, {cmd} month, {cmd} all",
+           cmd = Command::Stats(String::new()))?;
+    Ok(text)
+}
+
+/// Shows stats of the public chat the command is sent to
+pub async fn handle_command(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    msg: &Message,
+    args: &str,
+) -> HandlerResult {
+    log::info!("-> stats::handle_command {args:?}");
+    let cid = msg.chat.id;
+    if !matches!(msg.chat.kind, ChatKind::Public(_)) {
+        outbox::send(bot.send_message(cid,
+            "Please send it to the public chat you want stats of")).await?;
+        return Ok(())
+    }
+    let args = args.trim();
+    let period = if args.is_empty() {
+        Period::default()
+    } else {
+        match Period::maybe_from_id(args) {
+            Some(period) => period,
+            None => {
+                ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT), bot, cid,
+                    "I don't understand that, try day, week, month or all")
+                    .await?;
+                return Ok(())
+            },
+        }
+    };
+
+    let orders = db.chat_history(cid).await?;
+    let stats = Stats::new(&orders, period.since(db.now()));
+    let text = format(&mut db, &stats, period).await?;
+    outbox::send(bot.parse_mode(ParseMode::Html).send_message(cid, text))
+        .await?;
+    Ok(())
+}
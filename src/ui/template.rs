@@ -0,0 +1,243 @@
+//! Saving orders as templates and creating orders from them again
+//!
+//! Orders in `show_my_orders` have buttons to duplicate them or save them
+//! as a named template. `/templates` and `/new_order` show the templates,
+//! either way the new order starts as a prefilled draft.
+
+use teloxide::{
+    prelude::*,
+    types::{InlineKeyboardButton, InlineKeyboardMarkup, User},
+    dispatching::UpdateHandler,
+};
+use serde::{Serialize, Deserialize};
+
+use crate::error::Error;
+use crate::Db;
+use crate::order::{Draft, Order, OrderId};
+use crate::template::{self, Template};
+use crate::ui::{self, HandlerResult, MyDialogue};
+use crate::{logger, outbox, utils};
+use std::fmt::Write;
+
+const BTN_DATA_PREFIX: &str = "tp";
+
+/// Waiting for the user to name a template
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct State {
+    pub draft: Draft,
+}
+
+pub fn schema() -> UpdateHandler<Error> {
+    Update::filter_message()
+        .endpoint(receive_name)
+}
+
+fn button(text: &str, args: String) -> InlineKeyboardButton {
+    InlineKeyboardButton::callback(text.to_string(),
+                                   format!("{BTN_DATA_PREFIX} {args}"))
+}
+
+/// Buttons under an order in its owner's private chat
+pub fn order_buttons(oid: OrderId) -> Vec<InlineKeyboardButton> {
+    vec![
+        button("🔁 Duplicate", format!("dup {oid}")),
+        button("💾 Save as template", format!("save {oid}")),
+    ]
+}
+
+/// A button to create an order from each of `templates`
+pub fn templates_keyboard(templates: &[Template]) -> InlineKeyboardMarkup {
+    InlineKeyboardMarkup::new(templates.iter()
+        .map(|t| vec![button(&t.name, format!("use {}", template::key(&t.name)))]))
+}
+
+/// Shows templates of `user` in their private chat, with buttons to use
+/// or delete each of them
+pub async fn send_templates(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    user: Option<&User>,
+) -> HandlerResult {
+    log::info!("-> template::send_templates");
+    let uid = match user {
+        Some(user) => user.id,
+        None => return Ok(()),
+    };
+    let cid = utils::uid_to_cid(uid);
+    let templates = db.user_templates(uid).await?;
+    if templates.is_empty() {
+        outbox::send(bot.send_message(cid, "You have no templates yet. \
+Save one with the button under any of your orders in \"My orders\"")).await?;
+        return Ok(())
+    }
+    let keyboard = InlineKeyboardMarkup::new(templates.iter()
+        .map(|t| vec![
+            button(&t.name, format!("use {}", template::key(&t.name))),
+            button("🗑", format!("del {}", template::key(&t.name))),
+        ]));
+    let mut text = "Your templates, click one to create an order from it:"
+        .to_string();
+    for t in templates.iter() {
+        if let Some(recurrence) = &t.recurrence {
+            write!(&mut text, "\n - {} repeats {}", t.name,
+                   ui::recurring::format_recurrence(recurrence))?;
+        }
+    }
+    write!(&mut text, "\n\nMake orders from one on schedule with {}",
+           ui::commands::Command::Repeat(String::new()))?;
+    outbox::send(bot.send_message(cid, text).reply_markup(keyboard)).await?;
+    Ok(())
+}
+
+/// The order `oid` if `uid` owns it
+async fn own_order(
+    bot: &AutoSend<Bot>,
+    db: &mut Db,
+    dialogue: &MyDialogue,
+    q: &CallbackQuery,
+    oid: OrderId,
+) -> Result<Option<Order>, Error> {
+    let pcid = match ui::pcid_or_err(bot, db, q, dialogue).await {
+        Ok(pcid) => pcid,
+        // We've already told the user what's wrong
+        Err(_) => return Ok(None),
+    };
+    match db.get_order(pcid, oid).await? {
+        Some(order) if order.customer.id == q.from.id => Ok(Some(order)),
+        _ => {
+            ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT), bot.clone(),
+                         dialogue.chat_id(), "This order is gone").await?;
+            Ok(None)
+        },
+    }
+}
+
+/// If it's a template query then handle it and return `true`,
+/// otherwise just return `false`
+pub async fn try_handle_query(
+    bot: AutoSend<Bot>,
+    mut db: Db,
+    dialogue: MyDialogue,
+    q: CallbackQuery,
+    data: &str,
+) -> Result<bool, Error> {
+    let mut args = data.split(' ');
+    if args.next() != Some(BTN_DATA_PREFIX) {
+        return Ok(false)
+    }
+    let what = args.next();
+    let arg = args.next().and_then(|arg| arg.parse::<u64>().ok());
+    let (what, arg) = match (what, arg, args.next()) {
+        (Some(what), Some(arg), None) => (what, arg),
+        _ => {
+            log::warn!("template: malformed data {data:?}");
+            return Ok(true)
+        }
+    };
+    // Drafts are made only in private chats
+    if !dialogue.chat_id().is_user() {
+        log::warn!("template: query in a public chat {data:?}");
+        return Ok(true)
+    }
+    logger::set_handler("template");
+    log::info!("-> template::try_handle_query {what} {arg}");
+
+    let uid = q.from.id;
+    match what {
+        "dup" => {
+            let oid = OrderId(arg);
+            logger::set_order(oid);
+            if let Some(order) = own_order(&bot, &mut db, &dialogue, &q, oid).await? {
+                ui::new_order::start_draft(
+                    bot, db, dialogue, uid, Draft::from(&order)).await?;
+            }
+        },
+        "save" => {
+            let oid = OrderId(arg);
+            logger::set_order(oid);
+            if let Some(order) = own_order(&bot, &mut db, &dialogue, &q, oid).await? {
+                dialogue.update(ui::State::Template(
+                    State { draft: Draft::from(&order) })).await?;
+                outbox::send(bot.send_message(dialogue.chat_id(),
+                    "How do you want to call this template? \
+Something you'll recognise on a button, like \"Coffee beans\"")).await?;
+            }
+        },
+        "use" | "del" => {
+            let templates = db.user_templates(uid).await?;
+            let template = match templates.into_iter().find(|t| template::key(&t.name) == arg) {
+                Some(template) => template,
+                None => {
+                    ui::text_msg(Some(ui::TEMP_MSG_FAST_TIMEOUT), bot,
+                        dialogue.chat_id(), "This template is gone").await?;
+                    return Ok(true)
+                },
+            };
+            if what == "use" {
+                ui::new_order::start_draft(
+                    bot, db, dialogue, uid, template.draft).await?;
+                return Ok(true)
+            }
+            db.delete_template(uid, &template.name).await?;
+            if let Some(msg) = q.message {
+                outbox::send(bot.delete_message(msg.chat.id, msg.id)).await?;
+            }
+            send_templates(bot, db, Some(&q.from)).await?;
+        },
+        _ => log::warn!("template: unknown query {data:?}"),
+    }
+    Ok(true)
+}
+
+async fn receive_name(
+    bot: AutoSend<Bot>,
+    msg: Message,
+    mut db: Db,
+    dialogue: MyDialogue,
+    state: State,
+) -> HandlerResult {
+    logger::set_handler("template::receive_name");
+    log::info!("-> receive_name");
+    let cid = dialogue.chat_id();
+    let uid = match msg.from() {
+        Some(user) => user.id,
+        None => {
+            log::warn!("receive_name No user in msg {msg:?}");
+            return Err(format!("No user is msg {msg:?}").into());
+        },
+    };
+    let name = match template::check_name(msg.text().unwrap_or("")) {
+        Ok(name) => name.to_string(),
+        Err(e) => {
+            outbox::send(bot.send_message(cid, e)).await?;
+            return Ok(())
+        },
+    };
+
+    dialogue.exit().await?;
+    let templates = db.user_templates(uid).await?;
+    let existing = templates.iter().find(|t| t.name == name);
+    let exists = existing.is_some();
+    if !exists && templates.len() >= template::MAX_TEMPLATES {
+        outbox::send(bot.send_message(cid, format!("You already have \
+{} templates, please delete some in {} first", template::MAX_TEMPLATES,
+            ui::commands::Command::Templates))).await?;
+        return Ok(())
+    }
+    // A new draft doesn't change when the template repeats
+    let recurrence = existing.and_then(|t| t.recurrence.clone());
+    db.save_template(uid, &Template {
+        name: name.clone(),
+        draft: state.draft,
+        recurrence,
+    }).await?;
+    let text = if exists {
+        format!("Template \"{name}\" is updated")
+    } else {
+        format!("Template \"{name}\" is saved, you'll find it when you \
+create a new order")
+    };
+    outbox::send(bot.send_message(cid, text)).await?;
+    ui::main_menu::send_menu_link(bot, cid).await?;
+    Ok(())
+}